@@ -9,6 +9,7 @@ pub mod constants;
 mod block;
 mod block_header;
 mod merkle_root;
+mod partial_merkle_tree;
 mod transaction;
 
 /// `IndexedBlock` extension
@@ -27,6 +28,7 @@ pub use primitives::{hash, bytes, bigint, compact};
 pub use block::Block;
 pub use block_header::BlockHeader;
 pub use merkle_root::{merkle_root, merkle_node_hash};
+pub use partial_merkle_tree::{PartialMerkleTree, Error as PartialMerkleTreeError};
 pub use transaction::{Transaction, TransactionInput, TransactionOutput, OutPoint};
 
 pub use read_and_hash::{ReadAndHash, HashedData};
@@ -0,0 +1,325 @@
+use std::io;
+use std::cmp::min;
+use hash::H256;
+use bytes::Bytes;
+use ser::{Deserializable, Reader, Error as ReaderError, Serializable, Stream};
+use merkle_root::merkle_node_hash;
+
+/// Partial merkle tree, used in `merkleblock` message (BIP37).
+/// https://bitcoin.org/en/developer-reference#merkleblock
+#[derive(Debug, PartialEq, Clone)]
+pub struct PartialMerkleTree {
+	/// Total number of transactions in the block
+	pub total_transactions: u32,
+	/// Hashes of tree nodes, in depth-first order
+	pub hashes: Vec<H256>,
+	/// Flag bits, in depth-first order
+	pub flags: Vec<bool>,
+}
+
+/// Partial merkle tree extraction error
+#[derive(Debug, PartialEq)]
+pub enum Error {
+	/// Tree has no transactions
+	NoTransactions,
+	/// There are more hashes than transactions
+	TooManyHashes,
+	/// There are less flag bits than hashes
+	NotEnoughFlags,
+	/// All flag bits are used before tree is traversed
+	FlagsOverflow,
+	/// All hashes are used before tree is traversed
+	HashesOverflow,
+	/// Some flag bits (besides padding) are not used
+	UnusedFlags,
+	/// Some hashes are not used
+	UnusedHashes,
+	/// Both children of the node have the same hash (CVE-2012-2459)
+	DuplicateHash,
+}
+
+impl PartialMerkleTree {
+	/// Build partial merkle tree from hashes of all block transactions and their match flags
+	pub fn build(total_transactions: u32, matches: &[bool], hashes: &[H256]) -> Self {
+		assert_eq!(total_transactions as usize, matches.len());
+		assert_eq!(total_transactions as usize, hashes.len());
+
+		let mut tree = PartialMerkleTree {
+			total_transactions: total_transactions,
+			hashes: Vec::new(),
+			flags: Vec::new(),
+		};
+		let height = tree.tree_height();
+		tree.build_branch(height, 0, matches, hashes);
+		tree
+	}
+
+	/// Extract merkle root and hashes of matched transactions
+	pub fn extract_matches(&self) -> Result<(H256, Vec<H256>), Error> {
+		if self.total_transactions == 0 {
+			return Err(Error::NoTransactions);
+		}
+		if self.hashes.len() > self.total_transactions as usize {
+			return Err(Error::TooManyHashes);
+		}
+		if self.flags.len() < self.hashes.len() {
+			return Err(Error::NotEnoughFlags);
+		}
+
+		let mut flags_used = 0;
+		let mut hashes_used = 0;
+		let mut matches = Vec::new();
+		let height = self.tree_height();
+		let root = self.extract_branch(height, 0, &mut flags_used, &mut hashes_used, &mut matches)?;
+
+		// flags are serialized as bytes => only padding bits of the last byte may be left unused
+		if (flags_used + 7) / 8 != (self.flags.len() + 7) / 8 {
+			return Err(Error::UnusedFlags);
+		}
+		if hashes_used != self.hashes.len() {
+			return Err(Error::UnusedHashes);
+		}
+
+		Ok((root, matches))
+	}
+
+	/// Flag bits, packed into bytes (least significant bit first)
+	pub fn flags_bytes(&self) -> Bytes {
+		let mut bytes = vec![0u8; (self.flags.len() + 7) / 8];
+		for (index, flag) in self.flags.iter().enumerate() {
+			if *flag {
+				bytes[index / 8] |= 1 << (index % 8);
+			}
+		}
+		bytes.into()
+	}
+
+	/// Unpack flag bits from bytes (least significant bit first)
+	pub fn flags_from_bytes(bytes: &[u8]) -> Vec<bool> {
+		(0..bytes.len() * 8)
+			.map(|index| bytes[index / 8] & (1 << (index % 8)) != 0)
+			.collect()
+	}
+
+	fn build_branch(&mut self, height: usize, pos: usize, matches: &[bool], hashes: &[H256]) {
+		// determine whether this node is the parent of at least one matched transaction
+		let transactions_begin = pos << height;
+		let transactions_end = min(self.total_transactions as usize, (pos + 1) << height);
+		let flag = matches[transactions_begin..transactions_end].iter().any(|is_match| *is_match);
+		self.flags.push(flag);
+
+		if height == 0 || !flag {
+			// we're at the leaf level || there is no match
+			let hash = self.branch_hash(height, pos, hashes);
+			self.hashes.push(hash);
+		} else {
+			self.build_branch(height - 1, pos << 1, matches, hashes);
+			if (pos << 1) + 1 < self.level_width(height - 1) {
+				self.build_branch(height - 1, (pos << 1) + 1, matches, hashes);
+			}
+		}
+	}
+
+	fn extract_branch(&self, height: usize, pos: usize, flags_used: &mut usize, hashes_used: &mut usize, matches: &mut Vec<H256>) -> Result<H256, Error> {
+		if *flags_used >= self.flags.len() {
+			return Err(Error::FlagsOverflow);
+		}
+
+		let flag = self.flags[*flags_used];
+		*flags_used += 1;
+
+		if height == 0 || !flag {
+			// we're at the leaf level || there is no match
+			if *hashes_used >= self.hashes.len() {
+				return Err(Error::HashesOverflow);
+			}
+
+			let hash = self.hashes[*hashes_used].clone();
+			*hashes_used += 1;
+			if height == 0 && flag {
+				matches.push(hash.clone());
+			}
+
+			Ok(hash)
+		} else {
+			let left = self.extract_branch(height - 1, pos << 1, flags_used, hashes_used, matches)?;
+			if (pos << 1) + 1 < self.level_width(height - 1) {
+				let right = self.extract_branch(height - 1, (pos << 1) + 1, flags_used, hashes_used, matches)?;
+				if left == right {
+					return Err(Error::DuplicateHash);
+				}
+
+				Ok(merkle_node_hash(&left, &right))
+			} else {
+				Ok(merkle_node_hash(&left, &left))
+			}
+		}
+	}
+
+	fn branch_hash(&self, height: usize, pos: usize, hashes: &[H256]) -> H256 {
+		if height == 0 {
+			return hashes[pos].clone();
+		}
+
+		let left = self.branch_hash(height - 1, pos << 1, hashes);
+		let right = if (pos << 1) + 1 < self.level_width(height - 1) {
+			self.branch_hash(height - 1, (pos << 1) + 1, hashes)
+		} else {
+			left.clone()
+		};
+
+		merkle_node_hash(&left, &right)
+	}
+
+	fn tree_height(&self) -> usize {
+		let mut height = 0;
+		while self.level_width(height) > 1 {
+			height += 1;
+		}
+		height
+	}
+
+	fn level_width(&self, height: usize) -> usize {
+		(self.total_transactions as usize + (1 << height) - 1) >> height
+	}
+}
+
+impl Serializable for PartialMerkleTree {
+	fn serialize(&self, stream: &mut Stream) {
+		stream
+			.append(&self.total_transactions)
+			.append_list(&self.hashes)
+			.append(&self.flags_bytes());
+	}
+}
+
+impl Deserializable for PartialMerkleTree {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, ReaderError> where T: io::Read {
+		let total_transactions = try!(reader.read());
+		let hashes = try!(reader.read_list());
+		let flags: Bytes = try!(reader.read());
+
+		let tree = PartialMerkleTree {
+			total_transactions: total_transactions,
+			hashes: hashes,
+			flags: PartialMerkleTree::flags_from_bytes(&flags),
+		};
+
+		Ok(tree)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crypto::dhash256;
+	use hash::H256;
+	use ser::{serialize, deserialize};
+	use merkle_root::{merkle_root, merkle_node_hash};
+	use super::{PartialMerkleTree, Error};
+
+	fn test_hashes(count: u8) -> Vec<H256> {
+		(0..count).map(|n| H256::from(n + 1)).collect()
+	}
+
+	#[test]
+	fn partial_merkle_tree_single_transaction() {
+		let hashes = test_hashes(1);
+		let tree = PartialMerkleTree::build(1, &[true], &hashes);
+		assert_eq!(tree.hashes, hashes);
+		assert_eq!(tree.flags, vec![true]);
+		assert_eq!(tree.extract_matches(), Ok((hashes[0].clone(), hashes.clone())));
+
+		let tree = PartialMerkleTree::build(1, &[false], &hashes);
+		assert_eq!(tree.flags, vec![false]);
+		assert_eq!(tree.extract_matches(), Ok((hashes[0].clone(), vec![])));
+	}
+
+	#[test]
+	fn partial_merkle_tree_known_vector() {
+		// https://bitcoin.org/en/developer-reference#parsing-a-merkleblock-message
+		// 7 transactions, 5th is matched => 4 hashes and flags 0x1d
+		let hashes = test_hashes(7);
+		let matches = [false, false, false, false, true, false, false];
+		let tree = PartialMerkleTree::build(7, &matches, &hashes);
+		assert_eq!(tree.hashes, vec![
+			merkle_root(&hashes[0..4]),
+			hashes[4].clone(),
+			hashes[5].clone(),
+			merkle_node_hash(&hashes[6], &hashes[6]),
+		]);
+		assert_eq!(tree.flags_bytes(), vec![0x1d].into());
+
+		// round-trip through serialization
+		let serialized = serialize(&tree);
+		assert_eq!(&serialized[0..5], &[7u8, 0, 0, 0, 4]);
+		assert_eq!(&serialized[serialized.len() - 2..], &[1u8, 0x1d]);
+		let deserialized: PartialMerkleTree = deserialize(&serialized as &[u8]).unwrap();
+		assert_eq!(deserialized.extract_matches(), Ok((merkle_root(&hashes), vec![hashes[4].clone()])));
+	}
+
+	#[test]
+	// test from core implementation
+	// https://github.com/bitcoin/bitcoin/blob/master/src/test/pmt_tests.cpp
+	fn partial_merkle_tree_roundtrip() {
+		// xorshift, so that matches are pseudo-random, but reproducible
+		let mut state = 0x2545f491u32;
+		let mut next_random = move || {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			state
+		};
+
+		let tx_counts: Vec<u32> = vec![1, 4, 7, 17, 56, 100, 127, 256, 312, 513, 1000, 4095];
+		for tx_count in tx_counts {
+			let hashes: Vec<H256> = (0..tx_count).map(|n| dhash256(&serialize(&n))).collect();
+			let root = merkle_root(&hashes);
+
+			// mark different transactions as matched
+			for seed_tweak in 1..15 {
+				let matches: Vec<bool> = (0..tx_count).map(|_| next_random() & ((1 << (seed_tweak / 2)) - 1) == 0).collect();
+				let matched: Vec<H256> = hashes.iter()
+					.zip(matches.iter())
+					.filter(|&(_, is_match)| *is_match)
+					.map(|(hash, _)| hash.clone())
+					.collect();
+
+				let tree = PartialMerkleTree::build(tx_count, &matches, &hashes);
+				let deserialized: PartialMerkleTree = deserialize(&serialize(&tree) as &[u8]).unwrap();
+				assert_eq!(deserialized.extract_matches(), Ok((root.clone(), matched)));
+			}
+		}
+	}
+
+	#[test]
+	fn partial_merkle_tree_duplicate_hash() {
+		// 3 transactions with the last one duplicated have the same merkle root as 4 transactions
+		let mut hashes = test_hashes(3);
+		let last = hashes[2].clone();
+		hashes.push(last);
+		let tree = PartialMerkleTree::build(4, &[false, false, true, false], &hashes);
+		assert_eq!(tree.extract_matches(), Err(Error::DuplicateHash));
+	}
+
+	#[test]
+	fn partial_merkle_tree_malformed() {
+		let hashes = test_hashes(4);
+		let tree = PartialMerkleTree::build(4, &[false, true, false, false], &hashes);
+
+		let mut no_transactions = tree.clone();
+		no_transactions.total_transactions = 0;
+		assert_eq!(no_transactions.extract_matches(), Err(Error::NoTransactions));
+
+		let mut extra_hash = tree.clone();
+		extra_hash.hashes.push(hashes[0].clone());
+		assert_eq!(extra_hash.extract_matches(), Err(Error::UnusedHashes));
+
+		let mut extra_flags = tree.clone();
+		extra_flags.flags.extend(vec![false; 8]);
+		assert_eq!(extra_flags.extract_matches(), Err(Error::UnusedFlags));
+
+		let mut missing_hash = tree.clone();
+		missing_hash.hashes.pop();
+		assert_eq!(missing_hash.extract_matches(), Err(Error::HashesOverflow));
+	}
+}
@@ -21,6 +21,18 @@ pub trait BlockChain {
 	/// Decanonizes best block
 	fn decanonize(&self) -> Result<H256, Error>;
 
+	/// Clears all indices, derived from stored blocks, and remembers target block of reindex
+	fn clear_indices(&self, reindex_target: &H256) -> Result<(), Error>;
+
+	/// Returns target block of the unfinished reindex
+	fn reindex_target(&self) -> Option<H256>;
+
+	/// Returns true if all indices have been cleared by the unfinished reindex
+	fn are_indices_cleared(&self) -> bool;
+
+	/// Forgets target block of the finished reindex
+	fn finish_reindex(&self) -> Result<(), Error>;
+
 	/// Checks block origin
 	fn block_origin(&self, header: &IndexedBlockHeader) -> Result<BlockOrigin, Error>;
 }
@@ -17,18 +17,22 @@ use kv::{
 };
 use kv::{
 	COL_COUNT, COL_BLOCK_HASHES, COL_BLOCK_HEADERS, COL_BLOCK_TRANSACTIONS, COL_TRANSACTIONS,
-	COL_TRANSACTIONS_META, COL_BLOCK_NUMBERS
+	COL_TRANSACTIONS_META, COL_BLOCK_NUMBERS, COL_BLOCK_UNDO, COL_OUTPUT_SPENDERS,
+	COL_LEAF_BLOCKS
 };
 use best_block::BestBlock;
 use {
 	BlockRef, Error, BlockHeaderProvider, BlockProvider, BlockOrigin, TransactionMeta, IndexedBlockProvider,
 	TransactionMetaProvider, TransactionProvider, TransactionOutputProvider, BlockChain, Store,
-	SideChainOrigin, ForkChain, Forkable, CanonStore
+	SideChainOrigin, ForkChain, Forkable, CanonStore, BlockUndo, SpentOutput, OutputSpender
 };
 
 const KEY_VERSION: &'static str = "version";
 const KEY_BEST_BLOCK_NUMBER: &'static str = "best_block_number";
 const KEY_BEST_BLOCK_HASH: &'static str = "best_block_hash";
+const KEY_LEAF_BLOCKS_COUNT: &'static str = "leaf_blocks_count";
+const KEY_REINDEX_TARGET: &'static str = "reindex_target";
+const KEY_INDICES_CLEARED: &'static str = "indices_cleared";
 
 const DB_VERSION: u32 = 1;
 const MAX_FORK_ROUTE_PRESET: usize = 2048;
@@ -36,6 +40,10 @@ const MAX_FORK_ROUTE_PRESET: usize = 2048;
 pub struct BlockChainDatabase<T> where T: KeyValueDatabase {
 	best_block: RwLock<BestBlock>,
 	db: T,
+	/// Maintain index of spent outputs
+	spent_index: bool,
+	/// Store outputs, spent by canon blocks
+	block_undo: bool,
 }
 
 pub struct ForkChainDatabase<'a, T> where T: 'a + KeyValueDatabase {
@@ -61,9 +69,12 @@ impl BlockChainDatabase<CacheDatabase<AutoFlushingOverlayDatabase<DiskDatabase>>
 		cfg.set_cache(Some(COL_TRANSACTIONS_META), total_cache / 4);
 		cfg.set_cache(Some(COL_BLOCK_HEADERS), total_cache / 4);
 
-		cfg.set_cache(Some(COL_BLOCK_HASHES), total_cache / 12);
-		cfg.set_cache(Some(COL_BLOCK_TRANSACTIONS), total_cache / 12);
-		cfg.set_cache(Some(COL_BLOCK_NUMBERS), total_cache / 12);
+		cfg.set_cache(Some(COL_BLOCK_HASHES), total_cache / 20);
+		cfg.set_cache(Some(COL_BLOCK_TRANSACTIONS), total_cache / 20);
+		cfg.set_cache(Some(COL_BLOCK_NUMBERS), total_cache / 20);
+		cfg.set_cache(Some(COL_BLOCK_UNDO), total_cache / 20);
+		cfg.set_cache(Some(COL_OUTPUT_SPENDERS), total_cache / 20);
+		cfg.set_cache(Some(COL_LEAF_BLOCKS), total_cache / 20);
 
 		cfg.bloom_filters.insert(Some(COL_TRANSACTIONS_META), 32);
 
@@ -94,6 +105,8 @@ impl<T> BlockChainDatabase<CacheDatabase<AutoFlushingOverlayDatabase<T>>> where
 		BlockChainDatabase {
 			best_block: RwLock::new(best_block),
 			db: db,
+			spent_index: false,
+			block_undo: false,
 		}
 	}
 }
@@ -118,15 +131,33 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		BlockChainDatabase {
 			best_block: RwLock::new(best_block),
 			db: db,
+			spent_index: false,
+			block_undo: false,
 		}
 	}
 
+	/// Enables or disables index of spent outputs.
+	/// Index is only updated for blocks, canonized while it is enabled
+	pub fn with_spent_index(mut self, spent_index: bool) -> Self {
+		self.spent_index = spent_index;
+		self
+	}
+
+	/// Enables or disables storing of block undo data (outputs, spent by the block).
+	/// Undo data is only stored for blocks, canonized while it is enabled
+	pub fn with_block_undo(mut self, block_undo: bool) -> Self {
+		self.block_undo = block_undo;
+		self
+	}
+
 	pub fn best_block(&self) -> BestBlock {
 		self.best_block.read().clone()
 	}
 
 	pub fn fork(&self, side_chain: SideChainOrigin) -> Result<ForkChainDatabase<T>, Error> {
-		let overlay = BlockChainDatabase::open(OverlayDatabase::new(&self.db));
+		let overlay = BlockChainDatabase::open(OverlayDatabase::new(&self.db))
+			.with_spent_index(self.spent_index)
+			.with_block_undo(self.block_undo);
 
 		for hash in side_chain.decanonized_route.into_iter().rev() {
 			let decanonized_hash = overlay.decanonize()?;
@@ -212,6 +243,7 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		}
 
 		let mut update = DBTransaction::new();
+		self.insert_leaf_block(&mut update, &parent_hash, block.hash());
 		update.insert(KeyValue::BlockHeader(block.hash().clone(), block.header.raw));
 		let tx_hashes = block.transactions.iter().map(|tx| tx.hash.clone()).collect::<Vec<_>>();
 		update.insert(KeyValue::BlockTransactions(block.header.hash.clone(), List::from(tx_hashes)));
@@ -223,6 +255,43 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		self.db.write(update).map_err(Error::DatabaseError)
 	}
 
+	/// Returns hashes of all inserted blocks, which have no inserted children.
+	/// Includes both canonized and side chain tips
+	pub fn leaf_blocks(&self) -> Vec<H256> {
+		(0..self.leaf_blocks_count())
+			.map(|slot| self.get(Key::LeafBlock(slot))
+				.and_then(Value::as_leaf_block)
+				.expect("Inconsistent DB. Missing leaf block."))
+			.collect()
+	}
+
+	fn leaf_blocks_count(&self) -> u32 {
+		self.get(Key::Meta(KEY_LEAF_BLOCKS_COUNT))
+			.and_then(Value::as_meta)
+			.map(|bytes| deserialize(&**bytes).expect("Inconsistent DB. Invalid leaf blocks count."))
+			.unwrap_or_default()
+	}
+
+	/// Every leaf block is stored in its own slot. Child block takes the slot of its parent,
+	/// so that only a few single entries are updated on every insertion
+	fn insert_leaf_block(&self, update: &mut DBTransaction, parent_hash: &H256, hash: &H256) {
+		let slot = match self.get(Key::LeafBlockSlot(parent_hash.clone())).and_then(Value::as_leaf_block_slot) {
+			Some(slot) => {
+				update.delete(Key::LeafBlockSlot(parent_hash.clone()));
+				slot
+			},
+			None => {
+				// genesis block or a new fork
+				let count = self.leaf_blocks_count();
+				update.insert(KeyValue::Meta(KEY_LEAF_BLOCKS_COUNT, serialize(&(count + 1))));
+				count
+			},
+		};
+
+		update.insert(KeyValue::LeafBlock(slot, hash.clone()));
+		update.insert(KeyValue::LeafBlockSlot(hash.clone(), slot));
+	}
+
 	/// Marks block as a new best block.
 	/// Block must be already inserted into db, and it's parent must be current best block.
 	/// Updates meta data.
@@ -256,6 +325,7 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		update.insert(KeyValue::Meta(KEY_BEST_BLOCK_NUMBER, serialize(&new_best_block.number)));
 
 		let mut modified_meta: HashMap<H256, TransactionMeta> = HashMap::new();
+		let mut undo = BlockUndo::default();
 		if let Some(tx) = block.transactions.first() {
 			let meta = TransactionMeta::new_coinbase(new_best_block.number, tx.raw.outputs.len());
 			modified_meta.insert(tx.hash.clone(), meta);
@@ -267,17 +337,38 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 			for input in &tx.raw.inputs {
 				use std::collections::hash_map::Entry;
 
-				match modified_meta.entry(input.previous_output.hash.clone()) {
-					Entry::Occupied(mut entry) => {
-						let meta = entry.get_mut();
-						meta.denote_used(input.previous_output.index as usize);
-					},
+				let meta = match modified_meta.entry(input.previous_output.hash.clone()) {
+					Entry::Occupied(entry) => entry.into_mut(),
 					Entry::Vacant(entry) => {
-						let mut meta = self.transaction_meta(&input.previous_output.hash)
+						let meta = self.transaction_meta(&input.previous_output.hash)
 							.ok_or(Error::CannotCanonize)?;
-						meta.denote_used(input.previous_output.index as usize);
-						entry.insert(meta);
+						entry.insert(meta)
 					}
+				};
+				meta.denote_used(input.previous_output.index as usize);
+
+				if !self.block_undo {
+					continue;
+				}
+
+				// previous transaction is either canon, or is inserted with this block
+				let output = self.transaction(&input.previous_output.hash)
+					.and_then(|tx| tx.outputs.into_iter().nth(input.previous_output.index as usize))
+					.ok_or(Error::CannotCanonize)?;
+				undo.spent_outputs.push(SpentOutput {
+					value: output.value,
+					script_pubkey: output.script_pubkey,
+					height: meta.height(),
+					is_coinbase: meta.is_coinbase(),
+				});
+			}
+
+			if self.spent_index {
+				for (input_index, input) in tx.raw.inputs.iter().enumerate() {
+					update.insert(KeyValue::OutputSpender(input.previous_output.clone(), OutputSpender {
+						transaction_hash: tx.hash.clone(),
+						input_index: input_index as u32,
+					}));
 				}
 			}
 		}
@@ -285,6 +376,9 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		for (hash, meta) in modified_meta.into_iter() {
 			update.insert(KeyValue::TransactionMeta(hash, meta));
 		}
+		if self.block_undo {
+			update.insert(KeyValue::BlockUndo(new_best_block.hash.clone(), undo));
+		}
 
 		self.db.write(update).map_err(Error::DatabaseError)?;
 		*best_block = new_best_block;
@@ -315,6 +409,8 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		let mut update = DBTransaction::new();
 		update.delete(Key::BlockHash(block_number));
 		update.delete(Key::BlockNumber(block_hash.clone()));
+		// delete even if undo data is disabled now, because block could be canonized with undo data enabled
+		update.delete(Key::BlockUndo(block_hash.clone()));
 		update.insert(KeyValue::Meta(KEY_BEST_BLOCK_HASH, serialize(&new_best_block.hash)));
 		update.insert(KeyValue::Meta(KEY_BEST_BLOCK_NUMBER, serialize(&new_best_block.number)));
 
@@ -323,6 +419,9 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 			for input in &tx.raw.inputs {
 				use std::collections::hash_map::Entry;
 
+				// delete even if index is disabled now, because block could be canonized with index enabled
+				update.delete(Key::OutputSpender(input.previous_output.clone()));
+
 				match modified_meta.entry(input.previous_output.hash.clone()) {
 					Entry::Occupied(mut entry) => {
 						let meta = entry.get_mut();
@@ -351,6 +450,95 @@ impl<T> BlockChainDatabase<T> where T: KeyValueDatabase {
 		Ok(block_hash)
 	}
 
+	/// Clears all indices, derived from stored blocks: canon blocks numbers and hashes, transactions meta,
+	/// undo data, spent outputs index and the best block. Indices are deleted without reading them,
+	/// so this works even if they are inconsistent. Only raw blocks data (which is never modified) is read.
+	/// `reindex_target` is remembered until `finish_reindex` is called. Completion of clearing is also remembered,
+	/// so that interrupted clearing could be detected.
+	pub fn clear_indices(&self, reindex_target: &H256) -> Result<(), Error> {
+		let mut best_block = self.best_block.write();
+
+		// best block is deleted first => nothing is canonized until all indices are cleared
+		let mut update = DBTransaction::new();
+		update.insert(KeyValue::Meta(KEY_REINDEX_TARGET, serialize(reindex_target)));
+		update.delete(Key::Meta(KEY_INDICES_CLEARED));
+		update.delete(Key::Meta(KEY_BEST_BLOCK_HASH));
+		update.delete(Key::Meta(KEY_BEST_BLOCK_NUMBER));
+		self.db.write(update).map_err(Error::DatabaseError)?;
+		*best_block = BestBlock::default();
+
+		// visit every stored block, starting from leaves
+		let mut numbers: HashMap<H256, u32> = HashMap::new();
+		for leaf in self.leaf_blocks() {
+			let mut route = Vec::new();
+			let mut hash = leaf;
+			let mut number = loop {
+				if let Some(number) = numbers.get(&hash) {
+					break number + 1;
+				}
+
+				let header = self.block_header(hash.clone().into()).ok_or(Error::UnknownParent)?;
+				route.push(hash);
+				if header.previous_header_hash.is_zero() {
+					break 0;
+				}
+				hash = header.previous_header_hash;
+			};
+
+			for hash in route.into_iter().rev() {
+				self.clear_block_indices(&hash, number)?;
+				numbers.insert(hash, number);
+				number += 1;
+			}
+		}
+
+		let mut update = DBTransaction::new();
+		update.insert(KeyValue::Meta(KEY_INDICES_CLEARED, serialize(&true)));
+		self.db.write(update).map_err(Error::DatabaseError)
+	}
+
+	fn clear_block_indices(&self, hash: &H256, number: u32) -> Result<(), Error> {
+		let mut update = DBTransaction::new();
+		update.delete(Key::BlockHash(number));
+		update.delete(Key::BlockNumber(hash.clone()));
+		update.delete(Key::BlockUndo(hash.clone()));
+
+		let tx_hashes: Vec<H256> = self.get(Key::BlockTransactions(hash.clone()))
+			.and_then(Value::as_block_transactions)
+			.map(List::into)
+			.unwrap_or_default();
+		for tx_hash in tx_hashes {
+			if let Some(tx) = self.transaction(&tx_hash) {
+				for input in tx.inputs {
+					update.delete(Key::OutputSpender(input.previous_output));
+				}
+			}
+			update.delete(Key::TransactionMeta(tx_hash));
+		}
+
+		self.db.write(update).map_err(Error::DatabaseError)
+	}
+
+	/// Returns target block of the unfinished reindex
+	pub fn reindex_target(&self) -> Option<H256> {
+		self.get(Key::Meta(KEY_REINDEX_TARGET))
+			.and_then(Value::as_meta)
+			.map(|bytes| deserialize(&**bytes).expect("Inconsistent DB. Invalid reindex target."))
+	}
+
+	/// Returns true if all indices have been cleared by `clear_indices` and reindex is not finished yet
+	pub fn are_indices_cleared(&self) -> bool {
+		self.get(Key::Meta(KEY_INDICES_CLEARED)).is_some()
+	}
+
+	/// Forgets target block of the finished reindex
+	pub fn finish_reindex(&self) -> Result<(), Error> {
+		let mut update = DBTransaction::new();
+		update.delete(Key::Meta(KEY_REINDEX_TARGET));
+		update.delete(Key::Meta(KEY_INDICES_CLEARED));
+		self.db.write(update).map_err(Error::DatabaseError)
+	}
+
 	fn get(&self, key: Key) -> Option<Value> {
 		self.db.get(&key).expect("db value to be fine").into_option()
 	}
@@ -418,6 +606,11 @@ impl<T> BlockProvider for BlockChainDatabase<T> where T: KeyValueDatabase {
 			.filter_map(Value::as_transaction)
 			.collect()
 	}
+
+	fn block_undo(&self, hash: &H256) -> Option<BlockUndo> {
+		self.get(Key::BlockUndo(hash.clone()))
+			.and_then(Value::as_block_undo)
+	}
 }
 
 impl<T> IndexedBlockProvider for BlockChainDatabase<T> where T: KeyValueDatabase {
@@ -458,6 +651,11 @@ impl<T> TransactionMetaProvider for BlockChainDatabase<T> where T: KeyValueDatab
 		self.get(Key::TransactionMeta(hash.clone()))
 			.and_then(Value::as_transaction_meta)
 	}
+
+	fn output_spender(&self, outpoint: &OutPoint) -> Option<OutputSpender> {
+		self.get(Key::OutputSpender(outpoint.clone()))
+			.and_then(Value::as_output_spender)
+	}
 }
 
 impl<T> TransactionProvider for BlockChainDatabase<T> where T: KeyValueDatabase {
@@ -499,6 +697,22 @@ impl<T> BlockChain for BlockChainDatabase<T> where T: KeyValueDatabase {
 		BlockChainDatabase::decanonize(self)
 	}
 
+	fn clear_indices(&self, reindex_target: &H256) -> Result<(), Error> {
+		BlockChainDatabase::clear_indices(self, reindex_target)
+	}
+
+	fn reindex_target(&self) -> Option<H256> {
+		BlockChainDatabase::reindex_target(self)
+	}
+
+	fn are_indices_cleared(&self) -> bool {
+		BlockChainDatabase::are_indices_cleared(self)
+	}
+
+	fn finish_reindex(&self) -> Result<(), Error> {
+		BlockChainDatabase::finish_reindex(self)
+	}
+
 	fn block_origin(&self, header: &IndexedBlockHeader) -> Result<BlockOrigin, Error> {
 		BlockChainDatabase::block_origin(self, header)
 	}
@@ -540,4 +754,9 @@ impl<T> Store for BlockChainDatabase<T> where T: KeyValueDatabase {
 	fn difficulty(&self) -> f64 {
 		self.best_header().bits.to_f64()
 	}
+
+	/// get hashes of stored blocks, which have no stored children
+	fn leaf_blocks(&self) -> Vec<H256> {
+		BlockChainDatabase::leaf_blocks(self)
+	}
 }
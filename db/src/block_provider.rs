@@ -1,7 +1,7 @@
 use hash::H256;
 use bytes::Bytes;
 use chain::{BlockHeader, Transaction, Block, IndexedBlock, IndexedBlockHeader, IndexedTransaction};
-use {BlockRef};
+use {BlockRef, BlockUndo};
 
 pub trait BlockHeaderProvider {
 	/// resolves header bytes by block reference (number/hash)
@@ -32,6 +32,9 @@ pub trait BlockProvider: BlockHeaderProvider {
 
 	/// returns all transactions in the block by block reference (number/hash)
 	fn block_transactions(&self, block_ref: BlockRef) -> Vec<Transaction>;
+
+	/// resolves outputs, spent by the canon block inputs
+	fn block_undo(&self, hash: &H256) -> Option<BlockUndo>;
 }
 
 pub trait IndexedBlockProvider: BlockProvider {
@@ -0,0 +1,64 @@
+//! Block undo data
+
+use std::io;
+use bytes::Bytes;
+use ser::{Serializable, Deserializable, Error as ReaderError, Stream, Reader};
+
+/// Previous output, spent by the block input
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpentOutput {
+	/// Value of the spent output
+	pub value: u64,
+	/// Script of the spent output
+	pub script_pubkey: Bytes,
+	/// Height of the block, containing transaction with spent output
+	pub height: u32,
+	/// Is spent output an output of coinbase transaction
+	pub is_coinbase: bool,
+}
+
+/// Outputs, spent by the block inputs. Used to disconnect block without recomputing spent outputs.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BlockUndo {
+	/// Spent outputs in the order of block inputs (coinbase input is skipped)
+	pub spent_outputs: Vec<SpentOutput>,
+}
+
+impl Serializable for SpentOutput {
+	fn serialize(&self, stream: &mut Stream) {
+		stream
+			.append(&self.value)
+			.append(&self.script_pubkey)
+			.append(&self.height)
+			.append(&self.is_coinbase);
+	}
+}
+
+impl Deserializable for SpentOutput {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, ReaderError> where T: io::Read {
+		let result = SpentOutput {
+			value: reader.read()?,
+			script_pubkey: reader.read()?,
+			height: reader.read()?,
+			is_coinbase: reader.read()?,
+		};
+
+		Ok(result)
+	}
+}
+
+impl Serializable for BlockUndo {
+	fn serialize(&self, stream: &mut Stream) {
+		stream.append_list(&self.spent_outputs);
+	}
+}
+
+impl Deserializable for BlockUndo {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, ReaderError> where T: io::Read {
+		let result = BlockUndo {
+			spent_outputs: reader.read_list()?,
+		};
+
+		Ok(result)
+	}
+}
@@ -5,9 +5,9 @@ use parking_lot::RwLock;
 use hash::H256;
 use bytes::Bytes;
 use ser::List;
-use chain::{Transaction as ChainTransaction, BlockHeader};
+use chain::{Transaction as ChainTransaction, BlockHeader, OutPoint};
 use kv::{Transaction, Key, KeyState, Operation, Value, KeyValueDatabase, KeyValue};
-use {TransactionMeta};
+use {TransactionMeta, BlockUndo, OutputSpender};
 
 #[derive(Default, Debug)]
 struct InnerDatabase {
@@ -18,6 +18,10 @@ struct InnerDatabase {
 	transaction: HashMap<H256, KeyState<ChainTransaction>>,
 	transaction_meta: HashMap<H256, KeyState<TransactionMeta>>,
 	block_number: HashMap<H256, KeyState<u32>>,
+	block_undo: HashMap<H256, KeyState<BlockUndo>>,
+	output_spender: HashMap<OutPoint, KeyState<OutputSpender>>,
+	leaf_block: HashMap<u32, KeyState<H256>>,
+	leaf_block_slot: HashMap<H256, KeyState<u32>>,
 }
 
 #[derive(Default, Debug)]
@@ -49,6 +53,18 @@ impl MemoryDatabase {
 		let block_number = replace(&mut db.block_number, HashMap::default()).into_iter()
 			.flat_map(|(key, state)| state.into_operation(key, KeyValue::BlockNumber, Key::BlockNumber));
 
+		let block_undo = replace(&mut db.block_undo, HashMap::default()).into_iter()
+			.flat_map(|(key, state)| state.into_operation(key, KeyValue::BlockUndo, Key::BlockUndo));
+
+		let output_spender = replace(&mut db.output_spender, HashMap::default()).into_iter()
+			.flat_map(|(key, state)| state.into_operation(key, KeyValue::OutputSpender, Key::OutputSpender));
+
+		let leaf_block = replace(&mut db.leaf_block, HashMap::default()).into_iter()
+			.flat_map(|(key, state)| state.into_operation(key, KeyValue::LeafBlock, Key::LeafBlock));
+
+		let leaf_block_slot = replace(&mut db.leaf_block_slot, HashMap::default()).into_iter()
+			.flat_map(|(key, state)| state.into_operation(key, KeyValue::LeafBlockSlot, Key::LeafBlockSlot));
+
 		Transaction {
 			operations: meta
 				.chain(block_hash)
@@ -57,6 +73,10 @@ impl MemoryDatabase {
 				.chain(transaction)
 				.chain(transaction_meta)
 				.chain(block_number)
+				.chain(block_undo)
+				.chain(output_spender)
+				.chain(leaf_block)
+				.chain(leaf_block_slot)
 				.collect()
 		}
 	}
@@ -75,6 +95,10 @@ impl KeyValueDatabase for MemoryDatabase {
 					KeyValue::Transaction(key, value) => { db.transaction.insert(key, KeyState::Insert(value)); },
 					KeyValue::TransactionMeta(key, value) => { db.transaction_meta.insert(key, KeyState::Insert(value)); },
 					KeyValue::BlockNumber(key, value) => { db.block_number.insert(key, KeyState::Insert(value)); },
+					KeyValue::BlockUndo(key, value) => { db.block_undo.insert(key, KeyState::Insert(value)); },
+					KeyValue::OutputSpender(key, value) => { db.output_spender.insert(key, KeyState::Insert(value)); },
+					KeyValue::LeafBlock(key, value) => { db.leaf_block.insert(key, KeyState::Insert(value)); },
+					KeyValue::LeafBlockSlot(key, value) => { db.leaf_block_slot.insert(key, KeyState::Insert(value)); },
 				},
 				Operation::Delete(delete) => match delete {
 					Key::Meta(key) => { db.meta.insert(key, KeyState::Delete); }
@@ -84,6 +108,10 @@ impl KeyValueDatabase for MemoryDatabase {
 					Key::Transaction(key) => { db.transaction.insert(key, KeyState::Delete); }
 					Key::TransactionMeta(key) => { db.transaction_meta.insert(key, KeyState::Delete); }
 					Key::BlockNumber(key) => { db.block_number.insert(key, KeyState::Delete); }
+					Key::BlockUndo(key) => { db.block_undo.insert(key, KeyState::Delete); }
+					Key::OutputSpender(key) => { db.output_spender.insert(key, KeyState::Delete); }
+					Key::LeafBlock(key) => { db.leaf_block.insert(key, KeyState::Delete); }
+					Key::LeafBlockSlot(key) => { db.leaf_block_slot.insert(key, KeyState::Delete); }
 				}
 			}
 		}
@@ -100,6 +128,10 @@ impl KeyValueDatabase for MemoryDatabase {
 			Key::Transaction(ref key) => db.transaction.get(key).cloned().unwrap_or_default().map(Value::Transaction),
 			Key::TransactionMeta(ref key) => db.transaction_meta.get(key).cloned().unwrap_or_default().map(Value::TransactionMeta),
 			Key::BlockNumber(ref key) => db.block_number.get(key).cloned().unwrap_or_default().map(Value::BlockNumber),
+			Key::BlockUndo(ref key) => db.block_undo.get(key).cloned().unwrap_or_default().map(Value::BlockUndo),
+			Key::OutputSpender(ref key) => db.output_spender.get(key).cloned().unwrap_or_default().map(Value::OutputSpender),
+			Key::LeafBlock(ref key) => db.leaf_block.get(key).cloned().unwrap_or_default().map(Value::LeafBlock),
+			Key::LeafBlockSlot(ref key) => db.leaf_block_slot.get(key).cloned().unwrap_or_default().map(Value::LeafBlockSlot),
 		};
 
 		Ok(result)
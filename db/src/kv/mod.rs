@@ -14,5 +14,6 @@ pub use self::transaction::{
 	RawTransaction, Transaction, RawOperation, Operation, Location, KeyState,
 	Key, Value, KeyValue, RawKeyValue, RawKey,
 	COL_COUNT, COL_META, COL_BLOCK_HASHES, COL_BLOCK_HEADERS, COL_BLOCK_TRANSACTIONS,
-	COL_TRANSACTIONS, COL_TRANSACTIONS_META, COL_BLOCK_NUMBERS
+	COL_TRANSACTIONS, COL_TRANSACTIONS_META, COL_BLOCK_NUMBERS, COL_BLOCK_UNDO, COL_OUTPUT_SPENDERS,
+	COL_LEAF_BLOCKS
 };
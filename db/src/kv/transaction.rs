@@ -1,8 +1,8 @@
 use bytes::Bytes;
 use hash::H256;
 use ser::{serialize, List, deserialize};
-use chain::{Transaction as ChainTransaction, BlockHeader};
-use {TransactionMeta};
+use chain::{Transaction as ChainTransaction, BlockHeader, OutPoint};
+use {TransactionMeta, BlockUndo, OutputSpender};
 
 pub const COL_COUNT: u32 = 10;
 pub const COL_META: u32 = 0;
@@ -12,6 +12,10 @@ pub const COL_BLOCK_TRANSACTIONS: u32 = 3;
 pub const COL_TRANSACTIONS: u32 = 4;
 pub const COL_TRANSACTIONS_META: u32 = 5;
 pub const COL_BLOCK_NUMBERS: u32 = 6;
+pub const COL_BLOCK_UNDO: u32 = 7;
+pub const COL_OUTPUT_SPENDERS: u32 = 8;
+/// Leaf blocks slots (u32 keys) and slots of leaf blocks (H256 keys)
+pub const COL_LEAF_BLOCKS: u32 = 9;
 
 #[derive(Debug)]
 pub enum Operation {
@@ -28,6 +32,10 @@ pub enum KeyValue {
 	Transaction(H256, ChainTransaction),
 	TransactionMeta(H256, TransactionMeta),
 	BlockNumber(H256, u32),
+	BlockUndo(H256, BlockUndo),
+	OutputSpender(OutPoint, OutputSpender),
+	LeafBlock(u32, H256),
+	LeafBlockSlot(H256, u32),
 }
 
 #[derive(Debug)]
@@ -39,6 +47,10 @@ pub enum Key {
 	Transaction(H256),
 	TransactionMeta(H256),
 	BlockNumber(H256),
+	BlockUndo(H256),
+	OutputSpender(OutPoint),
+	LeafBlock(u32),
+	LeafBlockSlot(H256),
 }
 
 #[derive(Debug, Clone)]
@@ -50,6 +62,10 @@ pub enum Value {
 	Transaction(ChainTransaction),
 	TransactionMeta(TransactionMeta),
 	BlockNumber(u32),
+	BlockUndo(BlockUndo),
+	OutputSpender(OutputSpender),
+	LeafBlock(H256),
+	LeafBlockSlot(u32),
 }
 
 impl Value {
@@ -62,6 +78,10 @@ impl Value {
 			Key::Transaction(_) => deserialize(bytes).map(Value::Transaction),
 			Key::TransactionMeta(_) => deserialize(bytes).map(Value::TransactionMeta),
 			Key::BlockNumber(_) => deserialize(bytes).map(Value::BlockNumber),
+			Key::BlockUndo(_) => deserialize(bytes).map(Value::BlockUndo),
+			Key::OutputSpender(_) => deserialize(bytes).map(Value::OutputSpender),
+			Key::LeafBlock(_) => deserialize(bytes).map(Value::LeafBlock),
+			Key::LeafBlockSlot(_) => deserialize(bytes).map(Value::LeafBlockSlot),
 		}.map_err(|e| format!("{:?}", e))
 	}
 
@@ -113,6 +133,34 @@ impl Value {
 			_ => None,
 		}
 	}
+
+	pub fn as_block_undo(self) -> Option<BlockUndo> {
+		match self {
+			Value::BlockUndo(undo) => Some(undo),
+			_ => None,
+		}
+	}
+
+	pub fn as_output_spender(self) -> Option<OutputSpender> {
+		match self {
+			Value::OutputSpender(spender) => Some(spender),
+			_ => None,
+		}
+	}
+
+	pub fn as_leaf_block(self) -> Option<H256> {
+		match self {
+			Value::LeafBlock(hash) => Some(hash),
+			_ => None,
+		}
+	}
+
+	pub fn as_leaf_block_slot(self) -> Option<u32> {
+		match self {
+			Value::LeafBlockSlot(slot) => Some(slot),
+			_ => None,
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -215,6 +263,10 @@ impl<'a> From<&'a KeyValue> for RawKeyValue {
 			KeyValue::Transaction(ref key, ref value) => (COL_TRANSACTIONS, serialize(key), serialize(value)),
 			KeyValue::TransactionMeta(ref key, ref value) => (COL_TRANSACTIONS_META, serialize(key), serialize(value)),
 			KeyValue::BlockNumber(ref key, ref value) => (COL_BLOCK_NUMBERS, serialize(key), serialize(value)),
+			KeyValue::BlockUndo(ref key, ref value) => (COL_BLOCK_UNDO, serialize(key), serialize(value)),
+			KeyValue::OutputSpender(ref key, ref value) => (COL_OUTPUT_SPENDERS, serialize(key), serialize(value)),
+			KeyValue::LeafBlock(ref key, ref value) => (COL_LEAF_BLOCKS, serialize(key), serialize(value)),
+			KeyValue::LeafBlockSlot(ref key, ref value) => (COL_LEAF_BLOCKS, serialize(key), serialize(value)),
 		};
 
 		RawKeyValue {
@@ -249,6 +301,10 @@ impl<'a> From<&'a Key> for RawKey {
 			Key::Transaction(ref key) => (COL_TRANSACTIONS, serialize(key)),
 			Key::TransactionMeta(ref key) => (COL_TRANSACTIONS_META, serialize(key)),
 			Key::BlockNumber(ref key) => (COL_BLOCK_NUMBERS, serialize(key)),
+			Key::BlockUndo(ref key) => (COL_BLOCK_UNDO, serialize(key)),
+			Key::OutputSpender(ref key) => (COL_OUTPUT_SPENDERS, serialize(key)),
+			Key::LeafBlock(ref key) => (COL_LEAF_BLOCKS, serialize(key)),
+			Key::LeafBlockSlot(ref key) => (COL_LEAF_BLOCKS, serialize(key)),
 		};
 
 		RawKey {
@@ -19,8 +19,10 @@ mod block_impls;
 mod block_iterator;
 mod block_origin;
 mod block_provider;
+mod block_undo;
 mod block_ref;
 mod error;
+mod output_spender;
 mod store;
 mod transaction_meta;
 mod transaction_provider;
@@ -35,8 +37,10 @@ pub use block_iterator::BlockIterator;
 pub use block_origin::{BlockOrigin, SideChainOrigin};
 pub use block_provider::{BlockHeaderProvider, BlockProvider, IndexedBlockProvider};
 pub use block_ref::BlockRef;
+pub use block_undo::{BlockUndo, SpentOutput};
 pub use error::Error;
-pub use store::{AsSubstore, Store, SharedStore, CanonStore};
+pub use output_spender::OutputSpender;
+pub use store::{AsSubstore, Store, SharedStore, CanonStore, median_time_past};
 pub use transaction_meta::TransactionMeta;
 pub use transaction_provider::{TransactionProvider, TransactionOutputProvider, TransactionMetaProvider};
 
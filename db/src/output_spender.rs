@@ -0,0 +1,33 @@
+//! Spent outputs index entry
+
+use std::io;
+use hash::H256;
+use ser::{Serializable, Deserializable, Error as ReaderError, Stream, Reader};
+
+/// Transaction input, which has spent the output
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputSpender {
+	/// Hash of the spending transaction
+	pub transaction_hash: H256,
+	/// Index of the spending input
+	pub input_index: u32,
+}
+
+impl Serializable for OutputSpender {
+	fn serialize(&self, stream: &mut Stream) {
+		stream
+			.append(&self.transaction_hash)
+			.append(&self.input_index);
+	}
+}
+
+impl Deserializable for OutputSpender {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, ReaderError> where T: io::Read {
+		let result = OutputSpender {
+			transaction_hash: reader.read()?,
+			input_index: reader.read()?,
+		};
+
+		Ok(result)
+	}
+}
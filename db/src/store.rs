@@ -1,8 +1,9 @@
 use std::sync::Arc;
+use hash::H256;
 use chain::BlockHeader;
 use {
 	BestBlock, BlockProvider, BlockHeaderProvider, TransactionProvider, TransactionMetaProvider,
-	TransactionOutputProvider, BlockChain, IndexedBlockProvider, Forkable
+	TransactionOutputProvider, BlockChain, IndexedBlockProvider, Forkable, BlockAncestors, BlockRef
 };
 
 pub trait CanonStore: Store + Forkable {
@@ -19,6 +20,30 @@ pub trait Store: AsSubstore {
 
 	/// get blockchain difficulty
 	fn difficulty(&self) -> f64;
+
+	/// get hashes of stored blocks, which have no stored children
+	fn leaf_blocks(&self) -> Vec<H256>;
+
+	/// get median timestamp of given block and its 10 ancestors (BIP113 median time past).
+	/// Fewer timestamps are used for blocks near genesis
+	fn median_time_past(&self, block_ref: BlockRef) -> Option<u32> {
+		median_time_past(block_ref, self.as_block_header_provider())
+	}
+}
+
+/// Returns median timestamp of given block and its 10 ancestors (BIP113 median time past).
+/// Fewer timestamps are used for blocks near genesis
+pub fn median_time_past(block_ref: BlockRef, headers: &BlockHeaderProvider) -> Option<u32> {
+	let mut timestamps: Vec<u32> = BlockAncestors::new(block_ref, headers)
+		.take(11)
+		.map(|header| header.time)
+		.collect();
+	if timestamps.is_empty() {
+		return None;
+	}
+
+	timestamps.sort();
+	Some(timestamps[timestamps.len() / 2])
 }
 
 /// Allows casting Arc<Store> to reference to any substore type
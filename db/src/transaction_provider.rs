@@ -1,7 +1,7 @@
 use hash::H256;
 use bytes::Bytes;
 use chain::{Transaction, OutPoint, TransactionOutput};
-use {TransactionMeta};
+use {TransactionMeta, OutputSpender};
 
 /// Should be used to obtain all transactions from canon chain and forks.
 pub trait TransactionProvider {
@@ -31,4 +31,8 @@ pub trait TransactionMetaProvider: Send + Sync {
 	/// Returns None if transactin with given hash does not exist
 	/// Otherwise returns transaction meta object
 	fn transaction_meta(&self, hash: &H256) -> Option<TransactionMeta>;
+
+	/// Returns None if output is unspent, unknown or spent outputs index is disabled
+	/// Otherwise returns input of canon chain transaction, which has spent the output
+	fn output_spender(&self, outpoint: &OutPoint) -> Option<OutputSpender>;
 }
@@ -2,9 +2,9 @@ extern crate chain;
 extern crate db;
 extern crate test_data;
 
-use chain::IndexedBlock;
+use chain::{IndexedBlock, OutPoint};
 use db::kv::{MemoryDatabase, SharedMemoryDatabase};
-use db::{BlockChainDatabase, BlockProvider, SideChainOrigin, ForkChain};
+use db::{BlockChainDatabase, BlockProvider, TransactionMetaProvider, SideChainOrigin, ForkChain, SpentOutput, OutputSpender, Store};
 
 #[test]
 fn insert_block() {
@@ -126,3 +126,198 @@ fn switch_to_simple_fork() {
 	assert_eq!(store.best_block().hash, store.block_hash(2).unwrap());
 
 }
+
+#[test]
+fn block_undo() {
+	let store = BlockChainDatabase::open(MemoryDatabase::default()).with_block_undo(true);
+	let b0: IndexedBlock = test_data::block_builder()
+		.transaction()
+			.coinbase()
+			.output().value(1).build()
+			.build()
+		.transaction()
+			.output().value(50).build()
+			.build()
+		.merkled_header().build()
+		.build()
+		.into();
+	let b1: IndexedBlock = test_data::block_builder()
+		.transaction()
+			.coinbase()
+			.output().value(2).build()
+			.build()
+		.transaction()
+			.input().hash(b0.transactions[1].hash.clone()).build()
+			.output().value(30).build()
+			.output().value(20).build()
+			.build()
+		.derived_transaction(1, 0)
+			.output().value(30).build()
+			.build()
+		.merkled_header().parent(b0.hash().clone()).build()
+		.build()
+		.into();
+
+	store.insert(b0.clone()).unwrap();
+	store.insert(b1.clone()).unwrap();
+	assert!(store.block_undo(b1.hash()).is_none());
+
+	store.canonize(b0.hash()).unwrap();
+	store.canonize(b1.hash()).unwrap();
+	assert!(store.block_undo(b0.hash()).unwrap().spent_outputs.is_empty());
+	assert_eq!(store.block_undo(b1.hash()).unwrap().spent_outputs, vec![
+		SpentOutput {
+			value: 50,
+			script_pubkey: b0.transactions[1].raw.outputs[0].script_pubkey.clone(),
+			height: 0,
+			is_coinbase: false,
+		},
+		SpentOutput {
+			value: 30,
+			script_pubkey: b1.transactions[1].raw.outputs[0].script_pubkey.clone(),
+			height: 1,
+			is_coinbase: false,
+		},
+	]);
+
+	store.decanonize().unwrap();
+	assert!(store.block_undo(b1.hash()).is_none());
+}
+
+#[test]
+fn block_undo_disabled() {
+	let store = BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]);
+	assert!(store.block_undo(&test_data::genesis().hash()).is_none());
+}
+
+#[test]
+fn output_spender() {
+	let b0: IndexedBlock = test_data::block_builder()
+		.transaction()
+			.coinbase()
+			.output().value(1).build()
+			.build()
+		.transaction()
+			.output().value(50).build()
+			.build()
+		.merkled_header().build()
+		.build()
+		.into();
+	let b1: IndexedBlock = test_data::block_builder()
+		.transaction()
+			.coinbase()
+			.output().value(2).build()
+			.build()
+		.transaction()
+			.input().hash(b0.transactions[1].hash.clone()).build()
+			.output().value(30).build()
+			.build()
+		.merkled_header().parent(b0.hash().clone()).build()
+		.build()
+		.into();
+	let spent = OutPoint { hash: b0.transactions[1].hash.clone(), index: 0 };
+	let unspent = OutPoint { hash: b1.transactions[1].hash.clone(), index: 0 };
+
+	let store = BlockChainDatabase::init_test_chain(vec![b0.clone(), b1.clone()]);
+	assert_eq!(store.output_spender(&spent), None);
+
+	let store = BlockChainDatabase::open(MemoryDatabase::default()).with_spent_index(true);
+	store.insert(b0.clone()).unwrap();
+	store.insert(b1.clone()).unwrap();
+	store.canonize(b0.hash()).unwrap();
+	store.canonize(b1.hash()).unwrap();
+	assert_eq!(store.output_spender(&spent), Some(OutputSpender {
+		transaction_hash: b1.transactions[1].hash.clone(),
+		input_index: 0,
+	}));
+	assert_eq!(store.output_spender(&unspent), None);
+
+	store.decanonize().unwrap();
+	assert_eq!(store.output_spender(&spent), None);
+}
+
+#[test]
+fn clear_indices() {
+	let b0: IndexedBlock = test_data::genesis().into();
+	let b1: IndexedBlock = test_data::block_h1().into();
+	let store = BlockChainDatabase::init_test_chain(vec![b0.clone(), b1.clone()]);
+
+	assert!(!store.are_indices_cleared());
+	store.clear_indices(b1.hash()).unwrap();
+	assert_eq!(store.reindex_target(), Some(b1.hash().clone()));
+	assert!(store.are_indices_cleared());
+	assert_eq!(store.best_block(), Default::default());
+	assert_eq!(store.block_hash(0), None);
+	assert_eq!(store.block_number(b1.hash()), None);
+	assert!(store.transaction_meta(&b1.transactions[0].hash).is_none());
+	// raw blocks data is left untouched
+	assert!(store.block(b1.hash().clone().into()).is_some());
+
+	store.canonize(b0.hash()).unwrap();
+	store.canonize(b1.hash()).unwrap();
+	store.finish_reindex().unwrap();
+	assert_eq!(store.reindex_target(), None);
+	assert!(!store.are_indices_cleared());
+	assert_eq!(store.best_block().hash, b1.hash().clone());
+	assert!(store.transaction_meta(&b1.transactions[0].hash).is_some());
+}
+
+#[test]
+fn leaf_blocks() {
+	let store = BlockChainDatabase::open(MemoryDatabase::default());
+	let b0: IndexedBlock = test_data::genesis().into();
+	let b1: IndexedBlock = test_data::block_builder().header().nonce(1).parent(b0.hash().clone()).build().build().into();
+	let s1: IndexedBlock = test_data::block_builder().header().nonce(2).parent(b0.hash().clone()).build().build().into();
+	let b2: IndexedBlock = test_data::block_builder().header().parent(b1.hash().clone()).build().build().into();
+
+	assert!(store.leaf_blocks().is_empty());
+
+	store.insert(b0.clone()).unwrap();
+	assert_eq!(store.leaf_blocks(), vec![b0.hash().clone()]);
+
+	store.insert(b1.clone()).unwrap();
+	store.insert(s1.clone()).unwrap();
+	assert_eq!(store.leaf_blocks(), vec![b1.hash().clone(), s1.hash().clone()]);
+
+	// child takes the place of its parent
+	store.insert(b2.clone()).unwrap();
+	assert_eq!(store.leaf_blocks(), vec![b2.hash().clone(), s1.hash().clone()]);
+
+	// inserting already known block doesn't change anything
+	store.insert(b1.clone()).unwrap();
+	assert_eq!(store.leaf_blocks(), vec![b2.hash().clone(), s1.hash().clone()]);
+}
+
+#[test]
+fn median_time_past() {
+	let store = BlockChainDatabase::open(MemoryDatabase::default());
+	assert_eq!(store.median_time_past(0.into()), None);
+
+	// timestamps are deliberately out of order
+	let times = [10, 30, 20, 50, 40, 1, 100, 90, 70, 80, 60, 5, 110];
+	let mut parent_hash = None;
+	for time in times.iter().cloned() {
+		let header = test_data::block_builder().header().time(time);
+		let header = match parent_hash {
+			Some(parent_hash) => header.parent(parent_hash),
+			None => header,
+		};
+		let block: IndexedBlock = header.build().build().into();
+		store.insert(block.clone()).unwrap();
+		store.canonize(block.hash()).unwrap();
+		parent_hash = Some(block.hash().clone());
+	}
+
+	// near genesis: [10]
+	assert_eq!(store.median_time_past(0.into()), Some(10));
+	// [10, 30, 20] => 20
+	assert_eq!(store.median_time_past(2.into()), Some(20));
+	// [10, 30, 20, 50] => 30
+	assert_eq!(store.median_time_past(3.into()), Some(30));
+	// [10, 30, 20, 50, 40, 1, 100, 90, 70, 80, 60] => 50
+	assert_eq!(store.median_time_past(10.into()), Some(50));
+	// [30, 20, 50, 40, 1, 100, 90, 70, 80, 60, 5] => 50
+	assert_eq!(store.median_time_past(11.into()), Some(50));
+	// [20, 50, 40, 1, 100, 90, 70, 80, 60, 5, 110] => 60
+	assert_eq!(store.median_time_past(parent_hash.unwrap().into()), Some(60));
+}
@@ -15,9 +15,13 @@ mod block_assembler;
 mod cpu_miner;
 mod fee;
 mod memory_pool;
+mod standard;
 
 pub use block_assembler::{BlockAssembler, BlockTemplate};
 pub use cpu_miner::find_solution;
 pub use memory_pool::{MemoryPool, HashedOutPoint, Information as MemoryPoolInformation,
-	OrderingStrategy as MemoryPoolOrderingStrategy, DoubleSpendCheckResult, NonFinalDoubleSpendSet};
+	OrderingStrategy as MemoryPoolOrderingStrategy, DoubleSpendCheckResult, NonFinalDoubleSpendSet,
+	PackageLimits as MemoryPoolPackageLimits, InsertionError as MemoryPoolInsertionError,
+	InsertionResult as MemoryPoolInsertionResult};
 pub use fee::{transaction_fee, transaction_fee_rate};
+pub use standard::{StandardnessPolicy, NonStandardReason};
@@ -9,15 +9,24 @@ use db::{TransactionProvider, TransactionOutputProvider};
 use primitives::bytes::Bytes;
 use primitives::hash::H256;
 use chain::{IndexedTransaction, Transaction, OutPoint, TransactionOutput};
+use chain::constants::SEQUENCE_FINAL;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::BTreeSet;
 use std::collections::VecDeque;
 use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 use ser::{Serializable, serialize};
 use heapsize::HeapSizeOf;
 
+/// Fee rate (satoshis per 1000 bytes), which replacement transaction must additionally pay for its own relay (BIP125 rule 4)
+const INCREMENTAL_RELAY_FEE: u64 = 1_000;
+/// Maximal number of in-pool transactions, which could be replaced by single transaction (BIP125 rule 5)
+const MAX_REPLACED_TRANSACTIONS: usize = 100;
+/// Time (in seconds), after which minimal fee rate of the full pool is halved
+const MIN_FEE_RATE_HALFLIFE: u64 = 12 * 60 * 60;
+
 /// Transactions ordering strategy
 #[cfg_attr(feature="cargo-clippy", allow(enum_variant_names))]
 #[derive(Debug, Clone, Copy)]
@@ -37,6 +46,94 @@ pub struct Information {
 	pub transactions_count: usize,
 	/// Total number of bytes occupied by transactions from the `MemoryPool`
 	pub transactions_size_in_bytes: usize,
+	/// Maximal total size of transactions in the `MemoryPool`. None if pool size is not limited
+	pub max_size_in_bytes: Option<usize>,
+}
+
+/// Limits on in-pool ancestors and descendants of the transaction (as in Bitcoin Core mempool policy)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PackageLimits {
+	/// Maximal number of in-pool ancestors (including transaction itself)
+	pub max_ancestors_count: usize,
+	/// Maximal total size of in-pool ancestors (including transaction itself)
+	pub max_ancestors_size: usize,
+	/// Maximal number of in-pool descendants of any in-pool ancestor (including this ancestor)
+	pub max_descendants_count: usize,
+	/// Maximal total size of in-pool descendants of any in-pool ancestor (including this ancestor)
+	pub max_descendants_size: usize,
+}
+
+/// Reason of transaction rejection by the `MemoryPool`
+#[derive(Debug, PartialEq)]
+pub enum InsertionError {
+	/// Transaction has too many in-pool ancestors
+	TooManyAncestors {
+		count: usize,
+		max_count: usize,
+	},
+	/// Total size of transaction in-pool ancestors is too large
+	AncestorsSizeTooLarge {
+		size: usize,
+		max_size: usize,
+	},
+	/// In-pool ancestor of transaction would have too many in-pool descendants
+	TooManyDescendants {
+		ancestor: H256,
+		count: usize,
+		max_count: usize,
+	},
+	/// Total size of in-pool descendants of transaction in-pool ancestor would be too large
+	DescendantsSizeTooLarge {
+		ancestor: H256,
+		size: usize,
+		max_size: usize,
+	},
+	/// Output, spent by transaction, is neither in the pool, nor in the given outputs provider
+	MissingInput {
+		previous_output: OutPoint,
+	},
+	/// Memory pool is full and transaction does not pay enough to evict other transactions
+	MemoryPoolFull,
+	/// Some input of transaction is already spent by other in-pool transaction, which can not be replaced
+	DoubleSpend {
+		conflicting: H256,
+	},
+	/// Replacement transaction spends output of in-pool transaction, not spent by replaced transactions
+	ReplacementAddsUnconfirmedInput {
+		hash: H256,
+	},
+	/// Replacement transaction pays less fee than all replaced transactions
+	InsufficientReplacementFee {
+		fee: i64,
+		replaced_fee: i64,
+	},
+	/// Replacement transaction has lesser fee rate than all replaced transactions
+	InsufficientReplacementFeeRate {
+		fee_rate: f64,
+		replaced_fee_rate: f64,
+	},
+	/// Replacement transaction does not pay for its own relay bandwidth
+	InsufficientReplacementRelayFee {
+		additional_fee: i64,
+		required_fee: i64,
+	},
+	/// Replacement transaction replaces too many in-pool transactions
+	TooManyReplacedTransactions {
+		count: usize,
+		max_count: usize,
+	},
+	/// Transaction pays less fee than required by minimal fee rate of the pool
+	InsufficientFee {
+		fee: i64,
+		min_fee: i64,
+	},
+}
+
+/// Result of transaction insertion to the `MemoryPool`
+#[derive(Debug, Default, PartialEq)]
+pub struct InsertionResult {
+	/// Hashes of in-pool transactions (with all their descendants), replaced by inserted transaction (BIP125)
+	pub replaced: Vec<H256>,
 }
 
 /// Transactions memory pool
@@ -44,6 +141,16 @@ pub struct Information {
 pub struct MemoryPool {
 	/// Transactions storage
 	storage: Storage,
+	/// Package limits, checked on insertion
+	limits: PackageLimits,
+	/// Maximal total size of transactions in the pool
+	max_size_bytes: Option<usize>,
+	/// Number of transactions, evicted from the pool to free space for better transactions
+	evicted_count: usize,
+	/// Fee rate (satoshis per 1000 bytes) of the best evicted package, increased by incremental relay fee
+	rolling_min_fee_rate: u64,
+	/// Time, when `rolling_min_fee_rate` was last increased
+	rolling_min_fee_rate_time: Instant,
 }
 
 /// Single entry
@@ -69,6 +176,8 @@ pub struct Entry {
 	pub package_miner_fee: i64,
 	/// miner_virtual_fee + Sum(miner_virtual_fee) for all in-pool descendants
 	pub package_miner_virtual_fee: i64,
+	/// Time when transaction has entered the memory pool
+	pub entry_time: Instant,
 }
 
 /// Multi-index transactions storage
@@ -82,6 +191,8 @@ struct Storage {
 	by_hash: HashMap<H256, Entry>,
 	/// Transactions by previous output
 	by_previous_output: HashMap<HashedOutPoint, H256>,
+	/// All transactions (including pending), ordered by fee rate
+	by_fee_rate: BTreeSet<ByFeeRateOrderedEntry>,
 	/// References storage
 	references: ReferenceStorage,
 }
@@ -140,6 +251,18 @@ struct ByPackageScoreOrderedEntry {
 	package_miner_virtual_fee: i64,
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct ByFeeRateOrderedEntry {
+	/// Transaction hash
+	hash: H256,
+	/// Transaction size
+	size: usize,
+	/// Transaction fee
+	miner_fee: i64,
+	/// Time when transaction has entered the memory pool
+	entry_time: Instant,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct HashedOutPoint {
 	/// Transaction output point
@@ -214,6 +337,17 @@ impl<'a> From<&'a Entry> for ByPackageScoreOrderedEntry {
 	}
 }
 
+impl<'a> From<&'a Entry> for ByFeeRateOrderedEntry {
+	fn from(entry: &'a Entry) -> Self {
+		ByFeeRateOrderedEntry {
+			hash: entry.hash.clone(),
+			size: entry.size,
+			miner_fee: entry.miner_fee,
+			entry_time: entry.entry_time,
+		}
+	}
+}
+
 impl PartialOrd for ByTimestampOrderedEntry {
 	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
 		Some(self.cmp(other))
@@ -271,6 +405,32 @@ impl Ord for ByPackageScoreOrderedEntry {
 	}
 }
 
+impl PartialOrd for ByFeeRateOrderedEntry {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for ByFeeRateOrderedEntry {
+	fn cmp(&self, other: &Self) -> Ordering {
+		// greater fee rate goes first
+		let left = self.miner_fee * (other.size as i64);
+		let right = other.miner_fee * (self.size as i64);
+		let order = right.cmp(&left);
+		if order != Ordering::Equal {
+			return order
+		}
+
+		// earlier arrival goes first
+		let order = self.entry_time.cmp(&other.entry_time);
+		if order != Ordering::Equal {
+			return order
+		}
+
+		self.hash.cmp(&other.hash)
+	}
+}
+
 impl HeapSizeOf for Entry {
 	fn heap_size_of_children(&self) -> usize {
 		self.transaction.heap_size_of_children() + self.ancestors.heap_size_of_children()
@@ -284,6 +444,7 @@ impl Storage {
 			transactions_size_in_bytes: 0,
 			by_hash: HashMap::new(),
 			by_previous_output: HashMap::new(),
+			by_fee_rate: BTreeSet::new(),
 			references: ReferenceStorage {
 				by_input: HashMap::new(),
 				pending: HashSet::new(),
@@ -334,6 +495,9 @@ impl Storage {
 			assert_eq!(previous_tx, None); // transaction must be verified before => no double spend
 		}
 
+		// add to by-fee-rate ordering
+		self.by_fee_rate.insert((&entry).into());
+
 		// add to by_hash storage
 		self.by_hash.insert(entry.hash.clone(), entry);
 	}
@@ -350,6 +514,10 @@ impl Storage {
 		self.by_previous_output.contains_key(&prevout.clone().into())
 	}
 
+	pub fn output_spender(&self, prevout: &OutPoint) -> Option<H256> {
+		self.by_previous_output.get(&prevout.clone().into()).cloned()
+	}
+
 	pub fn set_virtual_fee(&mut self, h: &H256, virtual_fee: i64) {
 		// for updating ancestors
 		let mut miner_virtual_fee_change = 0i64;
@@ -418,6 +586,7 @@ impl Storage {
 
 				// remove from storage
 				self.references.remove(None, &self.by_hash, &entry);
+				self.by_fee_rate.remove(&(&entry).into());
 
 				entry
 			})
@@ -485,7 +654,7 @@ impl Storage {
 		Some(removed)
 	}
 
-	pub fn remove_by_parent_hash(&mut self, h: &H256) -> Option<Vec<IndexedTransaction>> {
+	pub fn remove_by_parent_hash(&mut self, h: &H256) -> Option<Vec<Entry>> {
 		// this code will run only when ancestor transaction is inserted
 		// in memory pool after its descendants
 		if let Some(mut descendants) = self.references.by_input.get(h).map(|d| d.iter().cloned().collect::<Vec<H256>>()) {
@@ -520,7 +689,7 @@ impl Storage {
 
 			// move all descendants out of storage for later insertion
 			Some(all_descendants.into_iter()
-					.filter_map(|hash| self.remove_by_hash(hash).map(|entry| IndexedTransaction::new(entry.hash, entry.transaction)))
+					.filter_map(|hash| self.remove_by_hash(hash))
 					.collect())
 		}
 		else {
@@ -560,6 +729,21 @@ impl Storage {
 	pub fn get_transactions_ids(&self) -> Vec<H256> {
 		self.by_hash.keys().cloned().collect()
 	}
+
+	pub fn get_descendants(&self, h: &H256) -> HashSet<H256> {
+		let mut all_descendants: HashSet<H256> = HashSet::new();
+		let mut descendants: Vec<H256> = self.references.by_input.get(h).map(|d| d.iter().cloned().collect()).unwrap_or_default();
+		while let Some(descendant) = descendants.pop() {
+			if !all_descendants.insert(descendant.clone()) {
+				continue
+			}
+
+			if let Some(grand_descendants) = self.references.by_input.get(&descendant) {
+				descendants.extend(grand_descendants.iter().cloned());
+			}
+		}
+		all_descendants
+	}
 }
 
 impl ReferenceStorage {
@@ -611,7 +795,9 @@ impl OrderedReferenceStorage {
 
 impl HeapSizeOf for Storage {
 	fn heap_size_of_children(&self) -> usize {
-		self.by_hash.heap_size_of_children() + self.references.heap_size_of_children()
+		// HeapSizeOf is not implemented for BTreeSet => rough estimation here
+		let by_fee_rate_size = self.by_fee_rate.len() * ::std::mem::size_of::<ByFeeRateOrderedEntry>();
+		self.by_hash.heap_size_of_children() + self.references.heap_size_of_children() + by_fee_rate_size
 	}
 }
 
@@ -634,10 +820,26 @@ impl HeapSizeOf for OrderedReferenceStorage {
 	}
 }
 
+impl Default for PackageLimits {
+	fn default() -> Self {
+		PackageLimits {
+			max_ancestors_count: 25,
+			max_ancestors_size: 101_000,
+			max_descendants_count: 25,
+			max_descendants_size: 101_000,
+		}
+	}
+}
+
 impl Default for MemoryPool {
 	fn default() -> Self {
 		MemoryPool {
 			storage: Storage::new(),
+			limits: PackageLimits::default(),
+			max_size_bytes: None,
+			evicted_count: 0,
+			rolling_min_fee_rate: 0,
+			rolling_min_fee_rate_time: Instant::now(),
 		}
 	}
 }
@@ -648,14 +850,81 @@ impl MemoryPool {
 		MemoryPool::default()
 	}
 
-	/// Insert verified transaction to the `MemoryPool`
+	/// Set package limits, checked on insertion
+	pub fn with_limits(mut self, limits: PackageLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Limit total size of pool transactions by given number of bytes.
+	/// When pool is full, transactions with lowest descendant score (with all descendants) are evicted
+	pub fn with_max_size(mut self, max_size_bytes: usize) -> Self {
+		self.max_size_bytes = Some(max_size_bytes);
+		self
+	}
+
+	/// Insert verified transaction to the `MemoryPool`, if it passes memory pool policy checks.
+	/// Outputs, spent by transaction, are read either from the pool, or from `outputs` provider.
+	/// Conflicting in-pool transactions are replaced if they signal replaceability (BIP125)
+	pub fn insert(&mut self, t: IndexedTransaction, outputs: &TransactionOutputProvider) -> Result<InsertionResult, InsertionError> {
+		let miner_fee = self.get_transaction_miner_fee(&t.raw, outputs)?;
+		let min_fee = (self.get_transaction_size(&t.raw) as u64 * self.min_fee_rate() / 1000) as i64;
+		if miner_fee < min_fee {
+			return Err(InsertionError::InsufficientFee {
+				fee: miner_fee,
+				min_fee: min_fee,
+			});
+		}
+		let replaced = self.select_replaced(&t.raw, miner_fee)?;
+		self.check_package_limits(&t.raw)?;
+		let evicted = self.select_evicted(&t.raw, miner_fee, &replaced)?;
+
+		let mut result = InsertionResult::default();
+		let conflicting: Vec<H256> = t.raw.inputs.iter()
+			.filter_map(|input| self.storage.output_spender(&input.previous_output))
+			.collect();
+		for conflicting in conflicting {
+			result.replaced.extend(self.remove_with_descendants(&conflicting).unwrap_or_default().into_iter().map(|tx| tx.hash));
+		}
+		for hash in evicted {
+			let score = match self.storage.get_by_hash(&hash) {
+				Some(entry) => self.get_descendant_score(entry),
+				None => continue,
+			};
+			self.on_package_evicted(score);
+			self.evicted_count += self.remove_with_descendants(&hash).map(|removed| removed.len()).unwrap_or(0);
+		}
+		self.insert_with_fee(t, miner_fee);
+		Ok(result)
+	}
+
+	/// Get number of transactions, evicted from the pool because it was full
+	pub fn evicted_count(&self) -> usize {
+		self.evicted_count
+	}
+
+	/// Get minimal fee rate (satoshis per 1000 bytes), which transaction must pay to enter the pool.
+	/// It rises when packages are evicted from the full pool and decays over time (as in Bitcoin Core)
+	pub fn min_fee_rate(&self) -> u64 {
+		let halflifes = self.rolling_min_fee_rate_time.elapsed().as_secs() / MIN_FEE_RATE_HALFLIFE;
+		let min_fee_rate = self.rolling_min_fee_rate.checked_shr(halflifes as u32).unwrap_or(0);
+		if min_fee_rate < INCREMENTAL_RELAY_FEE / 2 { 0 } else { min_fee_rate }
+	}
+
+	/// Insert verified transaction to the `MemoryPool` without policy checks.
+	/// Values of transaction inputs are not known here => sum of transaction outputs is used as its fee
 	pub fn insert_verified(&mut self, t: IndexedTransaction) {
-		let entry = self.make_entry(t);
+		let miner_fee = t.raw.outputs.iter().fold(0, |acc, output| acc + output.value) as i64;
+		self.insert_with_fee(t, miner_fee);
+	}
+
+	fn insert_with_fee(&mut self, t: IndexedTransaction, miner_fee: i64) {
+		let entry = self.make_entry(t, miner_fee);
 		let descendants = self.storage.remove_by_parent_hash(&entry.hash);
 		self.storage.insert(entry);
 		if let Some(descendants_iter) = descendants.map(|d| d.into_iter()) {
 			for descendant in descendants_iter {
-				let descendant_entry = self.make_entry(descendant);
+				let descendant_entry = self.make_entry(IndexedTransaction::new(descendant.hash, descendant.transaction), descendant.miner_fee);
 				self.storage.insert(descendant_entry);
 			}
 		}
@@ -666,6 +935,20 @@ impl MemoryPool {
 		MemoryPoolIterator::new(self, strategy)
 	}
 
+	/// Iterator over all memory pool transactions, ordered by fee rate (greater goes first).
+	/// Transactions with equal fee rate are ordered by the time they have entered the memory pool.
+	/// Unlike `iter`, ancestors are not guaranteed to be returned before descendant transactions
+	pub fn iter_by_fee_rate<'a>(&'a self) -> impl Iterator<Item=&'a Transaction> + 'a {
+		self.storage.by_fee_rate.iter()
+			.map(move |entry| self.storage.read_by_hash(&entry.hash)
+				.expect("by_fee_rate references entries from by_hash; qed"))
+	}
+
+	/// Get fee rate (fee per byte) of transaction by its hash
+	pub fn fee_rate(&self, hash: &H256) -> Option<f64> {
+		self.storage.get_by_hash(hash).map(|entry| entry.miner_fee as f64 / entry.size as f64)
+	}
+
 	/// Removes single transaction by its hash.
 	/// All descedants remain in the pool.
 	pub fn remove_by_hash(&mut self, h: &H256) -> Option<Transaction> {
@@ -682,11 +965,49 @@ impl MemoryPool {
 		self.storage.remove_by_prevout(prevout)
 	}
 
+	/// Removes transactions, which have entered the `MemoryPool` more than `max_age` ago.
+	/// All descendants of expired transactions are also removed
+	pub fn remove_expired(&mut self, max_age: Duration) -> Vec<IndexedTransaction> {
+		let expired: Vec<H256> = self.storage.by_hash.values()
+			.filter(|entry| entry.entry_time.elapsed() > max_age)
+			.map(|entry| entry.hash.clone())
+			.collect();
+
+		let mut removed: Vec<IndexedTransaction> = Vec::new();
+		for hash in expired {
+			// transaction could be already removed as a descendant of other expired transaction
+			removed.extend(self.remove_with_descendants(&hash).unwrap_or_default());
+		}
+		removed
+	}
+
+	/// Removes transaction and all its descendants.
+	/// Returns None if transaction is not in the pool
+	pub fn remove_with_descendants(&mut self, h: &H256) -> Option<Vec<IndexedTransaction>> {
+		self.storage.remove_by_hash(h).map(|entry| {
+			let outputs_count = entry.transaction.outputs.len();
+			let mut removed = vec![IndexedTransaction::new(entry.hash, entry.transaction)];
+			for index in 0..outputs_count {
+				let outpoint = OutPoint {
+					hash: h.clone(),
+					index: index as u32,
+				};
+				removed.extend(self.storage.remove_by_prevout(&outpoint).unwrap_or_default());
+			}
+			removed
+		})
+	}
+
 	/// Reads single transaction by its hash.
 	pub fn read_by_hash(&self, h: &H256) -> Option<&Transaction> {
 		self.storage.read_by_hash(h)
 	}
 
+	/// Reads memory pool entry of single transaction by its hash.
+	pub fn read_entry_by_hash(&self, h: &H256) -> Option<&Entry> {
+		self.storage.by_hash.get(h)
+	}
+
 	/// Reads hash of the 'top' transaction from the `MemoryPool` using selected strategy.
 	/// Ancestors are always returned before descendant transactions.
 	pub fn read_with_strategy(&mut self, strategy: OrderingStrategy) -> Option<H256> {
@@ -733,6 +1054,7 @@ impl MemoryPool {
 		Information {
 			transactions_count: self.storage.by_hash.len(),
 			transactions_size_in_bytes: self.storage.transactions_size_in_bytes,
+			max_size_in_bytes: self.max_size_bytes,
 		}
 	}
 
@@ -747,11 +1069,15 @@ impl MemoryPool {
 		self.storage.is_output_spent(prevout)
 	}
 
-	fn make_entry(&mut self, t: IndexedTransaction) -> Entry {
+	/// Returns hash of transaction, which spends given output
+	pub fn spender(&self, prevout: &OutPoint) -> Option<H256> {
+		self.storage.output_spender(prevout)
+	}
+
+	fn make_entry(&mut self, t: IndexedTransaction, miner_fee: i64) -> Entry {
 		let ancestors = self.get_ancestors(&t.raw);
 		let size = self.get_transaction_size(&t.raw);
 		let storage_index = self.get_storage_index();
-		let miner_fee = self.get_transaction_miner_fee(&t.raw);
 		Entry {
 			transaction: t.raw,
 			hash: t.hash,
@@ -764,7 +1090,192 @@ impl MemoryPool {
 			package_size: size,
 			package_miner_fee: miner_fee,
 			package_miner_virtual_fee: 0,
+			entry_time: Instant::now(),
+		}
+	}
+
+	fn check_package_limits(&self, t: &Transaction) -> Result<(), InsertionError> {
+		let size = self.get_transaction_size(t);
+		let ancestors = self.get_ancestors(t);
+
+		let ancestors_count = ancestors.len() + 1;
+		if ancestors_count > self.limits.max_ancestors_count {
+			return Err(InsertionError::TooManyAncestors {
+				count: ancestors_count,
+				max_count: self.limits.max_ancestors_count,
+			});
+		}
+
+		let ancestors_entries: Vec<_> = ancestors.iter().filter_map(|hash| self.storage.get_by_hash(hash)).collect();
+		let ancestors_size = ancestors_entries.iter().fold(size, |acc, entry| acc + entry.size);
+		if ancestors_size > self.limits.max_ancestors_size {
+			return Err(InsertionError::AncestorsSizeTooLarge {
+				size: ancestors_size,
+				max_size: self.limits.max_ancestors_size,
+			});
+		}
+
+		for ancestor_entry in ancestors_entries {
+			let descendants = self.storage.get_descendants(&ancestor_entry.hash);
+			// ancestor itself + its in-pool descendants + new transaction
+			let descendants_count = descendants.len() + 2;
+			if descendants_count > self.limits.max_descendants_count {
+				return Err(InsertionError::TooManyDescendants {
+					ancestor: ancestor_entry.hash.clone(),
+					count: descendants_count,
+					max_count: self.limits.max_descendants_count,
+				});
+			}
+
+			let descendants_size = descendants.iter()
+				.filter_map(|hash| self.storage.get_by_hash(hash))
+				.fold(ancestor_entry.size + size, |acc, entry| acc + entry.size);
+			if descendants_size > self.limits.max_descendants_size {
+				return Err(InsertionError::DescendantsSizeTooLarge {
+					ancestor: ancestor_entry.hash.clone(),
+					size: descendants_size,
+					max_size: self.limits.max_descendants_size,
+				});
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Select in-pool transactions (with all descendants), which are replaced by given transaction.
+	/// Replacement is only possible when all conflicting transactions signal replaceability,
+	/// replacement transaction pays more (both absolute fee and fee rate) than all replaced transactions
+	/// and number of replaced transactions is limited
+	fn select_replaced(&self, t: &Transaction, miner_fee: i64) -> Result<HashSet<H256>, InsertionError> {
+		let mut conflicting: Vec<&Entry> = Vec::new();
+		for input in &t.inputs {
+			if let Some(spender) = self.storage.output_spender(&input.previous_output) {
+				let entry = self.storage.get_by_hash(&spender).expect("by_previous_output references entries from by_hash; qed");
+				if !entry.transaction.inputs.iter().any(|input| input.sequence < SEQUENCE_FINAL - 1) {
+					return Err(InsertionError::DoubleSpend {
+						conflicting: spender,
+					});
+				}
+				conflicting.push(entry);
+			}
+		}
+
+		if conflicting.is_empty() {
+			return Ok(HashSet::new());
+		}
+
+		// replacement can only spend in-pool outputs of transactions, which are parents of replaced transactions
+		let conflicting_parents: HashSet<H256> = conflicting.iter()
+			.flat_map(|entry| entry.transaction.inputs.iter().map(|input| input.previous_output.hash.clone()))
+			.collect();
+		for input in &t.inputs {
+			let parent_hash = &input.previous_output.hash;
+			if self.storage.contains(parent_hash) && !conflicting_parents.contains(parent_hash) {
+				return Err(InsertionError::ReplacementAddsUnconfirmedInput {
+					hash: parent_hash.clone(),
+				});
+			}
+		}
+
+		let mut replaced: HashSet<H256> = HashSet::new();
+		for entry in conflicting {
+			replaced.insert(entry.hash.clone());
+			replaced.extend(self.storage.get_descendants(&entry.hash));
+		}
+		if replaced.len() > MAX_REPLACED_TRANSACTIONS {
+			return Err(InsertionError::TooManyReplacedTransactions {
+				count: replaced.len(),
+				max_count: MAX_REPLACED_TRANSACTIONS,
+			});
+		}
+
+		let (replaced_size, replaced_fee) = replaced.iter()
+			.filter_map(|hash| self.storage.get_by_hash(hash))
+			.fold((0usize, 0i64), |(size, fee), entry| (size + entry.size, fee + entry.miner_fee));
+
+		let size = self.get_transaction_size(t);
+		if miner_fee <= replaced_fee {
+			return Err(InsertionError::InsufficientReplacementFee {
+				fee: miner_fee,
+				replaced_fee: replaced_fee,
+			});
+		}
+		if miner_fee * (replaced_size as i64) <= replaced_fee * (size as i64) {
+			return Err(InsertionError::InsufficientReplacementFeeRate {
+				fee_rate: miner_fee as f64 / size as f64,
+				replaced_fee_rate: replaced_fee as f64 / replaced_size as f64,
+			});
+		}
+		let required_fee = (INCREMENTAL_RELAY_FEE * size as u64 / 1000) as i64;
+		if miner_fee - replaced_fee < required_fee {
+			return Err(InsertionError::InsufficientReplacementRelayFee {
+				additional_fee: miner_fee - replaced_fee,
+				required_fee: required_fee,
+			});
 		}
+
+		Ok(replaced)
+	}
+
+	/// Select transactions (roots of packages), which must be evicted to free space for given transaction.
+	/// Transactions from `removed` set are not counted, because they are removed before insertion
+	fn select_evicted(&self, t: &Transaction, miner_fee: i64, removed: &HashSet<H256>) -> Result<Vec<H256>, InsertionError> {
+		let max_size_bytes = match self.max_size_bytes {
+			Some(max_size_bytes) => max_size_bytes,
+			None => return Ok(Vec::new()),
+		};
+
+		let size = self.get_transaction_size(t);
+		let mut pool_size = self.storage.transactions_size_in_bytes - removed.iter()
+			.filter_map(|hash| self.storage.get_by_hash(hash))
+			.fold(0, |acc, entry| acc + entry.size);
+		if pool_size + size <= max_size_bytes {
+			return Ok(Vec::new());
+		}
+
+		let fee_rate = miner_fee as f64 / size as f64;
+		let ancestors = self.get_ancestors(t);
+		// descendants of ancestors are never evicted => ancestors stay in the pool
+		let mut candidates: Vec<(f64, &Entry)> = self.storage.by_hash.values()
+			.filter(|entry| !removed.contains(&entry.hash) && !ancestors.contains(&entry.hash))
+			.map(|entry| (self.get_descendant_score(entry), entry))
+			.collect();
+		// transactions with lowest descendant score go first, newer transactions go first among equals
+		candidates.sort_by(|&(left_score, left), &(right_score, right)| left_score.partial_cmp(&right_score)
+			.unwrap_or(Ordering::Equal)
+			.then_with(|| right.entry_time.cmp(&left.entry_time)));
+
+		let mut evicted: HashSet<H256> = removed.clone();
+		let mut evicted_roots: Vec<H256> = Vec::new();
+		for (score, entry) in candidates {
+			if pool_size + size <= max_size_bytes {
+				break;
+			}
+			if evicted.contains(&entry.hash) {
+				continue;
+			}
+			// only packages with lesser fee rate could be evicted
+			if score >= fee_rate {
+				return Err(InsertionError::MemoryPoolFull);
+			}
+
+			let mut package = self.storage.get_descendants(&entry.hash);
+			package.insert(entry.hash.clone());
+			for hash in package {
+				if let Some(package_entry) = self.storage.get_by_hash(&hash) {
+					if evicted.insert(hash) {
+						pool_size -= package_entry.size;
+					}
+				}
+			}
+			evicted_roots.push(entry.hash.clone());
+		}
+
+		if pool_size + size > max_size_bytes {
+			return Err(InsertionError::MemoryPoolFull);
+		}
+
+		Ok(evicted_roots)
 	}
 
 	fn get_ancestors(&self, t: &Transaction) -> HashSet<H256> {
@@ -780,14 +1291,47 @@ impl MemoryPool {
 		ancestors
 	}
 
+	/// Greater of transaction fee rate and fee rate of transaction with all in-pool descendants (as in Bitcoin Core)
+	fn get_descendant_score(&self, entry: &Entry) -> f64 {
+		let (package_size, package_fee) = self.storage.get_descendants(&entry.hash).iter()
+			.filter_map(|hash| self.storage.get_by_hash(hash))
+			.fold((entry.size, entry.miner_fee), |(size, fee), descendant| (size + descendant.size, fee + descendant.miner_fee));
+		let fee_rate = entry.miner_fee as f64 / entry.size as f64;
+		let package_fee_rate = package_fee as f64 / package_size as f64;
+		if fee_rate > package_fee_rate { fee_rate } else { package_fee_rate }
+	}
+
+	/// Raise minimal fee rate of the pool above fee rate (satoshis per byte) of evicted package
+	fn on_package_evicted(&mut self, score: f64) {
+		let min_fee_rate = (score * 1000.0) as u64 + INCREMENTAL_RELAY_FEE;
+		if min_fee_rate > self.min_fee_rate() {
+			self.rolling_min_fee_rate = min_fee_rate;
+			self.rolling_min_fee_rate_time = Instant::now();
+		}
+	}
+
 	fn get_transaction_size(&self, t: &Transaction) -> usize {
 		t.serialized_size()
 	}
 
-	fn get_transaction_miner_fee(&self, t: &Transaction) -> i64 {
-		let input_value = 0; // TODO: sum all inputs of transaction
+	/// Get transaction fee: sum of inputs minus sum of outputs.
+	/// Spent outputs are read from the pool (unconfirmed parents) or from `outputs` provider
+	fn get_transaction_miner_fee(&self, t: &Transaction, outputs: &TransactionOutputProvider) -> Result<i64, InsertionError> {
+		let mut input_value = 0u64;
+		for input in &t.inputs {
+			let previous_output = &input.previous_output;
+			let output = self.storage.read_by_hash(&previous_output.hash)
+				.and_then(|parent| parent.outputs.get(previous_output.index as usize).cloned())
+				.or_else(|| outputs.transaction_output(previous_output, usize::max_value()));
+			match output {
+				Some(output) => input_value += output.value,
+				None => return Err(InsertionError::MissingInput {
+					previous_output: previous_output.clone(),
+				}),
+			}
+		}
 		let output_value = t.outputs.iter().fold(0, |acc, output| acc + output.value);
-		(output_value - input_value) as i64
+		Ok(input_value as i64 - output_value as i64)
 	}
 
 	#[cfg(not(test))]
@@ -871,11 +1415,29 @@ impl<'a> Iterator for MemoryPoolIterator<'a> {
 mod tests {
 	extern crate test_data;
 
-	use chain::{Transaction, OutPoint};
+	use chain::{Transaction, TransactionOutput, OutPoint};
+	use db::TransactionOutputProvider;
 	use heapsize::HeapSizeOf;
-	use super::{MemoryPool, OrderingStrategy, DoubleSpendCheckResult};
+	use ser::Serializable;
+	use super::{MemoryPool, OrderingStrategy, DoubleSpendCheckResult, PackageLimits, InsertionError, InsertionResult,
+		INCREMENTAL_RELAY_FEE, MAX_REPLACED_TRANSACTIONS};
 	use self::test_data::{ChainBuilder, TransactionBuilder};
 
+	/// Outputs of transactions, which are not in the pool (i.e. are confirmed)
+	struct ConfirmedOutputs(Vec<Transaction>);
+
+	impl TransactionOutputProvider for ConfirmedOutputs {
+		fn transaction_output(&self, prevout: &OutPoint, _transaction_index: usize) -> Option<TransactionOutput> {
+			self.0.iter()
+				.find(|transaction| transaction.hash() == prevout.hash)
+				.and_then(|transaction| transaction.outputs.get(prevout.index as usize).cloned())
+		}
+
+		fn is_spent(&self, _prevout: &OutPoint) -> bool {
+			false
+		}
+	}
+
 	fn to_memory_pool(chain: &mut ChainBuilder) -> MemoryPool {
 		let mut pool = MemoryPool::new();
 		for transaction in chain.transactions.iter().cloned() {
@@ -1301,6 +1863,26 @@ mod tests {
 		assert_eq!(pool.information().transactions_count, 2);
 	}
 
+	#[test]
+	fn test_memory_pool_remove_with_descendants() {
+		let chain = &mut ChainBuilder::new();
+
+		TransactionBuilder::with_output(10).store(chain)	// transaction0
+			.into_input(0).add_output(20).store(chain)		// transaction0 -> transaction1
+			.into_input(0).add_output(30).store(chain)		// transaction0 -> transaction1 -> transaction2
+			.reset().add_output(40).store(chain);			// transaction3
+		let mut pool = MemoryPool::new();
+
+		pool.insert_verified(chain.at(0).into());
+		pool.insert_verified(chain.at(1).into());
+		pool.insert_verified(chain.at(2).into());
+		pool.insert_verified(chain.at(3).into());
+
+		assert_eq!(pool.remove_with_descendants(&chain.hash(1)), Some(vec![chain.at(1).into(), chain.at(2).into()]));
+		assert_eq!(pool.information().transactions_count, 2);
+		assert_eq!(pool.remove_with_descendants(&chain.hash(1)), None);
+	}
+
 	#[test]
 	fn test_memory_pool_check_double_spend() {
 		let chain = &mut ChainBuilder::new();
@@ -1396,4 +1978,310 @@ mod tests {
 		assert!(memory_pool.is_spent(&out1));
 		assert!(!memory_pool.is_spent(&out2));
 	}
+
+	#[test]
+	fn test_memory_pool_iter_by_fee_rate() {
+		let confirmed: Transaction = (0..5).fold(TransactionBuilder::default(), |builder, _| builder.add_output(100)).into();
+		let tx1: Transaction = TransactionBuilder::with_output(80).set_input(&confirmed, 0).into();				// fee: 20
+		let tx2: Transaction = TransactionBuilder::with_output(50).set_input(&confirmed, 1).into();				// fee: 50
+		let tx3: Transaction = TransactionBuilder::with_output(90).set_input(&confirmed, 2).into();				// fee: 10
+		let tx4: Transaction = TransactionBuilder::with_output(30).add_output(30).set_input(&confirmed, 3).into();	// fee: 40
+
+		let outputs = ConfirmedOutputs(vec![confirmed.clone()]);
+		let mut pool = MemoryPool::new();
+		assert_eq!(pool.iter_by_fee_rate().count(), 0);
+		for tx in vec![tx1.clone(), tx2.clone(), tx3.clone(), tx4.clone()] {
+			assert_eq!(pool.insert(tx.into(), &outputs), Ok(InsertionResult::default()));
+		}
+		// tx5 has the same fee rate as tx2, but arrives later
+		let tx5: Transaction = TransactionBuilder::with_output(50).set_input(&confirmed, 4).into();
+		assert_eq!(pool.insert(tx5.clone().into(), &outputs), Ok(InsertionResult::default()));
+
+		let ordered: Vec<_> = pool.iter_by_fee_rate().cloned().collect();
+		assert_eq!(ordered, vec![tx2.clone(), tx5.clone(), tx4.clone(), tx1.clone(), tx3.clone()]);
+		assert_eq!(pool.fee_rate(&tx2.hash()), Some(50f64 / tx2.serialized_size() as f64));
+		assert_eq!(pool.fee_rate(&Transaction::default().hash()), None);
+
+		pool.remove_by_hash(&tx2.hash());
+		assert_eq!(pool.iter_by_fee_rate().next(), Some(&tx5));
+	}
+
+	#[test]
+	fn test_memory_pool_ancestors_limit() {
+		// t0 <- t1 <- ... <- t25
+		let confirmed: Transaction = TransactionBuilder::with_output(1000).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(100).set_input(&confirmed, 0).store(chain);
+		for i in 1..26 {
+			TransactionBuilder::with_output(100 - i as u64).set_input(&chain.at(i - 1), 0).store(chain);
+		}
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new();
+		for i in 0..25 {
+			assert_eq!(pool.insert(chain.at(i).into(), &outputs), Ok(InsertionResult::default()));
+		}
+		assert_eq!(pool.insert(chain.at(25).into(), &outputs), Err(InsertionError::TooManyAncestors {
+			count: 26,
+			max_count: 25,
+		}));
+		assert_eq!(pool.information().transactions_count, 25);
+	}
+
+	#[test]
+	fn test_memory_pool_package_limits() {
+		let confirmed: Transaction = TransactionBuilder::with_output(100).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(10).add_output(20).add_output(30).set_input(&confirmed, 0).store(chain)	// t0
+			.reset().set_input(&chain.at(0), 0).add_output(10).store(chain)				// t0 -> t1
+			.reset().set_input(&chain.at(0), 1).add_output(20).store(chain)				// t0 -> t2
+			.reset().set_input(&chain.at(1), 0).add_output(10).store(chain);			// t0 -> t1 -> t3
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let limits = PackageLimits {
+			max_ancestors_count: 3,
+			max_ancestors_size: 1_000,
+			max_descendants_count: 2,
+			max_descendants_size: 1_000,
+		};
+		let mut pool = MemoryPool::new().with_limits(limits);
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(2).into(), &outputs), Err(InsertionError::TooManyDescendants {
+			ancestor: chain.at(0).hash(),
+			count: 3,
+			max_count: 2,
+		}));
+
+		let limits = PackageLimits {
+			max_ancestors_size: chain.size(0) + chain.size(1),
+			..limits
+		};
+		let mut pool = MemoryPool::new().with_limits(limits);
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(3).into(), &outputs), Err(InsertionError::AncestorsSizeTooLarge {
+			size: chain.size(0) + chain.size(1) + chain.size(3),
+			max_size: chain.size(0) + chain.size(1),
+		}));
+	}
+
+	#[test]
+	fn test_memory_pool_missing_input() {
+		let confirmed: Transaction = TransactionBuilder::with_output(100).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(90).set_input(&confirmed, 0).store(chain)	// t0
+			.reset().set_input(&confirmed, 1).add_output(90).store(chain);		// t1 (spends unknown output)
+
+		let outputs = ConfirmedOutputs(vec![confirmed.clone()]);
+		let mut pool = MemoryPool::new();
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Err(InsertionError::MissingInput {
+			previous_output: OutPoint { hash: confirmed.hash(), index: 1 },
+		}));
+		assert_eq!(pool.insert(chain.at(0).into(), &ConfirmedOutputs(vec![])), Err(InsertionError::MissingInput {
+			previous_output: OutPoint { hash: confirmed.hash(), index: 0 },
+		}));
+		assert_eq!(pool.information().transactions_count, 0);
+	}
+
+	#[test]
+	fn test_memory_pool_eviction() {
+		let confirmed: Transaction = (0..6).fold(TransactionBuilder::default(), |builder, _| builder.add_output(100)).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(90).set_input(&confirmed, 0).store(chain)	// t0 (fee: 10)
+			.reset().set_input(&chain.at(0), 0).add_output(75).store(chain)		// t0 -> t1 (fee: 15)
+			.reset().set_input(&confirmed, 1).add_output(80).store(chain)		// t2 (fee: 20)
+			.reset().set_input(&confirmed, 2).add_output(70).store(chain)		// t3 (fee: 30)
+			.reset().set_input(&confirmed, 3).add_output(60).store(chain)		// t4 (fee: 40)
+			.reset().set_input(&confirmed, 4).add_output(95).store(chain);		// t5 (fee: 5)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let max_size = chain.size(0) + chain.size(1) + chain.size(2) + chain.size(3);
+		let mut pool = MemoryPool::new().with_max_size(max_size);
+		for i in 0..4 {
+			assert_eq!(pool.insert(chain.at(i).into(), &outputs), Ok(InsertionResult::default()));
+		}
+		assert_eq!(pool.evicted_count(), 0);
+
+		// t0 has the lowest descendant score => it is evicted together with its descendant t1
+		assert_eq!(pool.insert(chain.at(4).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.evicted_count(), 2);
+		assert!(!pool.contains(&chain.hash(0)));
+		assert!(!pool.contains(&chain.hash(1)));
+		assert!(pool.contains(&chain.hash(2)) && pool.contains(&chain.hash(3)) && pool.contains(&chain.hash(4)));
+
+		// cheap transaction can not evict better transactions
+		let mut pool = MemoryPool::new().with_max_size(chain.size(2) + chain.size(3));
+		assert_eq!(pool.insert(chain.at(2).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(3).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(5).into(), &outputs), Err(InsertionError::MemoryPoolFull));
+		assert_eq!(pool.evicted_count(), 0);
+		assert_eq!(pool.information().transactions_count, 2);
+	}
+
+	#[test]
+	fn test_memory_pool_insert_double_spend() {
+		let confirmed: Transaction = TransactionBuilder::with_output(100).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(90).set_input(&confirmed, 0).store(chain)	// t0
+			.reset().set_input(&chain.at(0), 0).add_output(85).store(chain)		// t0 -> t1
+			.reset().set_input(&chain.at(0), 0).add_output(84).store(chain);		// t0 -> t2 (double spends t1)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new();
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(2).into(), &outputs), Err(InsertionError::DoubleSpend {
+			conflicting: chain.hash(1),
+		}));
+		assert!(pool.contains(&chain.hash(1)));
+		assert!(!pool.contains(&chain.hash(2)));
+	}
+
+	#[test]
+	fn test_memory_pool_replace_by_fee() {
+		let confirmed: Transaction = TransactionBuilder::with_output(1000).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(990).set_input(&confirmed, 0).lock().store(chain)	// t0 (fee: 10, signals replaceability)
+			.reset().set_input(&chain.at(0), 0).add_output(985).store(chain)			// t0 -> t1 (fee: 5)
+			.reset().set_input(&confirmed, 0).add_output(900).store(chain)				// t2 (fee: 100, replaces t0 && t1)
+			.reset().set_input(&confirmed, 0).add_output(988).store(chain)				// t3 (fee: 12, fee is too low)
+			.reset().set_input(&confirmed, 0).add_output(960).store(chain);			// t4 (fee: 40, does not pay for relay)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new();
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(3).into(), &outputs), Err(InsertionError::InsufficientReplacementFee {
+			fee: 12,
+			replaced_fee: 15,
+		}));
+		assert_eq!(pool.insert(chain.at(4).into(), &outputs), Err(InsertionError::InsufficientReplacementRelayFee {
+			additional_fee: 25,
+			required_fee: (INCREMENTAL_RELAY_FEE * chain.size(4) as u64 / 1000) as i64,
+		}));
+
+		let result = pool.insert(chain.at(2).into(), &outputs).expect("replacement is accepted");
+		assert_eq!(result.replaced.len(), 2);
+		assert!(result.replaced.contains(&chain.hash(0)));
+		assert!(result.replaced.contains(&chain.hash(1)));
+		assert_eq!(pool.get_transactions_ids(), vec![chain.hash(2)]);
+
+		// t2 does not signal replaceability
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Err(InsertionError::DoubleSpend {
+			conflicting: chain.hash(2),
+		}));
+	}
+
+	#[test]
+	fn test_memory_pool_replace_by_fee_with_new_unconfirmed_input() {
+		let confirmed: Transaction = TransactionBuilder::with_output(100).add_output(100).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(90).set_input(&confirmed, 0).lock().store(chain)	// t0 (signals replaceability)
+			.reset().set_input(&confirmed, 1).add_output(93).store(chain)				// t1
+			.reset().set_input(&confirmed, 0).add_input(&chain.at(1), 0).add_output(150).store(chain);	// t1 -> t2 (replaces t0)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new();
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(2).into(), &outputs), Err(InsertionError::ReplacementAddsUnconfirmedInput {
+			hash: chain.hash(1),
+		}));
+		assert!(pool.contains(&chain.hash(0)));
+	}
+
+	#[test]
+	fn test_memory_pool_replace_too_many_transactions() {
+		let chain = &mut ChainBuilder::new();
+		let outputs_count = MAX_REPLACED_TRANSACTIONS as u32 + 1;
+		let t0: Transaction = (0..outputs_count).fold(TransactionBuilder::default(), |builder, _| builder.add_output(10)).into();
+		let outputs = ConfirmedOutputs(vec![t0.clone()]);
+
+		// every transaction signals replaceability
+		let mut pool = MemoryPool::new();
+		for index in 0..outputs_count {
+			TransactionBuilder::default().set_input(&t0, index).add_output(1).lock().store(chain);
+			assert_eq!(pool.insert(chain.at(index as usize).into(), &outputs), Ok(InsertionResult::default()));
+		}
+
+		let replacement: Transaction = (0..outputs_count).fold(TransactionBuilder::with_output(1), |builder, index| builder.add_input(&t0, index)).into();
+		assert_eq!(pool.insert(replacement.into(), &outputs), Err(InsertionError::TooManyReplacedTransactions {
+			count: MAX_REPLACED_TRANSACTIONS + 1,
+			max_count: MAX_REPLACED_TRANSACTIONS,
+		}));
+		assert_eq!(pool.information().transactions_count, MAX_REPLACED_TRANSACTIONS + 1);
+	}
+
+	#[test]
+	fn test_memory_pool_eviction_by_descendant_score() {
+		let confirmed: Transaction = (0..4).fold(TransactionBuilder::default(), |builder, _| builder.add_output(1000)).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(999).set_input(&confirmed, 0).store(chain)	// t0 (fee: 1)
+			.reset().set_input(&chain.at(0), 0).add_output(899).store(chain)		// t0 -> t1 (fee: 100, pays for its parent)
+			.reset().set_input(&confirmed, 1).add_output(980).store(chain)		// t2 (fee: 20)
+			.reset().set_input(&confirmed, 2).add_output(970).store(chain);		// t3 (fee: 30)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new()
+			.with_limits(PackageLimits::default())
+			.with_max_size(chain.size(0) + chain.size(1) + chain.size(2));
+		for i in 0..3 {
+			assert_eq!(pool.insert(chain.at(i).into(), &outputs), Ok(InsertionResult::default()));
+		}
+		assert_eq!(pool.information().max_size_in_bytes, Some(chain.size(0) + chain.size(1) + chain.size(2)));
+
+		// t0 has the lowest fee rate, but t0 + t1 package has greater fee rate than t2 => t2 is evicted
+		assert_eq!(pool.insert(chain.at(3).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.evicted_count(), 1);
+		assert!(pool.contains(&chain.hash(0)) && pool.contains(&chain.hash(1)) && pool.contains(&chain.hash(3)));
+		assert!(!pool.contains(&chain.hash(2)));
+	}
+
+	#[test]
+	fn test_memory_pool_eviction_by_real_fee() {
+		let confirmed: Transaction = (0..3).fold(TransactionBuilder::default(), |builder, _| builder.add_output(1_000_000)).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(999_990).set_input(&confirmed, 0).store(chain)	// t0 (fee: 10, large outputs)
+			.reset().set_input(&confirmed, 1).add_output(1_000).store(chain)		// t1 (fee: 999_000, small outputs)
+			.reset().set_input(&confirmed, 2).add_output(990_000).store(chain);	// t2 (fee: 10_000)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new().with_max_size(chain.size(0) + chain.size(1));
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+
+		// t0 has the largest outputs sum, but the lowest fee => it is evicted
+		assert_eq!(pool.insert(chain.at(2).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.evicted_count(), 1);
+		assert!(!pool.contains(&chain.hash(0)));
+		assert!(pool.contains(&chain.hash(1)) && pool.contains(&chain.hash(2)));
+	}
+	#[test]
+	fn test_memory_pool_min_fee_rate_rises_on_eviction() {
+		let confirmed: Transaction = (0..4).fold(TransactionBuilder::default(), |builder, _| builder.add_output(1_000)).into();
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(990).set_input(&confirmed, 0).store(chain)	// t0 (fee: 10)
+			.reset().set_input(&confirmed, 1).add_output(500).store(chain)		// t1 (fee: 500)
+			.reset().set_input(&confirmed, 2).add_output(400).store(chain)		// t2 (fee: 600)
+			.reset().set_input(&confirmed, 3).add_output(980).store(chain);		// t3 (fee: 20)
+
+		let outputs = ConfirmedOutputs(vec![confirmed]);
+		let mut pool = MemoryPool::new().with_max_size(chain.size(0) + chain.size(1));
+		assert_eq!(pool.insert(chain.at(0).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.insert(chain.at(1).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.min_fee_rate(), 0);
+
+		// t0 is evicted => pool requires greater fee rate than t0 has
+		assert_eq!(pool.insert(chain.at(2).into(), &outputs), Ok(InsertionResult::default()));
+		assert_eq!(pool.evicted_count(), 1);
+		let min_fee_rate = (10.0 / chain.size(0) as f64 * 1000.0) as u64 + INCREMENTAL_RELAY_FEE;
+		assert_eq!(pool.min_fee_rate(), min_fee_rate);
+
+		// t3 pays more than evicted t0, but less than minimal fee of the pool
+		assert_eq!(pool.insert(chain.at(3).into(), &outputs), Err(InsertionError::InsufficientFee {
+			fee: 20,
+			min_fee: (chain.size(3) as u64 * min_fee_rate / 1000) as i64,
+		}));
+	}
 }
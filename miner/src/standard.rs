@@ -0,0 +1,161 @@
+//! Transaction standardness checks
+//!
+//! Standard transactions are transactions, which follow the relay policy of the node. Non-standard
+//! transactions are still valid, but are not accepted to the `MemoryPool` and are not relayed.
+use chain::Transaction;
+use script::{Script, ScriptType};
+use ser::Serializable;
+
+/// Size of input, which is spending output (used to compute dust threshold)
+const SPENDING_INPUT_SIZE: usize = 148;
+
+/// Reason, why transaction is not standard
+#[derive(Debug, PartialEq)]
+pub enum NonStandardReason {
+	/// Transaction size is above the limit
+	Size { size: usize, max_size: usize },
+	/// Output script (of output with given index) has non-standard type
+	ScriptPubKey(usize),
+	/// Output (with given index) value is below the dust threshold
+	Dust(usize),
+	/// Number of signature operations is above the limit
+	Sigops { sigops: usize, max_sigops: usize },
+	/// Transaction fee is below the minimal relay fee
+	Fee { fee: u64, min_fee: u64 },
+}
+
+/// Relay policy, which standard transactions must follow (as in Bitcoin Core)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StandardnessPolicy {
+	/// Maximal size of standard transaction
+	pub max_transaction_size: usize,
+	/// Maximal number of signature operations in standard transaction
+	pub max_transaction_sigops: usize,
+	/// Fee rate (satoshis per 1000 bytes), used to compute dust threshold
+	pub dust_relay_fee: u64,
+	/// Minimal fee rate (satoshis per 1000 bytes) of standard transaction
+	pub min_relay_fee: u64,
+}
+
+impl Default for StandardnessPolicy {
+	fn default() -> Self {
+		StandardnessPolicy {
+			max_transaction_size: 100_000,
+			max_transaction_sigops: 4_000,
+			dust_relay_fee: 3_000,
+			min_relay_fee: 1_000,
+		}
+	}
+}
+
+impl StandardnessPolicy {
+	/// Check that transaction follows the policy: output scripts are of standard types, output values are
+	/// above the dust threshold, size and number of signature operations are within limits and fee is not below
+	/// the minimal relay fee. Fee is only checked when total value of transaction inputs is known
+	pub fn check(&self, tx: &Transaction, input_value: Option<u64>) -> Result<(), NonStandardReason> {
+		let size = tx.serialized_size();
+		if size > self.max_transaction_size {
+			return Err(NonStandardReason::Size {
+				size: size,
+				max_size: self.max_transaction_size,
+			});
+		}
+
+		for (index, output) in tx.outputs.iter().enumerate() {
+			let script_type = Script::new(output.script_pubkey.clone()).script_type();
+			if script_type == ScriptType::NonStandard {
+				return Err(NonStandardReason::ScriptPubKey(index));
+			}
+			// null data outputs are unspendable => can't be dust
+			if script_type == ScriptType::NullData {
+				continue;
+			}
+			// dust output costs more to spend than it is worth
+			let spend_size = (output.serialized_size() + SPENDING_INPUT_SIZE) as u64;
+			if output.value < spend_size * self.dust_relay_fee / 1000 {
+				return Err(NonStandardReason::Dust(index));
+			}
+		}
+
+		let inputs_sigops: usize = tx.inputs.iter()
+			.map(|input| Script::new(input.script_sig.clone()).sigops_count(false))
+			.sum();
+		let outputs_sigops: usize = tx.outputs.iter()
+			.map(|output| Script::new(output.script_pubkey.clone()).sigops_count(false))
+			.sum();
+		let sigops = inputs_sigops + outputs_sigops;
+		if sigops > self.max_transaction_sigops {
+			return Err(NonStandardReason::Sigops {
+				sigops: sigops,
+				max_sigops: self.max_transaction_sigops,
+			});
+		}
+
+		if let Some(input_value) = input_value {
+			let output_value: u64 = tx.outputs.iter().map(|output| output.value).sum();
+			let fee = input_value.saturating_sub(output_value);
+			let min_fee = size as u64 * self.min_relay_fee / 1000;
+			if fee < min_fee {
+				return Err(NonStandardReason::Fee {
+					fee: fee,
+					min_fee: min_fee,
+				});
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chain::{Transaction, TransactionOutput};
+	use primitives::hash::H160;
+	use script::Builder;
+	use ser::Serializable;
+	use super::{StandardnessPolicy, NonStandardReason};
+
+	fn p2pkh_transaction(value: u64) -> Transaction {
+		Transaction {
+			version: 1,
+			inputs: vec![Default::default()],
+			outputs: vec![TransactionOutput {
+				value: value,
+				script_pubkey: Builder::build_p2pkh(&H160::default()).to_bytes(),
+			}],
+			lock_time: 0,
+		}
+	}
+
+	#[test]
+	fn test_standardness_policy_default() {
+		let policy = StandardnessPolicy::default();
+		let tx = p2pkh_transaction(546);
+		assert_eq!(policy.check(&tx, None), Ok(()));
+		assert_eq!(policy.check(&p2pkh_transaction(545), None), Err(NonStandardReason::Dust(0)));
+		assert_eq!(policy.check(&tx, Some(546)), Err(NonStandardReason::Fee {
+			fee: 0,
+			min_fee: tx.serialized_size() as u64,
+		}));
+		assert_eq!(policy.check(&tx, Some(546 + tx.serialized_size() as u64)), Ok(()));
+	}
+
+	#[test]
+	fn test_standardness_policy_custom() {
+		let policy = StandardnessPolicy {
+			max_transaction_size: 1_000,
+			max_transaction_sigops: 1,
+			dust_relay_fee: 0,
+			min_relay_fee: 0,
+		};
+		let mut tx = p2pkh_transaction(1);
+		assert_eq!(policy.check(&tx, Some(1)), Ok(()));
+
+		// + 1 signature operation in p2pkh output
+		tx.inputs[0].script_sig = vec![0xac].into();
+		assert_eq!(policy.check(&tx, Some(1)), Err(NonStandardReason::Sigops { sigops: 2, max_sigops: 1 }));
+
+		tx.inputs[0].script_sig = vec![0; 1_000].into();
+		assert_eq!(policy.check(&tx, Some(1)), Err(NonStandardReason::Size { size: tx.serialized_size(), max_size: 1_000 }));
+	}
+}
@@ -18,6 +18,8 @@ pub struct Config {
 	pub seeds: Vec<String>,
 	/// p2p/nodes.csv file path
 	pub node_table_path: path::PathBuf,
+	/// p2p/addresses.csv file path
+	pub address_manager_path: path::PathBuf,
 	/// Internet protocol.
 	pub internet_protocol: InternetProtocol,
 }
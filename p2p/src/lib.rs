@@ -33,5 +33,5 @@ pub use config::Config;
 pub use net::Config as NetConfig;
 pub use p2p::{P2P, Context};
 pub use event_loop::{event_loop, forever};
-pub use util::{NodeTableError, PeerId, PeerInfo, InternetProtocol, Direction};
+pub use util::{NodeTableError, PeerId, PeerInfo, InternetProtocol, Direction, AddressManager};
 pub use protocol::{InboundSyncConnection, InboundSyncConnectionRef, OutboundSyncConnection, OutboundSyncConnectionRef, LocalSyncNode, LocalSyncNodeRef};
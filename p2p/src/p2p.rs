@@ -1,6 +1,6 @@
 use std::{io, net, error, time};
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, IpAddr};
 use parking_lot::RwLock;
 use futures::{Future, finished, failed, BoxFuture};
 use futures::stream::Stream;
@@ -11,9 +11,10 @@ use tokio_core::reactor::{Handle, Remote, Timeout, Interval};
 use abstract_ns::Resolver;
 use ns_dns_tokio::DnsResolver;
 use message::{Payload, MessageResult, Message};
-use message::common::Services;
+use message::common::{Services, NetAddress};
+use message::types::addr::AddressEntry;
 use net::{connect, Connections, Channel, Config as NetConfig, accept_connection, ConnectionCounter};
-use util::{NodeTable, Node, NodeTableError, Direction};
+use util::{NodeTable, Node, NodeTableError, Direction, AddressManager};
 use session::{SessionFactory, SeednodeSessionFactory, NormalSessionFactory};
 use {Config, PeerId};
 use protocol::{LocalSyncNodeRef, InboundSyncConnectionRef, OutboundSyncConnectionRef};
@@ -21,6 +22,9 @@ use io::DeadlineStatus;
 
 pub type BoxedEmptyFuture = BoxFuture<(), ()>;
 
+/// Maximal number of attempts to select address from address manager, when creating outbound connections
+const MAX_ADDRESS_SELECTION_ATTEMPTS: usize = 100;
+
 /// Network context.
 pub struct Context {
 	/// Connections.
@@ -29,6 +33,8 @@ pub struct Context {
 	connection_counter: ConnectionCounter,
 	/// Node Table.
 	node_table: RwLock<NodeTable>,
+	/// Address manager.
+	address_manager: RwLock<AddressManager>,
 	/// Thread pool handle.
 	pool: CpuPool,
 	/// Remote event loop handle.
@@ -42,10 +48,18 @@ pub struct Context {
 impl Context {
 	/// Creates new context with reference to local sync node, thread pool and event loop.
 	pub fn new(local_sync_node: LocalSyncNodeRef, pool_handle: CpuPool, remote: Remote, config: Config) -> Result<Self, Box<error::Error>> {
+		let node_table = try!(NodeTable::from_file(&config.node_table_path));
+		let mut address_manager = try!(AddressManager::from_file(&config.address_manager_path));
+		for node in node_table.nodes() {
+			let source = node.address().ip();
+			address_manager.add(vec![node.into()], source);
+		}
+
 		let context = Context {
 			connections: Default::default(),
 			connection_counter: ConnectionCounter::new(config.inbound_connections, config.outbound_connections),
-			node_table: RwLock::new(try!(NodeTable::from_file(&config.node_table_path))),
+			node_table: RwLock::new(node_table),
+			address_manager: RwLock::new(address_manager),
 			pool: pool_handle,
 			remote: remote,
 			local_sync_node: local_sync_node,
@@ -89,6 +103,22 @@ impl Context {
 		self.node_table.write().insert_many(nodes);
 	}
 
+	/// Returns random addresses from address manager. Limited to 1000.
+	pub fn address_manager_entries(&self) -> Vec<AddressEntry> {
+		self.address_manager.read().addresses()
+	}
+
+	/// Updates address manager with addresses, received from peer with given ip.
+	pub fn update_address_manager(&self, entries: Vec<AddressEntry>, source: IpAddr) {
+		trace!("Updating address manager with {} entries from {}", entries.len(), source);
+		self.address_manager.write().add(entries, source);
+	}
+
+	/// Selects random address to connect to.
+	pub fn select_address(&self) -> Option<NetAddress> {
+		self.address_manager.read().select()
+	}
+
 	/// Adds node to table.
 	pub fn add_node(&self, addr: SocketAddr) -> Result<(), NodeTableError> {
 		trace!("Adding node {} to node table", &addr);
@@ -102,7 +132,7 @@ impl Context {
 	}
 
 	/// Every 10 seconds check if we have reached maximum number of outbound connections.
-	/// If not, connect to best peers.
+	/// If not, connect to peers, selected by address manager (or to best peers from node table).
 	pub fn autoconnect(context: Arc<Context>, handle: &Handle) {
 		let c = context.clone();
 		// every 10 seconds connect to new peers (if needed)
@@ -120,11 +150,27 @@ impl Context {
 
 				let needed = context.connection_counter.outbound_connections_needed() as usize;
 				if needed != 0 {
-					let used_addresses = context.connections.addresses();
-					let peers = context.node_table.read().nodes_with_services(&Services::default(), context.config.internet_protocol, &used_addresses, needed);
-					let addresses = peers.into_iter()
-						.map(|peer| peer.address())
-						.collect::<Vec<_>>();
+					let mut used_addresses = context.connections.addresses();
+					let mut addresses = Vec::with_capacity(needed);
+					for _ in 0..MAX_ADDRESS_SELECTION_ATTEMPTS {
+						if addresses.len() == needed {
+							break;
+						}
+
+						let address = match context.select_address() {
+							Some(address) => SocketAddr::new(address.address.into(), address.port.into()),
+							None => break,
+						};
+						if context.config.internet_protocol.is_allowed(&address) && used_addresses.insert(address) {
+							addresses.push(address);
+						}
+					}
+
+					// address manager could have not enough addresses (i.e. on first start) => use node table
+					if addresses.len() < needed {
+						let peers = context.node_table.read().nodes_with_services(&Services::default(), context.config.internet_protocol, &used_addresses, needed - addresses.len());
+						addresses.extend(peers.into_iter().map(|peer| peer.address()));
+					}
 
 					trace!("Creating {} more outbound connections", addresses.len());
 					for address in addresses {
@@ -136,6 +182,10 @@ impl Context {
 					error!("Saving node table to disk failed");
 				}
 
+				if let Err(_err) = context.address_manager.read().save_to_file(&context.config.address_manager_path) {
+					error!("Saving address manager to disk failed");
+				}
+
 				Ok(())
 			})
 			.for_each(|_| Ok(()))
@@ -154,6 +204,7 @@ impl Context {
 					// successfull hanshake
 					trace!("Connected to {}", connection.address);
 					context.node_table.write().insert(connection.address, connection.services);
+					context.address_manager.write().mark_good(&connection.address, connection.services);
 					let channel = context.connections.store::<T>(context.clone(), connection, Direction::Outbound);
 
 					// initialize session and then start reading messages
@@ -5,7 +5,7 @@ use message::{Error, Command, deserialize_payload, Payload};
 use message::types::{GetAddr, Addr};
 use protocol::Protocol;
 use net::PeerContext;
-use util::Direction;
+use util::{Direction, Node};
 
 pub struct AddrProtocol {
 	/// Context
@@ -35,7 +35,7 @@ impl Protocol for AddrProtocol {
 		// meanwhile seednodes, surprisingly, send addr message even before they are asked for it
 		if command == &GetAddr::command() {
 			let _: GetAddr = try!(deserialize_payload(payload, self.context.info().version));
-			let entries = self.context.global().node_table_entries().into_iter().map(Into::into).collect();
+			let entries = self.context.global().address_manager_entries();
 			let addr = Addr::new(entries);
 			self.context.send_response_inline(&addr);
 		} else if command == &Addr::command() {
@@ -45,8 +45,10 @@ impl Protocol for AddrProtocol {
 					unreachable!("This version of protocol is not supported!");
 				},
 				Addr::V31402(addr) => {
-					let nodes: Vec<_> = addr.addresses.into_iter().map(Into::into).collect();
+					let nodes: Vec<Node> = addr.addresses.into_iter().map(Into::into).collect();
 					let nodes_len = nodes.len();
+					let entries = nodes.iter().cloned().map(Into::into).collect();
+					self.context.global().update_address_manager(entries, self.context.info().address.ip());
 					self.context.global().update_node_table(nodes);
 					// seednodes are currently responding with two addr messages:
 					// 1) addr message with single address - seednode itself
@@ -0,0 +1,529 @@
+use std::{cmp, fs, io, path};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use rand::{self, Rng};
+use csv;
+use crypto::dhash256;
+use message::common::{NetAddress, Services};
+use message::types::addr::AddressEntry;
+
+/// Number of buckets in the table of addresses, we have never connected to
+const NEW_BUCKETS_COUNT: usize = 1024;
+/// Number of buckets in the table of addresses, we have successfully connected to
+const TRIED_BUCKETS_COUNT: usize = 256;
+/// Number of addresses in single bucket
+const BUCKET_SIZE: usize = 64;
+/// Maximal number of new table buckets, addresses from single source group could be placed to
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+/// Maximal number of tried table buckets, addresses from single group could be placed to
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+/// Maximal number of addresses in `addr` response
+const MAX_ADDR_RESPONSE: usize = 1000;
+
+/// Address book with new and tried tables, split into buckets as in Bitcoin Core.
+/// Bucket of address is selected using its network group (and group of the peer, which has sent this address),
+/// so that single peer (or group of peers) can not fill the whole table with its own addresses.
+pub struct AddressManager {
+	/// Secret key, used to randomize buckets selection
+	key: [u8; 32],
+	/// All known addresses
+	addresses: HashMap<SocketAddr, AddressInfo>,
+	/// Addresses, we have never connected to, by (bucket, position)
+	new_table: HashMap<(usize, usize), SocketAddr>,
+	/// Addresses, we have successfully connected to, by (bucket, position)
+	tried_table: HashMap<(usize, usize), SocketAddr>,
+}
+
+/// Known address
+struct AddressInfo {
+	/// Network address
+	address: NetAddress,
+	/// Last time address was seen
+	timestamp: u32,
+	/// Address of the peer, which has sent us this address
+	source: IpAddr,
+	/// True if address is in tried table
+	tried: bool,
+	/// Bucket and position of this address
+	slot: (usize, usize),
+}
+
+impl Default for AddressManager {
+	fn default() -> Self {
+		AddressManager::with_key(rand::random())
+	}
+}
+
+impl AddressManager {
+	/// Create address manager with given secret key
+	pub fn with_key(key: [u8; 32]) -> Self {
+		AddressManager {
+			key: key,
+			addresses: HashMap::new(),
+			new_table: HashMap::new(),
+			tried_table: HashMap::new(),
+		}
+	}
+
+	/// Number of addresses in new table
+	pub fn new_len(&self) -> usize {
+		self.new_table.len()
+	}
+
+	/// Number of addresses in tried table
+	pub fn tried_len(&self) -> usize {
+		self.tried_table.len()
+	}
+
+	/// Add addresses from `addr` message, received from peer with given ip address.
+	/// Non-routable addresses are ignored. Returns number of added addresses
+	pub fn add(&mut self, entries: Vec<AddressEntry>, source: IpAddr) -> usize {
+		let mut added = 0;
+		for entry in entries {
+			if self.add_entry(entry, source) {
+				added += 1;
+			}
+		}
+		added
+	}
+
+	/// Move address to the tried table, after successful connection to it
+	pub fn mark_good(&mut self, addr: &SocketAddr, services: Services) {
+		let addr = SocketAddr::new(normalize(addr.ip()), addr.port());
+		if !self.addresses.contains_key(&addr) {
+			let entry = AddressEntry {
+				timestamp: ::time::get_time().sec as u32,
+				address: NetAddress {
+					services: services,
+					address: addr.ip().into(),
+					port: addr.port().into(),
+				},
+			};
+			if !self.add_entry(entry, addr.ip()) {
+				return;
+			}
+		}
+
+		let new_slot = match self.addresses.get(&addr) {
+			Some(info) if !info.tried => info.slot,
+			_ => return,
+		};
+		self.new_table.remove(&new_slot);
+		self.move_to_tried_table(addr);
+	}
+
+	/// Random addresses for `getaddr` response, limited to 1000 entries
+	pub fn addresses(&self) -> Vec<AddressEntry> {
+		let mut rng = rand::thread_rng();
+		rand::sample(&mut rng, self.addresses.values(), MAX_ADDR_RESPONSE)
+			.into_iter()
+			.map(AddressInfo::entry)
+			.collect()
+	}
+
+	/// Select random address to connect to. Tried and new tables are selected with equal probability
+	pub fn select(&self) -> Option<NetAddress> {
+		let mut rng = rand::thread_rng();
+		let use_tried_table = match (self.new_table.is_empty(), self.tried_table.is_empty()) {
+			(true, true) => return None,
+			(true, false) => true,
+			(false, true) => false,
+			(false, false) => rng.gen(),
+		};
+
+		let table = if use_tried_table { &self.tried_table } else { &self.new_table };
+		let index = rng.gen_range(0, table.len());
+		table.values()
+			.nth(index)
+			.map(|addr| self.addresses[addr].address.clone())
+	}
+
+	/// Creates address manager from a file.
+	/// Addresses are placed to buckets using new secret key, so their slots could differ from saved ones
+	pub fn from_file<P>(path: P) -> Result<Self, io::Error> where P: AsRef<path::Path> {
+		fs::OpenOptions::new()
+			.create(true)
+			.read(true)
+			// without opening for write, mac os returns os error 22
+			.write(true)
+			.open(path)
+			.and_then(Self::load)
+	}
+
+	/// Saves address manager to file
+	pub fn save_to_file<P>(&self, path: P) -> Result<(), io::Error> where P: AsRef<path::Path> {
+		fs::File::create(path).and_then(|file| self.save(file))
+	}
+
+	/// Save addresses of new and tried tables in csv format.
+	pub fn save<W>(&self, write: W) -> Result<(), io::Error> where W: io::Write {
+		let mut writer = csv::Writer::from_writer(write)
+			.delimiter(b' ');
+
+		let err = || io::Error::new(io::ErrorKind::Other, "Write csv error");
+
+		for (addr, info) in &self.addresses {
+			let record = (addr.to_string(), info.timestamp, u64::from(info.address.services), info.source.to_string(), info.tried);
+			try!(writer.encode(record).map_err(|_| err()));
+		}
+
+		Ok(())
+	}
+
+	/// Loads addresses of new and tried tables from a csv source.
+	pub fn load<R>(read: R) -> Result<Self, io::Error> where R: io::Read {
+		let mut rdr = csv::Reader::from_reader(read)
+			.has_headers(false)
+			.delimiter(b' ');
+
+		let mut manager = AddressManager::default();
+
+		let err = || io::Error::new(io::ErrorKind::Other, "Load csv error");
+
+		for row in rdr.decode() {
+			let (addr, timestamp, services, source, tried): (String, u32, u64, String, bool) = try!(row.map_err(|_| err()));
+			let addr: SocketAddr = try!(addr.parse().map_err(|_| err()));
+			let source: IpAddr = try!(source.parse().map_err(|_| err()));
+			let entry = AddressEntry {
+				timestamp: timestamp,
+				address: NetAddress {
+					services: services.into(),
+					address: addr.ip().into(),
+					port: addr.port().into(),
+				},
+			};
+
+			if manager.add_entry(entry, source) && tried {
+				let new_slot = manager.addresses[&addr].slot;
+				manager.new_table.remove(&new_slot);
+				manager.move_to_tried_table(addr);
+			}
+		}
+
+		Ok(manager)
+	}
+
+	fn add_entry(&mut self, entry: AddressEntry, source: IpAddr) -> bool {
+		let ip = normalize(entry.address.address.into());
+		if !is_routable(&ip) {
+			return false;
+		}
+
+		let addr = SocketAddr::new(ip, entry.address.port.into());
+		if let Some(info) = self.addresses.get_mut(&addr) {
+			info.timestamp = cmp::max(info.timestamp, entry.timestamp);
+			info.address.services = entry.address.services;
+			return false;
+		}
+
+		// do not replace address, which already occupies the slot
+		let source = normalize(source);
+		let slot = self.new_slot(&addr, &source);
+		if self.new_table.contains_key(&slot) {
+			return false;
+		}
+
+		self.new_table.insert(slot, addr);
+		self.addresses.insert(addr, AddressInfo {
+			address: NetAddress {
+				services: entry.address.services,
+				address: ip.into(),
+				port: entry.address.port,
+			},
+			timestamp: entry.timestamp,
+			source: source,
+			tried: false,
+			slot: slot,
+		});
+		true
+	}
+
+	fn move_to_tried_table(&mut self, addr: SocketAddr) {
+		// previous owner of the slot goes back to the new table
+		let tried_slot = self.tried_slot(&addr);
+		if let Some(evicted) = self.tried_table.remove(&tried_slot) {
+			self.move_to_new_table(evicted);
+		}
+
+		self.tried_table.insert(tried_slot, addr);
+		let info = self.addresses.get_mut(&addr).expect("address is known to the caller; qed");
+		info.tried = true;
+		info.slot = tried_slot;
+	}
+
+	fn move_to_new_table(&mut self, addr: SocketAddr) {
+		let source = self.addresses[&addr].source;
+		let slot = self.new_slot(&addr, &source);
+		if self.new_table.contains_key(&slot) {
+			self.addresses.remove(&addr);
+			return;
+		}
+
+		self.new_table.insert(slot, addr);
+		let info = self.addresses.get_mut(&addr).expect("addr is read from the tried table; qed");
+		info.tried = false;
+		info.slot = slot;
+	}
+
+	fn new_slot(&self, addr: &SocketAddr, source: &IpAddr) -> (usize, usize) {
+		let group = network_group(&addr.ip());
+		let source_group = network_group(source);
+		let bucket_in_group = self.hash(&[&group, &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+		let bucket = (self.hash(&[&source_group, &u64_bytes(bucket_in_group)]) % NEW_BUCKETS_COUNT as u64) as usize;
+		(bucket, self.position(true, bucket, addr))
+	}
+
+	fn tried_slot(&self, addr: &SocketAddr) -> (usize, usize) {
+		let group = network_group(&addr.ip());
+		let bucket_in_group = self.hash(&[&address_key(addr)]) % TRIED_BUCKETS_PER_GROUP;
+		let bucket = (self.hash(&[&group, &u64_bytes(bucket_in_group)]) % TRIED_BUCKETS_COUNT as u64) as usize;
+		(bucket, self.position(false, bucket, addr))
+	}
+
+	fn position(&self, is_new: bool, bucket: usize, addr: &SocketAddr) -> usize {
+		(self.hash(&[&[is_new as u8], &u64_bytes(bucket as u64), &address_key(addr)]) % BUCKET_SIZE as u64) as usize
+	}
+
+	fn hash(&self, data: &[&[u8]]) -> u64 {
+		let mut input = self.key.to_vec();
+		for item in data {
+			input.extend_from_slice(item);
+		}
+
+		let hash = dhash256(&input);
+		hash[0..8].iter().rev().fold(0u64, |result, byte| (result << 8) | *byte as u64)
+	}
+}
+
+impl AddressInfo {
+	fn entry(&self) -> AddressEntry {
+		AddressEntry {
+			timestamp: self.timestamp,
+			address: self.address.clone(),
+		}
+	}
+}
+
+/// Returns true if address is publicly routable
+pub fn is_routable(ip: &IpAddr) -> bool {
+	match normalize(*ip) {
+		IpAddr::V4(ip) => is_routable_v4(&ip),
+		IpAddr::V6(ip) => is_routable_v6(&ip),
+	}
+}
+
+fn is_routable_v4(ip: &Ipv4Addr) -> bool {
+	let o = ip.octets();
+	!(o[0] == 0 // this network
+		|| o[0] == 10 // RFC1918
+		|| o[0] == 127 // loopback
+		|| (o[0] == 169 && o[1] == 254) // RFC3927 link-local
+		|| (o[0] == 172 && o[1] & 0xf0 == 16) // RFC1918
+		|| (o[0] == 192 && o[1] == 168) // RFC1918
+		|| (o[0] == 100 && o[1] & 0xc0 == 64) // RFC6598 shared address space
+		|| (o[0] == 198 && o[1] & 0xfe == 18) // RFC2544 benchmarking
+		|| (o[0] == 192 && o[1] == 0 && o[2] == 2) // RFC5737 documentation
+		|| (o[0] == 198 && o[1] == 51 && o[2] == 100) // RFC5737 documentation
+		|| (o[0] == 203 && o[1] == 0 && o[2] == 113) // RFC5737 documentation
+		|| o[0] >= 224) // multicast, reserved && broadcast
+}
+
+fn is_routable_v6(ip: &Ipv6Addr) -> bool {
+	let s = ip.segments();
+	!(ip.is_unspecified()
+		|| ip.is_loopback()
+		|| s[0] & 0xfe00 == 0xfc00 // RFC4193 unique local
+		|| s[0] & 0xffc0 == 0xfe80 // RFC4862 link-local
+		|| s[0] & 0xff00 == 0xff00 // multicast
+		|| (s[0] == 0x2001 && s[1] == 0x0db8) // RFC3849 documentation
+		|| (s[0] == 0x2001 && s[1] & 0xfff0 == 0x0010)) // RFC4843 ORCHID
+}
+
+/// Convert IPv4-mapped IPv6 address to IPv4 address
+fn normalize(ip: IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V6(v6) => match v6.segments() {
+			[0, 0, 0, 0, 0, 0xffff, _, _] => {
+				let o = v6.octets();
+				IpAddr::V4(Ipv4Addr::new(o[12], o[13], o[14], o[15]))
+			},
+			_ => ip,
+		},
+		IpAddr::V4(_) => ip,
+	}
+}
+
+/// Network group of address: /16 for IPv4 and /32 for IPv6 addresses
+fn network_group(ip: &IpAddr) -> Vec<u8> {
+	match normalize(*ip) {
+		IpAddr::V4(ip) => {
+			let o = ip.octets();
+			vec![1, o[0], o[1]]
+		},
+		IpAddr::V6(ip) => {
+			let o = ip.octets();
+			vec![2, o[0], o[1], o[2], o[3]]
+		},
+	}
+}
+
+fn address_key(addr: &SocketAddr) -> Vec<u8> {
+	let ip = match addr.ip() {
+		IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+		IpAddr::V6(ip) => ip,
+	};
+	let mut key = ip.octets().to_vec();
+	key.push((addr.port() >> 8) as u8);
+	key.push(addr.port() as u8);
+	key
+}
+
+fn u64_bytes(value: u64) -> [u8; 8] {
+	let mut bytes = [0u8; 8];
+	for (index, byte) in bytes.iter_mut().enumerate() {
+		*byte = (value >> (index * 8)) as u8;
+	}
+	bytes
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+	use std::net::{IpAddr, SocketAddr};
+	use message::common::{NetAddress, Services};
+	use message::types::addr::AddressEntry;
+	use super::{AddressManager, is_routable, NEW_BUCKETS_PER_SOURCE_GROUP, TRIED_BUCKETS_PER_GROUP};
+
+	fn entry(addr: &str) -> AddressEntry {
+		let addr: SocketAddr = addr.parse().unwrap();
+		AddressEntry {
+			timestamp: 0,
+			address: NetAddress {
+				services: Services::default(),
+				address: addr.ip().into(),
+				port: addr.port().into(),
+			},
+		}
+	}
+
+	fn ip(ip: &str) -> IpAddr {
+		ip.parse().unwrap()
+	}
+
+	#[test]
+	fn test_address_manager_routable() {
+		let non_routable = vec![
+			"0.1.2.3", "10.0.0.1", "127.0.0.1", "169.254.1.1", "172.16.0.1", "172.31.255.255", "192.168.1.1",
+			"100.64.0.1", "198.18.0.1", "192.0.2.1", "198.51.100.1", "203.0.113.1", "224.0.0.1", "255.255.255.255",
+			"::", "::1", "fc00::1", "fe80::1", "ff02::1", "2001:db8::1", "2001:10::1", "::ffff:192.168.1.1",
+		];
+		for address in non_routable {
+			assert!(!is_routable(&ip(address)), "{} is not routable", address);
+		}
+
+		let routable = vec!["1.2.3.4", "8.8.8.8", "172.32.0.1", "100.128.0.1", "2a00:1450::1", "::ffff:8.8.8.8"];
+		for address in routable {
+			assert!(is_routable(&ip(address)), "{} is routable", address);
+		}
+
+		let mut manager = AddressManager::with_key([1u8; 32]);
+		let entries = vec![entry("127.0.0.1:8333"), entry("8.8.8.8:8333"), entry("[fe80::1]:8333"), entry("[2a00:1450::1]:8333")];
+		assert_eq!(manager.add(entries, ip("8.8.4.4")), 2);
+		assert_eq!(manager.new_len(), 2);
+		assert_eq!(manager.addresses().len(), 2);
+	}
+
+	#[test]
+	fn test_address_manager_new_bucket_placement() {
+		let manager = AddressManager::with_key([1u8; 32]);
+
+		// placement is the same for sources from the same group
+		let addr: SocketAddr = "8.8.8.8:8333".parse().unwrap();
+		assert_eq!(manager.new_slot(&addr, &ip("1.2.3.4")), manager.new_slot(&addr, &ip("1.2.200.200")));
+
+		// addresses from single source group are placed into limited number of buckets
+		let buckets: HashSet<usize> = (0..4096u32)
+			.map(|n| SocketAddr::new(IpAddr::V4((0x0b000000 + n * 0x10000).into()), 8333))
+			.map(|addr| manager.new_slot(&addr, &ip("1.2.3.4")).0)
+			.collect();
+		assert!(buckets.len() <= NEW_BUCKETS_PER_SOURCE_GROUP as usize);
+		assert!(buckets.len() > 1);
+
+		// while addresses from different source groups are placed into more buckets
+		let buckets: HashSet<usize> = (0..4096u32)
+			.map(|n| IpAddr::V4((0x0b000000 + n * 0x10000).into()))
+			.map(|source| manager.new_slot(&"8.8.8.8:8333".parse().unwrap(), &source).0)
+			.collect();
+		assert!(buckets.len() > NEW_BUCKETS_PER_SOURCE_GROUP as usize);
+	}
+
+	#[test]
+	fn test_address_manager_tried_bucket_placement() {
+		let manager = AddressManager::with_key([1u8; 32]);
+
+		// addresses from single group are placed into limited number of buckets
+		let buckets: HashSet<usize> = (0..1024u32)
+			.map(|n| SocketAddr::new(IpAddr::V4((0x08080000 + n).into()), 8333))
+			.map(|addr| manager.tried_slot(&addr).0)
+			.collect();
+		assert!(buckets.len() <= TRIED_BUCKETS_PER_GROUP as usize);
+		assert!(buckets.len() > 1);
+
+		// addresses from different groups are placed into more buckets
+		let buckets: HashSet<usize> = (0..1024u32)
+			.map(|n| SocketAddr::new(IpAddr::V4((0x0b000000 + n * 0x10000).into()), 8333))
+			.map(|addr| manager.tried_slot(&addr).0)
+			.collect();
+		assert!(buckets.len() > TRIED_BUCKETS_PER_GROUP as usize);
+	}
+
+	#[test]
+	fn test_address_manager_mark_good() {
+		let mut manager = AddressManager::with_key([1u8; 32]);
+		assert_eq!(manager.select(), None);
+
+		assert_eq!(manager.add(vec![entry("8.8.8.8:8333"), entry("9.9.9.9:8333")], ip("1.2.3.4")), 2);
+		// duplicate address is not added
+		assert_eq!(manager.add(vec![entry("8.8.8.8:8333")], ip("4.3.2.1")), 0);
+		assert_eq!((manager.new_len(), manager.tried_len()), (2, 0));
+
+		manager.mark_good(&"8.8.8.8:8333".parse().unwrap(), Services::default());
+		assert_eq!((manager.new_len(), manager.tried_len()), (1, 1));
+
+		// unknown address is added directly to the tried table
+		manager.mark_good(&"1.1.1.1:8333".parse().unwrap(), Services::default());
+		assert_eq!((manager.new_len(), manager.tried_len()), (1, 2));
+
+		let selected = manager.select().unwrap();
+		let selected_ip: IpAddr = selected.address.into();
+		assert!(vec![ip("8.8.8.8"), ip("9.9.9.9"), ip("1.1.1.1")].contains(&selected_ip));
+	}
+
+	#[test]
+	fn test_address_manager_save_and_load() {
+		let mut manager = AddressManager::with_key([1u8; 32]);
+		assert_eq!(manager.add(vec![entry("8.8.8.8:8333"), entry("9.9.9.9:8333")], ip("1.2.3.4")), 2);
+		manager.mark_good(&"8.8.8.8:8333".parse().unwrap(), Services::default());
+
+		let mut db = Vec::new();
+		assert_eq!(manager.save(&mut db).unwrap(), ());
+		let loaded = AddressManager::load(&db as &[u8]).unwrap();
+		assert_eq!((loaded.new_len(), loaded.tried_len()), (1, 1));
+		let tried: SocketAddr = "8.8.8.8:8333".parse().unwrap();
+		let new: SocketAddr = "9.9.9.9:8333".parse().unwrap();
+		assert!(loaded.addresses[&tried].tried);
+		assert!(!loaded.addresses[&new].tried);
+		assert_eq!(loaded.addresses[&new].source, ip("1.2.3.4"));
+	}
+
+	#[test]
+	fn test_address_manager_getaddr_limit() {
+		let mut manager = AddressManager::with_key([1u8; 32]);
+		for n in 0..2000u32 {
+			let addr = SocketAddr::new(IpAddr::V4((0x0b000000 + n * 0x100).into()), 8333);
+			manager.add(vec![entry(&addr.to_string())], addr.ip());
+		}
+		assert!(manager.new_len() > 1000);
+		assert_eq!(manager.addresses().len(), 1000);
+	}
+}
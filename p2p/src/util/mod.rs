@@ -1,12 +1,14 @@
 pub mod nonce;
 pub mod time;
 pub mod interval;
+mod address_manager;
 mod internet_protocol;
 mod node_table;
 mod peer;
 mod response_queue;
 mod synchronizer;
 
+pub use self::address_manager::AddressManager;
 pub use self::internet_protocol::InternetProtocol;
 pub use self::node_table::{NodeTable, NodeTableError, Node};
 pub use self::peer::{PeerId, PeerInfo, Direction};
@@ -6,7 +6,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use sync::{create_sync_peers, create_local_sync_node, create_sync_connection_factory, SyncListener};
 use message::Services;
 use primitives::hash::H256;
-use util::{open_db, init_db, node_table_path};
+use util::{open_db, init_db, node_table_path, address_manager_path};
 use {config, p2p, PROTOCOL_VERSION, PROTOCOL_MINIMUM};
 use super::super::rpc;
 
@@ -89,6 +89,7 @@ pub fn start(cfg: config::Config) -> Result<(), String> {
 	init_db(&cfg, &db)?;
 
 	let nodes_path = node_table_path(&cfg);
+	let addresses_path = address_manager_path(&cfg);
 
 	let p2p_cfg = p2p::Config {
 		threads: cfg.p2p_threads,
@@ -107,11 +108,12 @@ pub fn start(cfg: config::Config) -> Result<(), String> {
 		peers: cfg.connect.map_or_else(|| vec![], |x| vec![x]),
 		seeds: cfg.seednodes,
 		node_table_path: nodes_path,
+		address_manager_path: addresses_path,
 		internet_protocol: cfg.internet_protocol,
 	};
 
 	let sync_peers = create_sync_peers();
-	let local_sync_node = create_local_sync_node(cfg.magic, db.clone(), sync_peers.clone());
+	let local_sync_node = try!(create_local_sync_node(cfg.magic, db.clone(), sync_peers.clone(), cfg.verification_threads, cfg.max_mempool_size));
 	let sync_connection_factory = create_sync_connection_factory(sync_peers.clone(), local_sync_node.clone());
 
 	if let Some(block_notify_command) = cfg.block_notify_command {
@@ -16,7 +16,11 @@ pub struct Config {
 	pub inbound_connections: u32,
 	pub outbound_connections: u32,
 	pub p2p_threads: usize,
+	pub verification_threads: usize,
 	pub db_cache: usize,
+	pub max_mempool_size: usize,
+	pub spent_index: bool,
+	pub block_undo: bool,
 	pub data_dir: Option<String>,
 	pub user_agent: String,
 	pub internet_protocol: InternetProtocol,
@@ -25,6 +29,7 @@ pub struct Config {
 }
 
 pub const DEFAULT_DB_CACHE: usize = 512;
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 300;
 
 pub fn parse(matches: &clap::ArgMatches) -> Result<Config, String> {
 	let print_to_console = matches.is_present("print-to-console");
@@ -45,6 +50,14 @@ pub fn parse(matches: &clap::ArgMatches) -> Result<Config, String> {
 		Magic::Regtest | Magic::Unitest => 1,
 	};
 
+	let verification_threads = match matches.value_of("verification-threads") {
+		Some(s) => try!(s.parse().map_err(|_| "Invalid verification threads - should be number".to_owned())),
+		None => match magic {
+			Magic::Testnet | Magic::Mainnet | Magic::Other(_) => 4,
+			Magic::Regtest | Magic::Unitest => 1,
+		},
+	};
+
 	// to skip idiotic 30 seconds delay in test-scripts
 	let user_agent = match magic {
 		Magic::Testnet | Magic::Mainnet | Magic::Unitest | Magic::Other(_) => USER_AGENT,
@@ -80,6 +93,11 @@ pub fn parse(matches: &clap::ArgMatches) -> Result<Config, String> {
 		None => DEFAULT_DB_CACHE,
 	};
 
+	let max_mempool_size: usize = match matches.value_of("max-mempool") {
+		Some(s) => try!(s.parse().map_err(|_| "Invalid max mempool size - should be number in MB".to_owned())),
+		None => DEFAULT_MAX_MEMPOOL_SIZE,
+	};
+
 	let data_dir = match matches.value_of("data-dir") {
 		Some(s) => Some(try!(s.parse().map_err(|_| "Invalid data-dir".to_owned()))),
 		None => None,
@@ -106,7 +124,11 @@ pub fn parse(matches: &clap::ArgMatches) -> Result<Config, String> {
 		inbound_connections: in_connections,
 		outbound_connections: out_connections,
 		p2p_threads: p2p_threads,
+		verification_threads: verification_threads,
 		db_cache: db_cache,
+		max_mempool_size: max_mempool_size * 1024 * 1024,
+		spent_index: matches.is_present("spent-index"),
+		block_undo: matches.is_present("undo-data"),
 		data_dir: data_dir,
 		user_agent: user_agent.to_string(),
 		internet_protocol: only_net,
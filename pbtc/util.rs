@@ -11,7 +11,9 @@ pub fn open_db(cfg: &Config) -> db::SharedStore {
 		Some(ref data_dir) => custom_path(&data_dir, "db"),
 		None => app_dir(AppDataType::UserData, &APP_INFO, "db").expect("Failed to get app dir"),
 	};
-	Arc::new(db::BlockChainDatabase::open_at_path(db_path, cfg.db_cache).expect("Failed to open database"))
+	Arc::new(db::BlockChainDatabase::open_at_path(db_path, cfg.db_cache).expect("Failed to open database")
+		.with_spent_index(cfg.spent_index)
+		.with_block_undo(cfg.block_undo))
 }
 
 pub fn node_table_path(cfg: &Config) -> PathBuf {
@@ -23,6 +25,15 @@ pub fn node_table_path(cfg: &Config) -> PathBuf {
 	node_table
 }
 
+pub fn address_manager_path(cfg: &Config) -> PathBuf {
+	let mut address_manager = match cfg.data_dir {
+		Some(ref data_dir) => custom_path(&data_dir, "p2p"),
+		None => app_dir(AppDataType::UserData, &APP_INFO, "p2p").expect("Failed to get app dir"),
+	};
+	address_manager.push("addresses.csv");
+	address_manager
+}
+
 pub fn init_db(cfg: &Config, db: &db::SharedStore) -> Result<(), String> {
 	// insert genesis block if db is empty
 	let genesis_block: IndexedBlock = cfg.magic.genesis_block().into();
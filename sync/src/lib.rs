@@ -18,6 +18,11 @@ extern crate miner;
 extern crate script;
 extern crate serialization as ser;
 extern crate rand;
+#[cfg(test)]
+extern crate serde;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_derive;
 extern crate network;
 
 mod blocks_writer;
@@ -76,8 +81,10 @@ pub fn create_sync_peers() -> PeersRef {
 	Arc::new(PeersImpl::default())
 }
 
-/// Creates local sync node for given `db`
-pub fn create_local_sync_node(network: Magic, db: db::SharedStore, peers: PeersRef) -> LocalNodeRef {
+/// Creates local sync node for given `db`.
+/// Input scripts are verified in parallel if `verification_threads` is greater than 1.
+/// Total size of memory pool transactions never exceeds `memory_pool_max_size` bytes
+pub fn create_local_sync_node(network: Magic, db: db::SharedStore, peers: PeersRef, verification_threads: usize, memory_pool_max_size: usize) -> Result<LocalNodeRef, String> {
 	use miner::MemoryPool;
 	use synchronization_chain::Chain as SyncChain;
 	use synchronization_executor::LocalSynchronizationTaskExecutor as SyncExecutor;
@@ -95,17 +102,25 @@ pub fn create_local_sync_node(network: Magic, db: db::SharedStore, peers: PeersR
 		close_connection_on_bad_block: network != Magic::Regtest,
 	};
 
-	let memory_pool = Arc::new(RwLock::new(MemoryPool::new()));
+	let memory_pool = Arc::new(RwLock::new(MemoryPool::new().with_max_size(memory_pool_max_size)));
 	let sync_state = SynchronizationStateRef::new(SynchronizationState::with_storage(db.clone()));
-	let sync_chain = SyncChain::new(db.clone(), memory_pool.clone());
-	let chain_verifier = Arc::new(ChainVerifier::new(db.clone(), network));
+	let mut sync_chain = SyncChain::new(db.clone(), memory_pool.clone());
+	sync_chain.set_check_block_timestamps(true);
+	sync_chain.set_network(network);
+	let chain_verifier = ChainVerifier::new(db.clone(), network);
+	let chain_verifier = match verification_threads {
+		0 | 1 => chain_verifier,
+		threads => try!(chain_verifier.with_threads(threads)
+			.map_err(|err| format!("Failed to create verification thread pool: {:?}", err))),
+	};
+	let chain_verifier = Arc::new(chain_verifier);
 	let sync_executor = SyncExecutor::new(peers.clone());
 	let sync_server = Arc::new(ServerImpl::new(peers.clone(), db.clone(), memory_pool.clone(), sync_executor.clone()));
 	let sync_client_core = SynchronizationClientCore::new(sync_client_config, sync_state.clone(), peers.clone(), sync_executor.clone(), sync_chain, chain_verifier.clone());
 	let verifier_sink = Arc::new(CoreVerificationSink::new(sync_client_core.clone()));
 	let verifier = AsyncVerifier::new(chain_verifier, db.clone(), memory_pool.clone(), verifier_sink);
 	let sync_client = SynchronizationClient::new(sync_state.clone(), sync_client_core, verifier);
-	Arc::new(SyncNode::new(network, db, memory_pool, peers, sync_state, sync_executor, sync_client, sync_server))
+	Ok(Arc::new(SyncNode::new(network, db, memory_pool, peers, sync_state, sync_executor, sync_client, sync_server)))
 }
 
 /// Create inbound synchronization connections factory for given local sync node.
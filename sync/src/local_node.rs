@@ -3,7 +3,7 @@ use parking_lot::{Mutex, Condvar};
 use time;
 use futures::{Future, lazy, finished};
 use chain::{Transaction, IndexedTransaction, IndexedBlock};
-use message::types;
+use message::{types, Payload};
 use miner::BlockAssembler;
 use network::Magic;
 use synchronization_client::{Client};
@@ -73,6 +73,11 @@ impl<T, U, V> LocalNode<T, U, V> where T: TaskExecutor, U: Server, V: Client {
 			self.peers.set_transaction_announcement_type(peer_index, TransactionAnnouncementType::DoNotAnnounce);
 		}
 
+		// ask peer to announce new blocks using headers (BIP130)
+		if version.version() >= types::SendHeaders::version() {
+			self.executor.execute(SynchronizationTask::SendHeaders(peer_index));
+		}
+
 		// start synchronization session with peer
 		self.client.on_connect(peer_index);
 	}
@@ -406,6 +411,20 @@ pub mod tests {
 		assert_eq!(tasks, vec![ServerTask::GetData(peer_index, types::GetData::with_inventory(inventory))]);
 	}
 
+	#[test]
+	fn local_node_sends_sendheaders_on_connect() {
+		let (executor, _, local_node) = create_local_node(None);
+
+		// peer doesn't support sendheaders
+		let peer_index1 = 0; local_node.on_connect(peer_index1, types::Version::default());
+		assert!(!executor.take_tasks().contains(&Task::SendHeaders(peer_index1)));
+
+		// peer supports sendheaders
+		let version = types::Version::V0(types::version::V0 { version: 70012, ..Default::default() });
+		let peer_index2 = 1; local_node.on_connect(peer_index2, version);
+		assert_eq!(executor.take_tasks()[0], Task::SendHeaders(peer_index2));
+	}
+
 	#[test]
 	fn local_node_accepts_local_transaction() {
 		let (executor, _, local_node) = create_local_node(None);
@@ -1,13 +1,39 @@
-use std::collections::{VecDeque, HashSet};
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{VecDeque, HashSet, HashMap};
 use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::path::Path;
+#[cfg(test)] use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+#[cfg(test)] use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
 use linked_hash_map::LinkedHashMap;
-use chain::{BlockHeader, Transaction, IndexedBlockHeader, IndexedBlock, IndexedTransaction};
+use bitcrypto::dhash256;
+use time;
+use chain::{Block, BlockHeader, Transaction, TransactionOutput, OutPoint, IndexedBlockHeader, IndexedBlock, IndexedTransaction};
+#[cfg(test)] use chain::constants::SEQUENCE_LOCKTIME_DISABLE_FLAG;
 use db;
-use miner::{MemoryPoolOrderingStrategy, MemoryPoolInformation};
+#[cfg(test)] use message::serialize_payload;
+use message::types::Headers;
+#[cfg(test)] use message::types::CompactBlock;
+use miner::{MemoryPoolOrderingStrategy, MemoryPoolInformation, MemoryPoolInsertionError, MemoryPoolInsertionResult};
+#[cfg(test)] use miner::{StandardnessPolicy, NonStandardReason};
 use primitives::bytes::Bytes;
+#[cfg(test)] use primitives::compact::Compact;
 use primitives::hash::H256;
-use utils::{BestHeadersChain, BestHeadersChainInformation, HashQueueChain, HashPosition};
-use types::{BlockHeight, StorageRef, MemoryPoolRef};
+use network::{Magic, ConsensusParams};
+#[cfg(test)] use script::{Script, Builder as ScriptBuilder};
+use ser::{Serializable, Stream, Reader};
+use utils::{AverageSpeedMeter, HashQueue, BestHeadersChain, BestHeadersChainInformation, HashQueueChain, HashPosition};
+#[cfg(test)] use utils::{build_compact_block_with_nonce, BloomFilter};
+use types::{BlockHeight, PeerIndex, StorageRef, MemoryPoolRef};
+use verification::median_timestamp;
+#[cfg(test)] use verification::{work_required, work_required_retarget, verify_relative_locktime};
+use verification::constants::BLOCK_MAX_FUTURE;
+#[cfg(test)] use verification::constants::RETARGETING_INTERVAL;
 
 /// Index of 'verifying' queue
 const VERIFYING_QUEUE: usize = 0;
@@ -17,6 +43,27 @@ const REQUESTED_QUEUE: usize = 1;
 const SCHEDULED_QUEUE: usize = 2;
 /// Number of hash queues
 const NUMBER_OF_QUEUES: usize = 3;
+/// Number of blocks to inspect when calculating storage insertion speed
+const STORAGE_SPEED_BLOCKS_TO_INSPECT: usize = 64;
+/// Height, reported for outputs of memory pool transactions
+#[cfg(test)]
+pub const MEMORY_POOL_HEIGHT: BlockHeight = 0x7FFFFFFF;
+/// Number of recent storage reorganizations to remember
+const MAX_RECENT_REORGS: usize = 64;
+/// Scale factor between block size and block weight
+#[cfg(test)]
+const WITNESS_SCALE_FACTOR: usize = 4;
+/// Default maximal number of buffered headers, which parents are not yet connected
+#[cfg(test)]
+const DEFAULT_MAX_BUFFERED_HEADERS: usize = 2048;
+/// Default minimal number of blocks in requested queue
+const DEFAULT_MIN_DOWNLOAD_WINDOW: BlockHeight = 32;
+/// Default maximal number of blocks in requested queue
+const DEFAULT_MAX_DOWNLOAD_WINDOW: BlockHeight = 256;
+/// Number of received blocks to inspect when calculating blocks download speed
+const DOWNLOAD_SPEED_BLOCKS_TO_INSPECT: usize = 64;
+/// Weight of the most recent block arrival latency in the average latency
+const DOWNLOAD_LATENCY_WEIGHT: f64 = 0.125;
 
 /// Block insertion result
 #[derive(Default, PartialEq)]
@@ -46,6 +93,163 @@ impl BlockInsertionResult {
 	}
 }
 
+/// Storage reorganization result
+#[cfg(test)]
+#[derive(Default, PartialEq)]
+pub struct ReorgResult {
+	/// Hashes of blocks, which were decanonized. Ordered from oldest to newest
+	pub decanonized_blocks_hashes: Vec<H256>,
+	/// Hashes of blocks, which were canonized. Ordered from oldest to newest
+	pub canonized_blocks_hashes: Vec<H256>,
+	/// Transaction to 'reverify'. Order matters
+	pub transactions_to_reverify: Vec<IndexedTransaction>,
+}
+
+#[cfg(test)]
+impl fmt::Debug for ReorgResult {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ReorgResult")
+			.field("decanonized_blocks_hashes", &self.decanonized_blocks_hashes.iter().map(H256::reversed).collect::<Vec<_>>())
+			.field("canonized_blocks_hashes", &self.canonized_blocks_hashes.iter().map(H256::reversed).collect::<Vec<_>>())
+			.field("transactions_to_reverify", &self.transactions_to_reverify)
+			.finish()
+	}
+}
+
+/// Error returned when block can not be invalidated
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum InvalidateError {
+	/// Block is unknown
+	UnknownBlock,
+	/// Genesis block can not be invalidated
+	GenesisBlock,
+	/// Database error
+	Database(db::Error),
+}
+
+/// Inconsistency, found in the storage
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum IntegrityError {
+	/// Canon block with given number is missing
+	MissingBlock(BlockHeight),
+	/// Merkle root of block transactions doesn't match the header
+	MerkleRootMismatch(H256),
+	/// Block parent isn't the previous canon block
+	BrokenLink(H256),
+}
+
+/// Error returned when block exceeds resource limits
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum BlockLimitError {
+	/// Block weight is above the limit
+	Weight { weight: usize, max_weight: usize },
+	/// Block signature operations cost is above the limit
+	Sigops { sigops: usize, max_sigops: usize },
+}
+
+/// Error returned when transaction can not be abandoned
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum AbandonError {
+	/// Transaction is already stored in the blockchain
+	AlreadyConfirmed,
+	/// Transaction is not in the memory pool
+	UnknownTransaction,
+}
+
+/// Error returned when in-memory chain state can not be saved or restored
+#[derive(Debug)]
+pub enum StateError {
+	/// File can not be read or written
+	Io(io::Error),
+	/// Saved state is malformed
+	Deserialize(ser::Error),
+}
+
+impl From<io::Error> for StateError {
+	fn from(err: io::Error) -> Self {
+		StateError::Io(err)
+	}
+}
+
+impl From<ser::Error> for StateError {
+	fn from(err: ser::Error) -> Self {
+		StateError::Deserialize(err)
+	}
+}
+
+#[cfg(test)]
+impl From<db::Error> for InvalidateError {
+	fn from(err: db::Error) -> Self {
+		InvalidateError::Database(err)
+	}
+}
+
+/// Error returned when block timestamp is invalid
+#[derive(Debug, PartialEq)]
+pub enum TimestampError {
+	/// Block timestamp is too far in the future
+	FutureTime {
+		/// Block timestamp
+		time: u32,
+		/// Maximal allowed timestamp
+		max_time: u32,
+	},
+	/// Block timestamp is not greater than median time of previous blocks
+	BeforeMedianTimePast {
+		/// Block timestamp
+		time: u32,
+		/// Median time of previous blocks
+		median_time_past: u32,
+	},
+}
+
+/// Error returned when block can not be inserted to the storage
+#[derive(Debug, PartialEq)]
+pub enum ReorgError {
+	/// Block insertion requires reorganization, which is deeper than allowed
+	TooDeep {
+		/// Number of blocks to decanonize
+		depth: BlockHeight,
+	},
+	/// Block timestamp is invalid
+	Timestamp(TimestampError),
+	/// Chain is in safe mode after too deep reorganization
+	SafeMode,
+	/// Database error
+	Database(db::Error),
+}
+
+impl From<db::Error> for ReorgError {
+	fn from(err: db::Error) -> Self {
+		ReorgError::Database(err)
+	}
+}
+
+/// Error returned when validated block can not be inserted to the storage
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum InsertError<E> {
+	/// Block has been rejected by the validator
+	Validation(E),
+	/// Block can not be inserted to the storage
+	Reorg(ReorgError),
+}
+
+#[cfg(test)]
+impl<E> From<ReorgError> for InsertError<E> {
+	fn from(err: ReorgError) -> Self {
+		InsertError::Reorg(err)
+	}
+}
+
+/// View of unspent outputs, as they are before the block is inserted
+#[cfg(test)]
+pub type UtxoView = db::TransactionOutputProvider;
+
 /// Block synchronization state
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum BlockState {
@@ -63,6 +267,33 @@ pub enum BlockState {
 	DeadEnd,
 }
 
+/// Position of the block relative to the known chain
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectPoint {
+	/// Block parent is the best stored or verifying block
+	ExtendsActiveTip,
+	/// Block parent (with given hash) is stored or verifying, but is not the best block
+	ExtendsSideBranch(H256),
+	/// Block parent is not stored or verifying yet
+	Orphan,
+	/// Block itself is already stored, verifying or marked as dead-end
+	AlreadyHave,
+}
+
+/// Reason, why the best storage block has been selected among competing tips
+#[cfg(test)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TipSelectionReason {
+	/// There are no other tips with the same number of blocks
+	MostWork,
+	/// Competing tips have the same number of blocks, the best block has been seen first
+	FirstSeen,
+	/// Competing tips have the same number of blocks and unknown (or equal) first-seen time,
+	/// the best block has the lowest hash
+	LowestHash,
+}
+
 /// Transactions synchronization state
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum TransactionState {
@@ -76,6 +307,103 @@ pub enum TransactionState {
 	Stored,
 }
 
+/// Order of memory pool transactions hashes
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MempoolOrder {
+	/// No particular order
+	Unordered,
+	/// Transactions with higher fee rate go first
+	FeeRateDesc,
+	/// Transactions, which have entered the memory pool earlier, go first
+	EntryTimeAsc,
+}
+
+/// Unspent transaction output
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct UtxoEntry {
+	/// Output reference
+	pub outpoint: OutPoint,
+	/// Output value
+	pub value: u64,
+	/// Output script
+	pub script_pubkey: Bytes,
+	/// Height of block, where output has been created (`MEMORY_POOL_HEIGHT` for memory pool outputs)
+	pub height: BlockHeight,
+	/// Is this an output of coinbase transaction
+	pub is_coinbase: bool,
+}
+
+/// Result of unspent outputs set scan
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+	/// Number of the best storage block at the moment of scan
+	pub height: BlockHeight,
+	/// Hash of the best storage block at the moment of scan
+	pub best_block_hash: H256,
+	/// Matching unspent outputs
+	pub unspents: Vec<UtxoEntry>,
+	/// Total value of matching unspent outputs
+	pub total_amount: u64,
+	/// Has scan been cancelled before all blocks were scanned
+	pub cancelled: bool,
+}
+
+/// Transaction with its location in the blockchain
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub struct TransactionLocation {
+	/// Transaction
+	pub transaction: Transaction,
+	/// Hash of the canon block, containing the transaction. None for memory pool transactions or if not requested
+	pub block_hash: Option<H256>,
+	/// Number of confirmations (0 for memory pool transactions). None if not requested
+	pub confirmations: Option<u32>,
+}
+
+/// Canon chain update event
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockEvent {
+	/// Block with given number and hash has been added to the canon chain
+	Connected(BlockHeight, H256),
+	/// Block with given number and hash has been removed from the canon chain
+	Disconnected(BlockHeight, H256),
+}
+
+/// Storage reorganization record
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReorgEvent {
+	/// Best storage block hash before reorganization
+	pub old_tip: H256,
+	/// Best storage block hash after reorganization
+	pub new_tip: H256,
+	/// Number of decanonized blocks
+	pub depth: BlockHeight,
+	/// Unix timestamp of the reorganization
+	pub time: i64,
+}
+
+/// Error returned when scheduled headers are too far ahead of the best storage block
+#[derive(Debug, PartialEq)]
+pub struct HeadersLookaheadError {
+	/// Hash of the first rejected header
+	pub hash: H256,
+	/// Number of blocks this header is ahead of the best storage block
+	pub blocks_ahead: BlockHeight,
+}
+
+/// Error returned when storage genesis block doesn't match the expected one
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub struct GenesisMismatch {
+	/// Expected genesis block hash
+	pub expected: H256,
+	/// Genesis block hash of the storage
+	pub actual: H256,
+}
+
 /// Synchronization chain information
 pub struct Information {
 	/// Number of blocks hashes currently scheduled for requesting
@@ -90,6 +418,145 @@ pub struct Information {
 	pub transactions: MemoryPoolInformation,
 	/// Information on headers chain
 	pub headers: BestHeadersChainInformation,
+	/// Number of headers, which blocks have been forgotten while waiting for their parent
+	pub orphaned: u32,
+}
+
+/// Blockchain state information, as required by `getblockchaininfo` RPC
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockchainInfo {
+	/// Hash of the best storage block
+	pub best_block_hash: H256,
+	/// Number of the best storage block
+	pub blocks: BlockHeight,
+	/// Number of the best known header
+	pub headers: BlockHeight,
+	/// Difficulty of the best storage block
+	pub difficulty: f64,
+	/// Median time of blocks before the best storage block
+	pub median_time: u32,
+	/// Estimated verification progress, from 0 to 1
+	pub verification_progress: f64,
+}
+
+/// Synchronization chain state snapshot
+#[cfg(test)]
+#[derive(Debug, Serialize)]
+pub struct ChainSnapshot {
+	/// Number of blocks hashes currently scheduled for requesting
+	pub scheduled: BlockHeight,
+	/// Number of blocks hashes currently requested from peers
+	pub requested: BlockHeight,
+	/// Number of blocks currently verifying
+	pub verifying: BlockHeight,
+	/// Number of blocks in the storage
+	pub stored: BlockHeight,
+	/// Number of headers in best headers chain
+	pub headers_best: u32,
+	/// Total number of headers
+	pub headers_total: u32,
+	/// Hash of the best storage block
+	pub best_storage_block_hash: String,
+	/// Hashes of side chains tips
+	pub fork_tips: Vec<String>,
+	/// Number of transactions in memory pool
+	pub memory_pool_transactions: usize,
+	/// Size of memory pool transactions in bytes
+	pub memory_pool_size: usize,
+	/// Unix timestamp of the last best storage block update
+	pub last_tip_update: Option<i64>,
+	/// Number of blocks, inserted to the storage per second
+	pub drain_rate: f64,
+}
+
+/// Information on current difficulty retarget period
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetargetInfo {
+	/// Number of blocks to be mined before next retarget
+	pub blocks_until_retarget: BlockHeight,
+	/// Number of seconds elapsed between first block of the period and the best block
+	pub period_timespan: u32,
+	/// Difficulty bits of the best block
+	pub current_bits: Compact,
+	/// Difficulty bits, expected after next retarget if blocks are mined with current speed
+	pub projected_bits: Compact,
+}
+
+/// Information on unspent outputs of canon storage blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct UtxoSetInfo {
+	/// Best storage block number
+	pub height: BlockHeight,
+	/// Best storage block hash
+	pub best_block_hash: H256,
+	/// Number of unspent outputs
+	pub transaction_outputs: u64,
+	/// Total value of unspent outputs
+	pub total_amount: u64,
+	/// Estimated size of serialized unspent outputs
+	pub serialized_size: u64,
+	/// Digest of unspent outputs set. None when information is maintained incrementally
+	pub hash_serialized: Option<H256>,
+}
+
+/// Aggregated memory pool statistics
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolInfo {
+	/// Number of transactions in the memory pool
+	pub transactions_count: usize,
+	/// Total serialized size of memory pool transactions
+	pub size_in_bytes: usize,
+	/// Total virtual size of memory pool transactions
+	pub virtual_size: usize,
+	/// Total fee of memory pool transactions
+	pub total_fee: u64,
+	/// Maximal total size of memory pool transactions. None if memory pool size is not limited
+	pub max_size_in_bytes: Option<usize>,
+}
+
+/// Verbose block information
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub struct VerboseBlock {
+	/// Block hash
+	pub hash: H256,
+	/// Block header
+	pub header: BlockHeader,
+	/// Serialized block size
+	pub size: usize,
+	/// Block height. None if block is on the side chain
+	pub height: Option<BlockHeight>,
+	/// Number of confirmations. -1 if block is on the side chain
+	pub confirmations: i64,
+	/// Median time of previous blocks
+	pub median_time: u32,
+	/// Hash of next canon block
+	pub next_block_hash: Option<H256>,
+	/// Transactions hashes
+	pub transactions_hashes: Vec<H256>,
+	/// Transactions (only when requested)
+	pub transactions: Option<Vec<Transaction>>,
+}
+
+/// Verbose block header information
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub struct VerboseHeader {
+	/// Block hash
+	pub hash: H256,
+	/// Block header
+	pub header: BlockHeader,
+	/// Block height. None if block is on the side chain
+	pub height: Option<BlockHeight>,
+	/// Number of confirmations. -1 if block is not in the canon storage chain
+	pub confirmations: i64,
+	/// Median time of previous blocks
+	pub median_time: u32,
+	/// Hash of next best chain block (stored or from the headers chain)
+	pub next_block_hash: Option<H256>,
 }
 
 /// Blockchain from synchroniation point of view, consisting of:
@@ -106,14 +573,94 @@ pub struct Chain {
 	storage: StorageRef,
 	/// In-memory queue of blocks hashes
 	hash_chain: HashQueueChain,
+	/// States of blocks from `hash_chain`, by hash
+	blocks_state: HashMap<H256, BlockState>,
 	/// In-memory queue of blocks headers
 	headers_chain: BestHeadersChain,
+	/// Peers, which have been assigned to requested blocks by `request_specific`
+	requested_by_peer: HashMap<H256, PeerIndex>,
+	/// Times, when blocks have been moved to requested queue
+	request_times: HashMap<H256, f64>,
+	/// Average time between block request and block arrival, in seconds
+	download_latency: Option<f64>,
+	/// Requested blocks arrival speed meter
+	download_speed_meter: AverageSpeedMeter,
+	/// Minimal and maximal number of blocks in requested queue
+	download_window_bounds: (BlockHeight, BlockHeight),
 	/// Currently verifying transactions
 	verifying_transactions: LinkedHashMap<H256, IndexedTransaction>,
 	/// Transactions memory pool
 	memory_pool: MemoryPoolRef,
-	/// Blocks that have been marked as dead-ends
+	/// Blocks that have been marked as dead-ends (i.e. failed verification)
 	dead_end_blocks: HashSet<H256>,
+	/// Blocks that have been marked as invalid manually. Unlike dead-ends, could be reconsidered
+	manually_invalid_blocks: HashSet<H256>,
+	/// Stored blocks, activation of which requires too deep reorganization. Not activated until reconsidered manually
+	not_activated_blocks: HashSet<H256>,
+	/// Tips of side chains, inserted to the storage
+	side_chain_tips: HashSet<H256>,
+	/// Times, when stored leaf blocks have been inserted during this session
+	first_seen: HashMap<H256, Instant>,
+	/// Maximal number of blocks headers are accepted ahead of the best storage block
+	max_header_lookahead: BlockHeight,
+	/// Maximal number of blocks, which could be decanonized by automatic reorganization
+	max_reorg_depth: BlockHeight,
+	/// Reorganizations, deeper than this number of blocks, switch chain to safe mode
+	safe_mode_depth: BlockHeight,
+	/// Is chain in safe mode (i.e. no blocks are inserted)
+	safe_mode: bool,
+	/// Blocks, refused while in safe mode (the oldest at the front). Inserted when safe mode is cleared
+	safe_mode_blocks: LinkedHashMap<H256, IndexedBlock>,
+	/// Check timestamps of inserted blocks
+	check_block_timestamps: bool,
+	/// Relay policy, which standard transactions must follow
+	#[cfg(test)]
+	standardness_policy: StandardnessPolicy,
+	/// Network we are working on (read by consensus checks, which are only used in tests yet)
+	#[allow(dead_code)]
+	network: Magic,
+	/// Consensus parameters of the network (read by consensus checks, which are only used in tests yet)
+	#[allow(dead_code)]
+	consensus: ConsensusParams,
+	/// Best block height, advertised by connected peers
+	#[cfg(test)]
+	peer_best_height: BlockHeight,
+	/// Unix timestamp of the last best storage block update (read by state snapshot, which is only used in tests yet)
+	#[allow(dead_code)]
+	last_tip_update: Option<i64>,
+	/// Storage insertion speed meter
+	storage_speed_meter: AverageSpeedMeter,
+	/// Number of transactions in canon storage blocks (lazily rebuilt from storage)
+	total_transactions: Cell<Option<u64>>,
+	/// Recent storage reorganizations (the most recent at the back)
+	recent_reorgs: VecDeque<ReorgEvent>,
+	/// Transactions, waiting to be announced to peers
+	relay_queue: HashQueue,
+	/// Is blocks requesting paused
+	sync_paused: bool,
+	/// Subscribers to best storage block updates
+	tip_subscribers: Vec<Sender<db::BestBlock>>,
+	/// Subscribers to canon chain updates
+	block_subscribers: Vec<Sender<BlockEvent>>,
+	/// Best storage block observer, called when best block number is a multiple of given number
+	milestone_observer: Option<(BlockHeight, Box<Fn(&db::BestBlock) + Send + Sync>)>,
+	/// Maintain unspent outputs set information on every storage update
+	maintain_utxo_set_info: bool,
+	/// Unspent outputs set information (only when maintained)
+	utxo_set_info: RefCell<Option<UtxoSetInfo>>,
+	/// Recently accessed headers of canon storage blocks, by number
+	index_cache: RefCell<LinkedHashMap<BlockHeight, IndexedBlockHeader>>,
+	/// Maximal number of entries in the `index_cache`
+	index_cache_size: usize,
+	/// Headers, which parents are not yet connected, by hash (the oldest at the front)
+	#[cfg(test)]
+	buffered_headers: LinkedHashMap<H256, BlockHeader>,
+	/// Hashes of buffered headers, by parent hash
+	#[cfg(test)]
+	buffered_headers_by_parent: HashMap<H256, Vec<H256>>,
+	/// Maximal number of entries in the `buffered_headers`
+	#[cfg(test)]
+	max_buffered_headers: usize,
 }
 
 impl BlockState {
@@ -150,965 +697,5629 @@ impl Chain {
 			best_storage_block: best_storage_block,
 			storage: storage,
 			hash_chain: HashQueueChain::with_number_of_queues(NUMBER_OF_QUEUES),
+			blocks_state: HashMap::new(),
 			headers_chain: BestHeadersChain::new(best_storage_block_hash),
+			requested_by_peer: HashMap::new(),
+			request_times: HashMap::new(),
+			download_latency: None,
+			download_speed_meter: AverageSpeedMeter::with_inspect_items(DOWNLOAD_SPEED_BLOCKS_TO_INSPECT),
+			download_window_bounds: (DEFAULT_MIN_DOWNLOAD_WINDOW, DEFAULT_MAX_DOWNLOAD_WINDOW),
 			verifying_transactions: LinkedHashMap::new(),
 			memory_pool: memory_pool,
 			dead_end_blocks: HashSet::new(),
+			manually_invalid_blocks: HashSet::new(),
+			not_activated_blocks: HashSet::new(),
+			side_chain_tips: HashSet::new(),
+			first_seen: HashMap::new(),
+			max_header_lookahead: BlockHeight::max_value(),
+			max_reorg_depth: BlockHeight::max_value(),
+			safe_mode_depth: BlockHeight::max_value(),
+			safe_mode: false,
+			safe_mode_blocks: LinkedHashMap::new(),
+			check_block_timestamps: false,
+			#[cfg(test)]
+			standardness_policy: StandardnessPolicy::default(),
+			network: Magic::Mainnet,
+			consensus: ConsensusParams::with_magic(Magic::Mainnet),
+			#[cfg(test)]
+			peer_best_height: 0,
+			last_tip_update: None,
+			storage_speed_meter: AverageSpeedMeter::with_inspect_items(STORAGE_SPEED_BLOCKS_TO_INSPECT),
+			total_transactions: Cell::new(None),
+			recent_reorgs: VecDeque::new(),
+			relay_queue: HashQueue::new(),
+			sync_paused: false,
+			tip_subscribers: Vec::new(),
+			block_subscribers: Vec::new(),
+			milestone_observer: None,
+			maintain_utxo_set_info: false,
+			utxo_set_info: RefCell::new(None),
+			index_cache: RefCell::new(LinkedHashMap::new()),
+			index_cache_size: 0,
+			#[cfg(test)]
+			buffered_headers: LinkedHashMap::new(),
+			#[cfg(test)]
+			buffered_headers_by_parent: HashMap::new(),
+			#[cfg(test)]
+			max_buffered_headers: DEFAULT_MAX_BUFFERED_HEADERS,
 		}
 	}
 
-	/// Get information on current blockchain state
-	pub fn information(&self) -> Information {
-		Information {
-			scheduled: self.hash_chain.len_of(SCHEDULED_QUEUE),
-			requested: self.hash_chain.len_of(REQUESTED_QUEUE),
-			verifying: self.hash_chain.len_of(VERIFYING_QUEUE),
-			stored: self.best_storage_block.number + 1,
-			transactions: self.memory_pool.read().information(),
-			headers: self.headers_chain.information(),
-		}
+	/// Create new `Chain` and restore tips of side chains, which are stored in the storage.
+	/// At most `max_side_chain_tips` tips are restored. Use `Chain::new` to skip this scan
+	#[cfg(test)]
+	pub fn new_with_recovery(storage: StorageRef, memory_pool: MemoryPoolRef, max_side_chain_tips: usize) -> Self {
+		let mut chain = Chain::new(storage, memory_pool);
+		let side_chain_tips: Vec<_> = chain.storage.leaf_blocks()
+			.into_iter()
+			.filter(|hash| chain.storage.block_number(hash).is_none())
+			.take(max_side_chain_tips)
+			.collect();
+		chain.side_chain_tips.extend(side_chain_tips);
+		chain
 	}
 
-	/// Get storage
-	pub fn storage(&self) -> StorageRef {
-		self.storage.clone()
+	/// Create new `Chain` with space for at least `expected_headers` in-memory headers and scheduled blocks hashes.
+	/// Avoids reallocations during large headers-first synchronization
+	#[cfg(test)]
+	pub fn with_capacity_hint(storage: StorageRef, memory_pool: MemoryPoolRef, expected_headers: usize) -> Self {
+		let mut chain = Chain::new(storage, memory_pool);
+		chain.headers_chain = BestHeadersChain::with_capacity(chain.best_storage_block.hash.clone(), expected_headers);
+		chain.hash_chain.reserve_at(SCHEDULED_QUEUE, expected_headers);
+		chain
 	}
 
-	/// Get memory pool
-	pub fn memory_pool(&self) -> MemoryPoolRef {
-		self.memory_pool.clone()
-	}
+	/// Create new `Chain` and restore in-memory headers and blocks queues, saved by `save_state`.
+	/// Only headers, which are descendants of the best storage block, are restored. Requests and verifications
+	/// do not survive restart => all restored blocks are scheduled
+	pub fn restore_state(storage: StorageRef, memory_pool: MemoryPoolRef, path: &Path) -> Result<Self, StateError> {
+		let mut data = Vec::new();
+		File::open(path)?.read_to_end(&mut data)?;
+		let mut reader = Reader::new(&data);
+		let headers: Vec<BlockHeader> = reader.read_list()?;
+		let mut queues: Vec<Vec<H256>> = Vec::with_capacity(NUMBER_OF_QUEUES);
+		for _ in 0..NUMBER_OF_QUEUES {
+			queues.push(reader.read_list()?);
+		}
 
-	/// Get number of blocks in given state
-	pub fn length_of_blocks_state(&self, state: BlockState) -> BlockHeight {
-		match state {
-			BlockState::Stored => self.best_storage_block.number + 1,
-			_ => self.hash_chain.len_of(state.to_queue_index()),
+		let mut chain = Chain::new(storage, memory_pool);
+
+		// blocks, which are already stored, are skipped
+		let best_storage_block_hash = chain.best_storage_block.hash.clone();
+		let first_index = match headers.iter().position(|header| header.previous_header_hash == best_storage_block_hash) {
+			Some(first_index) => first_index,
+			None => headers.len(),
+		};
+		let mut restored_headers: Vec<IndexedBlockHeader> = Vec::new();
+		for header in headers.into_iter().skip(first_index) {
+			let header: IndexedBlockHeader = header.into();
+			let parent_hash = restored_headers.last().map(|parent| parent.hash.clone()).unwrap_or_else(|| best_storage_block_hash.clone());
+			if header.raw.previous_header_hash != parent_hash {
+				break;
+			}
+			restored_headers.push(header);
 		}
+
+		let queued_hashes: HashSet<H256> = queues.into_iter().flat_map(|hashes| hashes.into_iter()).collect();
+		let scheduled_hashes: Vec<H256> = restored_headers.iter()
+			.map(|header| header.hash.clone())
+			.filter(|hash| queued_hashes.contains(hash))
+			.collect();
+		chain.headers_chain.insert_n(restored_headers);
+		chain.hash_chain.push_back_n_at(SCHEDULED_QUEUE, scheduled_hashes.clone());
+		chain.update_blocks_state(&scheduled_hashes);
+		Ok(chain)
 	}
 
-	/// Get n best blocks of given state
-	pub fn best_n_of_blocks_state(&self, state: BlockState, n: BlockHeight) -> Vec<H256> {
-		match state {
-			BlockState::Scheduled | BlockState::Requested | BlockState::Verifying => self.hash_chain.front_n_at(state.to_queue_index(), n),
-			_ => unreachable!("must be checked by caller"),
+	/// Save in-memory headers and blocks queues, so that they could be restored after restart by `restore_state`
+	pub fn save_state(&self, path: &Path) -> Result<(), StateError> {
+		let headers: Vec<BlockHeader> = (0..self.headers_chain.information().best)
+			.filter_map(|index| self.headers_chain.at(index))
+			.map(|header| header.raw)
+			.collect();
+		let mut stream = Stream::new();
+		stream.append_list(&headers);
+		for queue_index in 0..NUMBER_OF_QUEUES {
+			stream.append_list(&self.hash_chain.front_n_at(queue_index, self.hash_chain.len_of(queue_index)));
 		}
+
+		File::create(path)?.write_all(&stream.out())?;
+		Ok(())
 	}
 
-	/// Get best block
-	pub fn best_block(&self) -> db::BestBlock {
-		match self.hash_chain.back() {
-			Some(hash) => db::BestBlock {
-				number: self.best_storage_block.number + self.hash_chain.len(),
-				hash: hash.clone(),
+	/// Set maximal number of blocks headers are accepted ahead of the best storage block
+	#[cfg(test)]
+	pub fn set_max_header_lookahead(&mut self, blocks: BlockHeight) {
+		self.max_header_lookahead = blocks;
+	}
+
+	/// Set minimal and maximal number of blocks in requested queue, used by `current_download_window`
+	#[cfg(test)]
+	pub fn set_download_window_bounds(&mut self, min: BlockHeight, max: BlockHeight) {
+		assert!(min <= max, "minimal download window must not exceed maximal download window");
+		self.download_window_bounds = (min, max);
+	}
+
+	/// Get reason, why the best storage block is preferred over tips of side chains
+	#[cfg(test)]
+	pub fn tip_selection_reason(&self) -> TipSelectionReason {
+		let best_block = &self.best_storage_block;
+		match self.best_side_chain(best_block.number.saturating_sub(1), BlockHeight::max_value()) {
+			Some(ref origin) if origin.block_number == best_block.number => {
+				let tip = origin.canonized_route.last().expect("side chain block_number is above ancestor; qed");
+				self.compare_equal_tips(&best_block.hash, tip).1
 			},
-			None => self.best_storage_block.clone(),
+			_ => TipSelectionReason::MostWork,
 		}
 	}
 
-	/// Get best storage block
-	pub fn best_storage_block(&self) -> db::BestBlock {
-		self.best_storage_block.clone()
+	/// Set maximal number of blocks, which could be decanonized by automatic reorganization
+	#[cfg(test)]
+	pub fn set_max_reorg_depth(&mut self, depth: BlockHeight) {
+		self.max_reorg_depth = depth;
 	}
 
-	/// Get best block header
-	pub fn best_block_header(&self) -> db::BestBlock {
-		let headers_chain_information = self.headers_chain.information();
-		if headers_chain_information.best == 0 {
-			return self.best_storage_block()
-		}
-		db::BestBlock {
-			number: self.best_storage_block.number + headers_chain_information.best,
-			hash: self.headers_chain.at(headers_chain_information.best - 1)
-				.expect("got this index above; qed")
-				.hash,
-		}
+	/// Set maximal depth of reorganization, which is performed without entering safe mode.
+	/// In safe mode, `insert_best_block` refuses to insert blocks until `clear_safe_mode` is called
+	#[cfg(test)]
+	pub fn set_safe_mode_depth(&mut self, depth: BlockHeight) {
+		self.safe_mode_depth = depth;
 	}
 
-	/// Get block header by hash
-	pub fn block_hash(&self, number: BlockHeight) -> Option<H256> {
-		if number <= self.best_storage_block.number {
-			self.storage.block_hash(number)
-		} else {
-			// we try to keep these in order, but they are probably not
-			self.hash_chain.at(number - self.best_storage_block.number)
-		}
+	/// Is chain in safe mode after too deep reorganization
+	#[cfg(test)]
+	pub fn is_safe_mode(&self) -> bool {
+		self.safe_mode
 	}
 
-	/// Get block number by hash
-	pub fn block_number(&self, hash: &H256) -> Option<BlockHeight> {
-		if let Some(number) = self.storage.block_number(hash) {
-			return Some(number);
+	/// Leave safe mode and insert blocks, which have been refused while in safe mode.
+	/// Returns insertion results in the order blocks have been refused
+	#[cfg(test)]
+	pub fn clear_safe_mode(&mut self) -> Vec<(H256, Result<BlockInsertionResult, ReorgError>)> {
+		self.safe_mode = false;
+
+		// if retried block switches chain to safe mode again, the rest of blocks stays refused
+		let mut results = Vec::with_capacity(self.safe_mode_blocks.len());
+		while !self.safe_mode {
+			let (hash, block) = match self.safe_mode_blocks.pop_front() {
+				Some(entry) => entry,
+				None => break,
+			};
+			let result = self.insert_best_block(block);
+			results.push((hash, result));
 		}
-		self.headers_chain.height(hash).map(|p| self.best_storage_block.number + p + 1)
+		results
 	}
 
-	/// Get block header by number
-	pub fn block_header_by_number(&self, number: BlockHeight) -> Option<IndexedBlockHeader> {
-		if number <= self.best_storage_block.number {
-			self.storage.block_header(db::BlockRef::Number(number)).map(Into::into)
-		} else {
-			self.headers_chain.at(number - self.best_storage_block.number)
+	/// Enable or disable timestamps check of inserted blocks.
+	/// Disabled by default, because blocks are verified before insertion
+	pub fn set_check_block_timestamps(&mut self, check: bool) {
+		self.check_block_timestamps = check;
+	}
+
+	/// Set relay policy, which standard transactions must follow. Default policy is used by default
+	#[cfg(test)]
+	pub fn set_standardness_policy(&mut self, policy: StandardnessPolicy) {
+		self.standardness_policy = policy;
+	}
+
+	/// Set network we are working on. Mainnet is used by default
+	pub fn set_network(&mut self, network: Magic) {
+		self.network = network;
+		self.consensus = ConsensusParams::with_magic(network);
+	}
+
+	/// Set maximal number of cached canon storage blocks headers, used by number lookups.
+	/// Cache is disabled by default
+	#[cfg(test)]
+	pub fn set_index_cache_size(&mut self, entries: usize) {
+		self.index_cache_size = entries;
+		let mut index_cache = self.index_cache.borrow_mut();
+		while index_cache.len() > entries {
+			index_cache.pop_front();
 		}
 	}
 
-	/// Get block header by hash
-	pub fn block_header_by_hash(&self, hash: &H256) -> Option<IndexedBlockHeader> {
-		if let Some(block) = self.storage.block(db::BlockRef::Hash(hash.clone())) {
-			return Some(block.block_header.into());
+	/// Set maximal number of buffered headers, which parents are not yet connected.
+	/// The oldest headers are dropped when the buffer is full
+	#[cfg(test)]
+	pub fn set_max_buffered_headers(&mut self, max: usize) {
+		self.max_buffered_headers = max;
+		while self.buffered_headers.len() > max {
+			self.pop_oldest_buffered_header();
 		}
-		self.headers_chain.by_hash(hash)
 	}
 
-	/// Get block state
-	pub fn block_state(&self, hash: &H256) -> BlockState {
-		match self.hash_chain.contains_in(hash) {
-			Some(queue_index) => BlockState::from_queue_index(queue_index),
-			None => if self.storage.contains_block(db::BlockRef::Hash(hash.clone())) {
-				BlockState::Stored
-			} else if self.dead_end_blocks.contains(hash) {
-				BlockState::DeadEnd
-			} else {
-				BlockState::Unknown
-			},
+	/// Enable or disable incremental maintenance of unspent outputs set information.
+	/// Disabled by default, so every `utxo_set_info` call scans all canon blocks
+	#[cfg(test)]
+	pub fn set_maintain_utxo_set_info(&mut self, maintain: bool) {
+		self.maintain_utxo_set_info = maintain;
+		if !maintain {
+			*self.utxo_set_info.borrow_mut() = None;
 		}
 	}
 
-	/// Prepare block locator hashes, as described in protocol documentation:
-	/// https://en.bitcoin.it/wiki/Protocol_documentation#getblocks
-	/// When there are forked blocks in the queue, this method can result in
-	/// mixed block locator hashes ([0 - from fork1, 1 - from fork2, 2 - from fork1]).
-	/// Peer will respond with blocks of fork1 || fork2 => we could end up in some side fork
-	/// To resolve this, after switching to saturated state, we will also ask all peers for inventory.
-	pub fn block_locator_hashes(&self) -> Vec<H256> {
-		let mut block_locator_hashes: Vec<H256> = Vec::new();
+	/// Pause or resume requesting of scheduled blocks.
+	/// Already requested blocks are still verified and inserted while paused
+	#[cfg(test)]
+	pub fn set_sync_paused(&mut self, paused: bool) {
+		self.sync_paused = paused;
+	}
 
-		// calculate for hash_queue
-		let (local_index, step) = self.block_locator_hashes_for_queue(&mut block_locator_hashes);
+	/// Is requesting of scheduled blocks paused
+	#[cfg(test)]
+	pub fn is_sync_paused(&self) -> bool {
+		self.sync_paused
+	}
 
-		// calculate for storage
-		let storage_index = if self.best_storage_block.number < local_index { 0 } else { self.best_storage_block.number - local_index };
-		self.block_locator_hashes_for_storage(storage_index, step, &mut block_locator_hashes);
-		block_locator_hashes
+	/// Subscribe to best storage block updates. New best block is sent to the receiver on each update
+	#[cfg(test)]
+	pub fn subscribe_tip_updates(&mut self) -> Receiver<db::BestBlock> {
+		let (sender, receiver) = channel();
+		self.tip_subscribers.push(sender);
+		receiver
 	}
 
-	/// Schedule blocks hashes for requesting
-	pub fn schedule_blocks_headers(&mut self, headers: Vec<IndexedBlockHeader>) {
-		self.hash_chain.push_back_n_at(SCHEDULED_QUEUE, headers.iter().map(|h| h.hash.clone()).collect());
-		self.headers_chain.insert_n(headers);
+	/// Set observer, which is called when best storage block number becomes a multiple of `every`.
+	/// Unlike `subscribe_tip_updates`, most of updates are filtered out, which is cheaper during fast sync
+	#[cfg(test)]
+	pub fn set_milestone_observer(&mut self, every: BlockHeight, observer: Box<Fn(&db::BestBlock) + Send + Sync>) {
+		assert!(every != 0, "milestone interval must be non-zero");
+		self.milestone_observer = Some((every, observer));
 	}
 
-	/// Moves n blocks from scheduled queue to requested queue
-	pub fn request_blocks_hashes(&mut self, n: BlockHeight) -> Vec<H256> {
-		let scheduled = self.hash_chain.pop_front_n_at(SCHEDULED_QUEUE, n);
-		self.hash_chain.push_back_n_at(REQUESTED_QUEUE, scheduled.clone());
-		scheduled
+	/// Subscribe to canon chain updates. On reorganization, all disconnect events are sent before connect events
+	#[cfg(test)]
+	pub fn subscribe_connected_blocks(&mut self) -> Receiver<BlockEvent> {
+		let (sender, receiver) = channel();
+		self.block_subscribers.push(sender);
+		receiver
 	}
 
-	/// Add block to verifying queue
-	pub fn verify_block(&mut self, header: IndexedBlockHeader) {
-		// insert header to the in-memory chain in case when it is not already there (non-headers-first sync)
-		self.hash_chain.push_back_at(VERIFYING_QUEUE, header.hash.clone());
-		self.headers_chain.insert(header);
-	}
-
-	/// Add blocks to verifying queue
-	pub fn verify_blocks(&mut self, blocks: Vec<IndexedBlockHeader>) {
-		for block in blocks {
-			self.verify_block(block);
+	/// Check that storage genesis block is the expected one (i.e. storage belongs to the configured network)
+	#[cfg(test)]
+	pub fn check_genesis(&self, expected: &H256) -> Result<(), GenesisMismatch> {
+		if &self.genesis_block_hash == expected {
+			Ok(())
+		} else {
+			Err(GenesisMismatch {
+				expected: expected.clone(),
+				actual: self.genesis_block_hash.clone(),
+			})
 		}
 	}
 
-	/// Moves n blocks from requested queue to verifying queue
+	/// Get blockchain state information, which backs `getblockchaininfo` RPC
 	#[cfg(test)]
-	pub fn verify_blocks_hashes(&mut self, n: BlockHeight) -> Vec<H256> {
-		let requested = self.hash_chain.pop_front_n_at(REQUESTED_QUEUE, n);
-		self.hash_chain.push_back_n_at(VERIFYING_QUEUE, requested.clone());
-		requested
+	pub fn blockchain_info(&self) -> BlockchainInfo {
+		let best_block = &self.best_storage_block;
+		let best_header = self.storage.block_header(db::BlockRef::Hash(best_block.hash.clone()))
+			.expect("best storage block is stored; qed");
+		let headers = self.best_block_header().number;
+		let target = cmp::max(headers, self.peer_best_height);
+		let verification_progress = if target == 0 {
+			1_f64
+		} else {
+			best_block.number as f64 / target as f64
+		};
+
+		BlockchainInfo {
+			best_block_hash: best_block.hash.clone(),
+			blocks: best_block.number,
+			headers: headers,
+			difficulty: best_header.bits.to_f64(),
+			median_time: median_timestamp(&best_header, self.storage.as_block_header_provider()),
+			verification_progress: verification_progress,
+		}
 	}
 
-	/// Mark this block as dead end, so these tasks won't be synchronized
-	pub fn mark_dead_end_block(&mut self, hash: &H256) {
-		self.dead_end_blocks.insert(hash.clone());
+	/// Get information on current blockchain state
+	pub fn information(&self) -> Information {
+		Information {
+			scheduled: self.hash_chain.len_of(SCHEDULED_QUEUE),
+			requested: self.hash_chain.len_of(REQUESTED_QUEUE),
+			verifying: self.hash_chain.len_of(VERIFYING_QUEUE),
+			stored: self.best_storage_block.number + 1,
+			transactions: self.memory_pool.read().information(),
+			headers: self.headers_chain.information(),
+			orphaned: self.headers_chain.orphaned(),
+		}
 	}
 
-	/// Insert new best block to storage
-	pub fn insert_best_block(&mut self, block: IndexedBlock) -> Result<BlockInsertionResult, db::Error> {
-		assert_eq!(Some(self.storage.best_block().hash), self.storage.block_hash(self.storage.best_block().number));
-		let block_origin = self.storage.block_origin(&block.header)?;
-		trace!(target: "sync", "insert_best_block {:?} origin: {:?}", block.hash().reversed(), block_origin);
-		match block_origin {
-			db::BlockOrigin::KnownBlock => {
-				// there should be no known blocks at this point
-				unreachable!();
-			},
-			// case 1: block has been added to the main branch
-			db::BlockOrigin::CanonChain { .. } => {
-				self.storage.insert(block.clone())?;
-				self.storage.canonize(block.hash())?;
-
-				// remember new best block hash
-				self.best_storage_block = self.storage.best_block();
-
-				// remove inserted block + handle possible reorganization in headers chain
-				// TODO: mk, not sure if we need both of those params
-				self.headers_chain.block_inserted_to_storage(block.hash(), &self.best_storage_block.hash);
+	/// Get chain state snapshot.
+	/// Memory pool is read once, so that the snapshot is consistent
+	#[cfg(test)]
+	pub fn state_snapshot(&self) -> ChainSnapshot {
+		let memory_pool_information = self.memory_pool.read().information();
+		let headers_information = self.headers_chain.information();
+		ChainSnapshot {
+			scheduled: self.hash_chain.len_of(SCHEDULED_QUEUE),
+			requested: self.hash_chain.len_of(REQUESTED_QUEUE),
+			verifying: self.hash_chain.len_of(VERIFYING_QUEUE),
+			stored: self.best_storage_block.number + 1,
+			headers_best: headers_information.best,
+			headers_total: headers_information.total,
+			best_storage_block_hash: self.best_storage_block.hash.to_reversed_str(),
+			fork_tips: self.side_chain_tips.iter().map(H256::to_reversed_str).collect(),
+			memory_pool_transactions: memory_pool_information.transactions_count,
+			memory_pool_size: memory_pool_information.transactions_size_in_bytes,
+			last_tip_update: self.last_tip_update,
+			drain_rate: self.storage_speed_meter.speed(),
+		}
+	}
 
-				// double check
-				assert_eq!(self.best_storage_block.hash, block.hash().clone());
+	/// Get Graphviz DOT representation of the best storage block, in-memory headers chain and side chains.
+	/// Nodes are colored by block state, the best known block is drawn with double border
+	#[cfg(test)]
+	pub fn to_dot(&self) -> String {
+		use std::fmt::Write;
+
+		let best_block_hash = self.best_block_header().hash;
+		let mut nodes: Vec<(H256, BlockHeight)> = Vec::new();
+		let mut edges: Vec<(H256, H256)> = Vec::new();
+
+		nodes.push((self.best_storage_block.hash.clone(), self.best_storage_block.number));
+		let mut parent_hash = self.best_storage_block.hash.clone();
+		for index in 0..self.headers_chain.information().best {
+			let header = self.headers_chain.at(index).expect("index is less than headers chain length; qed");
+			nodes.push((header.hash.clone(), self.best_storage_block.number + index + 1));
+			edges.push((parent_hash, header.hash.clone()));
+			parent_hash = header.hash;
+		}
 
-				// all transactions from this block were accepted
-				// => delete accepted transactions from verification queue and from the memory pool
-				// + also remove transactions which spent outputs which have been spent by transactions from the block
-				let mut memory_pool = self.memory_pool.write();
-				for tx in &block.transactions {
-					memory_pool.remove_by_hash(&tx.hash);
-					self.verifying_transactions.remove(&tx.hash);
-					for tx_input in &tx.raw.inputs {
-						memory_pool.remove_by_prevout(&tx_input.previous_output);
-					}
+		for tip in &self.side_chain_tips {
+			// walk back to the canon ancestor
+			let mut route = vec![tip.clone()];
+			let ancestor = loop {
+				let header = match self.storage.block_header(route[route.len() - 1].clone().into()) {
+					Some(header) => header,
+					None => break None,
+				};
+				if let Some(number) = self.storage.block_number(&header.previous_header_hash) {
+					break Some((header.previous_header_hash, number));
 				}
-				// no transactions to reverify, because we have just appended new transactions to the blockchain
-
-				Ok(BlockInsertionResult {
-					canonized_blocks_hashes: vec![block.hash().clone()],
-					transactions_to_reverify: Vec::new(),
-				})
-			},
-			// case 2: block has been added to the side branch with reorganization to this branch
-			db::BlockOrigin::SideChainBecomingCanonChain(origin) => {
-				let fork = self.storage.fork(origin.clone())?;
-				fork.store().insert(block.clone())?;
-				fork.store().canonize(block.hash())?;
-				self.storage.switch_to_fork(fork)?;
-
-				// remember new best block hash
-				self.best_storage_block = self.storage.best_block();
+				route.push(header.previous_header_hash);
+			};
+
+			let (mut parent_hash, ancestor_number) = match ancestor {
+				Some(ancestor) => ancestor,
+				None => continue,
+			};
+			nodes.push((parent_hash.clone(), ancestor_number));
+			for (index, hash) in route.into_iter().rev().enumerate() {
+				nodes.push((hash.clone(), ancestor_number + index as BlockHeight + 1));
+				edges.push((parent_hash, hash.clone()));
+				parent_hash = hash;
+			}
+		}
 
-				// remove inserted block + handle possible reorganization in headers chain
-				// TODO: mk, not sure if we need both of those params
-				self.headers_chain.block_inserted_to_storage(block.hash(), &self.best_storage_block.hash);
+		let mut dot = String::from("digraph chain {\n");
+		let mut written_nodes = HashSet::new();
+		for (hash, number) in nodes {
+			if !written_nodes.insert(hash.clone()) {
+				continue;
+			}
 
-				// all transactions from this block were accepted
-				// + all transactions from previous blocks of this fork were accepted
-				// => delete accepted transactions from verification queue and from the memory pool
-				let this_block_transactions_hashes = block.transactions.iter().map(|tx| tx.hash.clone()).collect::<Vec<_>>();
-				let mut canonized_blocks_hashes = origin.canonized_route.clone();
-				let new_main_blocks_transactions_hashes = origin.canonized_route.into_iter()
-					.flat_map(|block_hash| self.storage.block_transaction_hashes(block_hash.into()))
-					.collect::<Vec<_>>();
+			let color = match self.block_state(&hash) {
+				BlockState::Unknown => "white",
+				BlockState::Scheduled => "lightblue",
+				BlockState::Requested => "yellow",
+				BlockState::Verifying => "orange",
+				BlockState::Stored => if self.storage.block_number(&hash).is_some() { "gray" } else { "lightgray" },
+				BlockState::DeadEnd => "red",
+			};
+			let peripheries = if hash == best_block_hash { 2 } else { 1 };
+			let hash = hash.to_reversed_str();
+			writeln!(dot, "\t\"{}\" [label=\"{}\\n{}\", style=filled, fillcolor={}, peripheries={}];", hash, number, &hash[..16], color, peripheries)
+				.expect("writing to String never fails; qed");
+		}
+		for (parent_hash, hash) in edges {
+			writeln!(dot, "\t\"{}\" -> \"{}\";", parent_hash.to_reversed_str(), hash.to_reversed_str())
+				.expect("writing to String never fails; qed");
+		}
+		dot.push_str("}\n");
+		dot
+	}
 
-				let mut memory_pool = self.memory_pool.write();
-				for transaction_accepted in this_block_transactions_hashes.into_iter().chain(new_main_blocks_transactions_hashes.into_iter()) {
-					memory_pool.remove_by_hash(&transaction_accepted);
-					self.verifying_transactions.remove(&transaction_accepted);
-				}
+	/// Check if any two transactions of the block share the same hash
+	#[cfg(test)]
+	pub fn block_has_duplicate_txids(block: &Block) -> bool {
+		let mut hashes = HashSet::with_capacity(block.transactions.len());
+		block.transactions.iter().any(|tx| !hashes.insert(tx.hash()))
+	}
 
-				// reverify all transactions from old main branch' blocks
-				let old_main_blocks_transactions = origin.decanonized_route.into_iter()
-					.flat_map(|block_hash| self.storage.indexed_block_transactions(block_hash.into()))
-					.collect::<Vec<_>>();
+	/// Get transaction virtual size: weight / 4, rounded up.
+	/// Weight is computed as base size * 3 + total size
+	#[cfg(test)]
+	pub fn transaction_virtual_size(tx: &Transaction) -> usize {
+		// there are no witnesses => base size is equal to total size
+		let base_size = tx.serialized_size();
+		let total_size = base_size;
+		let weight = base_size * (WITNESS_SCALE_FACTOR - 1) + total_size;
+		(weight + WITNESS_SCALE_FACTOR - 1) / WITNESS_SCALE_FACTOR
+	}
 
-				trace!(target: "sync", "insert_best_block, old_main_blocks_transactions: {:?}",
-					   old_main_blocks_transactions.iter().map(|tx| tx.hash.reversed()).collect::<Vec<H256>>());
+	/// Check that block weight and signature operations cost are within given limits.
+	/// Weight is computed as base size * 3 + total size, sigops cost is legacy sigops count * 4
+	#[cfg(test)]
+	pub fn block_within_limits(block: &Block, max_weight: usize, max_sigops: usize) -> Result<(), BlockLimitError> {
+		// there are no witnesses => base size is equal to total size
+		let size = block.serialized_size();
+		let weight = size * (WITNESS_SCALE_FACTOR - 1) + size;
+		if weight > max_weight {
+			return Err(BlockLimitError::Weight {
+				weight: weight,
+				max_weight: max_weight,
+			});
+		}
 
-				// reverify memory pool transactions, sorted by timestamp
-				let memory_pool_transactions_count = memory_pool.information().transactions_count;
-				let memory_pool_transactions: Vec<IndexedTransaction> = memory_pool
-					.remove_n_with_strategy(memory_pool_transactions_count, MemoryPoolOrderingStrategy::ByTimestamp)
-					.into_iter()
-					.map(|t| t.into())
-					.collect();
+		let sigops: usize = block.transactions.iter()
+			.map(|tx| {
+				let inputs_sigops: usize = tx.inputs.iter()
+					.map(|input| Script::new(input.script_sig.clone()).sigops_count(false))
+					.sum();
+				let outputs_sigops: usize = tx.outputs.iter()
+					.map(|output| Script::new(output.script_pubkey.clone()).sigops_count(false))
+					.sum();
+				inputs_sigops + outputs_sigops
+			})
+			.sum();
+		let sigops = sigops * WITNESS_SCALE_FACTOR;
+		if sigops > max_sigops {
+			return Err(BlockLimitError::Sigops {
+				sigops: sigops,
+				max_sigops: max_sigops,
+			});
+		}
 
-				// reverify verifying transactions
-				let verifying_transactions: Vec<IndexedTransaction> = self.verifying_transactions
-					.iter()
-					.map(|(_, t)| t.clone())
-					.collect();
-				self.verifying_transactions.clear();
+		Ok(())
+	}
 
-				canonized_blocks_hashes.push(block.hash().clone());
+	/// Check if transaction is final at given height and time (typically the height of the next block and median time past).
+	/// Transaction, all inputs of which have final sequence numbers, is always final
+	#[cfg(test)]
+	pub fn is_final_transaction(&self, tx: &Transaction, at_height: BlockHeight, at_time: u32) -> bool {
+		tx.is_final_in_block(at_height, at_time)
+	}
 
-				let result = BlockInsertionResult {
-					canonized_blocks_hashes: canonized_blocks_hashes,
-					// order matters: db transactions, then ordered mempool transactions, then ordered verifying transactions
-					transactions_to_reverify: old_main_blocks_transactions.into_iter()
-						.chain(memory_pool_transactions.into_iter())
-						.chain(verifying_transactions.into_iter())
-						.collect(),
-				};
+	/// Check if BIP68 relative lock-times of all transaction inputs are satisfied at given height
+	/// and median time past (typically the height of the next block and median time past of the best block).
+	/// Outputs of memory pool transactions are considered to be included in the block at `at_height`
+	#[cfg(test)]
+	pub fn sequence_locks_satisfied(&self, tx: &Transaction, at_height: BlockHeight, mtp: u32) -> bool {
+		if tx.version < 2 || tx.is_coinbase() {
+			return true;
+		}
 
-				trace!(target: "sync", "result: {:?}", result);
+		let mut input_heights = Vec::with_capacity(tx.inputs.len());
+		let mut input_times = Vec::with_capacity(tx.inputs.len());
+		for input in &tx.inputs {
+			// disabled locks are not checked => coin is not required
+			if input.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+				input_heights.push(0);
+				input_times.push(0);
+				continue;
+			}
 
-				Ok(result)
-			},
-			// case 3: block has been added to the side branch without reorganization to this branch
-			db::BlockOrigin::SideChain(_origin) => {
-				let block_hash = block.hash().clone();
-				self.storage.insert(block)?;
+			let coin_height = match self.get_utxo(&input.previous_output, true) {
+				Some(utxo) => utxo.height,
+				None => return false,
+			};
 
-				// remove inserted block + handle possible reorganization in headers chain
-				// TODO: mk, not sure if it's needed here at all
-				self.headers_chain.block_inserted_to_storage(&block_hash, &self.best_storage_block.hash);
+			// memory pool coins are considered to be included in the block at `at_height`
+			if coin_height == MEMORY_POOL_HEIGHT {
+				input_heights.push(at_height);
+				input_times.push(mtp);
+				continue;
+			}
 
-				// no transactions were accepted
-				// no transactions to reverify
-				Ok(BlockInsertionResult::default())
-			},
+			// time is measured from the median time past of the block, preceding the coin block
+			let coin_time = match self.storage.block_header(coin_height.into()) {
+				Some(coin_header) => median_timestamp(&coin_header, self.storage.as_block_header_provider()),
+				None => return false,
+			};
+			input_heights.push(coin_height);
+			input_times.push(coin_time);
 		}
-	}
 
-	/// Forget in-memory block
-	pub fn forget_block(&mut self, hash: &H256) -> HashPosition {
-		self.headers_chain.remove(hash);
-		self.forget_block_leave_header(hash)
+		verify_relative_locktime(tx, &input_heights, &input_times, at_height.saturating_sub(1), mtp).is_ok()
 	}
 
-	/// Forget in-memory blocks
-	pub fn forget_blocks(&mut self, hashes: &[H256]) {
-		for hash in hashes {
-			self.forget_block(hash);
+	/// Check that coinbase script of the block at given height starts with the serialized height (BIP34).
+	/// Always true for blocks before BIP34 activation
+	#[cfg(test)]
+	pub fn coinbase_height_matches(&self, block: &Block, height: BlockHeight) -> bool {
+		if height < self.consensus.bip34_height {
+			return true;
 		}
+
+		let prefix = ScriptBuilder::default()
+			.push_num(height.into())
+			.into_script();
+
+		block.transactions.first()
+			.and_then(|tx| tx.inputs.first())
+			.map(|input| input.script_sig.starts_with(&prefix))
+			.unwrap_or(false)
 	}
 
-	/// Forget in-memory block, but leave its header in the headers_chain (orphan queue)
-	pub fn forget_block_leave_header(&mut self, hash: &H256) -> HashPosition {
-		match self.hash_chain.remove_at(VERIFYING_QUEUE, hash) {
-			HashPosition::Missing => match self.hash_chain.remove_at(REQUESTED_QUEUE, hash) {
-				HashPosition::Missing => self.hash_chain.remove_at(SCHEDULED_QUEUE, hash),
-				position => position,
-			},
-			position => position,
+	/// Get number of transactions in all canon storage blocks.
+	/// Counter is rebuilt from storage on first call and then maintained on every storage update
+	#[cfg(test)]
+	pub fn total_transaction_count(&self) -> Result<u64, db::Error> {
+		if let Some(total_transactions) = self.total_transactions.get() {
+			return Ok(total_transactions);
 		}
-	}
 
-	/// Forget in-memory blocks, but leave their headers in the headers_chain (orphan queue)
-	pub fn forget_blocks_leave_header(&mut self, hashes: &[H256]) {
-		for hash in hashes {
-			self.forget_block_leave_header(hash);
+		let mut total_transactions = 0u64;
+		for number in 0..self.best_storage_block.number + 1 {
+			let hash = self.storage.block_hash(number)
+				.ok_or_else(|| db::Error::DatabaseError(format!("canon block #{} is missing", number)))?;
+			total_transactions += self.storage.block_transaction_hashes(hash.into()).len() as u64;
 		}
+		self.total_transactions.set(Some(total_transactions));
+		Ok(total_transactions)
 	}
 
-	/// Forget in-memory block by hash if it is currently in given state
+	/// Read canon storage blocks from `from_height` to `to_height` (inclusive) and pass them to the visitor.
+	/// Heights beyond the best storage block are ignored
 	#[cfg(test)]
-	pub fn forget_block_with_state(&mut self, hash: &H256, state: BlockState) -> HashPosition {
-		self.headers_chain.remove(hash);
-		self.forget_block_with_state_leave_header(hash, state)
-	}
+	pub fn rescan_blocks<F>(&self, from_height: BlockHeight, to_height: BlockHeight, mut visitor: F) -> Result<(), db::Error>
+		where F: FnMut(BlockHeight, &Block) {
+		let to_height = cmp::min(to_height, self.best_storage_block.number);
+		if from_height > to_height {
+			return Ok(());
+		}
 
-	/// Forget in-memory block by hash if it is currently in given state
-	pub fn forget_block_with_state_leave_header(&mut self, hash: &H256, state: BlockState) -> HashPosition {
-		self.hash_chain.remove_at(state.to_queue_index(), hash)
+		for number in from_height..to_height + 1 {
+			let block = self.storage.block(db::BlockRef::Number(number))
+				.ok_or_else(|| db::Error::DatabaseError(format!("canon block #{} is missing", number)))?;
+			visitor(number, &block);
+		}
+		Ok(())
 	}
 
-	/// Forget in-memory block by hash.
-	/// Also forget all its known children.
-	pub fn forget_block_with_children(&mut self, hash: &H256) {
-		let mut removal_stack: VecDeque<H256> = VecDeque::new();
-		let mut removal_queue: VecDeque<H256> = VecDeque::new();
-		removal_queue.push_back(hash.clone());
+	/// Get information on unspent outputs of canon storage blocks.
+	/// This scans all canon blocks, which is slow. When incremental maintenance is enabled, the scan
+	/// only happens on the first call, but information is then updated without `hash_serialized`
+	pub fn utxo_set_info(&self) -> Result<UtxoSetInfo, db::Error> {
+		if let Some(ref utxo_set_info) = *self.utxo_set_info.borrow() {
+			return Ok(utxo_set_info.clone());
+		}
 
-		// remove in reverse order to minimize headers operations
-		while let Some(hash) = removal_queue.pop_front() {
-			removal_queue.extend(self.headers_chain.children(&hash));
-			removal_stack.push_back(hash);
+		let mut utxo_set_info = UtxoSetInfo {
+			height: self.best_storage_block.number,
+			best_block_hash: self.best_storage_block.hash.clone(),
+			transaction_outputs: 0,
+			total_amount: 0,
+			serialized_size: 0,
+			hash_serialized: None,
+		};
+		// every unspent output is sequentially folded into the digest: digest = dhash256(digest + outpoint + height + coinbase + output)
+		let mut digest = H256::default();
+		for number in 0..self.best_storage_block.number + 1 {
+			let hash = self.storage.block_hash(number)
+				.ok_or_else(|| db::Error::DatabaseError(format!("canon block #{} is missing", number)))?;
+			for transaction in self.storage.block_transactions(hash.into()) {
+				let transaction_hash = transaction.hash();
+				let meta = self.storage.transaction_meta(&transaction_hash)
+					.ok_or_else(|| db::Error::DatabaseError(format!("meta of canon transaction {} is missing", transaction_hash.to_reversed_str())))?;
+				for (index, output) in transaction.outputs.iter().enumerate() {
+					if meta.is_spent(index) != Some(false) {
+						continue;
+					}
+
+					utxo_set_info.transaction_outputs += 1;
+					utxo_set_info.total_amount += output.value;
+					utxo_set_info.serialized_size += Chain::utxo_entry_size(output);
+
+					let outpoint = OutPoint {
+						hash: transaction_hash.clone(),
+						index: index as u32,
+					};
+					let mut stream = Stream::new();
+					stream.append(&digest)
+						.append(&outpoint)
+						.append(&meta.height())
+						.append(&meta.is_coinbase())
+						.append(output);
+					digest = dhash256(&stream.out());
+				}
+			}
 		}
-		while let Some(hash) = removal_stack.pop_back() {
-			self.forget_block(&hash);
+		utxo_set_info.hash_serialized = Some(digest);
+
+		if self.maintain_utxo_set_info {
+			*self.utxo_set_info.borrow_mut() = Some(utxo_set_info.clone());
 		}
+		Ok(utxo_set_info)
 	}
 
-	/// Forget all blocks with given state
-	pub fn forget_all_blocks_with_state(&mut self, state: BlockState) {
-		let hashes = self.hash_chain.remove_all_at(state.to_queue_index());
-		self.headers_chain.remove_n(hashes);
+	/// Get storage
+	pub fn storage(&self) -> StorageRef {
+		self.storage.clone()
 	}
 
-	/// Get transaction state
-	pub fn transaction_state(&self, hash: &H256) -> TransactionState {
-		if self.verifying_transactions.contains_key(hash) {
-			return TransactionState::Verifying;
+	/// Get memory pool
+	pub fn memory_pool(&self) -> MemoryPoolRef {
+		self.memory_pool.clone()
+	}
+
+	/// Get number of blocks in given state
+	pub fn length_of_blocks_state(&self, state: BlockState) -> BlockHeight {
+		match state {
+			BlockState::Stored => self.best_storage_block.number + 1,
+			_ => self.hash_chain.len_of(state.to_queue_index()),
 		}
-		if self.storage.contains_transaction(hash) {
-			return TransactionState::Stored;
+	}
+
+	/// Get n best blocks of given state
+	pub fn best_n_of_blocks_state(&self, state: BlockState, n: BlockHeight) -> Vec<H256> {
+		match state {
+			BlockState::Scheduled | BlockState::Requested | BlockState::Verifying => self.hash_chain.front_n_at(state.to_queue_index(), n),
+			_ => unreachable!("must be checked by caller"),
 		}
-		if self.memory_pool.read().contains(hash) {
-			return TransactionState::InMemory;
+	}
+
+	/// Get best block
+	pub fn best_block(&self) -> db::BestBlock {
+		match self.hash_chain.back() {
+			Some(hash) => db::BestBlock {
+				number: self.best_storage_block.number + self.hash_chain.len(),
+				hash: hash.clone(),
+			},
+			None => self.best_storage_block.clone(),
 		}
-		TransactionState::Unknown
 	}
 
-	/// Get transactions hashes with given state
-	pub fn transactions_hashes_with_state(&self, state: TransactionState) -> Vec<H256> {
-		match state {
-			TransactionState::InMemory => self.memory_pool.read().get_transactions_ids(),
-			TransactionState::Verifying => self.verifying_transactions.keys().cloned().collect(),
-			_ => panic!("wrong argument"),
+	/// Remember best block height, advertised by peer
+	#[cfg(test)]
+	pub fn update_peer_best_height(&mut self, height: BlockHeight) {
+		if height > self.peer_best_height {
+			self.peer_best_height = height;
 		}
 	}
 
-	/// Add transaction to verifying queue
-	pub fn verify_transaction(&mut self, tx: IndexedTransaction) {
-		self.verifying_transactions.insert(tx.hash.clone(), tx);
+	/// Get number of blocks we are behind the best block, advertised by peers.
+	/// Returns None if we are not behind
+	#[cfg(test)]
+	pub fn blocks_behind(&self) -> Option<BlockHeight> {
+		let best_block_number = self.best_block().number;
+		if self.peer_best_height > best_block_number {
+			Some(self.peer_best_height - best_block_number)
+		} else {
+			None
+		}
 	}
 
-	/// Remove verifying trasaction
-	pub fn forget_verifying_transaction(&mut self, hash: &H256) -> bool {
-		self.verifying_transactions.remove(hash).is_some()
+	/// Get up to `max` recent storage reorganizations, the most recent first
+	pub fn recent_reorgs(&self, max: usize) -> Vec<ReorgEvent> {
+		self.recent_reorgs.iter().rev().take(max).cloned().collect()
 	}
 
-	/// Remove verifying trasaction + all dependent transactions currently verifying
-	pub fn forget_verifying_transaction_with_children(&mut self, hash: &H256) {
-		self.forget_verifying_transaction(hash);
+	/// Get best storage block
+	pub fn best_storage_block(&self) -> db::BestBlock {
+		self.best_storage_block.clone()
+	}
 
-		// TODO: suboptimal
-		let mut queue: VecDeque<H256> = VecDeque::new();
-		queue.push_back(hash.clone());
-		while let Some(hash) = queue.pop_front() {
-			let all_keys: Vec<_> = self.verifying_transactions.keys().cloned().collect();
-			for h in all_keys {
-				let remove_verifying_transaction = {
-					if let Some(entry) = self.verifying_transactions.get(&h) {
-						if entry.raw.inputs.iter().any(|i| i.previous_output.hash == hash) {
-							queue.push_back(h.clone());
-							true
-						} else {
-							false
-						}
-					} else {
-						// iterating by previously read keys
-						unreachable!()
-					}
-				};
-
-				if remove_verifying_transaction {
-					self.verifying_transactions.remove(&h);
-				}
-			}
+	/// Get best block header
+	pub fn best_block_header(&self) -> db::BestBlock {
+		let headers_chain_information = self.headers_chain.information();
+		if headers_chain_information.best == 0 {
+			return self.best_storage_block()
+		}
+		db::BestBlock {
+			number: self.best_storage_block.number + headers_chain_information.best,
+			hash: self.headers_chain.at(headers_chain_information.best - 1)
+				.expect("got this index above; qed")
+				.hash,
 		}
 	}
 
-	/// Get transaction by hash (if it's in memory pool or verifying)
-	pub fn transaction_by_hash(&self, hash: &H256) -> Option<IndexedTransaction> {
-		self.verifying_transactions.get(hash).cloned()
-			.or_else(|| self.memory_pool.read().read_by_hash(hash).cloned().map(|t| t.into()))
+	/// Get timestamp of the best storage block
+	#[cfg(test)]
+	pub fn tip_timestamp(&self) -> u32 {
+		self.storage.block_header(self.best_storage_block.hash.clone().into())
+			.expect("best storage block header is always in the storage; qed")
+			.time
 	}
 
-	/// Insert transaction to memory pool
-	pub fn insert_verified_transaction(&mut self, transaction: IndexedTransaction) {
-		// we have verified transaction, but possibly this transaction replaces
-		// existing transaction from memory pool
-		// => remove previous transactions before
-		let mut memory_pool = self.memory_pool.write();
-		for input in &transaction.raw.inputs {
-			memory_pool.remove_by_prevout(&input.previous_output);
+	/// Check that block timestamp is not too far in the future and is greater than median time of previous blocks
+	pub fn check_block_timestamp(&self, header: &BlockHeader) -> Result<(), TimestampError> {
+		let max_time = time::get_time().sec as u32 + BLOCK_MAX_FUTURE as u32;
+		if header.time > max_time {
+			return Err(TimestampError::FutureTime {
+				time: header.time,
+				max_time: max_time,
+			});
 		}
-		// now insert transaction itself
-		memory_pool.insert_verified(transaction);
-	}
 
-	/// Calculate block locator hashes for hash queue
-	fn block_locator_hashes_for_queue(&self, hashes: &mut Vec<H256>) -> (BlockHeight, BlockHeight) {
-		let queue_len = self.hash_chain.len();
-		if queue_len == 0 {
-			return (0, 1);
+		let median_time_past = median_timestamp(header, self.storage.as_block_header_provider());
+		if header.time <= median_time_past {
+			return Err(TimestampError::BeforeMedianTimePast {
+				time: header.time,
+				median_time_past: median_time_past,
+			});
 		}
 
-		let mut index = queue_len - 1;
-		let mut step = 1u32;
-		loop {
-			let block_hash = self.hash_chain[index].clone();
-			hashes.push(block_hash);
+		Ok(())
+	}
 
-			if hashes.len() >= 10 {
-				step <<= 1;
-			}
-			if index < step {
-				return (step - index - 1, step);
-			}
-			index -= step;
+	/// Get difficulty bits, which are expected in the header, connecting to the canonized storage block.
+	/// On testnet, minimal difficulty is expected if header is mined more than 20 minutes after its parent.
+	#[cfg(test)]
+	pub fn expected_bits(&self, header: &BlockHeader) -> Option<Compact> {
+		self.storage.block_number(&header.previous_header_hash)
+			.map(|parent_number| work_required(header.previous_header_hash.clone(), header.time, parent_number + 1,
+				self.storage.as_block_header_provider(), self.network))
+	}
+
+	/// Get information on current difficulty retarget period
+	#[cfg(test)]
+	pub fn retarget_info(&self) -> RetargetInfo {
+		let best_number = self.best_storage_block.number;
+		let period_start = best_number - best_number % RETARGETING_INTERVAL;
+		let best_header = self.storage.block_header(best_number.into())
+			.expect("best storage block header is always in the storage; qed");
+		let start_header = self.storage.block_header(period_start.into())
+			.expect("period_start <= best_number; qed");
+
+		let period_timespan = best_header.time.saturating_sub(start_header.time);
+		let mined_blocks = best_number - period_start;
+		let projected_bits = match mined_blocks {
+			0 => best_header.bits,
+			_ => {
+				// retarget is based on timespan of RETARGETING_INTERVAL - 1 blocks
+				let projected_timespan = period_timespan as u64 * (RETARGETING_INTERVAL - 1) as u64 / mined_blocks as u64;
+				let projected_timespan = cmp::min(projected_timespan, u32::max_value() as u64) as u32;
+				work_required_retarget(self.network.max_bits(), start_header.time,
+					start_header.time.saturating_add(projected_timespan), best_header.bits)
+			},
+		};
+
+		RetargetInfo {
+			blocks_until_retarget: period_start + RETARGETING_INTERVAL - best_number,
+			period_timespan: period_timespan,
+			current_bits: best_header.bits,
+			projected_bits: projected_bits,
 		}
 	}
 
-	/// Calculate block locator hashes for storage
-	fn block_locator_hashes_for_storage(&self, mut index: BlockHeight, mut step: BlockHeight, hashes: &mut Vec<H256>) {
-		loop {
-			let block_hash = self.storage.block_hash(index)
-				.expect("private function; index calculated in `block_locator_hashes`; qed");
-			hashes.push(block_hash);
+	/// Check integrity of the last `depth` canon storage blocks.
+	/// Returns the first inconsistency found, starting from the best storage block
+	#[cfg(test)]
+	pub fn verify_storage(&self, depth: BlockHeight) -> Result<(), IntegrityError> {
+		let best_number = self.best_storage_block.number;
+		let first_number = (best_number + 1).saturating_sub(depth);
+		for number in (first_number..best_number + 1).rev() {
+			let block = self.storage.block_hash(number)
+				.and_then(|hash| self.storage.indexed_block(hash.into()))
+				.ok_or(IntegrityError::MissingBlock(number))?;
+
+			if block.transactions.is_empty() || block.merkle_root() != block.header.raw.merkle_root_hash {
+				return Err(IntegrityError::MerkleRootMismatch(block.header.hash));
+			}
 
-			if hashes.len() >= 10 {
-				step <<= 1;
+			if number != 0 && self.storage.block_hash(number - 1).as_ref() != Some(&block.header.raw.previous_header_hash) {
+				return Err(IntegrityError::BrokenLink(block.header.hash));
 			}
-			if index < step {
-				// always include genesis hash
-				if index != 0 {
-					hashes.push(self.genesis_block_hash.clone())
-				}
+		}
 
-				break;
+		Ok(())
+	}
+
+	/// Get block header by hash
+	pub fn block_hash(&self, number: BlockHeight) -> Option<H256> {
+		if number <= self.best_storage_block.number {
+			if self.index_cache_size != 0 {
+				return self.stored_block_header(number).map(|header| header.hash);
 			}
-			index -= step;
+			self.storage.block_hash(number)
+		} else {
+			// we try to keep these in order, but they are probably not
+			self.hash_chain.at(number - self.best_storage_block.number)
 		}
 	}
-}
 
-impl db::TransactionProvider for Chain {
-	fn transaction_bytes(&self, hash: &H256) -> Option<Bytes> {
-		self.memory_pool.read().transaction_bytes(hash)
-			.or_else(|| self.storage.transaction_bytes(hash))
+	/// Get block number by hash
+	pub fn block_number(&self, hash: &H256) -> Option<BlockHeight> {
+		if let Some(number) = self.storage.block_number(hash) {
+			return Some(number);
+		}
+		self.headers_chain.height(hash).map(|p| self.best_storage_block.number + p + 1)
 	}
 
-	fn transaction(&self, hash: &H256) -> Option<Transaction> {
-		self.memory_pool.read().transaction(hash)
-			.or_else(|| self.storage.transaction(hash))
+	/// Is block with given hash connected to the active (canon storage) chain
+	#[cfg(test)]
+	pub fn is_on_active_chain(&self, hash: &H256) -> bool {
+		self.active_chain_height_of(hash).is_some()
 	}
-}
 
-impl db::BlockHeaderProvider for Chain {
-	fn block_header_bytes(&self, block_ref: db::BlockRef) -> Option<Bytes> {
-		use ser::serialize;
-		self.block_header(block_ref).map(|h| serialize(&h))
+	/// Get height of block, connected to the active (canon storage) chain.
+	/// Unlike `block_number`, returns None for blocks which are only known from headers
+	#[cfg(test)]
+	pub fn active_chain_height_of(&self, hash: &H256) -> Option<BlockHeight> {
+		// storage only keeps numbers of canon blocks
+		self.storage.block_number(hash)
 	}
 
-	fn block_header(&self, block_ref: db::BlockRef) -> Option<BlockHeader> {
-		match block_ref {
-			db::BlockRef::Hash(hash) => self.block_header_by_hash(&hash).map(|h| h.raw),
-			db::BlockRef::Number(n) => self.block_header_by_number(n).map(|h| h.raw),
+	/// Get block header by number
+	pub fn block_header_by_number(&self, number: BlockHeight) -> Option<IndexedBlockHeader> {
+		if number <= self.best_storage_block.number {
+			self.stored_block_header(number)
+		} else {
+			self.headers_chain.at(number - self.best_storage_block.number)
 		}
 	}
-}
 
-impl fmt::Debug for Information {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "[sch:{} / bh:{} -> req:{} -> vfy:{} -> stored: {}]", self.scheduled, self.headers.best, self.requested, self.verifying, self.stored)
+	/// Get heights and hashes of at most `count` best chain blocks, starting from `start_height`.
+	/// Both stored blocks and blocks from the headers chain are returned
+	#[cfg(test)]
+	pub fn block_hashes_range(&self, start_height: BlockHeight, count: BlockHeight) -> Vec<(BlockHeight, H256)> {
+		let best_number = self.best_block_header().number;
+		if start_height > best_number {
+			return Vec::new();
+		}
+
+		let end_height = cmp::min(start_height.saturating_add(count), best_number + 1);
+		(start_height..end_height)
+			.filter_map(|number| {
+				let hash = if number <= self.best_storage_block.number {
+					self.storage.block_hash(number)
+				} else {
+					self.headers_chain.at(number - self.best_storage_block.number - 1).map(|header| header.hash)
+				};
+				hash.map(|hash| (number, hash))
+			})
+			.collect()
 	}
-}
 
-impl fmt::Debug for Chain {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		try!(writeln!(f, "chain: ["));
-		{
-			let mut num = self.best_storage_block.number;
-			try!(writeln!(f, "\tworse(stored): {} {:?}", 0, self.storage.block_hash(0)));
-			try!(writeln!(f, "\tbest(stored): {} {:?}", num, self.storage.block_hash(num)));
+	/// Get all best chain headers from genesis to the best header, both stored and from the headers chain.
+	/// This reads every stored header, so it is expensive for long chains => use `block_hashes_range` instead
+	#[cfg(test)]
+	pub fn all_connected_headers(&self) -> Vec<BlockHeader> {
+		let stored_headers = (0..self.best_storage_block.number + 1)
+			.map(|number| self.storage.block_header(db::BlockRef::Number(number))
+				.expect("all canon blocks headers are in the storage; qed"));
+		let in_memory_headers = (0..self.headers_chain.information().best)
+			.filter_map(|index| self.headers_chain.at(index))
+			.map(|header| header.raw);
+		stored_headers.chain(in_memory_headers).collect()
+	}
 
-			let queues = vec![
-				("verifying", VERIFYING_QUEUE),
-				("requested", REQUESTED_QUEUE),
-				("scheduled", SCHEDULED_QUEUE),
-			];
-			for (state, queue) in queues {
-				let queue_len = self.hash_chain.len_of(queue);
-				if queue_len != 0 {
-					try!(writeln!(f, "\tworse({}): {} {:?}", state, num + 1, self.hash_chain.front_at(queue)));
-					num += queue_len;
-					if let Some(pre_best) = self.hash_chain.pre_back_at(queue) {
-						try!(writeln!(f, "\tpre-best({}): {} {:?}", state, num - 1, pre_best));
-					}
-					try!(writeln!(f, "\tbest({}): {} {:?}", state, num, self.hash_chain.back_at(queue)));
-				}
-			}
+	/// Get block header by hash
+	pub fn block_header_by_hash(&self, hash: &H256) -> Option<IndexedBlockHeader> {
+		if let Some(header) = self.storage.block_header(db::BlockRef::Hash(hash.clone())) {
+			return Some(header.into());
 		}
-		writeln!(f, "]")
+		self.headers_chain.by_hash(hash)
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	extern crate test_data;
+	/// Build compact block from the stored block. Transactions with given indexes are prefilled
+	#[cfg(test)]
+	pub fn to_compact_block(&self, hash: &H256, prefill: &[usize], nonce: u64) -> Option<CompactBlock> {
+		let block = self.storage.indexed_block(hash.clone().into())?;
+		let prefilled_transactions_indexes = prefill.iter()
+			.cloned()
+			.filter(|index| *index < block.transactions.len())
+			.collect();
+		Some(CompactBlock {
+			header: build_compact_block_with_nonce(&block, prefilled_transactions_indexes, nonce),
+		})
+	}
 
-	use std::sync::Arc;
-	use parking_lot::RwLock;
-	use chain::{Transaction, IndexedBlockHeader};
-	use db::BlockChainDatabase;
-	use miner::MemoryPool;
-	use primitives::hash::H256;
-	use super::{Chain, BlockState, TransactionState, BlockInsertionResult};
-	use utils::HashPosition;
+	/// Check if any transaction of stored block matches the bloom filter. Matching stops at first matched transaction.
+	/// Filter could be updated, as when filtering transactions for the peer. Returns None if block is not stored
+	#[cfg(test)]
+	pub fn block_matches_filter(&self, block_hash: &H256, filter: &BloomFilter) -> Option<bool> {
+		if !self.storage.contains_block(block_hash.clone().into()) {
+			return None;
+		}
 
-	#[test]
-	fn chain_empty() {
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
-		let db_best_block = db.best_block();
-		let chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
-		assert_eq!(chain.information().scheduled, 0);
-		assert_eq!(chain.information().requested, 0);
-		assert_eq!(chain.information().verifying, 0);
-		assert_eq!(chain.information().stored, 1);
-		assert_eq!(chain.length_of_blocks_state(BlockState::Scheduled), 0);
-		assert_eq!(chain.length_of_blocks_state(BlockState::Requested), 0);
-		assert_eq!(chain.length_of_blocks_state(BlockState::Verifying), 0);
-		assert_eq!(chain.length_of_blocks_state(BlockState::Stored), 1);
-		assert_eq!(&chain.best_block(), &db_best_block);
-		assert_eq!(chain.block_state(&db_best_block.hash), BlockState::Stored);
-		assert_eq!(chain.block_state(&H256::from(0)), BlockState::Unknown);
+		let transactions = self.storage.indexed_block_transactions(block_hash.clone().into());
+		Some(transactions.iter().any(|tx| filter.filter_transaction(tx)))
 	}
 
-	#[test]
-	fn chain_block_path() {
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
-		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+	/// Get verbose information on stored block
+	#[cfg(test)]
+	pub fn block_verbose(&self, hash: &H256, include_txs: bool) -> Option<VerboseBlock> {
+		let block = self.storage.indexed_block(hash.clone().into())?;
+		let height = self.storage.block_number(hash);
+		let confirmations = match height {
+			Some(number) => (self.best_storage_block.number - number + 1) as i64,
+			None => -1,
+		};
+
+		Some(VerboseBlock {
+			hash: hash.clone(),
+			size: block.size(),
+			height: height,
+			confirmations: confirmations,
+			median_time: median_timestamp(&block.header.raw, self.storage.as_block_header_provider()),
+			next_block_hash: height.and_then(|number| self.storage.block_hash(number + 1)),
+			transactions_hashes: block.transactions.iter().map(|tx| tx.hash.clone()).collect(),
+			transactions: if include_txs {
+				Some(block.transactions.into_iter().map(|tx| tx.raw).collect())
+			} else {
+				None
+			},
+			header: block.header.raw,
+		})
+	}
 
-		// add 6 blocks to scheduled queue
-		let blocks = test_data::build_n_empty_blocks_from_genesis(6, 0);
-		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
-		let hashes: Vec<_> = headers.iter().map(|h| h.hash.clone()).collect();
-		chain.schedule_blocks_headers(headers.clone());
-		assert!(chain.information().scheduled == 6 && chain.information().requested == 0
-			&& chain.information().verifying == 0 && chain.information().stored == 1);
+	/// Get verbose information on block header, stored or from the headers chain
+	#[cfg(test)]
+	pub fn block_header_verbose(&self, hash: &H256) -> Option<VerboseHeader> {
+		let header = self.block_header_by_hash(hash)?;
+		let height = self.block_number(hash);
+		let confirmations = match self.storage.block_number(hash) {
+			Some(number) => (self.best_storage_block.number - number + 1) as i64,
+			None => -1,
+		};
+
+		Some(VerboseHeader {
+			hash: hash.clone(),
+			height: height,
+			confirmations: confirmations,
+			median_time: median_timestamp(&header.raw, self),
+			next_block_hash: height
+				.and_then(|number| self.block_hashes_range(number + 1, 1).into_iter().next())
+				.map(|(_, hash)| hash),
+			header: header.raw,
+		})
+	}
 
-		// move 2 best blocks to requested queue
-		chain.request_blocks_hashes(2);
-		assert!(chain.information().scheduled == 4 && chain.information().requested == 2
-			&& chain.information().verifying == 0 && chain.information().stored == 1);
-		// move 0 best blocks to requested queue
-		chain.request_blocks_hashes(0);
-		assert!(chain.information().scheduled == 4 && chain.information().requested == 2
-			&& chain.information().verifying == 0 && chain.information().stored == 1);
-		// move 1 best blocks to requested queue
-		chain.request_blocks_hashes(1);
-		assert!(chain.information().scheduled == 3 && chain.information().requested == 3
-			&& chain.information().verifying == 0 && chain.information().stored == 1);
+	/// Get digest of headers chain from genesis to given height (inclusive).
+	/// Every header hash is sequentially folded into the digest: digest = dhash256(digest + hash).
+	/// Returns None if there's no header at given height
+	#[cfg(test)]
+	pub fn header_chain_digest(&self, up_to_height: BlockHeight) -> Option<H256> {
+		if up_to_height > self.best_block_header().number {
+			return None;
+		}
 
-		// try to remove block 0 from scheduled queue => missing
-		assert_eq!(chain.forget_block_with_state(&hashes[0], BlockState::Scheduled), HashPosition::Missing);
-		assert!(chain.information().scheduled == 3 && chain.information().requested == 3
-			&& chain.information().verifying == 0 && chain.information().stored == 1);
-		// remove blocks 0 & 1 from requested queue
-		assert_eq!(chain.forget_block_with_state(&hashes[1], BlockState::Requested), HashPosition::Inside(1));
-		assert_eq!(chain.forget_block_with_state(&hashes[0], BlockState::Requested), HashPosition::Front);
-		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
-			&& chain.information().verifying == 0 && chain.information().stored == 1);
-		// mark 0 & 1 as verifying
-		chain.verify_block(headers[0].clone().into());
-		chain.verify_block(headers[1].clone().into());
-		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
-			&& chain.information().verifying == 2 && chain.information().stored == 1);
+		let mut digest = H256::default();
+		let mut buffer = Vec::with_capacity(64);
+		for number in 0..up_to_height + 1 {
+			let hash = if number <= self.best_storage_block.number {
+				self.storage.block_hash(number)?
+			} else {
+				self.headers_chain.at(number - self.best_storage_block.number - 1)?.hash
+			};
 
-		// mark block 0 as verified
-		assert_eq!(chain.forget_block_with_state(&hashes[0], BlockState::Verifying), HashPosition::Front);
-		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
-			&& chain.information().verifying == 1 && chain.information().stored == 1);
-		// insert new best block to the chain
-		chain.insert_best_block(test_data::block_h1().into()).expect("Db error");
-		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
-			&& chain.information().verifying == 1 && chain.information().stored == 2);
-		assert_eq!(db.best_block().number, 1);
+			buffer.clear();
+			buffer.extend_from_slice(&*digest);
+			buffer.extend_from_slice(&*hash);
+			digest = dhash256(&buffer);
+		}
+		Some(digest)
 	}
 
-	#[test]
-	fn chain_block_locator_hashes() {
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
-		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		let genesis_hash = chain.best_block().hash;
-		assert_eq!(chain.block_locator_hashes(), vec![genesis_hash.clone()]);
-
-		let block1 = test_data::block_h1();
-		let block1_hash = block1.hash();
-
-		chain.insert_best_block(block1.into()).expect("Error inserting new block");
-		assert_eq!(chain.block_locator_hashes(), vec![block1_hash.clone(), genesis_hash.clone()]);
+	/// Get block state
+	pub fn block_state(&self, hash: &H256) -> BlockState {
+		match self.blocks_state.get(hash) {
+			Some(state) => *state,
+			// stored blocks could also be invalidated manually
+			None => if self.is_invalid_block(hash) {
+				BlockState::DeadEnd
+			} else if self.storage.contains_block(db::BlockRef::Hash(hash.clone())) {
+				BlockState::Stored
+			} else {
+				BlockState::Unknown
+			},
+		}
+	}
+
+	/// Find out how the block connects to the known chain, based on state of its parent
+	#[cfg(test)]
+	pub fn block_connects(&self, block: &Block) -> ConnectPoint {
+		match self.block_state(&block.hash()) {
+			BlockState::Verifying | BlockState::Stored | BlockState::DeadEnd => return ConnectPoint::AlreadyHave,
+			BlockState::Unknown | BlockState::Scheduled | BlockState::Requested => (),
+		}
+
+		let parent_hash = &block.block_header.previous_header_hash;
+		let active_tip = self.hash_chain.back_at(VERIFYING_QUEUE)
+			.unwrap_or_else(|| self.best_storage_block.hash.clone());
+		match self.block_state(parent_hash) {
+			BlockState::Verifying | BlockState::Stored if *parent_hash == active_tip => ConnectPoint::ExtendsActiveTip,
+			BlockState::Verifying | BlockState::Stored => ConnectPoint::ExtendsSideBranch(parent_hash.clone()),
+			_ => ConnectPoint::Orphan,
+		}
+	}
+
+	/// Check if block should be requested from peers.
+	/// Returns false for blocks, which are already scheduled, requested, verifying, stored or marked as dead-ends
+	#[cfg(test)]
+	pub fn should_request(&self, hash: &H256) -> bool {
+		self.block_state(hash) == BlockState::Unknown
+	}
+
+	/// Order blocks hashes so that parents are placed before their children, using links of in-memory headers.
+	/// Blocks with parents outside of given set are roots. Roots with stored or queued parents come first,
+	/// followed by roots with unknown parents (or unknown headers). Input order is preserved otherwise
+	#[cfg(test)]
+	pub fn download_order(&self, hashes: &[H256]) -> Vec<H256> {
+		let mut unique_hashes = HashSet::with_capacity(hashes.len());
+		let hashes: Vec<_> = hashes.iter().filter(|hash| unique_hashes.insert((*hash).clone())).collect();
+
+		let mut children: HashMap<H256, Vec<&H256>> = HashMap::new();
+		let mut connected_roots = Vec::new();
+		let mut orphan_roots = Vec::new();
+		for hash in hashes {
+			match self.headers_chain.by_hash(hash).map(|header| header.raw.previous_header_hash) {
+				Some(ref parent_hash) if unique_hashes.contains(parent_hash) =>
+					children.entry(parent_hash.clone()).or_insert_with(Vec::new).push(hash),
+				Some(ref parent_hash) if self.block_state(parent_hash) != BlockState::Unknown =>
+					connected_roots.push(hash),
+				_ => orphan_roots.push(hash),
+			}
+		}
+
+		let mut ordered = Vec::with_capacity(unique_hashes.len());
+		let mut stack: Vec<&H256> = connected_roots.into_iter().chain(orphan_roots).rev().collect();
+		while let Some(hash) = stack.pop() {
+			ordered.push(hash.clone());
+			if let Some(hash_children) = children.get(hash) {
+				stack.extend(hash_children.iter().rev());
+			}
+		}
+		ordered
+	}
+
+	/// Find in-memory headers, which are referencing unknown parent.
+	/// Returns (child hash, missing parent hash) pairs
+	#[cfg(test)]
+	pub fn find_header_gaps(&self) -> Vec<(H256, H256)> {
+		(0..self.headers_chain.information().best)
+			.filter_map(|index| self.headers_chain.at(index))
+			.filter(|header| {
+				let parent_hash = &header.raw.previous_header_hash;
+				self.headers_chain.by_hash(parent_hash).is_none() && self.block_state(parent_hash) == BlockState::Unknown
+			})
+			.map(|header| (header.hash, header.raw.previous_header_hash))
+			.collect()
+	}
+
+	/// Buffer header, which parent is not yet connected. When the buffer is full, the oldest header is dropped
+	#[cfg(test)]
+	pub fn buffer_future_header(&mut self, header: BlockHeader) {
+		let hash = header.hash();
+		if self.max_buffered_headers == 0 || self.buffered_headers.contains_key(&hash) {
+			return;
+		}
+
+		while self.buffered_headers.len() >= self.max_buffered_headers {
+			self.pop_oldest_buffered_header();
+		}
+
+		self.buffered_headers_by_parent.entry(header.previous_header_hash.clone())
+			.or_insert_with(Vec::new)
+			.push(hash.clone());
+		self.buffered_headers.insert(hash, header);
+	}
+
+	/// Remove and return buffered headers, which parent is the newly connected block
+	#[cfg(test)]
+	pub fn release_buffered_children(&mut self, newly_connected: &H256) -> Vec<BlockHeader> {
+		match self.buffered_headers_by_parent.remove(newly_connected) {
+			Some(children) => children.into_iter()
+				.filter_map(|hash| self.buffered_headers.remove(&hash))
+				.collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Filter hashes of unknown blocks (i.e. blocks, which should be requested from peers).
+	/// Input order is preserved
+	#[cfg(test)]
+	pub fn unknown_hashes<'a>(&self, hashes: &'a [H256]) -> Vec<&'a H256> {
+		hashes.iter()
+			.filter(|hash| self.should_request(hash))
+			.collect()
+	}
+
+	/// Prepare block locator hashes, as described in protocol documentation:
+	/// https://en.bitcoin.it/wiki/Protocol_documentation#getblocks
+	/// When there are forked blocks in the queue, this method can result in
+	/// mixed block locator hashes ([0 - from fork1, 1 - from fork2, 2 - from fork1]).
+	/// Peer will respond with blocks of fork1 || fork2 => we could end up in some side fork
+	/// To resolve this, after switching to saturated state, we will also ask all peers for inventory.
+	pub fn block_locator_hashes(&self) -> Vec<H256> {
+		let mut block_locator_hashes: Vec<H256> = Vec::new();
+
+		// calculate for hash_queue
+		let (local_index, step) = self.block_locator_hashes_for_queue(&mut block_locator_hashes);
+
+		// calculate for storage
+		let storage_index = if self.best_storage_block.number < local_index { 0 } else { self.best_storage_block.number - local_index };
+		self.block_locator_hashes_for_storage(storage_index, step, &mut block_locator_hashes);
+		block_locator_hashes
+	}
+
+	/// Prepare block locator hashes, using only ancestors of the best block.
+	/// Unlike `block_locator_hashes`, in-memory blocks are only included when they form
+	/// a single chain, connected to the best storage block.
+	#[cfg(test)]
+	pub fn block_locator_hashes_for_best_chain(&self) -> Vec<H256> {
+		// in-memory part of the best chain, from best block down to the best storage block
+		let mut in_memory_hashes: Vec<H256> = Vec::new();
+		let mut hash = self.best_block().hash;
+		while let Some(header) = self.headers_chain.by_hash(&hash) {
+			in_memory_hashes.push(hash);
+			hash = header.raw.previous_header_hash;
+		}
+		// do not mix hashes from different forks
+		if hash != self.best_storage_block.hash {
+			in_memory_hashes.clear();
+		}
+
+		let storage_number = self.best_storage_block.number;
+		let best_number = storage_number + in_memory_hashes.len() as BlockHeight;
+		let mut block_locator_hashes: Vec<H256> = Vec::new();
+		let mut number = best_number;
+		let mut step = 1u32;
+		loop {
+			let block_hash = if number > storage_number {
+				in_memory_hashes[(best_number - number) as usize].clone()
+			} else {
+				self.storage.block_hash(number)
+					.expect("number <= best storage block number; qed")
+			};
+			block_locator_hashes.push(block_hash);
+
+			if block_locator_hashes.len() >= 10 {
+				step <<= 1;
+			}
+			if number < step {
+				// always include genesis hash
+				if number != 0 {
+					block_locator_hashes.push(self.storage.block_hash(0)
+						.expect("genesis block is always in the storage; qed"));
+				}
+				break;
+			}
+			number -= step;
+		}
+		block_locator_hashes
+	}
+
+	/// Calculate block locator hashes for peer with given best block height.
+	/// Locator is dense around `min(peer_height, best_storage_height)` and sparse in both directions from there
+	#[cfg(test)]
+	pub fn locator_targeting_height(&self, peer_height: BlockHeight) -> Vec<H256> {
+		let best_number = self.best_storage_block.number;
+		let anchor = cmp::min(peer_height, best_number);
+
+		// heights above the anchor, with exponentially growing distance from it
+		let mut heights: Vec<BlockHeight> = Vec::new();
+		let mut step = 1u32;
+		while anchor + step <= best_number {
+			heights.push(anchor + step);
+			step <<= 1;
+		}
+		if anchor != best_number && heights.last() != Some(&best_number) {
+			heights.push(best_number);
+		}
+		heights.reverse();
+
+		// anchor and heights below it: dense first, then with exponentially growing steps
+		let mut number = anchor;
+		let mut step = 1u32;
+		let mut dense_count = 0;
+		loop {
+			heights.push(number);
+			dense_count += 1;
+			if dense_count >= 10 {
+				step <<= 1;
+			}
+			if number < step {
+				break;
+			}
+			number -= step;
+		}
+		// always include genesis hash
+		if heights.last() != Some(&0) {
+			heights.push(0);
+		}
+
+		heights.into_iter()
+			.filter_map(|number| self.storage.block_hash(number))
+			.collect()
+	}
+
+	/// Find best common block of the canon chain and the chain, described by the block locator
+	#[cfg(test)]
+	pub fn best_common_block(&self, locator: &[H256], stop_hash: &H256) -> Option<BlockHeight> {
+		for block_hash in locator.iter().chain(&[stop_hash.clone()]) {
+			if let Some(block_number) = self.storage.block_number(block_hash) {
+				return Some(block_number);
+			}
+
+			// block is not in the main chain, but it could be in some fork
+			// => intersection of fork with main chain is the best common block
+			let mut block_hash = block_hash.clone();
+			while let Some(block_header) = self.storage.block_header(block_hash.clone().into()) {
+				if let Some(block_number) = self.storage.block_number(&block_header.previous_header_hash) {
+					return Some(block_number);
+				}
+				block_hash = block_header.previous_header_hash;
+			}
+		}
+
+		None
+	}
+
+	/// Get serialized `headers` message payload with up to `max` headers of canon blocks, following the best common block
+	/// with the locator (`getheaders` response). Stops at `stop_hash` (inclusive), unless it is zero
+	#[cfg(test)]
+	pub fn headers_message_bytes(&self, locator: &[H256], stop_hash: &H256, max: usize) -> Vec<u8> {
+		let mut headers = Vec::new();
+		if let Some(best_common_block) = self.best_common_block(locator, stop_hash) {
+			for number in best_common_block + 1..self.best_storage_block.number + 1 {
+				if headers.len() >= max {
+					break;
+				}
+
+				let header = self.storage.block_header(number.into())
+					.expect("number is less than or equal to the best storage block number; qed");
+				let is_stop_header = !stop_hash.is_zero() && &header.hash() == stop_hash;
+				headers.push(header);
+				if is_stop_header {
+					break;
+				}
+			}
+		}
+
+		serialize_payload(&Headers::with_headers(headers), 0)
+			.expect("headers message serialization never fails; qed")
+			.into()
+	}
+
+	/// Get up to `max` hashes of canon blocks, following the best common block with the locator (`getblocks` response).
+	/// Stops at `stop_hash` (exclusive), unless it is zero
+	#[cfg(test)]
+	pub fn inv_after_locator(&self, locator: &[H256], stop_hash: &H256, max: usize) -> Vec<H256> {
+		let best_common_block = match self.best_common_block(locator, stop_hash) {
+			Some(best_common_block) => best_common_block,
+			None => return Vec::new(),
+		};
+
+		(best_common_block + 1..self.best_storage_block.number + 1)
+			.filter_map(|number| self.storage.block_hash(number))
+			.take_while(|hash| stop_hash.is_zero() || hash != stop_hash)
+			.take(max)
+			.collect()
+	}
+
+	/// Schedule blocks hashes for requesting.
+	/// Headers which are too far ahead of the best storage block are rejected
+	pub fn schedule_blocks_headers(&mut self, mut headers: Vec<IndexedBlockHeader>) -> Result<(), HeadersLookaheadError> {
+		let result = self.truncate_headers_to_lookahead(&mut headers);
+		let hashes: Vec<_> = headers.iter().map(|h| h.hash.clone()).collect();
+		self.hash_chain.push_back_n_at(SCHEDULED_QUEUE, hashes.clone());
+		self.update_blocks_state(&hashes);
+		self.headers_chain.insert_n(headers);
+		result
+	}
+
+	/// Schedule blocks hashes for requesting, so that blocks with higher priority are requested first.
+	/// Blocks with the same priority are requested in the scheduling order.
+	#[cfg(test)]
+	pub fn schedule_blocks_headers_prioritized(&mut self, mut headers: Vec<IndexedBlockHeader>, priorities: &[u32]) -> Result<(), HeadersLookaheadError> {
+		assert_eq!(headers.len(), priorities.len());
+
+		let result = self.truncate_headers_to_lookahead(&mut headers);
+		for (header, priority) in headers.iter().zip(priorities) {
+			self.hash_chain.push_with_priority_at(SCHEDULED_QUEUE, header.hash.clone(), *priority);
+			self.update_block_state(&header.hash);
+		}
+		self.headers_chain.insert_n(headers);
+		result
+	}
+
+	/// Remove headers, which are too far ahead of the best storage block
+	fn truncate_headers_to_lookahead(&self, headers: &mut Vec<IndexedBlockHeader>) -> Result<(), HeadersLookaheadError> {
+		// headers are appended to the back of the in-memory chain
+		let first_blocks_ahead = self.best_block().number + 1 - self.best_storage_block.number;
+		let max_headers = if first_blocks_ahead > self.max_header_lookahead {
+			0
+		} else {
+			(self.max_header_lookahead - first_blocks_ahead) as usize + 1
+		};
+
+		if headers.len() > max_headers {
+			let rejected = headers.split_off(max_headers);
+			Err(HeadersLookaheadError {
+				hash: rejected[0].hash.clone(),
+				blocks_ahead: first_blocks_ahead + max_headers as BlockHeight,
+			})
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Moves n blocks from scheduled queue to requested queue
+	pub fn request_blocks_hashes(&mut self, n: BlockHeight) -> Vec<H256> {
+		self.request_blocks_hashes_bounded(n, None)
+	}
+
+	/// Moves up to n blocks from scheduled queue to requested queue.
+	/// When `max_in_flight` is given, requested queue never grows above this number of blocks
+	pub fn request_blocks_hashes_bounded(&mut self, n: BlockHeight, max_in_flight: Option<BlockHeight>) -> Vec<H256> {
+		if self.sync_paused {
+			return Vec::new();
+		}
+
+		let n = match max_in_flight {
+			Some(max_in_flight) => cmp::min(n, max_in_flight.saturating_sub(self.hash_chain.len_of(REQUESTED_QUEUE))),
+			None => n,
+		};
+		if n == 0 {
+			return Vec::new();
+		}
+
+		let scheduled = self.hash_chain.move_front_n(SCHEDULED_QUEUE, REQUESTED_QUEUE, n);
+		let now = time::precise_time_s();
+		self.request_times.extend(scheduled.iter().map(|hash| (hash.clone(), now)));
+		self.update_blocks_state(&scheduled);
+		scheduled
+	}
+
+	/// Get number of blocks, which should be in requested queue to keep peers busy.
+	/// Estimated as doubled product of blocks arrival speed and average block arrival latency,
+	/// so that the window could grow while peers keep up. Maximal bound is used until estimate is available
+	pub fn current_download_window(&self) -> BlockHeight {
+		let (min, max) = self.download_window_bounds;
+		let speed = self.download_speed_meter.speed();
+		match self.download_latency {
+			Some(latency) if speed != 0_f64 => {
+				let window = (2_f64 * speed * latency).ceil();
+				if window >= max as f64 {
+					max
+				} else {
+					cmp::max(min, window as BlockHeight)
+				}
+			},
+			_ => max,
+		}
+	}
+
+	/// Moves given scheduled blocks to requested queue and remembers that these blocks are requested from given peer.
+	/// Blocks, which are already requested, are reassigned to the peer. Returns hashes of assigned blocks
+	pub fn request_specific(&mut self, hashes: &[H256], peer_index: PeerIndex) -> Vec<H256> {
+		let mut requested = Vec::with_capacity(hashes.len());
+		for hash in hashes {
+			match self.block_state(hash) {
+				BlockState::Scheduled => {
+					self.hash_chain.move_at(SCHEDULED_QUEUE, REQUESTED_QUEUE, hash);
+					self.update_block_state(hash);
+					self.request_times.insert(hash.clone(), time::precise_time_s());
+				},
+				BlockState::Requested => (),
+				_ => continue,
+			}
+
+			self.requested_by_peer.insert(hash.clone(), peer_index);
+			requested.push(hash.clone());
+		}
+		requested
+	}
+
+	/// Moves requested blocks, assigned to given peer, to the front of scheduled queue.
+	/// Returns hashes of rescheduled blocks
+	#[cfg(test)]
+	pub fn reschedule_peer_blocks(&mut self, peer_index: PeerIndex) -> Vec<H256> {
+		let peer_blocks: HashSet<_> = self.requested_by_peer.iter()
+			.filter(|&(_, index)| *index == peer_index)
+			.map(|(hash, _)| hash.clone())
+			.collect();
+		for hash in &peer_blocks {
+			self.requested_by_peer.remove(hash);
+			self.request_times.remove(hash);
+		}
+
+		// preserve order of requested queue
+		let rescheduled: Vec<_> = self.hash_chain.front_n_at(REQUESTED_QUEUE, self.hash_chain.len_of(REQUESTED_QUEUE))
+			.into_iter()
+			.filter(|hash| peer_blocks.contains(hash))
+			.collect();
+		self.hash_chain.move_to_front_at(REQUESTED_QUEUE, SCHEDULED_QUEUE, rescheduled.clone());
+		self.update_blocks_state(&rescheduled);
+		rescheduled
+	}
+
+	/// Add block to verifying queue
+	pub fn verify_block(&mut self, header: IndexedBlockHeader) {
+		if let Some(request_time) = self.request_times.remove(&header.hash) {
+			self.on_requested_block_received(request_time);
+		}
+		// insert header to the in-memory chain in case when it is not already there (non-headers-first sync)
+		self.hash_chain.push_back_at(VERIFYING_QUEUE, header.hash.clone());
+		self.update_block_state(&header.hash);
+		self.headers_chain.insert(header);
+	}
+
+	/// Add blocks to verifying queue
+	pub fn verify_blocks(&mut self, blocks: Vec<IndexedBlockHeader>) {
+		for block in blocks {
+			self.verify_block(block);
+		}
+	}
+
+	/// Moves n blocks from requested queue to verifying queue
+	#[cfg(test)]
+	pub fn verify_blocks_hashes(&mut self, n: BlockHeight) -> Vec<H256> {
+		let requested = self.hash_chain.pop_front_n_at(REQUESTED_QUEUE, n);
+		self.hash_chain.push_back_n_at(VERIFYING_QUEUE, requested.clone());
+		self.update_blocks_state(&requested);
+		requested
+	}
+
+	/// Mark this block as dead end, so these tasks won't be synchronized
+	pub fn mark_dead_end_block(&mut self, hash: &H256) {
+		self.dead_end_blocks.insert(hash.clone());
+	}
+
+	/// Manually mark block and all its known descendants as invalid. Marks could be cleared by `reconsider_block`.
+	/// Returns hashes of all marked blocks
+	#[cfg(test)]
+	pub fn mark_invalid(&mut self, hash: &H256) -> Vec<H256> {
+		let mut invalid = vec![hash.clone()];
+		match self.storage.block_number(hash) {
+			// canon block => all following canon blocks and all in-memory blocks are descendants
+			Some(number) => {
+				invalid.extend((number + 1..self.best_storage_block.number + 1)
+					.filter_map(|number| self.storage.block_hash(number)));
+				let in_memory = (0..self.hash_chain.len())
+					.filter_map(|index| self.hash_chain.at(index))
+					.collect::<Vec<_>>();
+				self.forget_blocks(&in_memory);
+				invalid.extend(in_memory);
+			},
+			// in-memory (or side chain) block => only in-memory descendants are known
+			None => {
+				let forgotten = self.forget_branch(hash);
+				invalid.extend(forgotten);
+			},
+		}
+
+		self.manually_invalid_blocks.extend(invalid.iter().cloned());
+		invalid
+	}
+
+	/// Forget invalid block and all its in-memory descendants from all queues and mark them as dead-ends.
+	/// Returns hashes of purged descendants
+	#[cfg(test)]
+	pub fn purge_invalid_branch(&mut self, invalid_root: &H256) -> Vec<H256> {
+		let purged = self.forget_branch(invalid_root);
+		self.dead_end_blocks.insert(invalid_root.clone());
+		self.dead_end_blocks.extend(purged.iter().cloned());
+		purged
+	}
+
+	/// Mark block as invalid and reorganize storage to the best valid chain.
+	/// Backs the `invalidateblock` RPC
+	#[cfg(test)]
+	pub fn invalidate_block(&mut self, hash: &H256) -> Result<ReorgResult, InvalidateError> {
+		if *hash == self.genesis_block_hash {
+			return Err(InvalidateError::GenesisBlock);
+		}
+		if self.block_state(hash) == BlockState::Unknown {
+			return Err(InvalidateError::UnknownBlock);
+		}
+
+		let canon_number = self.storage.block_number(hash);
+		self.mark_invalid(hash);
+
+		// side chain & in-memory blocks do not require storage reorganization
+		let number = match canon_number {
+			Some(number) => number,
+			None => return Ok(ReorgResult::default()),
+		};
+
+		match self.best_side_chain(number - 1, BlockHeight::max_value()) {
+			Some(origin) => self.reorganize_storage(origin).map_err(Into::into),
+			None => {
+				let parent_hash = self.storage.block_hash(number - 1)
+					.expect("number is the number of canon non-genesis block; qed");
+				self.rollback_storage_to(&parent_hash).map_err(Into::into)
+			},
+		}
+	}
+
+	/// Clear manual invalid (or too deep reorganization) mark from the block and all its descendants and switch
+	/// to the best valid chain. Dead-end blocks (i.e. blocks, which have failed verification) are left invalid.
+	/// Backs the `reconsiderblock` RPC
+	#[cfg(test)]
+	pub fn reconsider_block(&mut self, hash: &H256) -> Result<Option<ReorgResult>, db::Error> {
+		let reconsidered = self.manually_invalid_blocks.iter()
+			.chain(self.not_activated_blocks.iter())
+			.filter(|marked_hash| *marked_hash == hash || self.is_storage_descendant_of(marked_hash, hash))
+			.cloned()
+			.collect::<Vec<_>>();
+		for reconsidered_hash in reconsidered {
+			self.manually_invalid_blocks.remove(&reconsidered_hash);
+			self.not_activated_blocks.remove(&reconsidered_hash);
+		}
+
+		self.activate_best_chain()
+	}
+
+	/// Rebuild storage indices (transactions meta, canon blocks numbers and the best block) from stored blocks data.
+	/// All indices are cleared and then blocks are canonized from genesis, raw blocks data is left untouched.
+	/// The target of reindex is the longest stored chain on top of the best block. It is persisted, so
+	/// interrupted reindex is continued by the next call from the last canonized block.
+	/// `progress` is called with (canonized block number, target block number) after every canonized block
+	pub fn reindex(&mut self, progress: Option<&mut FnMut(BlockHeight, BlockHeight)>) -> Result<(), db::Error> {
+		let old_best_block_hash = self.best_storage_block.hash.clone();
+		let result = self.rebuild_storage_indices(progress);
+
+		// all cached information is derived from storage indices
+		self.best_storage_block = self.storage.best_block();
+		self.total_transactions.set(None);
+		*self.utxo_set_info.borrow_mut() = None;
+		self.index_cache.borrow_mut().clear();
+
+		if self.best_storage_block.hash != old_best_block_hash {
+			self.on_best_storage_block_updated();
+
+			// all in-memory blocks are descendants of previous best block
+			let in_memory = (0..self.hash_chain.len())
+				.filter_map(|index| self.hash_chain.at(index))
+				.collect::<Vec<_>>();
+			self.forget_blocks(&in_memory);
+			self.headers_chain = BestHeadersChain::new(self.best_storage_block.hash.clone());
+		}
+
+		result
+	}
+
+	/// Reorganize storage to the longest valid side chain, if it is longer than the current best chain
+	/// and does not require reorganization deeper than allowed.
+	/// In-memory blocks are always descendants of the best storage block, so only stored side chains are considered.
+	/// Returns None if the current best chain is still the best one
+	#[cfg(test)]
+	pub fn activate_best_chain(&mut self) -> Result<Option<ReorgResult>, db::Error> {
+		let best_storage_block = self.best_storage_block.clone();
+		let origin = match self.best_side_chain(best_storage_block.number.saturating_sub(1), self.max_reorg_depth) {
+			Some(origin) => origin,
+			None => return Ok(None),
+		};
+
+		// tips with the same number of blocks => best block is kept, unless side chain tip wins the tiebreak
+		if origin.block_number == best_storage_block.number {
+			let tip = origin.canonized_route.last().expect("side chain block_number is above ancestor; qed");
+			if self.compare_equal_tips(&best_storage_block.hash, tip).0 {
+				return Ok(None);
+			}
+		}
+
+		self.reorganize_storage(origin).map(Some)
+	}
+
+	/// Rollback storage, so that block with given hash becomes the best storage block.
+	/// All in-memory blocks are forgotten, because they are descendants of the previous best block
+	#[cfg(test)]
+	pub fn rollback_storage_to(&mut self, hash: &H256) -> Result<ReorgResult, db::Error> {
+		let number = match self.storage.block_number(hash) {
+			Some(number) => number,
+			None => return Err(db::Error::CannotCanonize),
+		};
+
+		let origin = db::SideChainOrigin {
+			ancestor: number,
+			canonized_route: Vec::new(),
+			decanonized_route: (number + 1..self.best_storage_block.number + 1)
+				.filter_map(|number| self.storage.block_hash(number))
+				.collect(),
+			block_number: number,
+		};
+		self.reorganize_storage(origin)
+	}
+
+	/// Validate block against outputs, available to the block, and insert it to the storage if it is valid
+	#[cfg(test)]
+	pub fn insert_best_block_validated<F, E>(&mut self, block: IndexedBlock, validate: F) -> Result<BlockInsertionResult, InsertError<E>>
+		where F: FnOnce(&IndexedBlock, &UtxoView) -> Result<(), E> {
+		let block_origin = self.storage.block_origin(&block.header).map_err(ReorgError::from)?;
+		match block_origin {
+			db::BlockOrigin::KnownBlock => return Ok(BlockInsertionResult::default()),
+			db::BlockOrigin::CanonChain { .. } => {
+				validate(&block, self.storage.as_transaction_output_provider()).map_err(InsertError::Validation)?;
+			},
+			db::BlockOrigin::SideChain(origin) | db::BlockOrigin::SideChainBecomingCanonChain(origin) => {
+				let fork = self.storage.fork(origin).map_err(ReorgError::from)?;
+				validate(&block, fork.store().as_transaction_output_provider()).map_err(InsertError::Validation)?;
+			},
+		}
+
+		Ok(self.insert_best_block(block)?)
+	}
+
+	/// Insert new best block to storage
+	pub fn insert_best_block(&mut self, block: IndexedBlock) -> Result<BlockInsertionResult, ReorgError> {
+		if self.safe_mode {
+			self.safe_mode_blocks.insert(block.hash().clone(), block);
+			return Err(ReorgError::SafeMode);
+		}
+
+		let block_hash = block.hash().clone();
+		let parent_hash = block.header.raw.previous_header_hash.clone();
+		let result = self.insert_best_block_to_storage(block);
+
+		// too deep reorganization still inserts block to the side chain
+		let is_inserted = match result {
+			Ok(_) | Err(ReorgError::TooDeep { .. }) => true,
+			Err(_) => false,
+		};
+		if is_inserted {
+			self.requested_by_peer.remove(&block_hash);
+			self.request_times.remove(&block_hash);
+			// only leaf blocks are competing for the best block
+			self.first_seen.remove(&parent_hash);
+			self.first_seen.entry(block_hash).or_insert_with(Instant::now);
+		}
+
+		result
+	}
+
+	fn insert_best_block_to_storage(&mut self, block: IndexedBlock) -> Result<BlockInsertionResult, ReorgError> {
+		assert_eq!(Some(self.storage.best_block().hash), self.storage.block_hash(self.storage.best_block().number));
+		if self.check_block_timestamps {
+			self.check_block_timestamp(&block.header.raw).map_err(ReorgError::Timestamp)?;
+		}
+		let block_origin = self.storage.block_origin(&block.header)?;
+		trace!(target: "sync", "insert_best_block {:?} origin: {:?}", block.hash().reversed(), block_origin);
+		match block_origin {
+			db::BlockOrigin::KnownBlock => {
+				// there should be no known blocks at this point
+				unreachable!();
+			},
+			// case 1: block has been added to the main branch
+			db::BlockOrigin::CanonChain { block_number } => {
+				self.storage.insert(block.clone())?;
+				self.storage.canonize(block.hash())?;
+
+				// remember new best block hash
+				self.best_storage_block = self.storage.best_block();
+				self.on_best_storage_block_updated();
+				self.invalidate_index_cache(block_number);
+				self.on_canon_blocks_updated(block_number, &[], &[block.hash().clone()]);
+				self.update_total_transactions(&[block.hash().clone()], &[]);
+				self.update_utxo_set_info(&[block.hash().clone()], &[]);
+
+				// remove inserted block + handle possible reorganization in headers chain
+				// TODO: mk, not sure if we need both of those params
+				self.headers_chain.block_inserted_to_storage(block.hash(), &self.best_storage_block.hash);
+
+				// double check
+				assert_eq!(self.best_storage_block.hash, block.hash().clone());
+
+				// all transactions from this block were accepted
+				// => delete accepted transactions from verification queue and from the memory pool
+				// + also remove transactions which spent outputs which have been spent by transactions from the block
+				let mut memory_pool = self.memory_pool.write();
+				for tx in &block.transactions {
+					memory_pool.remove_by_hash(&tx.hash);
+					self.verifying_transactions.remove(&tx.hash);
+					for tx_input in &tx.raw.inputs {
+						memory_pool.remove_by_prevout(&tx_input.previous_output);
+					}
+				}
+				// no transactions to reverify, because we have just appended new transactions to the blockchain
+
+				Ok(BlockInsertionResult {
+					canonized_blocks_hashes: vec![block.hash().clone()],
+					transactions_to_reverify: Vec::new(),
+				})
+			},
+			// case 2: block has been added to the side branch with reorganization to this branch
+			db::BlockOrigin::SideChainBecomingCanonChain(origin) => {
+				// too deep reorganizations are not activated until block is reconsidered manually
+				let depth = origin.decanonized_route.len() as BlockHeight;
+				if depth > self.max_reorg_depth {
+					let block_hash = block.hash().clone();
+					self.side_chain_tips.remove(&block.header.raw.previous_header_hash);
+					self.side_chain_tips.insert(block_hash.clone());
+					self.storage.insert(block)?;
+					self.headers_chain.block_inserted_to_storage(&block_hash, &self.best_storage_block.hash);
+					self.not_activated_blocks.insert(block_hash);
+					return Err(ReorgError::TooDeep { depth: depth });
+				}
+
+				let fork = self.storage.fork(origin.clone())?;
+				fork.store().insert(block.clone())?;
+				fork.store().canonize(block.hash())?;
+				self.storage.switch_to_fork(fork)?;
+
+				// remember new best block hash
+				let old_best_block_hash = self.best_storage_block.hash.clone();
+				self.best_storage_block = self.storage.best_block();
+				self.on_best_storage_block_updated();
+				self.on_storage_reorganized(old_best_block_hash, depth);
+				if depth > self.safe_mode_depth {
+					warn!(target: "sync", "Reorganization of {} blocks: entering safe mode", depth);
+					self.safe_mode = true;
+				}
+
+				// remove inserted block + handle possible reorganization in headers chain
+				// TODO: mk, not sure if we need both of those params
+				self.headers_chain.block_inserted_to_storage(block.hash(), &self.best_storage_block.hash);
+
+				// previous best block is now the tip of the side chain
+				if let Some(old_best_block_hash) = origin.decanonized_route.last() {
+					self.side_chain_tips.insert(old_best_block_hash.clone());
+				}
+				self.side_chain_tips.remove(&block.header.raw.previous_header_hash);
+
+				let mut canonized_blocks_hashes = origin.canonized_route;
+				canonized_blocks_hashes.push(block.hash().clone());
+				self.invalidate_index_cache(origin.ancestor + 1);
+				self.on_canon_blocks_updated(origin.ancestor + 1, &origin.decanonized_route, &canonized_blocks_hashes);
+				self.update_total_transactions(&canonized_blocks_hashes, &origin.decanonized_route);
+				self.update_utxo_set_info(&canonized_blocks_hashes, &origin.decanonized_route);
+				let transactions_to_reverify = self.reorganize_transactions(&canonized_blocks_hashes, &origin.decanonized_route);
+
+				let result = BlockInsertionResult {
+					canonized_blocks_hashes: canonized_blocks_hashes,
+					transactions_to_reverify: transactions_to_reverify,
+				};
+
+				trace!(target: "sync", "result: {:?}", result);
+
+				Ok(result)
+			},
+			// case 3: block has been added to the side branch without reorganization to this branch
+			db::BlockOrigin::SideChain(_origin) => {
+				let block_hash = block.hash().clone();
+				self.side_chain_tips.remove(&block.header.raw.previous_header_hash);
+				self.side_chain_tips.insert(block_hash.clone());
+				self.storage.insert(block)?;
+
+				// remove inserted block + handle possible reorganization in headers chain
+				// TODO: mk, not sure if it's needed here at all
+				self.headers_chain.block_inserted_to_storage(&block_hash, &self.best_storage_block.hash);
+
+				// no transactions were accepted
+				// no transactions to reverify
+				Ok(BlockInsertionResult::default())
+			},
+		}
+	}
+
+	/// Forget in-memory block
+	pub fn forget_block(&mut self, hash: &H256) -> HashPosition {
+		self.headers_chain.remove(hash);
+		self.request_times.remove(hash);
+		self.forget_block_leave_header(hash)
+	}
+
+	/// Forget in-memory blocks
+	pub fn forget_blocks(&mut self, hashes: &[H256]) {
+		for hash in hashes {
+			self.forget_block(hash);
+		}
+	}
+
+	/// Forget in-memory block, but leave its header in the headers_chain (orphan queue)
+	pub fn forget_block_leave_header(&mut self, hash: &H256) -> HashPosition {
+		// request time is kept, because received blocks are forgotten right before moving to verifying queue
+		self.requested_by_peer.remove(hash);
+		let position = match self.hash_chain.remove_at(VERIFYING_QUEUE, hash) {
+			HashPosition::Missing => match self.hash_chain.remove_at(REQUESTED_QUEUE, hash) {
+				HashPosition::Missing => self.hash_chain.remove_at(SCHEDULED_QUEUE, hash),
+				position => position,
+			},
+			position => position,
+		};
+		self.update_block_state(hash);
+		if position != HashPosition::Missing {
+			self.headers_chain.mark_orphaned(hash);
+		}
+		position
+	}
+
+	/// Forget in-memory blocks, but leave their headers in the headers_chain (orphan queue)
+	pub fn forget_blocks_leave_header(&mut self, hashes: &[H256]) {
+		for hash in hashes {
+			self.forget_block_leave_header(hash);
+		}
+	}
+
+	/// Forget in-memory block by hash if it is currently in given state
+	#[cfg(test)]
+	pub fn forget_block_with_state(&mut self, hash: &H256, state: BlockState) -> HashPosition {
+		self.headers_chain.remove(hash);
+		self.forget_block_with_state_leave_header(hash, state)
+	}
+
+	/// Forget in-memory block by hash if it is currently in given state
+	pub fn forget_block_with_state_leave_header(&mut self, hash: &H256, state: BlockState) -> HashPosition {
+		let position = self.hash_chain.remove_at(state.to_queue_index(), hash);
+		if position != HashPosition::Missing {
+			self.update_block_state(hash);
+			self.requested_by_peer.remove(hash);
+			self.request_times.remove(hash);
+		}
+		position
+	}
+
+	/// Forget in-memory block by hash.
+	/// Also forget all its known children.
+	pub fn forget_block_with_children(&mut self, hash: &H256) {
+		let mut removal_stack: VecDeque<H256> = VecDeque::new();
+		let mut removal_queue: VecDeque<H256> = VecDeque::new();
+		removal_queue.push_back(hash.clone());
+
+		// remove in reverse order to minimize headers operations
+		while let Some(hash) = removal_queue.pop_front() {
+			removal_queue.extend(self.headers_chain.children(&hash));
+			removal_stack.push_back(hash);
+		}
+		while let Some(hash) = removal_stack.pop_back() {
+			self.forget_block(&hash);
+		}
+	}
+
+	/// Forget all blocks with given state
+	pub fn forget_all_blocks_with_state(&mut self, state: BlockState) {
+		let hashes = self.hash_chain.remove_all_at(state.to_queue_index());
+		for hash in &hashes {
+			self.update_block_state(hash);
+			self.requested_by_peer.remove(hash);
+			self.request_times.remove(hash);
+		}
+		self.headers_chain.remove_n(hashes);
+	}
+
+	/// Get transaction state
+	pub fn transaction_state(&self, hash: &H256) -> TransactionState {
+		if self.verifying_transactions.contains_key(hash) {
+			return TransactionState::Verifying;
+		}
+		if self.storage.contains_transaction(hash) {
+			return TransactionState::Stored;
+		}
+		if self.memory_pool.read().contains(hash) {
+			return TransactionState::InMemory;
+		}
+		TransactionState::Unknown
+	}
+
+	/// Get transactions hashes with given state
+	pub fn transactions_hashes_with_state(&self, state: TransactionState) -> Vec<H256> {
+		match state {
+			TransactionState::InMemory => self.memory_pool.read().get_transactions_ids(),
+			TransactionState::Verifying => self.verifying_transactions.keys().cloned().collect(),
+			_ => panic!("wrong argument"),
+		}
+	}
+
+	/// Add transaction to verifying queue
+	pub fn verify_transaction(&mut self, tx: IndexedTransaction) {
+		self.verifying_transactions.insert(tx.hash.clone(), tx);
+	}
+
+	/// Remove verifying trasaction
+	pub fn forget_verifying_transaction(&mut self, hash: &H256) -> bool {
+		self.verifying_transactions.remove(hash).is_some()
+	}
+
+	/// Remove verifying trasaction + all dependent transactions currently verifying
+	pub fn forget_verifying_transaction_with_children(&mut self, hash: &H256) {
+		self.forget_verifying_transaction(hash);
+
+		// TODO: suboptimal
+		let mut queue: VecDeque<H256> = VecDeque::new();
+		queue.push_back(hash.clone());
+		while let Some(hash) = queue.pop_front() {
+			let all_keys: Vec<_> = self.verifying_transactions.keys().cloned().collect();
+			for h in all_keys {
+				let remove_verifying_transaction = {
+					if let Some(entry) = self.verifying_transactions.get(&h) {
+						if entry.raw.inputs.iter().any(|i| i.previous_output.hash == hash) {
+							queue.push_back(h.clone());
+							true
+						} else {
+							false
+						}
+					} else {
+						// iterating by previously read keys
+						unreachable!()
+					}
+				};
+
+				if remove_verifying_transaction {
+					self.verifying_transactions.remove(&h);
+				}
+			}
+		}
+	}
+
+	/// Get transaction by hash (if it's in memory pool or verifying)
+	pub fn transaction_by_hash(&self, hash: &H256) -> Option<IndexedTransaction> {
+		self.verifying_transactions.get(hash).cloned()
+			.or_else(|| self.memory_pool.read().read_by_hash(hash).cloned().map(|t| t.into()))
+	}
+
+	/// Get memory pool or canon storage transaction by hash.
+	/// When `include_block_info` is true, containing block hash and number of confirmations are also returned
+	#[cfg(test)]
+	pub fn get_transaction(&self, hash: &H256, include_block_info: bool) -> Option<TransactionLocation> {
+		if let Some(transaction) = self.memory_pool.read().read_by_hash(hash).cloned() {
+			return Some(TransactionLocation {
+				transaction: transaction,
+				block_hash: None,
+				confirmations: if include_block_info { Some(0) } else { None },
+			});
+		}
+
+		// only canon transactions have meta
+		let meta = self.storage.transaction_meta(hash)?;
+		let transaction = self.storage.transaction(hash)?;
+		let (block_hash, confirmations) = if include_block_info {
+			(self.storage.block_hash(meta.height()), Some(self.best_storage_block.number - meta.height() + 1))
+		} else {
+			(None, None)
+		};
+
+		Some(TransactionLocation {
+			transaction: transaction,
+			block_hash: block_hash,
+			confirmations: confirmations,
+		})
+	}
+
+	/// Get outputs, spent by inputs of the canon block.
+	/// Only available if undo data was enabled when block was canonized
+	#[cfg(test)]
+	pub fn block_undo(&self, hash: &H256) -> Option<db::BlockUndo> {
+		self.storage.block_undo(hash)
+	}
+
+	/// Get hash of the canon transaction, which has spent given output, and index of the spending input.
+	/// Returns None if output is unspent, unknown or if storage does not maintain spent outputs index
+	#[cfg(test)]
+	pub fn spending_transaction(&self, outpoint: &OutPoint) -> Option<(H256, u32)> {
+		self.storage.output_spender(outpoint)
+			.map(|spender| (spender.transaction_hash, spender.input_index))
+	}
+
+	/// Get unspent output by outpoint.
+	/// When `include_mempool` is true, outputs of memory pool transactions are also considered
+	#[cfg(test)]
+	pub fn get_utxo(&self, outpoint: &OutPoint, include_mempool: bool) -> Option<UtxoEntry> {
+		if include_mempool {
+			let memory_pool = self.memory_pool.read();
+			if memory_pool.is_spent(outpoint) {
+				return None;
+			}
+			if let Some(transaction) = memory_pool.read_by_hash(&outpoint.hash) {
+				return transaction.outputs.get(outpoint.index as usize).map(|output| UtxoEntry {
+					outpoint: outpoint.clone(),
+					value: output.value,
+					script_pubkey: output.script_pubkey.clone(),
+					height: MEMORY_POOL_HEIGHT,
+					is_coinbase: false,
+				});
+			}
+		}
+
+		let meta = self.storage.transaction_meta(&outpoint.hash)?;
+		if meta.is_spent(outpoint.index as usize) != Some(false) {
+			return None;
+		}
+		self.storage.transaction_output(outpoint, usize::max_value()).map(|output| UtxoEntry {
+			outpoint: outpoint.clone(),
+			value: output.value,
+			script_pubkey: output.script_pubkey,
+			height: meta.height(),
+			is_coinbase: meta.is_coinbase(),
+		})
+	}
+
+	/// Get minimal fee rate (satoshis per 1000 bytes) of transactions, accepted to the memory pool:
+	/// relay fee rate of the policy, raised while memory pool evicts transactions because it is full
+	#[cfg(test)]
+	pub fn dynamic_mempool_min_fee(&self) -> u64 {
+		cmp::max(self.standardness_policy.min_relay_fee, self.memory_pool.read().min_fee_rate())
+	}
+
+	/// Check that transaction follows the relay policy (see `set_standardness_policy`).
+	/// Fee is checked against `dynamic_mempool_min_fee` and only when all inputs are known
+	#[cfg(test)]
+	pub fn is_standard(&self, tx: &Transaction) -> Result<(), NonStandardReason> {
+		// orphan transactions are checked when all parents are known
+		let input_value = tx.inputs.iter()
+			.map(|input| self.get_utxo(&input.previous_output, true).map(|utxo| utxo.value))
+			.fold(Some(0u64), |sum, value| match (sum, value) {
+				(Some(sum), Some(value)) => Some(sum + value),
+				_ => None,
+			});
+		let policy = StandardnessPolicy {
+			min_relay_fee: self.dynamic_mempool_min_fee(),
+			..self.standardness_policy
+		};
+		policy.check(tx, input_value)
+	}
+
+	/// Get coin-age priority of transaction: sum of input value * input confirmations, divided by transaction size.
+	/// Outputs of memory pool transactions have zero confirmations. Returns None if any input coin is unknown
+	#[cfg(test)]
+	pub fn input_coin_age(&self, tx: &Transaction) -> Option<f64> {
+		let memory_pool = self.memory_pool.read();
+		let mut coin_age = 0f64;
+		for input in &tx.inputs {
+			let previous_output = &input.previous_output;
+			if let Some(transaction) = memory_pool.read_by_hash(&previous_output.hash) {
+				// zero confirmations => zero age, but the coin must exist
+				transaction.outputs.get(previous_output.index as usize)?;
+				continue;
+			}
+
+			// coin could be already spent by this transaction => not using get_utxo here
+			let meta = self.storage.transaction_meta(&previous_output.hash)?;
+			let output = self.storage.transaction_output(previous_output, usize::max_value())?;
+			let confirmations = self.best_storage_block.number - meta.height() + 1;
+			coin_age += output.value as f64 * confirmations as f64;
+		}
+		Some(coin_age / tx.serialized_size() as f64)
+	}
+
+	/// Get unspent outputs, locked by one of given scripts and having at least `min_confirmations` confirmations.
+	/// Outputs, spent by memory pool transactions, are never included.
+	/// When `include_mempool` is true, outputs of memory pool transactions (with zero confirmations) are also considered
+	#[cfg(test)]
+	pub fn spendable_utxos(&self, scripts: &[Script], min_confirmations: u32, include_mempool: bool) -> Vec<UtxoEntry> {
+		let is_matching_script = |script_pubkey: &Bytes| scripts.iter().any(|script| &**script == &**script_pubkey);
+		let memory_pool = self.memory_pool.read();
+		let mut utxos = Vec::new();
+
+		// TODO: there's no by-script index in the storage => scanning all canon blocks
+		for number in 0..self.best_storage_block.number + 1 {
+			if self.best_storage_block.number - number + 1 < min_confirmations {
+				break;
+			}
+
+			let block_hash = match self.storage.block_hash(number) {
+				Some(block_hash) => block_hash,
+				None => continue,
+			};
+			for transaction in self.storage.block_transactions(block_hash.into()) {
+				let transaction_hash = transaction.hash();
+				for (index, output) in transaction.outputs.iter().enumerate() {
+					if !is_matching_script(&output.script_pubkey) {
+						continue;
+					}
+
+					let outpoint = OutPoint {
+						hash: transaction_hash.clone(),
+						index: index as u32,
+					};
+					if memory_pool.is_spent(&outpoint) {
+						continue;
+					}
+					utxos.extend(self.get_utxo(&outpoint, false));
+				}
+			}
+		}
+
+		if include_mempool && min_confirmations == 0 {
+			for entry in memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp) {
+				for (index, output) in entry.transaction.outputs.iter().enumerate() {
+					let outpoint = OutPoint {
+						hash: entry.hash.clone(),
+						index: index as u32,
+					};
+					if !is_matching_script(&output.script_pubkey) || memory_pool.is_spent(&outpoint) {
+						continue;
+					}
+
+					utxos.push(UtxoEntry {
+						outpoint: outpoint,
+						value: output.value,
+						script_pubkey: output.script_pubkey.clone(),
+						height: MEMORY_POOL_HEIGHT,
+						is_coinbase: false,
+					});
+				}
+			}
+		}
+
+		utxos
+	}
+
+	/// Find unspent outputs of canon storage blocks, which are locked by any of given scripts.
+	/// `progress` is called with (block number, best block number) after every scanned block.
+	/// Scan stops as soon as `cancel` is set, matching outputs found so far are returned
+	#[cfg(test)]
+	pub fn scan_utxo_set(&self, scripts: &HashSet<Script>, mut progress: Option<&mut FnMut(BlockHeight, BlockHeight)>, cancel: &AtomicBool) -> Result<ScanResult, db::Error> {
+		let best_block = self.best_storage_block.clone();
+		let mut result = ScanResult {
+			height: best_block.number,
+			best_block_hash: best_block.hash.clone(),
+			unspents: Vec::new(),
+			total_amount: 0,
+			cancelled: false,
+		};
+
+		// TODO: there's no by-script index in the storage => scanning all canon blocks
+		for number in 0..best_block.number + 1 {
+			if cancel.load(Ordering::Relaxed) {
+				result.cancelled = true;
+				break;
+			}
+
+			let hash = self.storage.block_hash(number)
+				.ok_or_else(|| db::Error::DatabaseError(format!("canon block #{} is missing", number)))?;
+			for transaction in self.storage.block_transactions(hash.into()) {
+				let transaction_hash = transaction.hash();
+				let meta = self.storage.transaction_meta(&transaction_hash)
+					.ok_or_else(|| db::Error::DatabaseError(format!("meta of canon transaction {} is missing", transaction_hash.to_reversed_str())))?;
+				for (index, output) in transaction.outputs.into_iter().enumerate() {
+					if meta.is_spent(index) != Some(false) || !scripts.contains(&Script::new(output.script_pubkey.clone())) {
+						continue;
+					}
+
+					result.total_amount += output.value;
+					result.unspents.push(UtxoEntry {
+						outpoint: OutPoint {
+							hash: transaction_hash.clone(),
+							index: index as u32,
+						},
+						value: output.value,
+						script_pubkey: output.script_pubkey,
+						height: meta.height(),
+						is_coinbase: meta.is_coinbase(),
+					});
+				}
+			}
+
+			if let Some(ref mut progress) = progress {
+				progress(number, best_block.number);
+			}
+		}
+
+		Ok(result)
+	}
+
+	/// Remove memory pool transactions, which have been added more than `max_age` ago (and all their descendants).
+	/// Returns hashes of removed transactions
+	pub fn expire_mempool(&mut self, max_age: Duration) -> Vec<H256> {
+		self.memory_pool.write().remove_expired(max_age)
+			.into_iter()
+			.map(|tx| tx.hash)
+			.collect()
+	}
+
+	/// Abandon memory pool transaction, so that its inputs could be spent again.
+	/// All in-pool descendants are also removed. Returns hashes of all removed transactions
+	#[cfg(test)]
+	pub fn abandon_transaction(&mut self, hash: &H256) -> Result<Vec<H256>, AbandonError> {
+		if self.storage.contains_transaction(hash) {
+			return Err(AbandonError::AlreadyConfirmed);
+		}
+
+		self.memory_pool.write().remove_with_descendants(hash)
+			.map(|removed| removed.into_iter().map(|tx| tx.hash).collect())
+			.ok_or(AbandonError::UnknownTransaction)
+	}
+
+	/// Get hashes of memory pool transactions, which are double-spent by transactions of given block.
+	/// Memory pool does not change. In-pool copies of block transactions are not reported
+	#[cfg(test)]
+	pub fn mempool_conflicts(&self, block: &Block) -> Vec<H256> {
+		let memory_pool = self.memory_pool.read();
+		let mut conflicts = Vec::new();
+		for transaction in &block.transactions {
+			let transaction_hash = transaction.hash();
+			for input in &transaction.inputs {
+				if let Some(spender) = memory_pool.spender(&input.previous_output) {
+					if spender != transaction_hash && !conflicts.contains(&spender) {
+						conflicts.push(spender);
+					}
+				}
+			}
+		}
+		conflicts
+	}
+
+	/// Get hashes of all memory pool transactions in given order
+	#[cfg(test)]
+	pub fn mempool_txids(&self, order: MempoolOrder) -> Vec<H256> {
+		let memory_pool = self.memory_pool.read();
+		match order {
+			MempoolOrder::Unordered => memory_pool.get_transactions_ids(),
+			MempoolOrder::FeeRateDesc => {
+				let mut entries: Vec<_> = memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp).collect();
+				// compare miner_fee / size without division
+				entries.sort_by(|left, right| (right.miner_fee * left.size as i64).cmp(&(left.miner_fee * right.size as i64)));
+				entries.into_iter().map(|entry| entry.hash.clone()).collect()
+			},
+			MempoolOrder::EntryTimeAsc => {
+				let mut entries: Vec<_> = memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp).collect();
+				entries.sort_by_key(|entry| entry.entry_time);
+				entries.into_iter().map(|entry| entry.hash.clone()).collect()
+			},
+		}
+	}
+
+	/// Queue transaction to be announced to peers. Transaction is queued only once
+	#[cfg(test)]
+	pub fn enqueue_for_relay(&mut self, hash: H256) {
+		if !self.relay_queue.contains(&hash) {
+			self.relay_queue.push_back(hash);
+		}
+	}
+
+	/// Remove up to `max` transactions from the relay queue, in the order they have been queued
+	#[cfg(test)]
+	pub fn drain_relay_queue(&mut self, max: usize) -> Vec<H256> {
+		let count = cmp::min(max, self.relay_queue.len() as usize);
+		self.relay_queue.pop_front_n(count as u32)
+	}
+
+	/// Get total size of memory pool transactions with fee rate (satoshis per byte) at or above each of given boundaries.
+	/// Result is sorted by ascending boundary
+	#[cfg(test)]
+	pub fn mempool_fee_histogram(&self, buckets: &[u64]) -> Vec<(u64, u64)> {
+		let mut buckets = buckets.to_vec();
+		buckets.sort();
+		buckets.dedup();
+
+		let memory_pool = self.memory_pool.read();
+		let mut histogram: Vec<(u64, u64)> = buckets.into_iter().map(|bucket| (bucket, 0)).collect();
+		for entry in memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp) {
+			// compare miner_fee / size with boundary without division
+			let miner_fee = cmp::max(entry.miner_fee, 0) as u64;
+			for bucket in histogram.iter_mut() {
+				if miner_fee >= bucket.0.saturating_mul(entry.size as u64) {
+					bucket.1 += entry.size as u64;
+				}
+			}
+		}
+		histogram
+	}
+
+	/// Get aggregated memory pool statistics
+	#[cfg(test)]
+	pub fn mempool_info(&self) -> MempoolInfo {
+		let memory_pool = self.memory_pool.read();
+		let information = memory_pool.information();
+		let (virtual_size, total_fee) = memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp)
+			.fold((0, 0), |(virtual_size, total_fee), entry| (
+				virtual_size + Chain::transaction_virtual_size(&entry.transaction),
+				total_fee + cmp::max(entry.miner_fee, 0) as u64,
+			));
+		MempoolInfo {
+			transactions_count: information.transactions_count,
+			size_in_bytes: information.transactions_size_in_bytes,
+			virtual_size: virtual_size,
+			total_fee: total_fee,
+			max_size_in_bytes: information.max_size_in_bytes,
+		}
+	}
+
+	/// Get total fee and size of memory pool transaction and all its in-pool ancestors
+	#[cfg(test)]
+	pub fn ancestor_package_stats(&self, hash: &H256) -> Option<(u64, usize)> {
+		let memory_pool = self.memory_pool.read();
+		let entry = memory_pool.read_entry_by_hash(hash)?;
+		let ancestors = entry.ancestors.iter().filter_map(|ancestor| memory_pool.read_entry_by_hash(ancestor));
+		Some(iter::once(entry).chain(ancestors).fold((0, 0), |(fee, size), entry|
+			(fee + cmp::max(entry.miner_fee, 0) as u64, size + entry.size)))
+	}
+
+	/// Get (child hash, parent hash) edges of memory pool transactions dependency graph.
+	/// Only edges, where both parent and child are in the memory pool, are returned
+	#[cfg(test)]
+	pub fn mempool_dependency_edges(&self) -> Vec<(H256, H256)> {
+		let memory_pool = self.memory_pool.read();
+		let mut edges = Vec::new();
+		for entry in memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp) {
+			let mut children: Vec<H256> = Vec::new();
+			for index in 0..entry.transaction.outputs.len() {
+				let outpoint = OutPoint {
+					hash: entry.hash.clone(),
+					index: index as u32,
+				};
+				if let Some(child) = memory_pool.spender(&outpoint) {
+					if !children.contains(&child) {
+						children.push(child);
+					}
+				}
+			}
+			edges.extend(children.into_iter().map(|child| (child, entry.hash.clone())));
+		}
+		edges
+	}
+
+	/// Get hashes of memory pool transactions, added after given instant, in entry time order
+	#[cfg(test)]
+	pub fn mempool_added_since(&self, since: Instant) -> Vec<H256> {
+		let memory_pool = self.memory_pool.read();
+		let mut entries: Vec<_> = memory_pool.iter(MemoryPoolOrderingStrategy::ByTimestamp)
+			.filter(|entry| entry.entry_time > since)
+			.collect();
+		entries.sort_by_key(|entry| entry.entry_time);
+		entries.into_iter().map(|entry| entry.hash.clone()).collect()
+	}
+
+	/// Insert transaction to memory pool, if it passes memory pool policy checks.
+	/// Conflicting in-pool transactions are only replaced when replacement is allowed (BIP125)
+	pub fn insert_verified_transaction(&mut self, transaction: IndexedTransaction) -> Result<MemoryPoolInsertionResult, MemoryPoolInsertionError> {
+		let result = try!(self.memory_pool.write().insert(transaction, self.storage.as_transaction_output_provider()));
+		// replaced transactions must not be announced anymore
+		for hash in &result.replaced {
+			self.relay_queue.remove(hash);
+		}
+		Ok(result)
+	}
+
+	/// Called when best storage block is updated
+	fn on_best_storage_block_updated(&mut self) {
+		self.last_tip_update = Some(time::get_time().sec);
+		self.storage_speed_meter.checkpoint();
+
+		// forget subscribers, which have dropped their receivers
+		let best_storage_block = &self.best_storage_block;
+		self.tip_subscribers.retain(|subscriber| subscriber.send(best_storage_block.clone()).is_ok());
+
+		if let Some((every, ref observer)) = self.milestone_observer {
+			if best_storage_block.number % every == 0 {
+				observer(best_storage_block);
+			}
+		}
+	}
+
+	/// Remember storage reorganization
+	fn on_storage_reorganized(&mut self, old_tip: H256, depth: BlockHeight) {
+		if self.recent_reorgs.len() == MAX_RECENT_REORGS {
+			self.recent_reorgs.pop_front();
+		}
+		self.recent_reorgs.push_back(ReorgEvent {
+			old_tip: old_tip,
+			new_tip: self.best_storage_block.hash.clone(),
+			depth: depth,
+			time: time::get_time().sec,
+		});
+	}
+
+	/// Get header of canon storage block, using the `index_cache`
+	fn stored_block_header(&self, number: BlockHeight) -> Option<IndexedBlockHeader> {
+		if self.index_cache_size == 0 {
+			return self.storage.block_header(db::BlockRef::Number(number)).map(Into::into);
+		}
+
+		let mut index_cache = self.index_cache.borrow_mut();
+		if let Some(header) = index_cache.get_refresh(&number) {
+			return Some(header.clone());
+		}
+
+		let header: Option<IndexedBlockHeader> = self.storage.block_header(db::BlockRef::Number(number)).map(Into::into);
+		if let Some(ref header) = header {
+			index_cache.insert(number, header.clone());
+			if index_cache.len() > self.index_cache_size {
+				index_cache.pop_front();
+			}
+		}
+		header
+	}
+
+	/// Forget cached headers of blocks, starting from `first_number`, after canon chain update
+	fn invalidate_index_cache(&self, first_number: BlockHeight) {
+		let mut index_cache = self.index_cache.borrow_mut();
+		let numbers: Vec<_> = index_cache.keys().filter(|number| **number >= first_number).cloned().collect();
+		for number in numbers {
+			index_cache.remove(&number);
+		}
+	}
+
+	/// Update state of block in `blocks_state` after the block has been moved between queues
+	fn update_block_state(&mut self, hash: &H256) {
+		match self.hash_chain.contains_in(hash) {
+			Some(queue_index) => {
+				self.blocks_state.insert(hash.clone(), BlockState::from_queue_index(queue_index));
+			},
+			None => {
+				self.blocks_state.remove(hash);
+			},
+		}
+	}
+
+	/// Update states of blocks in `blocks_state` after the blocks have been moved between queues
+	fn update_blocks_state(&mut self, hashes: &[H256]) {
+		for hash in hashes {
+			self.update_block_state(hash);
+		}
+	}
+
+	/// Check that `blocks_state` agrees with queues
+	#[cfg(test)]
+	fn assert_blocks_state_consistent(&self) {
+		for (hash, state) in &self.blocks_state {
+			assert_eq!(self.hash_chain.contains_in(hash), Some(state.to_queue_index()));
+		}
+		for index in 0..self.hash_chain.len() {
+			let hash = self.hash_chain.at(index).expect("index is less than hash chain length; qed");
+			assert!(self.blocks_state.contains_key(&hash));
+		}
+	}
+
+	/// Is block marked as dead-end or as invalid manually
+	fn is_invalid_block(&self, hash: &H256) -> bool {
+		self.dead_end_blocks.contains(hash) || self.manually_invalid_blocks.contains(hash)
+	}
+
+	/// Forget block and all its in-memory descendants from all queues.
+	/// Returns hashes of forgotten descendants
+	#[cfg(test)]
+	fn forget_branch(&mut self, root: &H256) -> Vec<H256> {
+		let mut descendants = Vec::new();
+		let mut queue = VecDeque::new();
+		queue.push_back(root.clone());
+		while let Some(hash) = queue.pop_front() {
+			let children = self.headers_chain.children(&hash);
+			queue.extend(children.iter().cloned());
+			descendants.extend(children);
+		}
+		self.forget_block_with_children(root);
+		descendants
+	}
+
+	/// Update blocks download speed and latency estimates, when requested block is received
+	fn on_requested_block_received(&mut self, request_time: f64) {
+		let latency = (time::precise_time_s() - request_time).max(0_f64);
+		self.download_latency = Some(match self.download_latency {
+			Some(average) => average + DOWNLOAD_LATENCY_WEIGHT * (latency - average),
+			None => latency,
+		});
+		self.download_speed_meter.checkpoint();
+	}
+
+	/// Drop the oldest buffered header
+	#[cfg(test)]
+	fn pop_oldest_buffered_header(&mut self) {
+		if let Some((hash, header)) = self.buffered_headers.pop_front() {
+			let is_last_child = match self.buffered_headers_by_parent.get_mut(&header.previous_header_hash) {
+				Some(children) => {
+					children.retain(|child| child != &hash);
+					children.is_empty()
+				},
+				None => false,
+			};
+			if is_last_child {
+				self.buffered_headers_by_parent.remove(&header.previous_header_hash);
+			}
+		}
+	}
+
+	/// Notify subscribers about blocks, removed from and added to the canon chain, starting from `first_number`
+	fn on_canon_blocks_updated(&mut self, first_number: BlockHeight, decanonized_route: &[H256], canonized_route: &[H256]) {
+		if self.block_subscribers.is_empty() {
+			return;
+		}
+
+		let events: Vec<_> = decanonized_route.iter().enumerate().rev()
+			.map(|(index, hash)| BlockEvent::Disconnected(first_number + index as BlockHeight, hash.clone()))
+			.chain(canonized_route.iter().enumerate()
+				.map(|(index, hash)| BlockEvent::Connected(first_number + index as BlockHeight, hash.clone())))
+			.collect();
+		// forget subscribers, which have dropped their receivers
+		self.block_subscribers.retain(|subscriber| events.iter().all(|event| subscriber.send(event.clone()).is_ok()));
+	}
+
+	/// Update transactions counter after storage update
+	fn update_total_transactions(&self, canonized_route: &[H256], decanonized_route: &[H256]) {
+		if let Some(total_transactions) = self.total_transactions.get() {
+			let transactions_count = |route: &[H256]| route.iter()
+				.map(|hash| self.storage.block_transaction_hashes(hash.clone().into()).len() as u64)
+				.sum::<u64>();
+			let total_transactions = total_transactions + transactions_count(canonized_route) - transactions_count(decanonized_route);
+			self.total_transactions.set(Some(total_transactions));
+		}
+	}
+
+	/// Update unspent outputs set information after storage update
+	fn update_utxo_set_info(&self, canonized_route: &[H256], decanonized_route: &[H256]) {
+		let mut utxo_set_info = self.utxo_set_info.borrow_mut();
+		let utxo_set_info = match *utxo_set_info {
+			Some(ref mut utxo_set_info) => utxo_set_info,
+			None => return,
+		};
+
+		utxo_set_info.height = self.best_storage_block.number;
+		utxo_set_info.best_block_hash = self.best_storage_block.hash.clone();
+		// digest can't be updated incrementally
+		utxo_set_info.hash_serialized = None;
+
+		// undo transactions in reverse order, so that outputs spent in the same route are restored properly
+		for hash in decanonized_route.iter().rev() {
+			for transaction in self.storage.block_transactions(hash.clone().into()).iter().rev() {
+				self.update_utxo_set_info_with_transaction(utxo_set_info, transaction, false);
+			}
+		}
+		for hash in canonized_route {
+			for transaction in &self.storage.block_transactions(hash.clone().into()) {
+				self.update_utxo_set_info_with_transaction(utxo_set_info, transaction, true);
+			}
+		}
+	}
+
+	/// Add (or remove) transaction outputs to the unspent outputs set information and remove (or restore) spent outputs
+	fn update_utxo_set_info_with_transaction(&self, utxo_set_info: &mut UtxoSetInfo, transaction: &Transaction, connect: bool) {
+		let spent_outputs: Vec<_> = if transaction.is_coinbase() {
+			Vec::new()
+		} else {
+			// decanonized transactions have no meta => can't use transaction_output here
+			transaction.inputs.iter()
+				.filter_map(|input| self.storage.transaction(&input.previous_output.hash)
+					.and_then(|tx| tx.outputs.into_iter().nth(input.previous_output.index as usize)))
+				.collect()
+		};
+
+		let (added_outputs, removed_outputs) = if connect {
+			(&transaction.outputs, &spent_outputs)
+		} else {
+			(&spent_outputs, &transaction.outputs)
+		};
+		for output in added_outputs {
+			utxo_set_info.transaction_outputs += 1;
+			utxo_set_info.total_amount += output.value;
+			utxo_set_info.serialized_size += Chain::utxo_entry_size(output);
+		}
+		for output in removed_outputs {
+			utxo_set_info.transaction_outputs -= 1;
+			utxo_set_info.total_amount -= output.value;
+			utxo_set_info.serialized_size -= Chain::utxo_entry_size(output);
+		}
+	}
+
+	/// Estimated size of serialized unspent output: outpoint + height + coinbase flag + output
+	fn utxo_entry_size(output: &TransactionOutput) -> u64 {
+		(36 + 4 + 1 + output.serialized_size()) as u64
+	}
+
+	/// Find the longest valid side chain, which is longer than `min_block_number` blocks
+	/// and requires decanonization of at most `max_reorg_depth` blocks
+	#[cfg(test)]
+	fn best_side_chain(&self, min_block_number: BlockHeight, max_reorg_depth: BlockHeight) -> Option<db::SideChainOrigin> {
+		let mut best_origin: Option<db::SideChainOrigin> = None;
+		for tip in &self.side_chain_tips {
+			// go back to the canon chain, skipping chains with invalid or not activated blocks
+			let mut canonized_route = Vec::new();
+			let mut hash = tip.clone();
+			let ancestor = loop {
+				if self.is_invalid_block(&hash) || self.not_activated_blocks.contains(&hash) {
+					break None;
+				}
+				if let Some(number) = self.storage.block_number(&hash) {
+					break Some(number);
+				}
+				match self.storage.block_header(hash.clone().into()) {
+					Some(header) => {
+						canonized_route.push(hash);
+						hash = header.previous_header_hash;
+					},
+					None => break None,
+				}
+			};
+
+			let ancestor = match ancestor {
+				Some(ancestor) => ancestor,
+				None => continue,
+			};
+			let block_number = ancestor + canonized_route.len() as BlockHeight;
+			if self.best_storage_block.number - ancestor > max_reorg_depth {
+				continue;
+			}
+			if block_number <= min_block_number {
+				continue;
+			}
+			if let Some(ref best_origin) = best_origin {
+				if block_number < best_origin.block_number {
+					continue;
+				}
+				let best_tip = best_origin.canonized_route.last().expect("side chain block_number is above ancestor; qed");
+				if block_number == best_origin.block_number && self.compare_equal_tips(best_tip, tip).0 {
+					continue;
+				}
+			}
+
+			canonized_route.reverse();
+			best_origin = Some(db::SideChainOrigin {
+				ancestor: ancestor,
+				canonized_route: canonized_route,
+				decanonized_route: (ancestor + 1..self.best_storage_block.number + 1)
+					.filter_map(|number| self.storage.block_hash(number))
+					.collect(),
+				block_number: block_number,
+			});
+		}
+		best_origin
+	}
+
+	/// Compare tips with the same number of blocks. Returns true if the `left` tip is preferred:
+	/// the tip, which has been seen first wins, the tip with the lowest hash wins when first-seen time is unknown
+	#[cfg(test)]
+	fn compare_equal_tips(&self, left: &H256, right: &H256) -> (bool, TipSelectionReason) {
+		match (self.first_seen.get(left), self.first_seen.get(right)) {
+			(Some(left_seen), Some(right_seen)) if left_seen != right_seen => (left_seen < right_seen, TipSelectionReason::FirstSeen),
+			_ => (left < right, TipSelectionReason::LowestHash),
+		}
+	}
+
+	/// Get the tip of the longest stored chain, which is built on top of the best storage block
+	fn reindex_target(&self) -> H256 {
+		let best_block = self.storage.best_block();
+		let mut target = (best_block.hash.clone(), 0);
+		for leaf in self.storage.leaf_blocks() {
+			let mut hash = leaf.clone();
+			let mut blocks_ahead = 0;
+			while hash != best_block.hash && self.storage.block_number(&hash).is_none() {
+				match self.storage.block_header(hash.clone().into()) {
+					Some(header) => hash = header.previous_header_hash,
+					None => break,
+				}
+				blocks_ahead += 1;
+			}
+
+			if hash == best_block.hash && blocks_ahead > target.1 {
+				target = (leaf, blocks_ahead);
+			}
+		}
+		target.0
+	}
+
+	/// Get hashes of all blocks from genesis to the given block
+	fn reindex_route(&self, target: H256) -> Result<Vec<H256>, db::Error> {
+		let mut route = Vec::new();
+		let mut hash = target;
+		while !hash.is_zero() {
+			let header = self.storage.block_header(hash.clone().into())
+				.ok_or_else(|| db::Error::DatabaseError(format!("block {} is missing", hash.to_reversed_str())))?;
+			route.push(hash);
+			hash = header.previous_header_hash;
+		}
+		route.reverse();
+		Ok(route)
+	}
+
+	/// Clear storage indices and canonize blocks from genesis to the reindex target.
+	/// Reindex is continued from the best block if it has been interrupted after indices were cleared
+	fn rebuild_storage_indices(&self, mut progress: Option<&mut FnMut(BlockHeight, BlockHeight)>) -> Result<(), db::Error> {
+		let target = match self.storage.reindex_target() {
+			Some(target) => target,
+			None => self.reindex_target(),
+		};
+		// clearing could be interrupted (and genesis block canonized again on restart) => clear everything again
+		if !self.storage.are_indices_cleared() {
+			self.storage.clear_indices(&target)?;
+		}
+
+		let route = self.reindex_route(target)?;
+		let best_block = self.storage.best_block();
+		let first_index = if best_block.hash.is_zero() {
+			0
+		} else {
+			route.iter()
+				.position(|hash| *hash == best_block.hash)
+				.map(|index| index + 1)
+				.ok_or_else(|| db::Error::DatabaseError("best block is not on the reindex route".into()))?
+		};
+
+		let target_number = route.len() as BlockHeight - 1;
+		for (number, hash) in route.iter().enumerate().skip(first_index) {
+			self.storage.canonize(hash)?;
+			// genesis block is not reported
+			if number == 0 {
+				continue;
+			}
+			if let Some(ref mut progress) = progress {
+				progress(number as BlockHeight, target_number);
+			}
+		}
+
+		self.storage.finish_reindex()
+	}
+
+	/// Reorganize storage to the given side chain (which is already in the storage)
+	#[cfg(test)]
+	fn reorganize_storage(&mut self, origin: db::SideChainOrigin) -> Result<ReorgResult, db::Error> {
+		trace!(target: "sync", "reorganize_storage origin: {:?}", origin);
+
+		let fork = self.storage.fork(origin.clone())?;
+		self.storage.switch_to_fork(fork)?;
+		let old_best_block_hash = self.best_storage_block.hash.clone();
+		self.best_storage_block = self.storage.best_block();
+		self.on_best_storage_block_updated();
+		if !origin.decanonized_route.is_empty() {
+			self.on_storage_reorganized(old_best_block_hash, origin.decanonized_route.len() as BlockHeight);
+		}
+		self.invalidate_index_cache(origin.ancestor + 1);
+		self.on_canon_blocks_updated(origin.ancestor + 1, &origin.decanonized_route, &origin.canonized_route);
+
+		// update side chains tips
+		if let Some(old_best_block_hash) = origin.decanonized_route.last() {
+			self.side_chain_tips.insert(old_best_block_hash.clone());
+		}
+		if let Some(new_best_block_hash) = origin.canonized_route.last() {
+			self.side_chain_tips.remove(new_best_block_hash);
+		}
+
+		// all in-memory blocks are descendants of previous best block
+		let in_memory = (0..self.hash_chain.len())
+			.filter_map(|index| self.hash_chain.at(index))
+			.collect::<Vec<_>>();
+		self.forget_blocks(&in_memory);
+		self.headers_chain = BestHeadersChain::new(self.best_storage_block.hash.clone());
+
+		self.update_total_transactions(&origin.canonized_route, &origin.decanonized_route);
+		self.update_utxo_set_info(&origin.canonized_route, &origin.decanonized_route);
+		let transactions_to_reverify = self.reorganize_transactions(&origin.canonized_route, &origin.decanonized_route);
+		Ok(ReorgResult {
+			decanonized_blocks_hashes: origin.decanonized_route,
+			canonized_blocks_hashes: origin.canonized_route,
+			transactions_to_reverify: transactions_to_reverify,
+		})
+	}
+
+	/// Update transactions after storage reorganization.
+	/// Returns transactions to 'reverify'
+	fn reorganize_transactions(&mut self, canonized_route: &[H256], decanonized_route: &[H256]) -> Vec<IndexedTransaction> {
+		// all transactions from new main branch' blocks were accepted
+		// => delete accepted transactions from verification queue and from the memory pool
+		let new_main_blocks_transactions_hashes = canonized_route.iter()
+			.flat_map(|block_hash| self.storage.block_transaction_hashes(block_hash.clone().into()))
+			.collect::<Vec<_>>();
+
+		let mut memory_pool = self.memory_pool.write();
+		for transaction_accepted in new_main_blocks_transactions_hashes {
+			memory_pool.remove_by_hash(&transaction_accepted);
+			self.verifying_transactions.remove(&transaction_accepted);
+		}
+
+		// reverify all transactions from old main branch' blocks
+		let old_main_blocks_transactions = decanonized_route.iter()
+			.flat_map(|block_hash| self.storage.indexed_block_transactions(block_hash.clone().into()))
+			.collect::<Vec<_>>();
+
+		trace!(target: "sync", "reorganize_transactions, old_main_blocks_transactions: {:?}",
+			   old_main_blocks_transactions.iter().map(|tx| tx.hash.reversed()).collect::<Vec<H256>>());
+
+		// reverify memory pool transactions, sorted by timestamp
+		let memory_pool_transactions_count = memory_pool.information().transactions_count;
+		let memory_pool_transactions: Vec<IndexedTransaction> = memory_pool
+			.remove_n_with_strategy(memory_pool_transactions_count, MemoryPoolOrderingStrategy::ByTimestamp)
+			.into_iter()
+			.map(|t| t.into())
+			.collect();
+
+		// reverify verifying transactions
+		let verifying_transactions: Vec<IndexedTransaction> = self.verifying_transactions
+			.iter()
+			.map(|(_, t)| t.clone())
+			.collect();
+		self.verifying_transactions.clear();
+
+		// order matters: db transactions, then ordered mempool transactions, then ordered verifying transactions
+		old_main_blocks_transactions.into_iter()
+			.chain(memory_pool_transactions.into_iter())
+			.chain(verifying_transactions.into_iter())
+			.collect()
+	}
+
+	/// Check if `hash` is a descendant of `ancestor_hash` in the storage
+	#[cfg(test)]
+	fn is_storage_descendant_of(&self, hash: &H256, ancestor_hash: &H256) -> bool {
+		let ancestor_number = match self.storage.block_header(ancestor_hash.clone().into()) {
+			Some(_) => self.storage.block_number(ancestor_hash),
+			None => return false,
+		};
+
+		let mut hash = hash.clone();
+		while let Some(header) = self.storage.block_header(hash.clone().into()) {
+			if header.previous_header_hash == *ancestor_hash {
+				return true;
+			}
+			// canon blocks can not descend from side chain blocks
+			match (self.storage.block_number(&header.previous_header_hash), ancestor_number) {
+				(Some(_), None) => return false,
+				(Some(number), Some(ancestor_number)) if number < ancestor_number => return false,
+				_ => (),
+			}
+			hash = header.previous_header_hash;
+		}
+		false
+	}
+
+	/// Calculate block locator hashes for hash queue
+	fn block_locator_hashes_for_queue(&self, hashes: &mut Vec<H256>) -> (BlockHeight, BlockHeight) {
+		let queue_len = self.hash_chain.len();
+		if queue_len == 0 {
+			return (0, 1);
+		}
+
+		let mut index = queue_len - 1;
+		let mut step = 1u32;
+		loop {
+			let block_hash = self.hash_chain[index].clone();
+			hashes.push(block_hash);
+
+			if hashes.len() >= 10 {
+				step <<= 1;
+			}
+			if index < step {
+				return (step - index - 1, step);
+			}
+			index -= step;
+		}
+	}
+
+	/// Calculate block locator hashes for storage
+	fn block_locator_hashes_for_storage(&self, mut index: BlockHeight, mut step: BlockHeight, hashes: &mut Vec<H256>) {
+		loop {
+			let block_hash = self.storage.block_hash(index)
+				.expect("private function; index calculated in `block_locator_hashes`; qed");
+			hashes.push(block_hash);
+
+			if hashes.len() >= 10 {
+				step <<= 1;
+			}
+			if index < step {
+				// always include genesis hash
+				if index != 0 {
+					hashes.push(self.genesis_block_hash.clone())
+				}
+
+				break;
+			}
+			index -= step;
+		}
+	}
+}
+
+impl db::TransactionProvider for Chain {
+	fn transaction_bytes(&self, hash: &H256) -> Option<Bytes> {
+		self.memory_pool.read().transaction_bytes(hash)
+			.or_else(|| self.storage.transaction_bytes(hash))
+	}
+
+	fn transaction(&self, hash: &H256) -> Option<Transaction> {
+		self.memory_pool.read().transaction(hash)
+			.or_else(|| self.storage.transaction(hash))
+	}
+}
+
+impl db::BlockHeaderProvider for Chain {
+	fn block_header_bytes(&self, block_ref: db::BlockRef) -> Option<Bytes> {
+		use ser::serialize;
+		self.block_header(block_ref).map(|h| serialize(&h))
+	}
+
+	fn block_header(&self, block_ref: db::BlockRef) -> Option<BlockHeader> {
+		match block_ref {
+			db::BlockRef::Hash(hash) => self.block_header_by_hash(&hash).map(|h| h.raw),
+			db::BlockRef::Number(n) => self.block_header_by_number(n).map(|h| h.raw),
+		}
+	}
+}
+
+impl fmt::Debug for Information {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "[sch:{} / bh:{} / orph:{} -> req:{} -> vfy:{} -> stored: {}]", self.scheduled, self.headers.best, self.orphaned, self.requested, self.verifying, self.stored)
+	}
+}
+
+impl fmt::Debug for Chain {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(writeln!(f, "chain: ["));
+		{
+			let mut num = self.best_storage_block.number;
+			try!(writeln!(f, "\tworse(stored): {} {:?}", 0, self.storage.block_hash(0)));
+			try!(writeln!(f, "\tbest(stored): {} {:?}", num, self.storage.block_hash(num)));
+
+			let queues = vec![
+				("verifying", VERIFYING_QUEUE),
+				("requested", REQUESTED_QUEUE),
+				("scheduled", SCHEDULED_QUEUE),
+			];
+			for (state, queue) in queues {
+				let queue_len = self.hash_chain.len_of(queue);
+				if queue_len != 0 {
+					try!(writeln!(f, "\tworse({}): {} {:?}", state, num + 1, self.hash_chain.front_at(queue)));
+					num += queue_len;
+					if let Some(pre_best) = self.hash_chain.pre_back_at(queue) {
+						try!(writeln!(f, "\tpre-best({}): {} {:?}", state, num - 1, pre_best));
+					}
+					try!(writeln!(f, "\tbest({}): {} {:?}", state, num, self.hash_chain.back_at(queue)));
+				}
+			}
+		}
+		writeln!(f, "]")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate test_data;
+
+	use std::sync::Arc;
+	use parking_lot::RwLock;
+	use chain::{Transaction, TransactionInput, OutPoint, IndexedBlock, IndexedBlockHeader};
+	use chain::constants::{SEQUENCE_FINAL, LOCKTIME_THRESHOLD};
+	use db::BlockChainDatabase;
+	use miner::{MemoryPool, StandardnessPolicy};
+	use network::Magic;
+	use primitives::compact::Compact;
+	use primitives::hash::H256;
+	use script::Script;
+	use verification::constants::DOUBLE_SPACING_SECONDS;
+	use super::{Chain, BlockState, ConnectPoint, TransactionState, BlockInsertionResult, HeadersLookaheadError, InvalidateError, MempoolOrder, AbandonError,
+		BlockLimitError, IntegrityError, InsertError, UtxoView, BlockEvent, GenesisMismatch, MempoolInfo, TipSelectionReason, BlockchainInfo, ScanResult, NonStandardReason,
+		ReorgError, TimestampError, MEMORY_POOL_HEIGHT};
+	use utils::HashPosition;
+
+	/// Genesis block and block with transaction, which outputs are spent by memory pool transactions
+	fn blocks_with_funding_transaction(values: &[u64]) -> (Vec<IndexedBlock>, Transaction) {
+		let genesis = test_data::genesis();
+		let funding: Transaction = values.iter()
+			.fold(test_data::TransactionBuilder::default(), |builder, value| builder.add_output(*value))
+			.into();
+		let b1 = test_data::block_builder()
+			.transaction().coinbase().build()
+			.with_transaction(funding.clone())
+			.merkled_header().parent(genesis.hash()).build()
+			.build();
+		(vec![genesis.into(), b1.into()], funding)
+	}
+
+	#[test]
+	fn chain_empty() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let db_best_block = db.best_block();
+		let chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.information().scheduled, 0);
+		assert_eq!(chain.information().requested, 0);
+		assert_eq!(chain.information().verifying, 0);
+		assert_eq!(chain.information().stored, 1);
+		assert_eq!(chain.length_of_blocks_state(BlockState::Scheduled), 0);
+		assert_eq!(chain.length_of_blocks_state(BlockState::Requested), 0);
+		assert_eq!(chain.length_of_blocks_state(BlockState::Verifying), 0);
+		assert_eq!(chain.length_of_blocks_state(BlockState::Stored), 1);
+		assert_eq!(&chain.best_block(), &db_best_block);
+		assert_eq!(chain.block_state(&db_best_block.hash), BlockState::Stored);
+		assert_eq!(chain.block_state(&H256::from(0)), BlockState::Unknown);
+	}
+
+	#[test]
+	fn chain_block_path() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+
+		// add 6 blocks to scheduled queue
+		let blocks = test_data::build_n_empty_blocks_from_genesis(6, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		let hashes: Vec<_> = headers.iter().map(|h| h.hash.clone()).collect();
+		chain.schedule_blocks_headers(headers.clone()).expect("no lookahead limit");
+		assert!(chain.information().scheduled == 6 && chain.information().requested == 0
+			&& chain.information().verifying == 0 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+
+		// move 2 best blocks to requested queue
+		chain.request_blocks_hashes(2);
+		assert!(chain.information().scheduled == 4 && chain.information().requested == 2
+			&& chain.information().verifying == 0 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+		// move 0 best blocks to requested queue
+		chain.request_blocks_hashes(0);
+		assert!(chain.information().scheduled == 4 && chain.information().requested == 2
+			&& chain.information().verifying == 0 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+		// move 1 best blocks to requested queue
+		chain.request_blocks_hashes(1);
+		assert!(chain.information().scheduled == 3 && chain.information().requested == 3
+			&& chain.information().verifying == 0 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+
+		// try to remove block 0 from scheduled queue => missing
+		assert_eq!(chain.forget_block_with_state(&hashes[0], BlockState::Scheduled), HashPosition::Missing);
+		assert!(chain.information().scheduled == 3 && chain.information().requested == 3
+			&& chain.information().verifying == 0 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+		// remove blocks 0 & 1 from requested queue
+		assert_eq!(chain.forget_block_with_state(&hashes[1], BlockState::Requested), HashPosition::Inside(1));
+		assert_eq!(chain.forget_block_with_state(&hashes[0], BlockState::Requested), HashPosition::Front);
+		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
+			&& chain.information().verifying == 0 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+		// mark 0 & 1 as verifying
+		chain.verify_block(headers[0].clone().into());
+		chain.verify_block(headers[1].clone().into());
+		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
+			&& chain.information().verifying == 2 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+
+		// mark block 0 as verified
+		assert_eq!(chain.forget_block_with_state(&hashes[0], BlockState::Verifying), HashPosition::Front);
+		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
+			&& chain.information().verifying == 1 && chain.information().stored == 1);
+		chain.assert_blocks_state_consistent();
+		// insert new best block to the chain
+		chain.insert_best_block(test_data::block_h1().into()).expect("Db error");
+		assert!(chain.information().scheduled == 3 && chain.information().requested == 1
+			&& chain.information().verifying == 1 && chain.information().stored == 2);
+		chain.assert_blocks_state_consistent();
+		assert_eq!(db.best_block().number, 1);
+	}
+
+	#[test]
+	fn chain_block_locator_hashes() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let genesis_hash = chain.best_block().hash;
+		assert_eq!(chain.block_locator_hashes(), vec![genesis_hash.clone()]);
+
+		let block1 = test_data::block_h1();
+		let block1_hash = block1.hash();
+
+		chain.insert_best_block(block1.into()).expect("Error inserting new block");
+		assert_eq!(chain.block_locator_hashes(), vec![block1_hash.clone(), genesis_hash.clone()]);
+
+		let block2 = test_data::block_h2();
+		let block2_hash = block2.hash();
+
+		chain.insert_best_block(block2.into()).expect("Error inserting new block");
+		assert_eq!(chain.block_locator_hashes(), vec![block2_hash.clone(), block1_hash.clone(), genesis_hash.clone()]);
+
+		let blocks0 = test_data::build_n_empty_blocks_from_genesis(11, 0);
+		let headers0: Vec<IndexedBlockHeader> = blocks0.into_iter().map(|b| b.block_header.into()).collect();
+		let hashes0: Vec<_> = headers0.iter().map(|h| h.hash.clone()).collect();
+		chain.schedule_blocks_headers(headers0.clone()).expect("no lookahead limit");
+		chain.request_blocks_hashes(10);
+		chain.verify_blocks_hashes(10);
+
+		assert_eq!(chain.block_locator_hashes(), vec![
+			hashes0[10].clone(),
+			hashes0[9].clone(),
+			hashes0[8].clone(),
+			hashes0[7].clone(),
+			hashes0[6].clone(),
+			hashes0[5].clone(),
+			hashes0[4].clone(),
+			hashes0[3].clone(),
+			hashes0[2].clone(),
+			hashes0[1].clone(),
+			block2_hash.clone(),
+			genesis_hash.clone(),
+		]);
+
+		let blocks1 = test_data::build_n_empty_blocks_from(6, 0, &headers0[10].raw);
+		let headers1: Vec<IndexedBlockHeader> = blocks1.into_iter().map(|b| b.block_header.into()).collect();
+		let hashes1: Vec<_> = headers1.iter().map(|h| h.hash.clone()).collect();
+		chain.schedule_blocks_headers(headers1.clone()).expect("no lookahead limit");
+		chain.request_blocks_hashes(10);
+
+		assert_eq!(chain.block_locator_hashes(), vec![
+			hashes1[5].clone(),
+			hashes1[4].clone(),
+			hashes1[3].clone(),
+			hashes1[2].clone(),
+			hashes1[1].clone(),
+			hashes1[0].clone(),
+			hashes0[10].clone(),
+			hashes0[9].clone(),
+			hashes0[8].clone(),
+			hashes0[7].clone(),
+			hashes0[5].clone(),
+			hashes0[1].clone(),
+			genesis_hash.clone(),
+		]);
+
+		let blocks2 = test_data::build_n_empty_blocks_from(3, 0, &headers1[5].raw);
+		let headers2: Vec<IndexedBlockHeader> = blocks2.into_iter().map(|b| b.block_header.into()).collect();
+		let hashes2: Vec<_> = headers2.iter().map(|h| h.hash.clone()).collect();
+		chain.schedule_blocks_headers(headers2).expect("no lookahead limit");
+
+		assert_eq!(chain.block_locator_hashes(), vec![
+			hashes2[2].clone(),
+			hashes2[1].clone(),
+			hashes2[0].clone(),
+			hashes1[5].clone(),
+			hashes1[4].clone(),
+			hashes1[3].clone(),
+			hashes1[2].clone(),
+			hashes1[1].clone(),
+			hashes1[0].clone(),
+			hashes0[10].clone(),
+			hashes0[8].clone(),
+			hashes0[4].clone(),
+			genesis_hash.clone(),
+		]);
+	}
+
+	#[test]
+	fn chain_transaction_state() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let genesis_block = test_data::genesis();
+		let block1 = test_data::block_h1();
+		let tx1: Transaction = test_data::TransactionBuilder::with_version(1).into();
+		let tx2: Transaction = test_data::TransactionBuilder::with_version(2).into();
+		let tx1_hash = tx1.hash();
+		let tx2_hash = tx2.hash();
+		chain.verify_transaction(tx1.into());
+		chain.insert_verified_transaction(tx2.into()).expect("transaction is accepted");
+
+		assert_eq!(chain.transaction_state(&genesis_block.transactions[0].hash()), TransactionState::Stored);
+		assert_eq!(chain.transaction_state(&block1.transactions[0].hash()), TransactionState::Unknown);
+		assert_eq!(chain.transaction_state(&tx1_hash), TransactionState::Verifying);
+		assert_eq!(chain.transaction_state(&tx2_hash), TransactionState::InMemory);
+	}
+
+	#[test]
+	fn chain_block_transaction_is_removed_from_on_block_insert() {
+		let b0 = test_data::block_builder().header().build()
+			.transaction().coinbase()
+				.output().value(10).build()
+				.build()
+			.build();
+		let b1 = test_data::block_builder().header().parent(b0.hash()).build()
+			.transaction().coinbase()
+				.output().value(10).build()
+				.build()
+			.transaction()
+				.input().hash(b0.transactions[0].hash()).index(0).build()
+				.build()
+			.build();
+		let tx1 = b1.transactions[0].clone();
+		let tx1_hash = tx1.hash();
+		let tx2 = b1.transactions[1].clone();
+		let tx2_hash = tx2.hash();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![b0.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.verify_transaction(tx1.into());
+		chain.insert_verified_transaction(tx2.into()).expect("transaction is accepted");
+
+		// only one transaction is in the memory pool
+		assert_eq!(chain.information().transactions.transactions_count, 1);
+
+		// when block is inserted to the database => all accepted transactions are removed from mempool && verifying queue
+		chain.insert_best_block(b1.into()).expect("block accepted");
+
+		assert_eq!(chain.information().transactions.transactions_count, 0);
+		assert!(!chain.forget_verifying_transaction(&tx1_hash));
+		assert!(!chain.forget_verifying_transaction(&tx2_hash));
+	}
+
+	#[test]
+	fn chain_forget_verifying_transaction_with_children() {
+		let test_chain = &mut test_data::ChainBuilder::new();
+		test_data::TransactionBuilder::with_output(100).store(test_chain)	// t1
+			.into_input(0).add_output(200).store(test_chain)				// t1 -> t2
+			.into_input(0).add_output(300).store(test_chain)				// t1 -> t2 -> t3
+			.set_default_input(0).set_output(400).store(test_chain);		// t4
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.verify_transaction(test_chain.at(0).into());
+		chain.verify_transaction(test_chain.at(1).into());
+		chain.verify_transaction(test_chain.at(2).into());
+		chain.verify_transaction(test_chain.at(3).into());
+
+		chain.forget_verifying_transaction_with_children(&test_chain.at(0).hash());
+		assert!(!chain.forget_verifying_transaction(&test_chain.at(0).hash()));
+		assert!(!chain.forget_verifying_transaction(&test_chain.at(1).hash()));
+		assert!(!chain.forget_verifying_transaction(&test_chain.at(2).hash()));
+		assert!(chain.forget_verifying_transaction(&test_chain.at(3).hash()));
+	}
+
+	#[test]
+	fn chain_transactions_hashes_with_state() {
+		let test_chain = &mut test_data::ChainBuilder::new();
+		test_data::TransactionBuilder::with_output(100).store(test_chain)	// t1
+			.into_input(0).add_output(200).store(test_chain)				// t1 -> t2
+			.into_input(0).add_output(300).store(test_chain)				// t1 -> t2 -> t3
+			.reset().set_output(400).store(test_chain);					// t4
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(test_chain.at(0).into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(test_chain.at(1).into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(test_chain.at(2).into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(test_chain.at(3).into()).expect("transaction is accepted");
+
+		let chain_transactions = chain.transactions_hashes_with_state(TransactionState::InMemory);
+		assert!(chain_transactions.contains(&test_chain.at(0).hash()));
+		assert!(chain_transactions.contains(&test_chain.at(1).hash()));
+		assert!(chain_transactions.contains(&test_chain.at(2).hash()));
+		assert!(chain_transactions.contains(&test_chain.at(3).hash()));
+	}
+
+	#[test]
+	fn memory_pool_transactions_are_reverified_after_reorganization() {
+		let b0 = test_data::block_builder().header().build().build();
+		let b1 = test_data::block_builder().header().nonce(1).parent(b0.hash()).build().build();
+		let b2 = test_data::block_builder().header().nonce(2).parent(b0.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+
+		let tx1: Transaction = test_data::TransactionBuilder::with_version(1).into();
+		let tx1_hash = tx1.hash();
+		let tx2: Transaction = test_data::TransactionBuilder::with_version(2).into();
+		let tx2_hash = tx2.hash();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![b0.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.verify_transaction(tx1.into());
+		chain.insert_verified_transaction(tx2.into()).expect("transaction is accepted");
+
+		// no reorg
+		let result = chain.insert_best_block(b1.into()).expect("no error");
+		assert_eq!(result.transactions_to_reverify.len(), 0);
+
+		// no reorg
+		let result = chain.insert_best_block(b2.into()).expect("no error");
+		assert_eq!(result.transactions_to_reverify.len(), 0);
+
+		// reorg
+		let result = chain.insert_best_block(b3.into()).expect("no error");
+		assert_eq!(result.transactions_to_reverify.len(), 2);
+		assert!(result.transactions_to_reverify.iter().any(|ref tx| &tx.hash == &tx1_hash));
+		assert!(result.transactions_to_reverify.iter().any(|ref tx| &tx.hash == &tx2_hash));
+	}
+
+	#[test]
+	fn fork_chain_block_transaction_is_removed_from_on_block_insert() {
+		let genesis = test_data::genesis();
+		let b0 = test_data::block_builder().header().parent(genesis.hash()).build().build(); // genesis -> b0
+		let b1 = test_data::block_builder().header().nonce(1).parent(b0.hash()).build()
+			.transaction().output().value(10).build().build()
+			.build(); // genesis -> b0 -> b1[tx1]
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build()
+			.transaction().output().value(20).build().build()
+			.build(); // genesis -> b0 -> b1[tx1] -> b2[tx2]
+		let b3 = test_data::block_builder().header().nonce(2).parent(b0.hash()).build()
+			.transaction().output().value(30).build().build()
+			.build(); // genesis -> b0 -> b3[tx3]
+		let b4 = test_data::block_builder().header().parent(b3.hash()).build()
+			.transaction().output().value(40).build().build()
+			.build(); // genesis -> b0 -> b3[tx3] -> b4[tx4]
+		let b5 = test_data::block_builder().header().parent(b4.hash()).build()
+			.transaction().output().value(50).build().build()
+			.build(); // genesis -> b0 -> b3[tx3] -> b4[tx4] -> b5[tx5]
+
+		let tx1 = b1.transactions[0].clone();
+		let tx1_hash = tx1.hash();
+		let tx2 = b2.transactions[0].clone();
+		let tx2_hash = tx2.hash();
+		let tx3 = b3.transactions[0].clone();
+		let tx4 = b4.transactions[0].clone();
+		let tx5 = b5.transactions[0].clone();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+
+		chain.insert_verified_transaction(tx3.into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx4.into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx5.into()).expect("transaction is accepted");
+
+		assert_eq!(chain.insert_best_block(b0.clone().into()).expect("block accepted"), BlockInsertionResult::with_canonized_blocks(vec![b0.hash()]));
+		assert_eq!(chain.information().transactions.transactions_count, 3);
+		assert_eq!(chain.insert_best_block(b1.clone().into()).expect("block accepted"), BlockInsertionResult::with_canonized_blocks(vec![b1.hash()]));
+		assert_eq!(chain.information().transactions.transactions_count, 3);
+		assert_eq!(chain.insert_best_block(b2.clone().into()).expect("block accepted"), BlockInsertionResult::with_canonized_blocks(vec![b2.hash()]));
+		assert_eq!(chain.information().transactions.transactions_count, 3);
+		assert_eq!(chain.insert_best_block(b3.clone().into()).expect("block accepted"), BlockInsertionResult::default());
+		assert_eq!(chain.information().transactions.transactions_count, 3);
+		assert_eq!(chain.insert_best_block(b4.clone().into()).expect("block accepted"), BlockInsertionResult::default());
+		assert_eq!(chain.information().transactions.transactions_count, 3);
+		// order matters
+		let insert_result = chain.insert_best_block(b5.clone().into()).expect("block accepted");
+		let transactions_to_reverify_hashes: Vec<_> = insert_result
+			.transactions_to_reverify
+			.into_iter()
+			.map(|tx| tx.hash)
+			.collect();
+		assert_eq!(transactions_to_reverify_hashes, vec![tx1_hash, tx2_hash]);
+		assert_eq!(insert_result.canonized_blocks_hashes, vec![b3.hash(), b4.hash(), b5.hash()]);
+		assert_eq!(chain.information().transactions.transactions_count, 0); // tx3, tx4, tx5 are added to the database
+	}
+
+	#[test]
+	fn double_spend_transaction_is_removed_from_memory_pool_when_output_is_spent_in_block_transaction() {
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let b0 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build()
+			.transaction()
+				.lock_time(1)
+				.input().hash(tx0.hash()).index(0).build()
+				.build()
+			.build(); // genesis -> b0[tx1]
+		// tx1 && tx2 are spending same output
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(20).add_input(&tx0, 0).into();
+		let tx3: Transaction = test_data::TransactionBuilder::with_output(30).into();
+
+		// insert tx2 to memory pool
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx3.clone().into()).expect("transaction is accepted");
+		// insert verified block with tx1
+		chain.insert_best_block(b0.into()).expect("no error");
+		// => tx2 is removed from memory pool, but tx3 remains
+		assert_eq!(chain.information().transactions.transactions_count, 1);
+	}
+
+	#[test]
+	fn update_memory_pool_transaction() {
+		use self::test_data::{ChainBuilder, TransactionBuilder};
+
+		let (blocks, funding) = blocks_with_funding_transaction(&[10_000]);
+		let data_chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(9_000).set_input(&funding, 0).lock().store(data_chain)	// funding -> transaction0 (fee: 1_000)
+			.reset().set_input(&funding, 0).add_output(5_000).store(data_chain);			// funding -> transaction1 (fee: 5_000)
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(data_chain.at(0).into()).expect("transaction is accepted");
+		assert_eq!(chain.information().transactions.transactions_count, 1);
+		chain.enqueue_for_relay(data_chain.hash(0));
+		let result = chain.insert_verified_transaction(data_chain.at(1).into()).expect("transaction is accepted");
+		assert_eq!(result.replaced, vec![data_chain.hash(0)]);
+		assert_eq!(chain.information().transactions.transactions_count, 1); // tx was replaces
+		assert!(chain.drain_relay_queue(10).is_empty());
+	}
+
+	#[test]
+	fn chain_schedule_blocks_headers_beyond_lookahead() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_max_header_lookahead(3);
+
+		let blocks = test_data::build_n_empty_blocks_from_genesis(5, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		assert_eq!(chain.schedule_blocks_headers(headers[0..2].to_vec()), Ok(()));
+		assert_eq!(chain.schedule_blocks_headers(headers[2..5].to_vec()), Err(HeadersLookaheadError {
+			hash: headers[3].hash.clone(),
+			blocks_ahead: 4,
+		}));
+		assert_eq!(chain.information().scheduled, 3);
+		assert_eq!(chain.information().headers.best, 3);
+		assert_eq!(chain.schedule_blocks_headers(headers[3..5].to_vec()), Err(HeadersLookaheadError {
+			hash: headers[3].hash.clone(),
+			blocks_ahead: 4,
+		}));
+		assert_eq!(chain.information().scheduled, 3);
+	}
+
+	#[test]
+	fn chain_schedule_blocks_headers_prioritized() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+
+		let blocks = test_data::build_n_empty_blocks_from_genesis(5, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		chain.schedule_blocks_headers(headers[0..1].to_vec()).expect("no error");
+		chain.schedule_blocks_headers_prioritized(headers[1..5].to_vec(), &[1, 3, 3, 2]).expect("no error");
+		assert_eq!(chain.information().scheduled, 5);
+		assert_eq!(chain.information().headers.best, 5);
+		assert_eq!(chain.request_blocks_hashes(3), vec![headers[2].hash.clone(), headers[3].hash.clone(), headers[4].hash.clone()]);
+		assert_eq!(chain.request_blocks_hashes(2), vec![headers[1].hash.clone(), headers[0].hash.clone()]);
+	}
+
+	#[test]
+	fn chain_get_utxo() {
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(20).add_input(&tx0, 0).into();
+		let tx0_output = OutPoint { hash: tx0.hash(), index: 0 };
+		let tx1_output = OutPoint { hash: tx1.hash(), index: 0 };
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let utxo = chain.get_utxo(&tx0_output, true).expect("genesis coinbase output is unspent");
+		assert_eq!(utxo.value, tx0.outputs[0].value);
+		assert_eq!(utxo.height, 0);
+		assert!(utxo.is_coinbase);
+		assert_eq!(chain.get_utxo(&OutPoint { hash: tx0.hash(), index: 1 }, true), None);
+
+		chain.insert_verified_transaction(tx1.into()).expect("transaction is accepted");
+		assert_eq!(chain.get_utxo(&tx0_output, true), None);
+		assert_eq!(chain.get_utxo(&tx0_output, false), Some(utxo));
+		assert_eq!(chain.get_utxo(&tx1_output, false), None);
+		let utxo = chain.get_utxo(&tx1_output, true).expect("memory pool output is unspent");
+		assert_eq!(utxo.value, 20);
+		assert_eq!(utxo.height, MEMORY_POOL_HEIGHT);
+		assert!(!utxo.is_coinbase);
+	}
+
+	#[test]
+	fn chain_invalidate_block_switches_to_side_chain() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, b2.hash());
+
+		let result = chain.invalidate_block(&b1.hash()).expect("no error");
+		assert_eq!(result.decanonized_blocks_hashes, vec![b1.hash(), b2.hash()]);
+		assert_eq!(result.canonized_blocks_hashes, vec![s1.hash()]);
+		assert_eq!(chain.best_storage_block().hash, s1.hash());
+		assert_eq!(chain.block_state(&b1.hash()), BlockState::DeadEnd);
+		assert_eq!(chain.block_state(&b2.hash()), BlockState::DeadEnd);
+
+		chain.reconsider_block(&b1.hash()).expect("no error");
+		assert_eq!(chain.block_state(&b1.hash()), BlockState::Stored);
+		assert_eq!(chain.block_state(&b2.hash()), BlockState::Stored);
+	}
+
+	#[test]
+	fn chain_invalidate_block_rolls_back_to_parent() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+
+		assert_eq!(chain.invalidate_block(&genesis.hash()), Err(InvalidateError::GenesisBlock));
+		assert_eq!(chain.invalidate_block(&H256::from(1)), Err(InvalidateError::UnknownBlock));
+
+		let result = chain.invalidate_block(&b1.hash()).expect("no error");
+		assert_eq!(result.decanonized_blocks_hashes, vec![b1.hash()]);
+		assert!(result.canonized_blocks_hashes.is_empty());
+		assert_eq!(chain.best_storage_block().hash, genesis.hash());
+	}
+
+	#[test]
+	fn chain_state_snapshot() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let snapshot = chain.state_snapshot();
+		assert_eq!(snapshot.stored, 1);
+		assert_eq!(snapshot.last_tip_update, None);
+
+		chain.insert_best_block(test_data::block_h1().into()).expect("no error");
+		let snapshot = chain.state_snapshot();
+		assert_eq!(snapshot.stored, 2);
+		assert_eq!(snapshot.best_storage_block_hash, test_data::block_h1().hash().to_reversed_str());
+		assert!(snapshot.last_tip_update.is_some());
+		assert!(snapshot.fork_tips.is_empty());
+	}
+
+	#[test]
+	fn chain_block_has_duplicate_txids() {
+		let empty = test_data::block_builder().header().build().build();
+		assert!(!Chain::block_has_duplicate_txids(&empty));
+
+		let unique = test_data::block_builder().header().build()
+			.transaction().output().value(10).build().build()
+			.transaction().output().value(20).build().build()
+			.build();
+		assert!(!Chain::block_has_duplicate_txids(&unique));
+
+		let duplicates = test_data::block_builder().header().build()
+			.transaction().output().value(10).build().build()
+			.transaction().output().value(20).build().build()
+			.transaction().output().value(10).build().build()
+			.build();
+		assert!(Chain::block_has_duplicate_txids(&duplicates));
+	}
+
+	#[test]
+	fn chain_is_final_transaction() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let transaction = |lock_time: u32, sequence: u32| Transaction {
+			version: 1,
+			inputs: vec![
+				TransactionInput { previous_output: OutPoint { hash: H256::from(1), index: 0 }, script_sig: Default::default(), sequence: SEQUENCE_FINAL },
+				TransactionInput { previous_output: OutPoint { hash: H256::from(2), index: 0 }, script_sig: Default::default(), sequence: sequence },
+			],
+			outputs: vec![],
+			lock_time: lock_time,
+		};
+
+		// no locktime
+		assert!(chain.is_final_transaction(&transaction(0, 0), 0, 0));
+
+		// height-based locktime
+		let tx = transaction(100, 0);
+		assert!(!chain.is_final_transaction(&tx, 99, u32::max_value()));
+		assert!(!chain.is_final_transaction(&tx, 100, u32::max_value()));
+		assert!(chain.is_final_transaction(&tx, 101, 0));
+
+		// time-based locktime
+		let tx = transaction(LOCKTIME_THRESHOLD + 100, 0);
+		assert!(!chain.is_final_transaction(&tx, u32::max_value(), LOCKTIME_THRESHOLD + 99));
+		assert!(!chain.is_final_transaction(&tx, u32::max_value(), LOCKTIME_THRESHOLD + 100));
+		assert!(chain.is_final_transaction(&tx, 0, LOCKTIME_THRESHOLD + 101));
+
+		// final sequences disable locktime
+		assert!(chain.is_final_transaction(&transaction(100, SEQUENCE_FINAL), 0, 0));
+		assert!(chain.is_final_transaction(&transaction(LOCKTIME_THRESHOLD + 100, SEQUENCE_FINAL), 0, 0));
+	}
+
+	#[test]
+	fn chain_coinbase_height_matches() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_network(Magic::Testnet);
+
+		// 21111 is BIP34 activation height on testnet
+		let encoded = test_data::block_builder()
+			.transaction()
+				.input().coinbase().signature("02775200").build()
+				.output().value(50).build()
+				.build()
+			.header().build()
+			.build();
+		let not_encoded = test_data::block_builder()
+			.transaction()
+				.coinbase()
+				.output().value(50).build()
+				.build()
+			.header().build()
+			.build();
+
+		assert!(chain.coinbase_height_matches(&encoded, 21111));
+		assert!(!chain.coinbase_height_matches(&encoded, 21112));
+		assert!(chain.coinbase_height_matches(&not_encoded, 21110));
+		assert!(!chain.coinbase_height_matches(&not_encoded, 21111));
+		assert!(!chain.coinbase_height_matches(&test_data::block_builder().header().build().build(), 21111));
+
+		chain.set_network(Magic::Mainnet);
+		assert!(chain.coinbase_height_matches(&not_encoded, 21111));
+	}
+
+	#[test]
+	fn chain_blocks_behind() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.blocks_behind(), None);
+
+		chain.update_peer_best_height(10);
+		chain.update_peer_best_height(5);
+		assert_eq!(chain.blocks_behind(), Some(10));
+
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		chain.schedule_blocks_headers(blocks.into_iter().map(|b| b.block_header.into()).collect()).expect("no error");
+		assert_eq!(chain.blocks_behind(), Some(7));
+
+		chain.update_peer_best_height(3);
+		assert_eq!(chain.blocks_behind(), Some(7));
+	}
+
+	#[test]
+	fn chain_to_dot() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.schedule_blocks_headers(vec![b2.block_header.clone().into(), b3.block_header.clone().into()]).expect("no error");
+		chain.request_blocks_hashes(1);
+
+		let dot = chain.to_dot();
+		assert!(dot.starts_with("digraph chain {\n"));
+		assert!(dot.ends_with("}\n"));
+		let node = |hash: H256, number: u32, color: &str, peripheries: u32| {
+			let hash = hash.to_reversed_str();
+			format!("\t\"{}\" [label=\"{}\\n{}\", style=filled, fillcolor={}, peripheries={}];\n", hash, number, &hash[..16], color, peripheries)
+		};
+		let edge = |from: H256, to: H256| format!("\t\"{}\" -> \"{}\";\n", from.to_reversed_str(), to.to_reversed_str());
+		assert!(dot.contains(&node(genesis.hash(), 0, "gray", 1)));
+		assert!(dot.contains(&node(b1.hash(), 1, "gray", 1)));
+		assert!(dot.contains(&node(s1.hash(), 1, "lightgray", 1)));
+		assert!(dot.contains(&node(b2.hash(), 2, "yellow", 1)));
+		assert!(dot.contains(&node(b3.hash(), 3, "lightblue", 2)));
+		assert!(dot.contains(&edge(genesis.hash(), s1.hash())));
+		assert!(dot.contains(&edge(b1.hash(), b2.hash())));
+		assert!(dot.contains(&edge(b2.hash(), b3.hash())));
+	}
+
+	#[test]
+	fn chain_should_request() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		assert!(chain.should_request(&headers[0].hash));
+
+		chain.schedule_blocks_headers(headers[0..2].to_vec()).expect("no lookahead limit");
+		chain.request_blocks_hashes(1);
+		assert!(!chain.should_request(&test_data::genesis().hash()));
+		assert!(!chain.should_request(&headers[0].hash));
+		assert!(!chain.should_request(&headers[1].hash));
+		assert!(chain.should_request(&headers[2].hash));
+
+		chain.mark_dead_end_block(&headers[2].hash);
+		assert!(!chain.should_request(&headers[2].hash));
+	}
+
+	#[test]
+	fn chain_unknown_hashes() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let blocks = test_data::build_n_empty_blocks_from_genesis(4, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		chain.schedule_blocks_headers(headers[0..2].to_vec()).expect("no lookahead limit");
+		chain.mark_dead_end_block(&headers[3].hash);
+
+		let hashes = vec![
+			headers[2].hash.clone(),
+			test_data::genesis().hash(),
+			headers[0].hash.clone(),
+			H256::from(1),
+			headers[3].hash.clone(),
+			headers[1].hash.clone(),
+		];
+		assert_eq!(chain.unknown_hashes(&hashes), vec![&hashes[0], &hashes[3]]);
+		assert!(chain.unknown_hashes(&[]).is_empty());
+	}
+
+	#[test]
+	fn chain_total_transaction_count() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build()
+			.transaction().output().value(10).build().build()
+			.transaction().output().value(20).build().build()
+			.build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build()
+			.transaction().output().value(30).build().build()
+			.build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build()
+			.transaction().output().value(40).build().build()
+			.build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.total_transaction_count(), Ok(1));
+		chain.insert_best_block(b1.into()).expect("no error");
+		assert_eq!(chain.total_transaction_count(), Ok(3));
+		chain.insert_best_block(s1.into()).expect("no error");
+		assert_eq!(chain.total_transaction_count(), Ok(3));
+		chain.insert_best_block(s2.into()).expect("no error");
+		assert_eq!(chain.total_transaction_count(), Ok(3));
+
+		// counter is rebuilt from the storage
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.total_transaction_count(), Ok(3));
+	}
+
+	#[test]
+	fn chain_refuses_too_deep_reorganization() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+		let s3 = test_data::block_builder().header().parent(s2.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_max_reorg_depth(1);
+		chain.insert_best_block(b1.into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		assert_eq!(chain.insert_best_block(s3.clone().into()), Err(ReorgError::TooDeep { depth: 2 }));
+		assert_eq!(chain.best_storage_block().hash, b2.hash());
+		assert!(chain.storage().contains_block(s3.hash().into()));
+		// s3 is stored, but not activated => it is not a dead-end
+		assert_eq!(chain.block_state(&s3.hash()), BlockState::Stored);
+
+		// descendants of not activated block are also not activated
+		let s4 = test_data::block_builder().header().parent(s3.hash()).build().build();
+		assert_eq!(chain.insert_best_block(s4.clone().into()), Err(ReorgError::TooDeep { depth: 2 }));
+		assert_eq!(chain.block_state(&s4.hash()), BlockState::Stored);
+		chain.set_max_reorg_depth(2);
+		assert_eq!(chain.activate_best_chain(), Ok(None));
+
+		let result = chain.reconsider_block(&s3.hash()).expect("no error").expect("reorganization");
+		assert_eq!(result.canonized_blocks_hashes, vec![s1.hash(), s2.hash(), s3.hash(), s4.hash()]);
+		assert_eq!(chain.best_storage_block().hash, s4.hash());
+	}
+
+	#[test]
+	fn chain_activate_best_chain() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+		let s3 = test_data::block_builder().header().parent(s2.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_max_reorg_depth(1);
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		assert_eq!(chain.insert_best_block(s3.clone().into()), Err(ReorgError::TooDeep { depth: 2 }));
+		assert_eq!(chain.activate_best_chain(), Ok(None));
+
+		// s3 is not invalid anymore, but reorganization is still too deep
+		assert_eq!(chain.reconsider_block(&s3.hash()), Ok(None));
+		assert_eq!(chain.activate_best_chain(), Ok(None));
+
+		chain.set_max_reorg_depth(2);
+		let result = chain.activate_best_chain().expect("no error").expect("reorganization");
+		assert_eq!(result.decanonized_blocks_hashes, vec![b1.hash(), b2.hash()]);
+		assert_eq!(result.canonized_blocks_hashes, vec![s1.hash(), s2.hash(), s3.hash()]);
+		assert_eq!(chain.best_storage_block().hash, s3.hash());
+		assert_eq!(chain.activate_best_chain(), Ok(None));
+	}
+
+	#[test]
+	fn chain_recent_reorgs() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		assert!(chain.recent_reorgs(10).is_empty());
+
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		chain.insert_best_block(b3.clone().into()).expect("no error");
+		let reorgs = chain.recent_reorgs(10);
+		assert_eq!(reorgs.len(), 2);
+		assert_eq!((reorgs[0].old_tip.clone(), reorgs[0].new_tip.clone(), reorgs[0].depth), (s2.hash(), b3.hash(), 2));
+		assert_eq!((reorgs[1].old_tip.clone(), reorgs[1].new_tip.clone(), reorgs[1].depth), (b1.hash(), s2.hash(), 1));
+		assert_eq!(chain.recent_reorgs(1), vec![reorgs[0].clone()]);
+
+		chain.rollback_storage_to(&b1.hash()).expect("no error");
+		let reorgs = chain.recent_reorgs(10);
+		assert_eq!(reorgs.len(), 3);
+		assert_eq!((reorgs[0].old_tip.clone(), reorgs[0].new_tip.clone(), reorgs[0].depth), (b3.hash(), b1.hash(), 2));
+	}
+
+	#[test]
+	fn chain_block_verbose() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(test_data::block_h1().into()).expect("no error");
+		chain.insert_best_block(test_data::block_h2().into()).expect("no error");
+
+		let block = chain.block_verbose(&test_data::block_h1().hash(), false).expect("stored block");
+		assert_eq!(block.height, Some(1));
+		assert_eq!(block.confirmations, 2);
+		assert_eq!(block.next_block_hash, Some(test_data::block_h2().hash()));
+		assert_eq!(block.transactions_hashes, vec![test_data::block_h1().transactions[0].hash()]);
+		assert_eq!(block.transactions, None);
+
+		let block = chain.block_verbose(&test_data::block_h2().hash(), true).expect("stored block");
+		assert_eq!(block.confirmations, 1);
+		assert_eq!(block.next_block_hash, None);
+		assert_eq!(block.transactions, Some(test_data::block_h2().transactions));
+
+		assert_eq!(chain.block_verbose(&H256::from(1), true), None);
+	}
+
+	#[test]
+	fn chain_spendable_utxos() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(10).script_pubkey("51").build().build()
+			.transaction().output().value(20).script_pubkey("51").build().build()
+			.transaction().output().value(30).script_pubkey("52").build().build()
+			.build();
+		let b1_tx0 = b1.transactions[0].clone();
+		let b1_tx1 = b1.transactions[1].clone();
+		let tx: Transaction = test_data::TransactionBuilder::with_output(5).add_input(&b1_tx1, 0).into();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.into()).expect("no error");
+
+		let scripts: Vec<Script> = vec!["51".into()];
+		let utxos = chain.spendable_utxos(&scripts, 1, false);
+		assert_eq!(utxos.iter().map(|utxo| utxo.value).collect::<Vec<_>>(), vec![10, 20]);
+		assert!(utxos[0].is_coinbase);
+		assert_eq!(utxos[1].outpoint, OutPoint { hash: b1_tx1.hash(), index: 0 });
+		assert!(chain.spendable_utxos(&scripts, 2, false).is_empty());
+
+		// output spent by memory pool transaction is excluded
+		chain.insert_verified_transaction(tx.into()).expect("transaction is accepted");
+		let utxos = chain.spendable_utxos(&scripts, 1, false);
+		assert_eq!(utxos.len(), 1);
+		assert_eq!(utxos[0].outpoint, OutPoint { hash: b1_tx0.hash(), index: 0 });
+	}
+
+	#[test]
+	fn chain_header_chain_digest() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into(), test_data::block_h1().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let headers: Vec<IndexedBlockHeader> = test_data::build_n_empty_blocks_from(2, 0, &test_data::block_h1().block_header)
+			.into_iter().map(|b| b.block_header.into()).collect();
+		chain.schedule_blocks_headers(headers).expect("no lookahead limit");
+
+		let genesis_digest = chain.header_chain_digest(0).expect("genesis header is known");
+		assert!(genesis_digest != chain.header_chain_digest(1).expect("stored header is known"));
+		assert!(chain.header_chain_digest(3).is_some());
+		assert_eq!(chain.header_chain_digest(4), None);
+
+		// digest does not depend on headers after given height
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.header_chain_digest(0), Some(genesis_digest));
+		assert_eq!(chain.header_chain_digest(1), None);
+	}
+
+	#[test]
+	fn chain_expire_mempool() {
+		use std::collections::HashSet;
+		use std::thread;
+		use std::time::Duration;
+
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(10).into();
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(20).add_input(&tx1, 0).into();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
+		assert!(chain.expire_mempool(Duration::from_secs(60 * 60)).is_empty());
+		assert_eq!(chain.information().transactions.transactions_count, 2);
+
+		thread::sleep(Duration::from_millis(20));
+		let expired: HashSet<_> = chain.expire_mempool(Duration::from_millis(10)).into_iter().collect();
+		assert_eq!(expired, vec![tx1.hash(), tx2.hash()].into_iter().collect());
+		assert_eq!(chain.information().transactions.transactions_count, 0);
+	}
+
+	#[test]
+	fn chain_mempool_txids() {
+		use std::collections::HashSet;
+		use std::thread;
+		use std::time::Duration;
+
+		let (blocks, funding) = blocks_with_funding_transaction(&[100, 100]);
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(90).add_input(&funding, 0).into();
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(70).add_input(&funding, 1).into();
+		let tx3: Transaction = test_data::TransactionBuilder::with_output(85).add_input(&tx1, 0).into();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert!(chain.mempool_txids(MempoolOrder::Unordered).is_empty());
+
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		thread::sleep(Duration::from_millis(2));
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
+		thread::sleep(Duration::from_millis(2));
+		chain.insert_verified_transaction(tx3.clone().into()).expect("transaction is accepted");
+
+		let unordered: HashSet<_> = chain.mempool_txids(MempoolOrder::Unordered).into_iter().collect();
+		assert_eq!(unordered, vec![tx1.hash(), tx2.hash(), tx3.hash()].into_iter().collect());
+		assert_eq!(chain.mempool_txids(MempoolOrder::EntryTimeAsc), vec![tx1.hash(), tx2.hash(), tx3.hash()]);
+		assert_eq!(chain.mempool_txids(MempoolOrder::FeeRateDesc)[0], tx2.hash());
+	}
+
+	#[test]
+	fn chain_mempool_added_since() {
+		use std::thread;
+		use std::time::{Duration, Instant};
+
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(10).into();
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(20).into();
+		let tx3: Transaction = test_data::TransactionBuilder::with_output(30).into();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let start = Instant::now();
+		thread::sleep(Duration::from_millis(2));
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		thread::sleep(Duration::from_millis(2));
+		let last_relay = Instant::now();
+		thread::sleep(Duration::from_millis(2));
+		chain.insert_verified_transaction(tx3.clone().into()).expect("transaction is accepted");
+		thread::sleep(Duration::from_millis(2));
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
+
+		assert_eq!(chain.mempool_added_since(start), vec![tx1.hash(), tx3.hash(), tx2.hash()]);
+		assert_eq!(chain.mempool_added_since(last_relay), vec![tx3.hash(), tx2.hash()]);
+		assert!(chain.mempool_added_since(Instant::now()).is_empty());
+	}
+
+	#[test]
+	fn chain_inv_after_locator() {
+		let genesis = test_data::genesis();
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(test_data::block_h1().into()).expect("no error");
+		chain.insert_best_block(test_data::block_h2().into()).expect("no error");
+
+		let h1 = test_data::block_h1().hash();
+		let h2 = test_data::block_h2().hash();
+		assert_eq!(chain.inv_after_locator(&[genesis.hash()], &H256::default(), 500), vec![h1.clone(), h2.clone()]);
+		assert_eq!(chain.inv_after_locator(&[H256::from(1), h1.clone()], &H256::default(), 500), vec![h2.clone()]);
+		assert_eq!(chain.inv_after_locator(&[genesis.hash()], &h2, 500), vec![h1.clone()]);
+		assert_eq!(chain.inv_after_locator(&[genesis.hash()], &H256::default(), 1), vec![h1]);
+		assert!(chain.inv_after_locator(&[h2], &H256::default(), 500).is_empty());
+		assert!(chain.inv_after_locator(&[H256::from(1)], &H256::default(), 500).is_empty());
+	}
+
+	#[test]
+	fn chain_rejects_block_with_invalid_timestamp() {
+		let genesis = test_data::genesis();
+		let genesis_time = genesis.block_header.time;
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).time(genesis_time).build().build();
+		let b2 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).time(genesis_time + 1).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).time(u32::max_value()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_check_block_timestamps(true);
+		assert_eq!(chain.tip_timestamp(), genesis_time);
+		let (b1_hash, b2_hash) = (b1.hash(), b2.hash());
+		assert_eq!(chain.insert_best_block(b1.into()), Err(ReorgError::Timestamp(TimestampError::BeforeMedianTimePast {
+			time: genesis_time,
+			median_time_past: genesis_time,
+		})));
+		// rejected block is not remembered as seen
+		assert!(!chain.first_seen.contains_key(&b1_hash));
+		chain.insert_best_block(b2.into()).expect("no error");
+		assert_eq!(chain.tip_timestamp(), genesis_time + 1);
+		assert!(chain.first_seen.contains_key(&b2_hash));
+		match chain.insert_best_block(b3.into()) {
+			Err(ReorgError::Timestamp(TimestampError::FutureTime { time, .. })) => assert_eq!(time, u32::max_value()),
+			result => panic!("unexpected result: {:?}", result),
+		}
+	}
+
+	#[test]
+	fn chain_expected_bits_testnet_min_difficulty() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).time(genesis.block_header.time + 600)
+			.bits(Compact::new(0x1c00ffff)).build().build();
+		let at_boundary = test_data::block_builder().header().parent(b1.hash())
+			.time(b1.block_header.time + DOUBLE_SPACING_SECONDS).build().build();
+		let after_boundary = test_data::block_builder().header().parent(b1.hash())
+			.time(b1.block_header.time + DOUBLE_SPACING_SECONDS + 1).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into(), b1.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.expected_bits(&after_boundary.block_header), Some(Compact::new(0x1c00ffff)));
+
+		chain.set_network(Magic::Testnet);
+		assert_eq!(chain.expected_bits(&at_boundary.block_header), Some(Compact::new(0x1c00ffff)));
+		assert_eq!(chain.expected_bits(&after_boundary.block_header), Some(Magic::Testnet.max_bits()));
+
+		let unknown_parent = test_data::block_builder().header().parent(H256::from(1)).build().build();
+		assert_eq!(chain.expected_bits(&unknown_parent.block_header), None);
+	}
+
+	#[test]
+	fn chain_to_compact_block() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.transaction().output().value(20).build().build()
+			.transaction().output().value(30).build().build()
+			.build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+
+		let compact_block = chain.to_compact_block(&b1.hash(), &[0, 5], 42).expect("stored block");
+		assert_eq!(compact_block.header.header, b1.block_header);
+		assert_eq!(compact_block.header.nonce, 42);
+		assert_eq!(compact_block.header.short_ids.len(), 2);
+		assert_eq!(compact_block.header.prefilled_transactions.len(), 1);
+		assert_eq!(compact_block.header.prefilled_transactions[0].index, 0);
+		assert_eq!(compact_block, chain.to_compact_block(&b1.hash(), &[0], 42).expect("stored block"));
+		assert!(compact_block != chain.to_compact_block(&b1.hash(), &[0], 43).expect("stored block"));
+
+		assert_eq!(chain.to_compact_block(&H256::from(1), &[], 42), None);
+	}
+
+	#[test]
+	fn chain_new_with_recovery() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		{
+			let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+			chain.insert_best_block(b1.clone().into()).expect("no error");
+			chain.insert_best_block(s1.clone().into()).expect("no error");
+			assert_eq!(chain.information().fork_tips, vec![s1.hash().to_reversed_str()]);
+		}
+
+		let chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		assert!(chain.information().fork_tips.is_empty());
+
+		let chain = Chain::new_with_recovery(db.clone(), Arc::new(RwLock::new(MemoryPool::new())), 0);
+		assert!(chain.information().fork_tips.is_empty());
+
+		let mut chain = Chain::new_with_recovery(db.clone(), Arc::new(RwLock::new(MemoryPool::new())), 16);
+		assert_eq!(chain.information().fork_tips, vec![s1.hash().to_reversed_str()]);
+
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, s2.hash());
+		assert_eq!(chain.information().fork_tips, vec![b1.hash().to_reversed_str()]);
+	}
+
+	#[test]
+	fn chain_retarget_info() {
+		let genesis = test_data::genesis();
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let info = chain.retarget_info();
+		assert_eq!(info.blocks_until_retarget, 2016);
+		assert_eq!(info.period_timespan, 0);
+		assert_eq!(info.current_bits, genesis.block_header.bits);
+		assert_eq!(info.projected_bits, genesis.block_header.bits);
+
+		let build_chain = |spacing: u32| {
+			let mut blocks: Vec<IndexedBlock> = vec![genesis.clone().into()];
+			for i in 1..4 {
+				let block = test_data::block_builder().header()
+					.parent(blocks[i - 1].hash().clone())
+					.time(genesis.block_header.time + spacing * i as u32)
+					.bits(Compact::new(0x1c00ffff))
+					.build().build();
+				blocks.push(block.into());
+			}
+			let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
+			Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())))
+		};
+
+		let info = build_chain(300).retarget_info();
+		assert_eq!(info.blocks_until_retarget, 2013);
+		assert_eq!(info.period_timespan, 900);
+		assert_eq!(info.current_bits, Compact::new(0x1c00ffff));
+		assert!(info.projected_bits.to_f64() > info.current_bits.to_f64());
+
+		let info = build_chain(1200).retarget_info();
+		assert_eq!(info.period_timespan, 3600);
+		assert!(info.projected_bits.to_f64() < info.current_bits.to_f64());
+	}
+
+	#[test]
+	fn chain_abandon_transaction() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.build();
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(10).add_input(&b1.transactions[0], 0).into();
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(5).add_input(&tx1, 0).into();
+		let tx3: Transaction = test_data::TransactionBuilder::with_output(20).into();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into(), b1.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx3.clone().into()).expect("transaction is accepted");
+
+		assert_eq!(chain.abandon_transaction(&b1.transactions[0].hash()), Err(AbandonError::AlreadyConfirmed));
+		assert_eq!(chain.abandon_transaction(&tx1.hash()), Ok(vec![tx1.hash(), tx2.hash()]));
+		assert_eq!(chain.abandon_transaction(&tx2.hash()), Err(AbandonError::UnknownTransaction));
+		assert_eq!(chain.transaction_state(&tx1.hash()), TransactionState::Unknown);
+		assert_eq!(chain.transaction_state(&tx3.hash()), TransactionState::InMemory);
+		assert!(!chain.memory_pool().read().is_spent(&OutPoint { hash: b1.transactions[0].hash(), index: 0 }));
+	}
+
+	#[test]
+	fn chain_block_within_limits() {
+		use ser::Serializable;
+
+		let block = test_data::block_builder().header().build()
+			.transaction().coinbase().output().value(10).script_pubkey("ac").build().build()
+			.transaction().output().value(20).script_pubkey("acac").build().build()
+			.build();
+		let weight = block.serialized_size() * 4;
+		let sigops = 3 * 4;
+
+		assert_eq!(Chain::block_within_limits(&block, weight, sigops), Ok(()));
+		assert_eq!(Chain::block_within_limits(&block, weight - 1, sigops),
+			Err(BlockLimitError::Weight { weight: weight, max_weight: weight - 1 }));
+		assert_eq!(Chain::block_within_limits(&block, weight, sigops - 1),
+			Err(BlockLimitError::Sigops { sigops: sigops, max_sigops: sigops - 1 }));
+	}
+
+	#[test]
+	fn chain_block_hashes_range() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(4, 0);
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into(), blocks[0].clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let headers: Vec<IndexedBlockHeader> = blocks.iter().skip(1).map(|b| b.block_header.clone().into()).collect();
+		chain.schedule_blocks_headers(headers.clone()).expect("no lookahead limit");
+
+		assert_eq!(chain.block_hashes_range(0, 100), vec![
+			(0, genesis.hash()),
+			(1, blocks[0].hash()),
+			(2, blocks[1].hash()),
+			(3, blocks[2].hash()),
+			(4, blocks[3].hash()),
+		]);
+		assert_eq!(chain.block_hashes_range(1, 2), vec![(1, blocks[0].hash()), (2, blocks[1].hash())]);
+		assert_eq!(chain.block_hashes_range(4, 1), vec![(4, blocks[3].hash())]);
+		assert!(chain.block_hashes_range(2, 0).is_empty());
+		assert!(chain.block_hashes_range(5, 10).is_empty());
+		assert_eq!(chain.block_hashes_range(3, u32::max_value()).len(), 2);
+	}
+
+	#[test]
+	fn chain_relay_queue() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert!(chain.drain_relay_queue(10).is_empty());
+
+		chain.enqueue_for_relay(H256::from(1));
+		chain.enqueue_for_relay(H256::from(2));
+		chain.enqueue_for_relay(H256::from(1));
+		chain.enqueue_for_relay(H256::from(3));
+
+		assert_eq!(chain.drain_relay_queue(2), vec![H256::from(1), H256::from(2)]);
+		chain.enqueue_for_relay(H256::from(1));
+		assert_eq!(chain.drain_relay_queue(10), vec![H256::from(3), H256::from(1)]);
+		assert!(chain.drain_relay_queue(10).is_empty());
+	}
+
+	#[test]
+	fn chain_sequence_locks_satisfied() {
+		use chain::constants::{SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG};
+
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).time(genesis.block_header.time + 600).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.build();
+		let b1_tx = b1.transactions[0].clone();
+		let mempool_tx: Transaction = test_data::TransactionBuilder::with_output(20).into();
+		let spend = |hash: H256, version: i32, sequence: u32| Transaction {
+			version: version,
+			inputs: vec![TransactionInput { previous_output: OutPoint { hash: hash, index: 0 }, script_sig: Default::default(), sequence: sequence }],
+			outputs: vec![],
+			lock_time: 0,
+		};
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into(), b1.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(mempool_tx.clone().into()).expect("transaction is accepted");
+
+		// block-based lock: coin at height 1, locked for 2 blocks => spendable at height 3
+		assert!(!chain.sequence_locks_satisfied(&spend(b1_tx.hash(), 2, 2), 2, u32::max_value()));
+		assert!(chain.sequence_locks_satisfied(&spend(b1_tx.hash(), 2, 2), 3, u32::max_value()));
+		// lock is ignored when disabled or for version 1 transactions
+		assert!(chain.sequence_locks_satisfied(&spend(b1_tx.hash(), 2, 2 | SEQUENCE_LOCKTIME_DISABLE_FLAG), 2, 0));
+		assert!(chain.sequence_locks_satisfied(&spend(b1_tx.hash(), 1, 2), 2, 0));
+
+		// time-based lock: coin time is the median time past of the block before coin block (genesis), locked for 512 seconds
+		let time_locked = spend(b1_tx.hash(), 2, SEQUENCE_LOCKTIME_TYPE_FLAG | 1);
+		assert!(!chain.sequence_locks_satisfied(&time_locked, 100, genesis.block_header.time + 511));
+		assert!(chain.sequence_locks_satisfied(&time_locked, 100, genesis.block_header.time + 512));
+
+		// memory pool coins are considered to be included in the next block
+		assert!(chain.sequence_locks_satisfied(&spend(mempool_tx.hash(), 2, 0), 2, 0));
+		assert!(!chain.sequence_locks_satisfied(&spend(mempool_tx.hash(), 2, 1), 2, 0));
+		assert!(!chain.sequence_locks_satisfied(&spend(mempool_tx.hash(), 2, SEQUENCE_LOCKTIME_TYPE_FLAG | 1), 2, 1000));
+
+		// unknown coins never satisfy locks
+		assert!(!chain.sequence_locks_satisfied(&spend(H256::from(1), 2, 0), 100, u32::max_value()));
+	}
+
+	#[test]
+	fn chain_verify_storage() {
+		let genesis = test_data::genesis();
+		// header of b1 doesn't commit to its transactions
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.build();
+		let b2 = test_data::block_builder()
+			.transaction().coinbase().output().value(20).build().build()
+			.transaction().output().value(30).build().build()
+			.merkled_header().parent(b1.hash()).build()
+			.build();
+		let b3 = test_data::block_builder()
+			.transaction().coinbase().output().value(40).build().build()
+			.merkled_header().parent(b2.hash()).build()
+			.build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into(), b1.clone().into(), b2.into(), b3.into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.verify_storage(0), Ok(()));
+		assert_eq!(chain.verify_storage(2), Ok(()));
+		assert_eq!(chain.verify_storage(3), Err(IntegrityError::MerkleRootMismatch(b1.hash())));
+		assert_eq!(chain.verify_storage(100), Err(IntegrityError::MerkleRootMismatch(b1.hash())));
+	}
+
+	#[test]
+	fn chain_mempool_fee_histogram() {
+		use ser::Serializable;
+
+		// transactions without outputs are paying all their input value as fee
+		let (blocks, funding) = blocks_with_funding_transaction(&[1_000, 100_000]);
+		let tx1: Transaction = test_data::TransactionBuilder::default().add_input(&funding, 0).into();
+		let tx2: Transaction = test_data::TransactionBuilder::default().add_input(&funding, 1).into();
+		let (tx1_size, tx2_size) = (tx1.serialized_size() as u64, tx2.serialized_size() as u64);
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.mempool_fee_histogram(&[0, 1]), vec![(0, 0), (1, 0)]);
+
+		chain.insert_verified_transaction(tx1.into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx2.into()).expect("transaction is accepted");
+		assert_eq!(chain.mempool_fee_histogram(&[1_000, 0, 100_000, 1_000]), vec![
+			(0, tx1_size + tx2_size),
+			(1_000, tx2_size),
+			(100_000, 0),
+		]);
+		assert_eq!(chain.mempool_fee_histogram(&[100_000 / tx2_size]), vec![(100_000 / tx2_size, tx2_size)]);
+		assert_eq!(chain.mempool_fee_histogram(&[100_000 / tx2_size + 1]), vec![(100_000 / tx2_size + 1, 0)]);
+		assert!(chain.mempool_fee_histogram(&[]).is_empty());
+	}
+
+	#[test]
+	fn chain_get_transaction() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b1_tx = b1.transactions[0].clone();
+		let mempool_tx: Transaction = test_data::TransactionBuilder::with_output(20).into();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into(), b1.clone().into(), b2.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(mempool_tx.clone().into()).expect("transaction is accepted");
+
+		let location = chain.get_transaction(&b1_tx.hash(), true).expect("canon transaction");
+		assert_eq!(location.transaction, b1_tx);
+		assert_eq!(location.block_hash, Some(b1.hash()));
+		assert_eq!(location.confirmations, Some(2));
+
+		let location = chain.get_transaction(&b1_tx.hash(), false).expect("canon transaction");
+		assert_eq!((location.block_hash, location.confirmations), (None, None));
+
+		let location = chain.get_transaction(&mempool_tx.hash(), true).expect("memory pool transaction");
+		assert_eq!(location.transaction, mempool_tx);
+		assert_eq!((location.block_hash, location.confirmations), (None, Some(0)));
+
+		assert_eq!(chain.get_transaction(&H256::from(1), true), None);
+	}
+
+	#[test]
+	fn chain_sync_paused() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.iter().map(|b| b.block_header.clone().into()).collect();
+		chain.schedule_blocks_headers(headers.clone()).expect("no lookahead limit");
+		assert_eq!(chain.request_blocks_hashes(1), vec![headers[0].hash.clone()]);
+		assert!(!chain.is_sync_paused());
+
+		chain.set_sync_paused(true);
+		assert!(chain.is_sync_paused());
+		assert!(chain.request_blocks_hashes(10).is_empty());
+		assert_eq!(chain.length_of_blocks_state(BlockState::Scheduled), 2);
+
+		// requested blocks are still accepted
+		chain.insert_best_block(blocks[0].clone().into()).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, headers[0].hash);
+
+		chain.set_sync_paused(false);
+		assert_eq!(chain.request_blocks_hashes(10), vec![headers[1].hash.clone(), headers[2].hash.clone()]);
+	}
+
+	#[test]
+	fn chain_locator_targeting_height() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(30, 0);
+		let mut stored: Vec<IndexedBlock> = vec![genesis.clone().into()];
+		stored.extend(blocks.iter().cloned().map(Into::into));
+		let hashes: Vec<H256> = stored.iter().map(|b| b.hash().clone()).collect();
+		let db = Arc::new(BlockChainDatabase::init_test_chain(stored));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let locator = |heights: &[usize]| heights.iter().map(|h| hashes[*h].clone()).collect::<Vec<_>>();
+
+		assert_eq!(chain.locator_targeting_height(5), locator(&[30, 21, 13, 9, 7, 6, 5, 4, 3, 2, 1, 0]));
+		assert_eq!(chain.locator_targeting_height(100), locator(&[30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 19, 15, 7, 0]));
+		assert_eq!(chain.locator_targeting_height(30), chain.locator_targeting_height(100));
+		assert_eq!(chain.locator_targeting_height(0), locator(&[30, 16, 8, 4, 2, 1, 0]));
+	}
+
+	#[test]
+	fn chain_insert_best_block_validated() {
+		fn inputs_known(block: &IndexedBlock, view: &UtxoView) -> Result<(), H256> {
+			for tx in block.transactions.iter().skip(1) {
+				for input in &tx.raw.inputs {
+					if view.transaction_output(&input.previous_output, usize::max_value()).is_none() {
+						return Err(input.previous_output.hash.clone());
+					}
+				}
+			}
+			Ok(())
+		}
+
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build()
+			.transaction().coinbase().output().value(20).build().build()
+			.build();
+		let b2_invalid = test_data::block_builder().header().nonce(1).parent(b1.hash()).build()
+			.transaction().coinbase().output().value(30).build().build()
+			.transaction().input().hash(s1.transactions[0].hash()).build().output().value(5).build().build()
+			.build();
+		let b2 = test_data::block_builder().header().nonce(2).parent(b1.hash()).build()
+			.transaction().coinbase().output().value(30).build().build()
+			.transaction().input().hash(b1.transactions[0].hash()).build().output().value(5).build().build()
+			.build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build()
+			.transaction().coinbase().output().value(40).build().build()
+			.transaction().input().hash(s1.transactions[0].hash()).build().output().value(5).build().build()
+			.build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block_validated(b1.clone().into(), inputs_known).expect("no error");
+		chain.insert_best_block_validated(s1.clone().into(), inputs_known).expect("no error");
 
-		let block2 = test_data::block_h2();
-		let block2_hash = block2.hash();
+		// side chain outputs are not available to the canon chain block
+		assert_eq!(chain.insert_best_block_validated(b2_invalid.clone().into(), inputs_known),
+			Err(InsertError::Validation(s1.transactions[0].hash())));
+		assert_eq!(chain.block_state(&b2_invalid.hash()), BlockState::Unknown);
 
-		chain.insert_best_block(block2.into()).expect("Error inserting new block");
-		assert_eq!(chain.block_locator_hashes(), vec![block2_hash.clone(), block1_hash.clone(), genesis_hash.clone()]);
+		chain.insert_best_block_validated(b2.clone().into(), inputs_known).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, b2.hash());
 
-		let blocks0 = test_data::build_n_empty_blocks_from_genesis(11, 0);
-		let headers0: Vec<IndexedBlockHeader> = blocks0.into_iter().map(|b| b.block_header.into()).collect();
-		let hashes0: Vec<_> = headers0.iter().map(|h| h.hash.clone()).collect();
-		chain.schedule_blocks_headers(headers0.clone());
-		chain.request_blocks_hashes(10);
-		chain.verify_blocks_hashes(10);
+		// side chain block is validated against its own chain
+		chain.insert_best_block_validated(s2.clone().into(), inputs_known).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, b2.hash());
+		assert_eq!(chain.block_state(&s2.hash()), BlockState::Stored);
+	}
 
-		assert_eq!(chain.block_locator_hashes(), vec![
-			hashes0[10].clone(),
-			hashes0[9].clone(),
-			hashes0[8].clone(),
-			hashes0[7].clone(),
-			hashes0[6].clone(),
-			hashes0[5].clone(),
-			hashes0[4].clone(),
-			hashes0[3].clone(),
-			hashes0[2].clone(),
-			hashes0[1].clone(),
-			block2_hash.clone(),
-			genesis_hash.clone(),
-		]);
+	#[test]
+	fn chain_ancestor_package_stats() {
+		use ser::Serializable;
 
-		let blocks1 = test_data::build_n_empty_blocks_from(6, 0, &headers0[10].raw);
-		let headers1: Vec<IndexedBlockHeader> = blocks1.into_iter().map(|b| b.block_header.into()).collect();
-		let hashes1: Vec<_> = headers1.iter().map(|h| h.hash.clone()).collect();
-		chain.schedule_blocks_headers(headers1.clone());
-		chain.request_blocks_hashes(10);
+		let (blocks, funding) = blocks_with_funding_transaction(&[20, 100]);
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(10).add_input(&funding, 0).into();	// fee: 10
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(5).add_input(&tx1, 0).into();		// fee: 5
+		let tx3: Transaction = test_data::TransactionBuilder::with_output(2).add_input(&tx2, 0).into();		// fee: 3
+		let tx4: Transaction = test_data::TransactionBuilder::default().add_input(&funding, 1).into();		// fee: 100
 
-		assert_eq!(chain.block_locator_hashes(), vec![
-			hashes1[5].clone(),
-			hashes1[4].clone(),
-			hashes1[3].clone(),
-			hashes1[2].clone(),
-			hashes1[1].clone(),
-			hashes1[0].clone(),
-			hashes0[10].clone(),
-			hashes0[9].clone(),
-			hashes0[8].clone(),
-			hashes0[7].clone(),
-			hashes0[5].clone(),
-			hashes0[1].clone(),
-			genesis_hash.clone(),
-		]);
+		let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx3.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx4.clone().into()).expect("transaction is accepted");
+
+		assert_eq!(chain.ancestor_package_stats(&tx1.hash()), Some((10, tx1.serialized_size())));
+		assert_eq!(chain.ancestor_package_stats(&tx3.hash()),
+			Some((18, tx1.serialized_size() + tx2.serialized_size() + tx3.serialized_size())));
+		assert_eq!(chain.ancestor_package_stats(&tx4.hash()), Some((100, tx4.serialized_size())));
+		assert_eq!(chain.ancestor_package_stats(&H256::from(1)), None);
+	}
 
-		let blocks2 = test_data::build_n_empty_blocks_from(3, 0, &headers1[5].raw);
-		let headers2: Vec<IndexedBlockHeader> = blocks2.into_iter().map(|b| b.block_header.into()).collect();
-		let hashes2: Vec<_> = headers2.iter().map(|h| h.hash.clone()).collect();
-		chain.schedule_blocks_headers(headers2);
+	#[test]
+	fn chain_subscribe_tip_updates() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
 
-		assert_eq!(chain.block_locator_hashes(), vec![
-			hashes2[2].clone(),
-			hashes2[1].clone(),
-			hashes2[0].clone(),
-			hashes1[5].clone(),
-			hashes1[4].clone(),
-			hashes1[3].clone(),
-			hashes1[2].clone(),
-			hashes1[1].clone(),
-			hashes1[0].clone(),
-			hashes0[10].clone(),
-			hashes0[8].clone(),
-			hashes0[4].clone(),
-			genesis_hash.clone(),
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let receiver1 = chain.subscribe_tip_updates();
+		let receiver2 = chain.subscribe_tip_updates();
+
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		assert_eq!(receiver1.try_recv().map(|best| (best.number, best.hash)), Ok((1, b1.hash())));
+		assert_eq!(receiver2.try_recv().map(|best| (best.number, best.hash)), Ok((1, b1.hash())));
+		assert!(receiver1.try_recv().is_err());
+
+		// dropped receiver doesn't break insertion
+		drop(receiver2);
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		assert_eq!(receiver1.try_recv().map(|best| (best.number, best.hash)), Ok((2, b2.hash())));
+	}
+
+	#[test]
+	fn chain_subscribe_connected_blocks() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let receiver = chain.subscribe_connected_blocks();
+
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![BlockEvent::Connected(1, b1.hash())]);
+
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![
+			BlockEvent::Disconnected(1, b1.hash()),
+			BlockEvent::Connected(1, s1.hash()),
+			BlockEvent::Connected(2, s2.hash()),
 		]);
+
+		chain.rollback_storage_to(&s1.hash()).expect("no error");
+		assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![BlockEvent::Disconnected(2, s2.hash())]);
 	}
 
 	#[test]
-	fn chain_transaction_state() {
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+	fn chain_transaction_virtual_size() {
+		use ser::Serializable;
+
+		let tx: Transaction = test_data::TransactionBuilder::with_output(10).into();
+		assert_eq!(Chain::transaction_virtual_size(&tx), tx.serialized_size());
+
+		let tx: Transaction = test_data::TransactionBuilder::with_output(10).add_input(&tx, 0).add_output(20).into();
+		assert_eq!(Chain::transaction_virtual_size(&tx), tx.serialized_size());
+	}
+
+	#[test]
+	fn chain_active_chain_height_of() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+		let s3 = test_data::block_builder().header().parent(s2.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
 		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		let genesis_block = test_data::genesis();
-		let block1 = test_data::block_h1();
-		let tx1: Transaction = test_data::TransactionBuilder::with_version(1).into();
-		let tx2: Transaction = test_data::TransactionBuilder::with_version(2).into();
-		let tx1_hash = tx1.hash();
-		let tx2_hash = tx2.hash();
-		chain.verify_transaction(tx1.into());
-		chain.insert_verified_transaction(tx2.into());
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.schedule_blocks_headers(vec![b2.block_header.clone().into()]).expect("no lookahead limit");
+
+		assert_eq!(chain.active_chain_height_of(&genesis.hash()), Some(0));
+		assert_eq!(chain.active_chain_height_of(&b1.hash()), Some(1));
+		assert!(chain.is_on_active_chain(&b1.hash()));
+		assert_eq!(chain.active_chain_height_of(&s1.hash()), None);
+		assert_eq!(chain.block_number(&b2.hash()), Some(2));
+		assert_eq!(chain.active_chain_height_of(&b2.hash()), None);
+
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		chain.insert_best_block(s3.clone().into()).expect("no error");
+		assert_eq!(chain.active_chain_height_of(&b1.hash()), None);
+		assert!(!chain.is_on_active_chain(&b1.hash()));
+		assert_eq!(chain.active_chain_height_of(&s1.hash()), Some(1));
+		assert_eq!(chain.active_chain_height_of(&s3.hash()), Some(3));
+	}
+
+	#[test]
+	fn chain_headers_message_bytes() {
+		use message::deserialize_payload;
+		use message::types::Headers;
+
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(4, 0);
+		let mut stored: Vec<IndexedBlock> = vec![genesis.clone().into()];
+		stored.extend(blocks.iter().cloned().map(Into::into));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(stored));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let headers = |bytes: Vec<u8>| deserialize_payload::<Headers>(&bytes, 0).expect("valid headers message").headers;
+
+		let bytes = chain.headers_message_bytes(&[blocks[0].hash()], &H256::default(), 2);
+		// count + 2 * (header + zero transactions count)
+		assert_eq!(bytes.len(), 1 + 2 * (80 + 1));
+		assert_eq!(bytes[0], 2);
+		assert_eq!(bytes[81], 0);
+		assert_eq!(headers(bytes), vec![blocks[1].block_header.clone(), blocks[2].block_header.clone()]);
+
+		let bytes = chain.headers_message_bytes(&[genesis.hash()], &blocks[1].hash(), 100);
+		assert_eq!(headers(bytes), vec![blocks[0].block_header.clone(), blocks[1].block_header.clone()]);
+
+		let bytes = chain.headers_message_bytes(&[H256::from(1)], &H256::default(), 100);
+		assert_eq!(bytes, vec![0]);
+	}
+
+	#[test]
+	fn chain_block_header_by_hash_matches_stored_block() {
+		use db::{BlockProvider, BlockRef};
+
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_h1();
+		let b2 = test_data::block_h2();
+
+		let storage = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into(), b1.clone().into()]));
+		let mut chain = Chain::new(storage.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.schedule_blocks_headers(vec![b2.block_header.clone().into()]).expect("no lookahead limit");
+
+		// header-only read returns the same header as the full block read
+		for hash in &[genesis.hash(), b1.hash()] {
+			let block = storage.block(BlockRef::Hash(hash.clone())).expect("block is stored");
+			assert_eq!(chain.block_header_by_hash(hash).map(|header| header.raw), Some(block.block_header));
+		}
+		assert_eq!(chain.block_header_by_number(1).map(|header| header.raw), Some(b1.block_header));
+		assert_eq!(chain.block_header_by_hash(&b2.hash()).map(|header| header.raw), Some(b2.block_header));
+	}
+
+	#[test]
+	fn chain_index_cache_invalidated_on_reorg() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_index_cache_size(1);
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		assert_eq!(chain.block_hash(1), Some(b1.hash()));
+		assert_eq!(chain.block_hash(0), Some(genesis.hash()));
+		assert_eq!(chain.block_header_by_number(0).map(|header| header.hash), Some(genesis.hash()));
+
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		assert_eq!(chain.block_hash(1), Some(s1.hash()));
+		assert_eq!(chain.block_header_by_number(1).map(|header| header.hash), Some(s1.hash()));
+
+		chain.rollback_storage_to(&genesis.hash()).expect("no error");
+		assert_eq!(chain.block_hash(1), None);
+		assert_eq!(chain.block_hash(0), Some(genesis.hash()));
+	}
+
+	#[test]
+	fn chain_block_connects() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.block_connects(&genesis), ConnectPoint::AlreadyHave);
+		assert_eq!(chain.block_connects(&b1), ConnectPoint::ExtendsActiveTip);
+		assert_eq!(chain.block_connects(&b2), ConnectPoint::Orphan);
+
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		assert_eq!(chain.block_connects(&b1), ConnectPoint::AlreadyHave);
+		assert_eq!(chain.block_connects(&b2), ConnectPoint::ExtendsActiveTip);
+		assert_eq!(chain.block_connects(&s1), ConnectPoint::ExtendsSideBranch(genesis.hash()));
+
+		chain.verify_block(b2.block_header.clone().into());
+		assert_eq!(chain.block_connects(&b2), ConnectPoint::AlreadyHave);
+		assert_eq!(chain.block_connects(&b3), ConnectPoint::ExtendsActiveTip);
+	}
+
+	#[test]
+	fn chain_mempool_conflicts() {
+		use self::test_data::TransactionBuilder;
+
+		let (blocks, tx0) = blocks_with_funding_transaction(&[100, 100]);
+		let tx1: Transaction = TransactionBuilder::with_output(10).add_input(&tx0, 0).into();
+		let tx2: Transaction = TransactionBuilder::with_output(20).add_input(&tx0, 0).into();
+		let tx3: Transaction = TransactionBuilder::with_output(5).add_input(&tx1, 0).into();
+		let tx4: Transaction = TransactionBuilder::with_output(40).add_input(&tx0, 1).into();
+		let parent_hash = blocks[1].hash().clone();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx3.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx4.clone().into()).expect("transaction is accepted");
+
+		let block = test_data::block_builder()
+			.transaction().coinbase().build()
+			.with_transaction(tx2.clone())
+			.with_transaction(tx4.clone())
+			.merkled_header().parent(parent_hash).build()
+			.build();
+		assert_eq!(chain.mempool_conflicts(&block), vec![tx1.hash()]);
+		assert_eq!(chain.information().transactions.transactions_count, 3);
+	}
+
+	#[test]
+	fn chain_with_capacity_hint() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.iter().map(|block| block.block_header.clone().into()).collect();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::with_capacity_hint(db, Arc::new(RwLock::new(MemoryPool::new())), 1000);
+		chain.schedule_blocks_headers(headers).expect("no error");
+		assert_eq!(chain.information().scheduled, 3);
+		assert_eq!(chain.best_block_header().hash, blocks[2].hash());
+		assert_eq!(chain.block_state(&blocks[0].hash()), BlockState::Scheduled);
+	}
+
+	#[test]
+	fn chain_utxo_set_info() {
+		use self::test_data::TransactionBuilder;
+
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let tx1: Transaction = TransactionBuilder::with_output(10).add_output(20).add_input(&tx0, 0).into();
+		let b1 = test_data::block_builder()
+			.transaction().coinbase().output().value(50).build().build()
+			.with_transaction(tx1.clone())
+			.merkled_header().parent(genesis.hash()).build()
+			.build();
+		let s1 = test_data::block_builder()
+			.transaction().coinbase().output().value(1).build().build()
+			.merkled_header().parent(genesis.hash()).nonce(1).build()
+			.build();
+		let s2 = test_data::block_builder()
+			.transaction().coinbase().output().value(2).build().build()
+			.merkled_header().parent(s1.hash()).build()
+			.build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_maintain_utxo_set_info(true);
+		let info = chain.utxo_set_info().expect("no error");
+		assert_eq!(info.height, 0);
+		assert_eq!(info.transaction_outputs, 1);
+		assert_eq!(info.total_amount, tx0.outputs[0].value);
+		assert!(info.hash_serialized.is_some());
+
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		let info = chain.utxo_set_info().expect("no error");
+		assert_eq!(info.height, 1);
+		assert_eq!(info.best_block_hash, b1.hash());
+		assert_eq!(info.transaction_outputs, 3);
+		assert_eq!(info.total_amount, 80);
+		assert_eq!(info.hash_serialized, None);
+
+		// maintained information matches full scan
+		chain.set_maintain_utxo_set_info(false);
+		let scanned = chain.utxo_set_info().expect("no error");
+		assert_eq!(scanned.serialized_size, info.serialized_size);
+		assert!(scanned.hash_serialized.is_some());
+		chain.set_maintain_utxo_set_info(true);
+		chain.utxo_set_info().expect("no error");
+
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.insert_best_block(s2.clone().into()).expect("no error");
+		let info = chain.utxo_set_info().expect("no error");
+		assert_eq!(info.best_block_hash, s2.hash());
+		assert_eq!(info.transaction_outputs, 3);
+		assert_eq!(info.total_amount, tx0.outputs[0].value + 3);
+
+		chain.set_maintain_utxo_set_info(false);
+		let scanned = chain.utxo_set_info().expect("no error");
+		assert_eq!(scanned.transaction_outputs, info.transaction_outputs);
+		assert_eq!(scanned.total_amount, info.total_amount);
+		assert_eq!(scanned.serialized_size, info.serialized_size);
+	}
+
+	#[test]
+	fn chain_find_header_gaps() {
+		use utils::BestHeadersChain;
+
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.iter().map(|block| block.block_header.clone().into()).collect();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.schedule_blocks_headers(headers.clone()).expect("no error");
+		assert_eq!(chain.find_header_gaps(), vec![]);
+
+		// headers chain, which is not connected to the storage
+		chain.headers_chain = BestHeadersChain::new(blocks[0].hash());
+		chain.headers_chain.insert_n(headers[1..].to_vec());
+		assert_eq!(chain.find_header_gaps(), vec![]);
+		chain.forget_block_leave_header(&blocks[0].hash());
+		assert_eq!(chain.find_header_gaps(), vec![(blocks[1].hash(), blocks[0].hash())]);
+	}
+
+	#[test]
+	fn chain_milestone_observer() {
+		use parking_lot::Mutex;
+
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(5, 0);
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let milestones = Arc::new(Mutex::new(Vec::new()));
+		let observer_milestones = milestones.clone();
+		chain.set_milestone_observer(2, Box::new(move |block| observer_milestones.lock().push(block.number)));
+		let tip_updates = chain.subscribe_tip_updates();
+
+		for block in &blocks {
+			chain.insert_best_block(block.clone().into()).expect("no error");
+		}
+		assert_eq!(*milestones.lock(), vec![2, 4]);
+		assert_eq!(tip_updates.try_iter().count(), 5);
+	}
+
+	#[test]
+	fn chain_input_coin_age() {
+		use ser::Serializable;
+		use self::test_data::TransactionBuilder;
+
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(2, 0);
+		let tx1: Transaction = TransactionBuilder::with_output(10).add_input(&tx0, 0).into();
+		let tx2: Transaction = TransactionBuilder::with_output(5).add_input(&tx1, 0).add_input(&tx0, 0).into();
+		let tx3: Transaction = TransactionBuilder::with_output(5).add_input(&tx1, 1).into();
+		let tx4: Transaction = TransactionBuilder::with_output(5).add_input(&tx2, 0).into();
+
+		let mut stored: Vec<IndexedBlock> = vec![genesis.into()];
+		stored.extend(blocks.into_iter().map(Into::into));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(stored));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+
+		let expected = tx0.outputs[0].value as f64 * 3f64 / tx1.serialized_size() as f64;
+		assert_eq!(chain.input_coin_age(&tx1), Some(expected));
+		let expected = tx0.outputs[0].value as f64 * 3f64 / tx2.serialized_size() as f64;
+		assert_eq!(chain.input_coin_age(&tx2), Some(expected));
+		assert_eq!(chain.input_coin_age(&tx3), None);
+		assert_eq!(chain.input_coin_age(&tx4), None);
+	}
+
+	#[test]
+	fn chain_enters_safe_mode_on_deep_reorganization() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let s2 = test_data::block_builder().header().parent(s1.hash()).build().build();
+		let s3 = test_data::block_builder().header().parent(s2.hash()).build().build();
+		let s4 = test_data::block_builder().header().parent(s3.hash()).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.set_safe_mode_depth(1);
+		chain.insert_best_block(b1.into()).expect("no error");
+		chain.insert_best_block(b2.into()).expect("no error");
+		chain.insert_best_block(s1.into()).expect("no error");
+		chain.insert_best_block(s2.into()).expect("no error");
+		assert!(!chain.is_safe_mode());
+		chain.insert_best_block(s3.clone().into()).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, s3.hash());
+		assert!(chain.is_safe_mode());
+
+		assert_eq!(chain.insert_best_block(s4.clone().into()), Err(ReorgError::SafeMode));
+		assert_eq!(chain.best_storage_block().hash, s3.hash());
+
+		// refused block is inserted when safe mode is cleared
+		let results = chain.clear_safe_mode();
+		assert!(!chain.is_safe_mode());
+		assert_eq!(results, vec![(s4.hash(), Ok(BlockInsertionResult::with_canonized_blocks(vec![s4.hash()])))]);
+		assert_eq!(chain.best_storage_block().hash, s4.hash());
+		assert_eq!(chain.clear_safe_mode(), vec![]);
+	}
+
+	#[test]
+	fn chain_download_order() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(4, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.iter().map(|block| block.block_header.clone().into()).collect();
+		let hashes: Vec<_> = blocks.iter().map(|block| block.hash()).collect();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.schedule_blocks_headers(headers).expect("no error");
+
+		let unknown = H256::from(1);
+		let shuffled = vec![hashes[3].clone(), unknown.clone(), hashes[1].clone(), hashes[0].clone(), hashes[1].clone()];
+		assert_eq!(chain.download_order(&shuffled), vec![hashes[3].clone(), hashes[0].clone(), hashes[1].clone(), unknown.clone()]);
+		assert_eq!(chain.download_order(&[hashes[2].clone(), hashes[1].clone()]), vec![hashes[1].clone(), hashes[2].clone()]);
+	}
+
+	#[test]
+	fn chain_reschedule_peer_blocks() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(5, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.iter().map(|block| block.block_header.clone().into()).collect();
+		let hashes: Vec<_> = blocks.iter().map(|block| block.hash()).collect();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.schedule_blocks_headers(headers).expect("no error");
+		assert_eq!(chain.request_specific(&[hashes[0].clone(), hashes[2].clone()], 1), vec![hashes[0].clone(), hashes[2].clone()]);
+		assert_eq!(chain.request_specific(&[hashes[1].clone(), H256::from(1)], 2), vec![hashes[1].clone()]);
+		assert_eq!(chain.block_state(&hashes[2]), BlockState::Requested);
+		assert_eq!(chain.information().scheduled, 2);
+
+		// forgotten blocks are not rescheduled
+		chain.forget_block_with_state_leave_header(&hashes[0], BlockState::Requested);
+		chain.verify_block(blocks[0].block_header.clone().into());
+
+		assert_eq!(chain.reschedule_peer_blocks(1), vec![hashes[2].clone()]);
+		assert_eq!(chain.block_state(&hashes[2]), BlockState::Scheduled);
+		assert_eq!(chain.block_state(&hashes[1]), BlockState::Requested);
+		assert_eq!(chain.request_blocks_hashes(1), vec![hashes[2].clone()]);
+		assert_eq!(chain.reschedule_peer_blocks(1), vec![]);
+	}
+
+	#[test]
+	fn chain_all_connected_headers() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(4, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks[2..].iter().map(|block| block.block_header.clone().into()).collect();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(blocks[0].clone().into()).expect("no error");
+		chain.insert_best_block(blocks[1].clone().into()).expect("no error");
+		chain.schedule_blocks_headers(headers).expect("no error");
+
+		let all_headers = chain.all_connected_headers();
+		let mut expected = vec![genesis.block_header.clone()];
+		expected.extend(blocks.iter().map(|block| block.block_header.clone()));
+		assert_eq!(all_headers, expected);
+		assert!(all_headers.windows(2).all(|pair| pair[1].previous_header_hash == pair[0].hash()));
+	}
+
+	#[test]
+	fn chain_rescan_blocks() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let mut stored: Vec<IndexedBlock> = vec![genesis.clone().into()];
+		stored.extend(blocks.iter().cloned().map(Into::into));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(stored));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+
+		let mut visited = Vec::new();
+		chain.rescan_blocks(1, 100, |number, block| visited.push((number, block.hash()))).expect("no error");
+		assert_eq!(visited, vec![(1, blocks[0].hash()), (2, blocks[1].hash()), (3, blocks[2].hash())]);
+
+		let mut visited = Vec::new();
+		chain.rescan_blocks(0, 0, |number, block| visited.push((number, block.hash()))).expect("no error");
+		assert_eq!(visited, vec![(0, genesis.hash())]);
+
+		chain.rescan_blocks(4, 10, |_, _| panic!("no blocks beyond the best block")).expect("no error");
+	}
+
+	#[test]
+	fn chain_block_header_verbose() {
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(blocks[0].clone().into()).expect("no error");
+		chain.insert_best_block(blocks[1].clone().into()).expect("no error");
+		chain.schedule_blocks_headers(vec![blocks[2].block_header.clone().into()]).expect("no error");
+
+		let header = chain.block_header_verbose(&blocks[0].hash()).expect("stored header");
+		assert_eq!(header.header, blocks[0].block_header);
+		assert_eq!(header.height, Some(1));
+		assert_eq!(header.confirmations, 2);
+		assert_eq!(header.median_time, genesis.block_header.time);
+		assert_eq!(header.next_block_hash, Some(blocks[1].hash()));
+
+		let header = chain.block_header_verbose(&blocks[1].hash()).expect("stored header");
+		assert_eq!(header.confirmations, 1);
+		assert_eq!(header.next_block_hash, Some(blocks[2].hash()));
+
+		let header = chain.block_header_verbose(&blocks[2].hash()).expect("in-memory header");
+		assert_eq!(header.height, Some(3));
+		assert_eq!(header.confirmations, -1);
+		assert_eq!(header.next_block_hash, None);
+
+		assert_eq!(chain.block_header_verbose(&H256::from(1)), None);
+	}
+
+	#[test]
+	fn chain_block_matches_filter() {
+		use std::iter::repeat;
+		use message::types::{FilterLoad, FilterAdd, FilterFlags};
+		use utils::BloomFilter;
+
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_h1();
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into(), b1.clone().into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+
+		let mut filter = BloomFilter::default();
+		assert_eq!(chain.block_matches_filter(&b1.hash(), &filter), Some(true));
+		filter.set_bloom_filter(FilterLoad {
+			filter: repeat(0u8).take(1024).collect::<Vec<_>>().into(),
+			hash_functions: 10,
+			tweak: 5,
+			flags: FilterFlags::None,
+		});
+		assert_eq!(chain.block_matches_filter(&b1.hash(), &filter), Some(false));
+		filter.update_bloom_filter(FilterAdd { data: (&*b1.transactions[0].hash()).into() });
+		assert_eq!(chain.block_matches_filter(&b1.hash(), &filter), Some(true));
+		assert_eq!(chain.block_matches_filter(&genesis.hash(), &filter), Some(false));
+		assert_eq!(chain.block_matches_filter(&H256::from(1), &filter), None);
+	}
+
+	#[test]
+	fn chain_reindex() {
+		use self::test_data::TransactionBuilder;
+
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let tx1: Transaction = TransactionBuilder::with_output(10).add_input(&tx0, 0).into();
+		let b1 = test_data::block_builder()
+			.transaction().coinbase().build()
+			.with_transaction(tx1.clone())
+			.merkled_header().parent(genesis.hash()).build()
+			.build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+		let tx0_output = OutPoint { hash: tx0.hash(), index: 0 };
+		let tx1_output = OutPoint { hash: tx1.hash(), index: 0 };
+		let genesis_hash = genesis.hash();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		chain.insert_best_block(b3.clone().into()).expect("no error");
+
+		let mut calls = Vec::new();
+		chain.reindex(Some(&mut |number, target| calls.push((number, target)))).expect("no error");
+		assert_eq!(calls, vec![(1, 3), (2, 3), (3, 3)]);
+		assert_eq!(chain.best_storage_block().hash, b3.hash());
+		assert_eq!(chain.get_utxo(&tx0_output, false), None);
+		assert_eq!(chain.get_utxo(&tx1_output, false).map(|utxo| utxo.height), Some(1));
+
+		// reindex is continued up to the longest chain on top of the best block
+		db.decanonize().expect("no error");
+		db.decanonize().expect("no error");
+		chain.reindex(None).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, b3.hash());
+		assert_eq!(chain.block_hash(3), Some(b3.hash()));
+		assert_eq!(chain.get_utxo(&tx1_output, false).map(|utxo| utxo.height), Some(1));
+
+		// interrupted reindex is continued from the last canonized block
+		db.clear_indices(&b3.hash()).expect("no error");
+		db.canonize(&genesis_hash).expect("no error");
+		db.canonize(&b1.hash()).expect("no error");
+		let mut calls = Vec::new();
+		chain.reindex(Some(&mut |number, target| calls.push((number, target)))).expect("no error");
+		assert_eq!(calls, vec![(2, 3), (3, 3)]);
+		assert_eq!(chain.best_storage_block().hash, b3.hash());
+		assert_eq!(db.reindex_target(), None);
+		assert!(!db.are_indices_cleared());
+		assert_eq!(chain.get_utxo(&tx1_output, false).map(|utxo| utxo.height), Some(1));
+	}
+
+	#[test]
+	fn chain_reindex_after_interrupted_clear() {
+		use db::BlockProvider;
+		use db::kv::{Transaction as DBTransaction, Key, KeyValue, KeyValueDatabase, SharedMemoryDatabase};
+		use ser::serialize;
+
+		let genesis = test_data::genesis();
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let genesis_hash = genesis.hash();
+		let b2_hash = blocks[1].hash();
+		let b3_hash = blocks[2].hash();
+
+		let kv = SharedMemoryDatabase::default();
+		let db = Arc::new(BlockChainDatabase::open(kv.clone()));
+		let mut stored: Vec<IndexedBlock> = vec![genesis.into()];
+		stored.extend(blocks.into_iter().map(Into::into));
+		for block in stored {
+			let hash = block.hash().clone();
+			db.insert(block).expect("no error");
+			db.canonize(&hash).expect("no error");
+		}
+
+		// clearing (with b2 as reindex target) is interrupted after the best block and genesis indices are deleted
+		let mut update = DBTransaction::new();
+		update.insert(KeyValue::Meta("reindex_target", serialize(&b2_hash)));
+		update.delete(Key::Meta("best_block_hash"));
+		update.delete(Key::Meta("best_block_number"));
+		update.delete(Key::BlockHash(0));
+		update.delete(Key::BlockNumber(genesis_hash.clone()));
+		kv.write(update).expect("no error");
+
+		// genesis block is canonized again on restart
+		let db = Arc::new(BlockChainDatabase::open(kv.clone()));
+		db.canonize(&genesis_hash).expect("no error");
+		assert!(!db.are_indices_cleared());
+
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		let mut calls = Vec::new();
+		chain.reindex(Some(&mut |number, target| calls.push((number, target)))).expect("no error");
+		assert_eq!(calls, vec![(1, 2), (2, 2)]);
+		assert_eq!(chain.best_storage_block().hash, b2_hash);
+		assert_eq!(db.reindex_target(), None);
+		// stale indices of b3 are cleared
+		assert_eq!(db.block_hash(3), None);
+		assert_eq!(db.block_number(&b3_hash), None);
+	}
+
+	#[test]
+	fn chain_reindex_with_missing_transaction_meta() {
+		use db::kv::{Transaction as DBTransaction, Key, KeyValueDatabase, SharedMemoryDatabase};
+
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(10).add_input(&tx0, 0).into();
+		let b1 = test_data::block_builder()
+			.transaction().coinbase().build()
+			.with_transaction(tx1.clone())
+			.merkled_header().parent(genesis.hash()).build()
+			.build();
+
+		let kv = SharedMemoryDatabase::default();
+		let db = Arc::new(BlockChainDatabase::open(kv.clone()));
+		let blocks: Vec<IndexedBlock> = vec![genesis.into(), b1.clone().into()];
+		for block in blocks {
+			let hash = block.hash().clone();
+			db.insert(block).expect("no error");
+			db.canonize(&hash).expect("no error");
+		}
+
+		// corrupt storage: meta of the spent transaction is lost => b1 can not be decanonized
+		let mut update = DBTransaction::new();
+		update.delete(Key::TransactionMeta(tx0.hash()));
+		kv.write(update).expect("no error");
+		assert!(db.decanonize().is_err());
+
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.reindex(None).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, b1.hash());
+		assert_eq!(chain.get_utxo(&OutPoint { hash: tx1.hash(), index: 0 }, false).map(|utxo| utxo.height), Some(1));
+	}
+
+	#[test]
+	fn chain_buffer_future_header() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+		let b2_fork = test_data::block_builder().header().parent(b1.hash()).nonce(1).build().build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.buffer_future_header(b3.block_header.clone());
+		chain.buffer_future_header(b2.block_header.clone());
+		chain.buffer_future_header(b2_fork.block_header.clone());
+		chain.buffer_future_header(b2_fork.block_header.clone());
+
+		assert_eq!(chain.release_buffered_children(&b3.hash()), vec![]);
+		assert_eq!(chain.release_buffered_children(&b1.hash()), vec![b2.block_header.clone(), b2_fork.block_header.clone()]);
+		assert_eq!(chain.release_buffered_children(&b1.hash()), vec![]);
+		assert_eq!(chain.release_buffered_children(&b2.hash()), vec![b3.block_header.clone()]);
+
+		// the oldest headers are dropped when the buffer is full
+		chain.set_max_buffered_headers(2);
+		chain.buffer_future_header(b3.block_header.clone());
+		chain.buffer_future_header(b2.block_header.clone());
+		chain.buffer_future_header(b2_fork.block_header.clone());
+		assert_eq!(chain.release_buffered_children(&b2.hash()), vec![]);
+		assert_eq!(chain.release_buffered_children(&b1.hash()), vec![b2.block_header.clone(), b2_fork.block_header.clone()]);
+	}
 
-		assert_eq!(chain.transaction_state(&genesis_block.transactions[0].hash()), TransactionState::Stored);
-		assert_eq!(chain.transaction_state(&block1.transactions[0].hash()), TransactionState::Unknown);
-		assert_eq!(chain.transaction_state(&tx1_hash), TransactionState::Verifying);
-		assert_eq!(chain.transaction_state(&tx2_hash), TransactionState::InMemory);
+	#[test]
+	fn chain_check_genesis() {
+		let genesis = test_data::genesis();
+		let genesis_hash = genesis.hash();
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+
+		assert_eq!(chain.check_genesis(&genesis_hash), Ok(()));
+		assert_eq!(chain.check_genesis(&H256::default()), Err(GenesisMismatch {
+			expected: H256::default(),
+			actual: genesis_hash,
+		}));
 	}
 
 	#[test]
-	fn chain_block_transaction_is_removed_from_on_block_insert() {
-		let b0 = test_data::block_builder().header().build()
-			.transaction().coinbase()
-				.output().value(10).build()
-				.build()
-			.build();
-		let b1 = test_data::block_builder().header().parent(b0.hash()).build()
-			.transaction().coinbase()
-				.output().value(10).build()
-				.build()
-			.transaction()
-				.input().hash(b0.transactions[0].hash()).index(0).build()
-				.build()
-			.build();
-		let tx1 = b1.transactions[0].clone();
-		let tx1_hash = tx1.hash();
-		let tx2 = b1.transactions[1].clone();
-		let tx2_hash = tx2.hash();
+	fn chain_mempool_dependency_edges() {
+		use self::test_data::TransactionBuilder;
 
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![b0.into()]));
-		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		chain.verify_transaction(tx1.into());
-		chain.insert_verified_transaction(tx2.into());
+		let genesis = test_data::genesis();
+		let confirmed = genesis.transactions[0].clone();
+		let tx0: Transaction = TransactionBuilder::with_output(10).add_output(20).add_input(&confirmed, 0).into();
+		let tx1: Transaction = TransactionBuilder::with_output(5).add_input(&tx0, 0).add_input(&tx0, 1).into();
+		let tx2: Transaction = TransactionBuilder::with_output(3).add_input(&tx1, 0).into();
 
-		// only one transaction is in the memory pool
-		assert_eq!(chain.information().transactions.transactions_count, 1);
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.mempool_dependency_edges(), vec![]);
 
-		// when block is inserted to the database => all accepted transactions are removed from mempool && verifying queue
-		chain.insert_best_block(b1.into()).expect("block accepted");
+		chain.insert_verified_transaction(tx0.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx2.clone().into()).expect("transaction is accepted");
 
-		assert_eq!(chain.information().transactions.transactions_count, 0);
-		assert!(!chain.forget_verifying_transaction(&tx1_hash));
-		assert!(!chain.forget_verifying_transaction(&tx2_hash));
+		let edges = chain.mempool_dependency_edges();
+		assert_eq!(edges.len(), 2);
+		assert!(edges.contains(&(tx1.hash(), tx0.hash())));
+		assert!(edges.contains(&(tx2.hash(), tx1.hash())));
 	}
 
 	#[test]
-	fn chain_forget_verifying_transaction_with_children() {
-		let test_chain = &mut test_data::ChainBuilder::new();
-		test_data::TransactionBuilder::with_output(100).store(test_chain)	// t1
-			.into_input(0).add_output(200).store(test_chain)				// t1 -> t2
-			.into_input(0).add_output(300).store(test_chain)				// t1 -> t2 -> t3
-			.set_default_input(0).set_output(400).store(test_chain);		// t4
+	fn chain_current_download_window() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
 
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
 		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		chain.verify_transaction(test_chain.at(0).into());
-		chain.verify_transaction(test_chain.at(1).into());
-		chain.verify_transaction(test_chain.at(2).into());
-		chain.verify_transaction(test_chain.at(3).into());
-
-		chain.forget_verifying_transaction_with_children(&test_chain.at(0).hash());
-		assert!(!chain.forget_verifying_transaction(&test_chain.at(0).hash()));
-		assert!(!chain.forget_verifying_transaction(&test_chain.at(1).hash()));
-		assert!(!chain.forget_verifying_transaction(&test_chain.at(2).hash()));
-		assert!(chain.forget_verifying_transaction(&test_chain.at(3).hash()));
+		// maximal window is used until blocks are received
+		assert_eq!(chain.current_download_window(), 256);
+		chain.set_download_window_bounds(4, 16);
+		assert_eq!(chain.current_download_window(), 16);
+
+		chain.schedule_blocks_headers(vec![b1.block_header.clone().into(), b2.block_header.clone().into(), b3.block_header.clone().into()])
+			.expect("no error");
+		assert_eq!(chain.request_blocks_hashes(3), vec![b1.hash(), b2.hash(), b3.hash()]);
+		// received blocks are forgotten before moving to verifying queue
+		chain.forget_blocks_leave_header(&[b1.hash(), b2.hash(), b3.hash()]);
+		chain.verify_block(b1.block_header.clone().into());
+		chain.verify_block(b2.block_header.clone().into());
+		chain.verify_block(b3.block_header.clone().into());
+
+		let window = chain.current_download_window();
+		assert!(window >= 4 && window <= 16);
+		assert!(chain.request_times.is_empty());
 	}
 
 	#[test]
-	fn chain_transactions_hashes_with_state() {
-		let test_chain = &mut test_data::ChainBuilder::new();
-		test_data::TransactionBuilder::with_output(100).store(test_chain)	// t1
-			.into_input(0).add_output(200).store(test_chain)				// t1 -> t2
-			.into_input(0).add_output(300).store(test_chain)				// t1 -> t2 -> t3
-			.set_default_input(0).set_output(400).store(test_chain);		// t4
+	fn chain_mempool_info() {
+		use ser::Serializable;
 
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let (blocks, funding) = blocks_with_funding_transaction(&[1_000, 100_000]);
+		let tx1: Transaction = test_data::TransactionBuilder::default().add_input(&funding, 0).into();
+		let tx2: Transaction = test_data::TransactionBuilder::default().add_input(&funding, 1).into();
+		let size = tx1.serialized_size() + tx2.serialized_size();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(blocks));
 		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		chain.insert_verified_transaction(test_chain.at(0).into());
-		chain.insert_verified_transaction(test_chain.at(1).into());
-		chain.insert_verified_transaction(test_chain.at(2).into());
-		chain.insert_verified_transaction(test_chain.at(3).into());
+		assert_eq!(chain.mempool_info(), MempoolInfo {
+			transactions_count: 0,
+			size_in_bytes: 0,
+			virtual_size: 0,
+			total_fee: 0,
+			max_size_in_bytes: None,
+		});
+
+		chain.insert_verified_transaction(tx1.into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx2.into()).expect("transaction is accepted");
+		assert_eq!(chain.mempool_info(), MempoolInfo {
+			transactions_count: 2,
+			size_in_bytes: size,
+			virtual_size: size,
+			total_fee: 101_000,
+			max_size_in_bytes: None,
+		});
 
-		let chain_transactions = chain.transactions_hashes_with_state(TransactionState::InMemory);
-		assert!(chain_transactions.contains(&test_chain.at(0).hash()));
-		assert!(chain_transactions.contains(&test_chain.at(1).hash()));
-		assert!(chain_transactions.contains(&test_chain.at(2).hash()));
-		assert!(chain_transactions.contains(&test_chain.at(3).hash()));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new().with_max_size(size))));
+		assert_eq!(chain.mempool_info().max_size_in_bytes, Some(size));
 	}
 
 	#[test]
-	fn memory_pool_transactions_are_reverified_after_reorganization() {
-		let b0 = test_data::block_builder().header().build().build();
-		let b1 = test_data::block_builder().header().nonce(1).parent(b0.hash()).build().build();
-		let b2 = test_data::block_builder().header().nonce(2).parent(b0.hash()).build().build();
+	fn chain_purge_invalid_branch() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
 		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+		let b4 = test_data::block_builder().header().parent(b3.hash()).build().build();
 
-		let tx1: Transaction = test_data::TransactionBuilder::with_version(1).into();
-		let tx1_hash = tx1.hash();
-		let tx2: Transaction = test_data::TransactionBuilder::with_version(2).into();
-		let tx2_hash = tx2.hash();
-
-		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![b0.into()]));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
 		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		chain.verify_transaction(tx1.into());
-		chain.insert_verified_transaction(tx2.into());
-
-		// no reorg
-		let result = chain.insert_best_block(b1.into()).expect("no error");
-		assert_eq!(result.transactions_to_reverify.len(), 0);
+		chain.schedule_blocks_headers(vec![b1.block_header.clone().into(), b2.block_header.clone().into(),
+			b3.block_header.clone().into(), b4.block_header.clone().into()]).expect("no error");
+		chain.request_blocks_hashes(2);
+		chain.verify_blocks_hashes(1);
+		assert_eq!(chain.block_state(&b1.hash()), BlockState::Verifying);
+		assert_eq!(chain.block_state(&b2.hash()), BlockState::Requested);
+		assert_eq!(chain.block_state(&b3.hash()), BlockState::Scheduled);
+
+		assert_eq!(chain.purge_invalid_branch(&b2.hash()), vec![b3.hash(), b4.hash()]);
+		assert_eq!(chain.block_state(&b1.hash()), BlockState::Verifying);
+		assert_eq!(chain.block_state(&b2.hash()), BlockState::DeadEnd);
+		assert_eq!(chain.block_state(&b3.hash()), BlockState::DeadEnd);
+		assert_eq!(chain.block_state(&b4.hash()), BlockState::DeadEnd);
+		assert_eq!(chain.length_of_blocks_state(BlockState::Requested), 0);
+		assert_eq!(chain.length_of_blocks_state(BlockState::Scheduled), 0);
+		assert_eq!(chain.best_block_header().hash, b1.hash());
+		chain.assert_blocks_state_consistent();
+	}
 
-		// no reorg
-		let result = chain.insert_best_block(b2.into()).expect("no error");
-		assert_eq!(result.transactions_to_reverify.len(), 0);
+	#[test]
+	fn chain_tip_selection_reason() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
 
-		// reorg
-		let result = chain.insert_best_block(b3.into()).expect("no error");
-		assert_eq!(result.transactions_to_reverify.len(), 2);
-		assert!(result.transactions_to_reverify.iter().any(|ref tx| &tx.hash == &tx1_hash));
-		assert!(result.transactions_to_reverify.iter().any(|ref tx| &tx.hash == &tx2_hash));
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.tip_selection_reason(), TipSelectionReason::MostWork);
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		assert_eq!(chain.tip_selection_reason(), TipSelectionReason::MostWork);
+
+		// tips with the same number of blocks => the first seen is preferred
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		assert_eq!(chain.tip_selection_reason(), TipSelectionReason::FirstSeen);
+		assert_eq!(chain.activate_best_chain(), Ok(None));
+		assert_eq!(chain.best_storage_block().hash, b1.hash());
+
+		// first-seen time of restored tips is unknown => the lowest hash is preferred
+		let mut chain = Chain::new_with_recovery(db, Arc::new(RwLock::new(MemoryPool::new())), 16);
+		assert_eq!(chain.tip_selection_reason(), TipSelectionReason::LowestHash);
+		let lowest_hash = if b1.hash() < s1.hash() { b1.hash() } else { s1.hash() };
+		chain.activate_best_chain().expect("no error");
+		assert_eq!(chain.best_storage_block().hash, lowest_hash);
+		assert_eq!(chain.tip_selection_reason(), TipSelectionReason::LowestHash);
+		assert_eq!(chain.activate_best_chain(), Ok(None));
 	}
 
 	#[test]
-	fn fork_chain_block_transaction_is_removed_from_on_block_insert() {
+	fn chain_blockchain_info() {
 		let genesis = test_data::genesis();
-		let b0 = test_data::block_builder().header().parent(genesis.hash()).build().build(); // genesis -> b0
-		let b1 = test_data::block_builder().header().nonce(1).parent(b0.hash()).build()
-			.transaction().output().value(10).build().build()
-			.build(); // genesis -> b0 -> b1[tx1]
-		let b2 = test_data::block_builder().header().parent(b1.hash()).build()
-			.transaction().output().value(20).build().build()
-			.build(); // genesis -> b0 -> b1[tx1] -> b2[tx2]
-		let b3 = test_data::block_builder().header().nonce(2).parent(b0.hash()).build()
-			.transaction().output().value(30).build().build()
-			.build(); // genesis -> b0 -> b3[tx3]
-		let b4 = test_data::block_builder().header().parent(b3.hash()).build()
-			.transaction().output().value(40).build().build()
-			.build(); // genesis -> b0 -> b3[tx3] -> b4[tx4]
-		let b5 = test_data::block_builder().header().parent(b4.hash()).build()
-			.transaction().output().value(50).build().build()
-			.build(); // genesis -> b0 -> b3[tx3] -> b4[tx4] -> b5[tx5]
+		let b1 = test_data::block_builder().header().parent(genesis.hash()).time(genesis.block_header.time + 1).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).time(genesis.block_header.time + 2).build().build();
 
-		let tx1 = b1.transactions[0].clone();
-		let tx1_hash = tx1.hash();
-		let tx2 = b2.transactions[0].clone();
-		let tx2_hash = tx2.hash();
-		let tx3 = b3.transactions[0].clone();
-		let tx4 = b4.transactions[0].clone();
-		let tx5 = b5.transactions[0].clone();
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.schedule_blocks_headers(vec![b2.block_header.clone().into()]).expect("no error");
+		chain.update_peer_best_height(4);
+
+		assert_eq!(chain.blockchain_info(), BlockchainInfo {
+			best_block_hash: b1.hash(),
+			blocks: 1,
+			headers: 2,
+			difficulty: b1.block_header.bits.to_f64(),
+			median_time: test_data::genesis().block_header.time,
+			verification_progress: 0.25,
+		});
+	}
+
+	#[test]
+	fn chain_reconsider_block() {
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
 
 		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
 		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		chain.invalidate_block(&b1.hash()).expect("no error");
+		assert_eq!(chain.best_storage_block().hash, s1.hash());
+
+		// reconsidered chain is activated again
+		let result = chain.reconsider_block(&b1.hash()).expect("no error").expect("reorganization");
+		assert_eq!(result.decanonized_blocks_hashes, vec![s1.hash()]);
+		assert_eq!(result.canonized_blocks_hashes, vec![b1.hash(), b2.hash()]);
+		assert_eq!(chain.best_storage_block().hash, b2.hash());
+
+		// dead-end blocks could not be reconsidered
+		chain.mark_dead_end_block(&s1.hash());
+		assert_eq!(chain.reconsider_block(&s1.hash()), Ok(None));
+		assert_eq!(chain.block_state(&s1.hash()), BlockState::DeadEnd);
+	}
 
-		chain.insert_verified_transaction(tx3.into());
-		chain.insert_verified_transaction(tx4.into());
-		chain.insert_verified_transaction(tx5.into());
+	#[test]
+	fn chain_scan_utxo_set() {
+		use std::collections::HashSet;
+		use std::sync::atomic::{AtomicBool, Ordering};
+		use self::test_data::TransactionBuilder;
 
-		assert_eq!(chain.insert_best_block(b0.clone().into()).expect("block accepted"), BlockInsertionResult::with_canonized_blocks(vec![b0.hash()]));
-		assert_eq!(chain.information().transactions.transactions_count, 3);
-		assert_eq!(chain.insert_best_block(b1.clone().into()).expect("block accepted"), BlockInsertionResult::with_canonized_blocks(vec![b1.hash()]));
-		assert_eq!(chain.information().transactions.transactions_count, 3);
-		assert_eq!(chain.insert_best_block(b2.clone().into()).expect("block accepted"), BlockInsertionResult::with_canonized_blocks(vec![b2.hash()]));
-		assert_eq!(chain.information().transactions.transactions_count, 3);
-		assert_eq!(chain.insert_best_block(b3.clone().into()).expect("block accepted"), BlockInsertionResult::default());
-		assert_eq!(chain.information().transactions.transactions_count, 3);
-		assert_eq!(chain.insert_best_block(b4.clone().into()).expect("block accepted"), BlockInsertionResult::default());
-		assert_eq!(chain.information().transactions.transactions_count, 3);
-		// order matters
-		let insert_result = chain.insert_best_block(b5.clone().into()).expect("block accepted");
-		let transactions_to_reverify_hashes: Vec<_> = insert_result
-			.transactions_to_reverify
-			.into_iter()
-			.map(|tx| tx.hash)
-			.collect();
-		assert_eq!(transactions_to_reverify_hashes, vec![tx1_hash, tx2_hash]);
-		assert_eq!(insert_result.canonized_blocks_hashes, vec![b3.hash(), b4.hash(), b5.hash()]);
-		assert_eq!(chain.information().transactions.transactions_count, 0); // tx3, tx4, tx5 are added to the database
+		let genesis = test_data::genesis();
+		let tx0 = genesis.transactions[0].clone();
+		let mut tx1: Transaction = TransactionBuilder::with_output(10).add_output(20).add_input(&tx0, 0).into();
+		tx1.outputs[0].script_pubkey = vec![0x51].into();
+		tx1.outputs[1].script_pubkey = vec![0x52].into();
+		let tx2: Transaction = TransactionBuilder::with_output(5).add_input(&tx1, 1).into();
+		let b1 = test_data::block_builder()
+			.transaction().coinbase().build()
+			.with_transaction(tx1.clone())
+			.merkled_header().parent(genesis.hash()).build()
+			.build();
+		let b2 = test_data::block_builder()
+			.transaction().coinbase().build()
+			.with_transaction(tx2.clone())
+			.merkled_header().parent(b1.hash()).build()
+			.build();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		chain.insert_best_block(b2.clone().into()).expect("no error");
+
+		let mut scripts = HashSet::new();
+		scripts.insert(Script::new(vec![0x51].into()));
+		scripts.insert(Script::new(vec![0x52].into()));
+		let mut calls = Vec::new();
+		let cancel = AtomicBool::new(false);
+		let result = chain.scan_utxo_set(&scripts, Some(&mut |number, best| calls.push((number, best))), &cancel).expect("no error");
+		assert_eq!(calls, vec![(0, 2), (1, 2), (2, 2)]);
+		assert_eq!(result.height, 2);
+		assert_eq!(result.best_block_hash, b2.hash());
+		assert_eq!(result.unspents.len(), 1);
+		assert_eq!(result.unspents[0].outpoint, OutPoint { hash: tx1.hash(), index: 0 });
+		assert_eq!(result.unspents[0].height, 1);
+		assert_eq!(result.total_amount, 10);
+		assert!(!result.cancelled);
+
+		cancel.store(true, Ordering::Relaxed);
+		let result = chain.scan_utxo_set(&scripts, None, &cancel).expect("no error");
+		assert!(result.unspents.is_empty());
+		assert!(result.cancelled);
 	}
 
 	#[test]
-	fn double_spend_transaction_is_removed_from_memory_pool_when_output_is_spent_in_block_transaction() {
+	fn chain_is_standard() {
+		use chain::TransactionOutput;
+		use primitives::bytes::Bytes;
+		use primitives::hash::H160;
+		use script::Builder;
+		use ser::Serializable;
+		use self::test_data::TransactionBuilder;
+
 		let genesis = test_data::genesis();
 		let tx0 = genesis.transactions[0].clone();
-		let b0 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build()
-			.transaction()
-				.lock_time(1)
-				.input().hash(tx0.hash()).index(0).build()
-				.build()
-			.build(); // genesis -> b0[tx1]
-		// tx1 && tx2 are spending same output
-		let tx2: Transaction = test_data::TransactionBuilder::with_output(20).add_input(&tx0, 0).into();
-		let tx3: Transaction = test_data::TransactionBuilder::with_output(20).add_input(&tx0, 1).into();
+		let input_value = tx0.outputs[0].value;
+		let p2pkh = Builder::build_p2pkh(&H160::default()).to_bytes();
+		let build = |outputs: Vec<(u64, Bytes)>| {
+			let mut tx: Transaction = TransactionBuilder::default().add_input(&tx0, 0).into();
+			tx.outputs = outputs.into_iter()
+				.map(|(value, script_pubkey)| TransactionOutput { value: value, script_pubkey: script_pubkey })
+				.collect();
+			tx
+		};
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		assert_eq!(chain.is_standard(&build(vec![(input_value - 10_000, p2pkh.clone())])), Ok(()));
+		assert_eq!(chain.is_standard(&build(vec![
+			(input_value - 10_000, p2pkh.clone()),
+			(0, Builder::build_nulldata(&[1, 2, 3]).to_bytes()),
+		])), Ok(()));
+		assert_eq!(chain.is_standard(&build(vec![(546, p2pkh.clone()), (10_000, vec![0x51, 0x51].into())])),
+			Err(NonStandardReason::ScriptPubKey(1)));
+		assert_eq!(chain.is_standard(&build(vec![(10_000, p2pkh.clone()), (545, p2pkh.clone())])),
+			Err(NonStandardReason::Dust(1)));
+
+		let tx = build(vec![(input_value, p2pkh.clone())]);
+		let min_fee = tx.serialized_size() as u64;
+		assert_eq!(chain.is_standard(&tx), Err(NonStandardReason::Fee { fee: 0, min_fee: min_fee }));
+
+		let mut tx = build(vec![(input_value - 10_000, p2pkh.clone())]);
+		// + 1 signature operation in p2pkh output
+		tx.inputs[0].script_sig = vec![0xac; 4_000].into();
+		assert_eq!(chain.is_standard(&tx), Err(NonStandardReason::Sigops { sigops: 4_001, max_sigops: 4_000 }));
+
+		tx.inputs[0].script_sig = vec![0; 100_000].into();
+		assert_eq!(chain.is_standard(&tx), Err(NonStandardReason::Size { size: tx.serialized_size(), max_size: 100_000 }));
+
+		// fee of transaction with unknown inputs is not checked
+		let mut tx = build(vec![(input_value * 2, p2pkh.clone())]);
+		tx.inputs[0].previous_output.hash = H256::default();
+		assert_eq!(chain.is_standard(&tx), Ok(()));
+
+		// configured policy is used
+		let mut chain = chain;
+		chain.set_standardness_policy(StandardnessPolicy {
+			min_relay_fee: 0,
+			..StandardnessPolicy::default()
+		});
+		assert_eq!(chain.is_standard(&build(vec![(input_value, p2pkh.clone())])), Ok(()));
+	}
 
-		// insert tx2 to memory pool
+	#[test]
+	fn chain_dynamic_mempool_min_fee() {
+		use chain::TransactionOutput;
+		use primitives::hash::H160;
+		use script::Builder;
+		use ser::Serializable;
+		use self::test_data::TransactionBuilder;
+
+		let genesis = test_data::genesis();
+		let tx0: Transaction = TransactionBuilder::with_output(1_000).add_output(1_000).add_output(1_000)
+			.add_input(&genesis.transactions[0], 0).into();
+		let tx1: Transaction = TransactionBuilder::with_output(990).add_input(&tx0, 0).into();	// fee: 10
+		let tx2: Transaction = TransactionBuilder::with_output(400).add_input(&tx0, 1).into();	// fee: 600
+		let max_size = tx0.serialized_size() + tx1.serialized_size();
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new().with_max_size(max_size))));
+		chain.insert_verified_transaction(tx0.clone().into()).expect("transaction is accepted");
+		chain.insert_verified_transaction(tx1.clone().into()).expect("transaction is accepted");
+		assert_eq!(chain.dynamic_mempool_min_fee(), 1_000);
+
+		// tx1 is evicted => minimal fee rate rises above its fee rate
+		chain.insert_verified_transaction(tx2.into()).expect("transaction is accepted");
+		let min_fee_rate = (10.0 / tx1.serialized_size() as f64 * 1000.0) as u64 + 1_000;
+		assert_eq!(chain.dynamic_mempool_min_fee(), min_fee_rate);
+
+		// transaction, paying static relay fee, is not accepted anymore
+		let mut tx3: Transaction = TransactionBuilder::default().add_input(&tx0, 2).into();
+		tx3.outputs = vec![TransactionOutput { value: 0, script_pubkey: Builder::build_p2pkh(&H160::default()).to_bytes() }];
+		let fee = tx3.serialized_size() as u64;
+		tx3.outputs[0].value = 1_000 - fee;
+		assert_eq!(chain.is_standard(&tx3), Err(NonStandardReason::Fee { fee: fee, min_fee: fee * min_fee_rate / 1000 }));
+	}
+
+	#[test]
+	fn chain_save_and_restore_state() {
+		use std::env;
+		use std::fs;
+
+		let genesis = test_data::genesis();
+		let b1 = test_data::block_builder().header().nonce(1).parent(genesis.hash()).build().build();
+		let b2 = test_data::block_builder().header().parent(b1.hash()).build().build();
+		let b3 = test_data::block_builder().header().parent(b2.hash()).build().build();
+		let b4 = test_data::block_builder().header().parent(b3.hash()).build().build();
+		let s1 = test_data::block_builder().header().nonce(2).parent(genesis.hash()).build().build();
+		let path = env::temp_dir().join(format!("pbtc-chain-state-{}", b1.hash().to_reversed_str()));
+
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.into()]));
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.schedule_blocks_headers(vec![b1.block_header.clone().into(), b2.block_header.clone().into(),
+			b3.block_header.clone().into(), b4.block_header.clone().into()]).expect("no error");
+		chain.request_blocks_hashes(3);
+		chain.verify_blocks_hashes(1);
+		chain.save_state(&path).expect("no error");
+
+		let mut restored = Chain::restore_state(db.clone(), Arc::new(RwLock::new(MemoryPool::new())), &path).expect("no error");
+		let (information, restored_information) = (chain.information(), restored.information());
+		// requested and verifying blocks are scheduled again
+		assert_eq!(restored_information.scheduled, 4);
+		assert_eq!(restored_information.requested, 0);
+		assert_eq!(restored_information.verifying, 0);
+		assert_eq!(restored_information.stored, information.stored);
+		assert_eq!(restored_information.headers.best, information.headers.best);
+		assert_eq!(restored_information.headers.total, information.headers.total);
+		assert_eq!(restored.block_state(&b1.hash()), BlockState::Scheduled);
+		assert_eq!(restored.block_state(&b3.hash()), BlockState::Scheduled);
+		assert_eq!(restored.best_block_header().hash, b4.hash());
+		// restored blocks are requested again in chain order
+		assert_eq!(restored.request_blocks_hashes(4), vec![b1.hash(), b2.hash(), b3.hash(), b4.hash()]);
+		assert_eq!(restored.block_state(&b1.hash()), BlockState::Requested);
+
+		// stored blocks are skipped
+		chain.insert_best_block(b1.clone().into()).expect("no error");
+		let restored = Chain::restore_state(db.clone(), Arc::new(RwLock::new(MemoryPool::new())), &path).expect("no error");
+		assert_eq!(restored.information().headers.best, 3);
+		assert_eq!(restored.block_state(&b2.hash()), BlockState::Scheduled);
+
+		// blocks, which are not descendants of the best storage block, are dropped
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+		chain.invalidate_block(&b1.hash()).expect("no error");
+		chain.insert_best_block(s1.clone().into()).expect("no error");
+		let restored = Chain::restore_state(db, Arc::new(RwLock::new(MemoryPool::new())), &path).expect("no error");
+		assert_eq!(restored.information().headers.best, 0);
+		assert_eq!(restored.length_of_blocks_state(BlockState::Scheduled), 0);
+
+		fs::remove_file(&path).expect("no error");
+	}
+
+	#[test]
+	fn chain_orphaned_headers_information() {
 		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
-		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		chain.insert_verified_transaction(tx2.clone().into());
-		chain.insert_verified_transaction(tx3.clone().into());
-		// insert verified block with tx1
-		chain.insert_best_block(b0.into()).expect("no error");
-		// => tx2 is removed from memory pool, but tx3 remains
-		assert_eq!(chain.information().transactions.transactions_count, 1);
+		let mut chain = Chain::new(db.clone(), Arc::new(RwLock::new(MemoryPool::new())));
+
+		let blocks = test_data::build_n_empty_blocks_from_genesis(3, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		chain.schedule_blocks_headers(headers.clone()).expect("no lookahead limit");
+		chain.request_blocks_hashes(3);
+		assert_eq!(chain.information().orphaned, 0);
+
+		// block 2 is received before its parent => header is left dangling
+		chain.forget_blocks_leave_header(&[headers[2].hash.clone()]);
+		assert_eq!(chain.information().orphaned, 1);
+		assert_eq!(format!("{:?}", chain.information()), "[sch:0 / bh:3 / orph:1 -> req:2 -> vfy:0 -> stored: 1]");
+
+		// forgetting unknown block does not change anything
+		chain.forget_blocks_leave_header(&[headers[2].hash.clone()]);
+		assert_eq!(chain.information().orphaned, 1);
+
+		// parent arrives => orphaned block is verified
+		chain.forget_blocks_leave_header(&[headers[1].hash.clone()]);
+		assert_eq!(chain.information().orphaned, 2);
+		chain.verify_blocks(vec![headers[1].clone(), headers[2].clone()]);
+		assert_eq!(chain.information().orphaned, 0);
+		assert_eq!(chain.information().headers.best, 3);
 	}
 
 	#[test]
-	fn update_memory_pool_transaction() {
-		use self::test_data::{ChainBuilder, TransactionBuilder};
+	fn chain_block_locator_hashes_for_best_chain() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let genesis_hash = chain.best_block().hash;
+		assert_eq!(chain.block_locator_hashes_for_best_chain(), vec![genesis_hash.clone()]);
 
-		let data_chain = &mut ChainBuilder::new();
-		TransactionBuilder::with_output(10).add_output(10).add_output(10).store(data_chain)		// transaction0
-			.reset().set_input(&data_chain.at(0), 0).add_output(20).lock().store(data_chain)	// transaction0 -> transaction1
-			.reset().set_input(&data_chain.at(0), 0).add_output(30).store(data_chain);			// transaction0 -> transaction2
+		let block1 = test_data::block_h1();
+		let block1_hash = block1.hash();
+		chain.insert_best_block(block1.into()).expect("Error inserting new block");
+		let block2 = test_data::block_h2();
+		let block2_hash = block2.hash();
+		chain.insert_best_block(block2.into()).expect("Error inserting new block");
+		assert_eq!(chain.block_locator_hashes_for_best_chain(), vec![block2_hash.clone(), block1_hash.clone(), genesis_hash.clone()]);
+
+		// blocks from fork, starting at genesis, are in the queue
+		let blocks0 = test_data::build_n_empty_blocks_from_genesis(11, 0);
+		let headers0: Vec<IndexedBlockHeader> = blocks0.into_iter().map(|b| b.block_header.into()).collect();
+		let hashes0: Vec<_> = headers0.iter().map(|h| h.hash.clone()).collect();
+		chain.schedule_blocks_headers(headers0.clone()).expect("no lookahead limit");
+		chain.request_blocks_hashes(10);
+		chain.verify_blocks_hashes(10);
+		let blocks1 = test_data::build_n_empty_blocks_from(6, 0, &headers0[10].raw);
+		let headers1: Vec<IndexedBlockHeader> = blocks1.into_iter().map(|b| b.block_header.into()).collect();
+		let hashes1: Vec<_> = headers1.iter().map(|h| h.hash.clone()).collect();
+		chain.schedule_blocks_headers(headers1.clone()).expect("no lookahead limit");
+		chain.request_blocks_hashes(10);
 
+		// regular locator mixes hashes from both forks
+		let locator = chain.block_locator_hashes();
+		assert!(locator.contains(&hashes1[5]) && locator.contains(&block2_hash));
+		// best chain locator only contains hashes from the storage chain
+		let locator = chain.block_locator_hashes_for_best_chain();
+		assert_eq!(locator, vec![block2_hash.clone(), block1_hash.clone(), genesis_hash.clone()]);
+		assert!(!hashes0.iter().chain(hashes1.iter()).any(|h| locator.contains(h)));
+
+		// blocks from the best chain are in the queue
 		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
 		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
-		chain.insert_verified_transaction(data_chain.at(1).into());
-		assert_eq!(chain.information().transactions.transactions_count, 1);
-		chain.insert_verified_transaction(data_chain.at(2).into());
-		assert_eq!(chain.information().transactions.transactions_count, 1); // tx was replaces
+		chain.schedule_blocks_headers(headers0.clone()).expect("no lookahead limit");
+		chain.schedule_blocks_headers(headers1.clone()).expect("no lookahead limit");
+		chain.request_blocks_hashes(10);
+		assert_eq!(chain.block_locator_hashes_for_best_chain(), vec![
+			hashes1[5].clone(),
+			hashes1[4].clone(),
+			hashes1[3].clone(),
+			hashes1[2].clone(),
+			hashes1[1].clone(),
+			hashes1[0].clone(),
+			hashes0[10].clone(),
+			hashes0[9].clone(),
+			hashes0[8].clone(),
+			hashes0[7].clone(),
+			hashes0[5].clone(),
+			hashes0[1].clone(),
+			genesis_hash.clone(),
+		]);
+	}
+
+	#[test]
+	fn chain_request_blocks_hashes_bounded() {
+		let db = Arc::new(BlockChainDatabase::init_test_chain(vec![test_data::genesis().into()]));
+		let mut chain = Chain::new(db, Arc::new(RwLock::new(MemoryPool::new())));
+		let blocks = test_data::build_n_empty_blocks_from_genesis(20, 0);
+		let headers: Vec<IndexedBlockHeader> = blocks.into_iter().map(|b| b.block_header.into()).collect();
+		chain.schedule_blocks_headers(headers.clone()).expect("no lookahead limit");
+
+		assert_eq!(chain.request_blocks_hashes_bounded(3, Some(5)).len(), 3);
+		assert_eq!(chain.request_blocks_hashes_bounded(3, Some(5)).len(), 2);
+		for _ in 0..5 {
+			assert_eq!(chain.request_blocks_hashes_bounded(3, Some(5)).len(), 0);
+			assert_eq!(chain.information().requested, 5);
+		}
+
+		// requested blocks are received => more blocks could be requested
+		chain.verify_blocks_hashes(2);
+		assert_eq!(chain.request_blocks_hashes_bounded(3, Some(5)), vec![headers[5].hash.clone(), headers[6].hash.clone()]);
+		assert_eq!(chain.information().requested, 5);
+		assert_eq!(chain.information().scheduled, 13);
+
+		// no limit
+		assert_eq!(chain.request_blocks_hashes_bounded(3, None).len(), 3);
+		assert_eq!(chain.information().requested, 8);
 	}
 }
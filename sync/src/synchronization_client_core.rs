@@ -12,7 +12,7 @@ use miner::transaction_fee_rate;
 use network::Magic;
 use primitives::hash::H256;
 use verification::BackwardsCompatibleChainVerifier as ChainVerifier;
-use synchronization_chain::{Chain, BlockState, TransactionState, BlockInsertionResult};
+use synchronization_chain::{Chain, BlockState, TransactionState, BlockInsertionResult, ReorgError};
 use synchronization_executor::{Task, TaskExecutor};
 use synchronization_manager::ManagementWorker;
 use synchronization_peers_tasks::PeersTasks;
@@ -25,8 +25,6 @@ use utils::{AverageSpeedMeter, MessageBlockHeadersProvider, OrphanBlocksPool, Or
 
 /// Approximate maximal number of blocks hashes in scheduled queue.
 const MAX_SCHEDULED_HASHES: BlockHeight = 4 * 1024;
-/// Approximate maximal number of blocks hashes in requested queue.
-const MAX_REQUESTED_BLOCKS: BlockHeight = 256;
 /// Approximate maximal number of blocks in verifying queue.
 const MAX_VERIFYING_BLOCKS: BlockHeight = 256;
 /// Minimum number of blocks to request from peer
@@ -308,7 +306,13 @@ impl<T> ClientCore for SynchronizationClientCore<T> where T: TaskExecutor {
 
 				// prepare new headers array
 				let new_headers = headers.split_off(first_unknown_index);
-				self.chain.schedule_blocks_headers(new_headers);
+				if let Err(error) = self.chain.schedule_blocks_headers(new_headers) {
+					debug!(target: "sync", "Dropped headers from peer#{} beyond the lookahead window: first dropped header {} is {} blocks ahead of best storage block",
+						peer_index,
+						error.hash.to_reversed_str(),
+						error.blocks_ahead
+					);
+				}
 
 				// switch to synchronization state
 				if !self.state.is_synchronizing() {
@@ -626,7 +630,7 @@ impl<T> ClientCore for SynchronizationClientCore<T> where T: TaskExecutor {
 				// check if we can move some blocks from scheduled to requested queue
 				{
 					let scheduled_hashes_len = self.chain.length_of_blocks_state(BlockState::Scheduled);
-					if requested_hashes_len + verifying_hashes_len < MAX_REQUESTED_BLOCKS + MAX_VERIFYING_BLOCKS && scheduled_hashes_len != 0 {
+					if requested_hashes_len + verifying_hashes_len < self.chain.current_download_window() + MAX_VERIFYING_BLOCKS && scheduled_hashes_len != 0 {
 						let chunk_size = min(MAX_BLOCKS_IN_REQUEST, max(scheduled_hashes_len / blocks_idle_peers_len, MIN_BLOCKS_IN_REQUEST));
 						let hashes_to_request_len = chunk_size * blocks_idle_peers_len;
 						let hashes_to_request = self.chain.request_blocks_hashes(hashes_to_request_len);
@@ -866,7 +870,9 @@ impl<T> SynchronizationClientCore<T> where T: TaskExecutor {
 	fn process_peer_transaction(&mut self, _peer_index: Option<PeerIndex>, transaction: IndexedTransaction, relay: bool) -> Option<VecDeque<IndexedTransaction>> {
 		match self.try_append_transaction(transaction.clone(), relay) {
 			Err(AppendTransactionError::Orphan(unknown_parents)) => {
-				self.orphaned_transactions_pool.insert(transaction, unknown_parents);
+				for evicted in self.orphaned_transactions_pool.insert(transaction, unknown_parents) {
+					trace!(target: "sync", "Orphan transaction {} has been evicted from the pool", evicted.to_reversed_str());
+				}
 				None
 			},
 			Err(AppendTransactionError::Synchronizing) => None,
@@ -1064,6 +1070,25 @@ impl<T> SynchronizationClientCore<T> where T: TaskExecutor {
 				}
 				Some(verification_tasks)
 			},
+			Err(ReorgError::TooDeep { depth }) => {
+				// block is stored to the side chain, but is not activated
+				warn!(target: "sync", "Block {} requires too deep reorganization: {} blocks", block_hash.to_reversed_str(), depth);
+
+				self.awake_waiting_threads(&block_hash);
+				self.execute_synchronization_tasks(None, None);
+				Some(Vec::new())
+			},
+			Err(ReorgError::Timestamp(error)) => {
+				self.on_block_verification_error(&format!("{:?}", error), &block_hash);
+				Some(Vec::new())
+			},
+			Err(ReorgError::SafeMode) => {
+				// block is kept by the chain and inserted when safe mode is cleared manually
+				warn!(target: "sync", "Block {} is not inserted until safe mode is cleared", block_hash.to_reversed_str());
+
+				self.awake_waiting_threads(&block_hash);
+				Some(Vec::new())
+			},
 			Err(e) => {
 				// process as irrecoverable failure
 				panic!("Block {} insertion failed with error {:?}", block_hash.to_reversed_str(), e);
@@ -1112,7 +1137,26 @@ impl<T> SynchronizationClientCore<T> where T: TaskExecutor {
 		}
 
 		// transaction was in verification queue => insert to memory pool
-		self.chain.insert_verified_transaction(transaction.clone());
+		match self.chain.insert_verified_transaction(transaction.clone()) {
+			Ok(ref result) if !result.replaced.is_empty() => {
+				let replaced: Vec<_> = result.replaced.iter().map(H256::to_reversed_str).collect();
+				debug!(target: "sync", "Transaction {} has replaced memory pool transactions {:?}", transaction.hash.to_reversed_str(), replaced);
+			},
+			Ok(_) => (),
+			Err(error) => {
+				// memory pool policy is local => rejected transaction is not relayed, but peer is not penalized
+				debug!(target: "sync", "Transaction {} is rejected by memory pool: {:?}", transaction.hash.to_reversed_str(), error);
+
+				// transactions, which are spending outputs of rejected transaction, are also rejected
+				self.chain.forget_verifying_transaction_with_children(&transaction.hash);
+
+				// call verification future, if any
+				if let Some(future_sink) = self.verifying_transactions_sinks.remove(&transaction.hash) {
+					future_sink.on_transaction_verification_error(&format!("Transaction is rejected by memory pool: {:?}", error), &transaction.hash);
+				}
+				return;
+			},
+		}
 
 		// calculate transaction fee rate
 		let transaction_fee_rate = transaction_fee_rate(&self.chain, &transaction.raw);
@@ -1187,7 +1231,7 @@ pub mod tests {
 	use primitives::hash::H256;
 	use verification::BackwardsCompatibleChainVerifier as ChainVerifier;
 	use inbound_connection::tests::DummyOutboundSyncConnection;
-	use synchronization_chain::Chain;
+	use synchronization_chain::{Chain, TransactionState};
 	use synchronization_client::{SynchronizationClient, Client};
 	use synchronization_peers::PeersImpl;
 	use synchronization_executor::Task;
@@ -2171,6 +2215,33 @@ pub mod tests {
 		sync.on_transaction(1, test_data::TransactionBuilder::with_default_input(0).into());
 	}
 
+	#[test]
+	fn when_transaction_is_rejected_by_memory_pool() {
+		let tx0 = test_data::genesis().transactions[0].clone();
+		let tx1: Transaction = test_data::TransactionBuilder::with_output(10).add_input(&tx0, 0).into();
+		let tx2: Transaction = test_data::TransactionBuilder::with_output(20).add_input(&tx0, 0).into();
+
+		let (executor, core, sync) = create_sync(None, None);
+		core.lock().peers.insert(0, DummyOutboundSyncConnection::new());
+		core.lock().peers.insert(1, DummyOutboundSyncConnection::new());
+
+		sync.on_transaction(0, tx1.clone().into());
+		assert_eq!(core.lock().information().chain.transactions.transactions_count, 1);
+		executor.take_tasks();
+
+		// tx1 does not signal replaceability => tx2 is rejected and not relayed, but peer is not penalized
+		sync.on_transaction(1, tx2.clone().into());
+		assert_eq!(core.lock().information().chain.transactions.transactions_count, 1);
+		assert_eq!(core.lock().chain().transaction_state(&tx1.hash()), TransactionState::InMemory);
+		assert_eq!(core.lock().chain().transaction_state(&tx2.hash()), TransactionState::Unknown);
+		assert!(executor.take_tasks().iter().all(|task| match *task {
+			Task::RelayNewTransaction(_, _) => false,
+			_ => true,
+		}));
+		assert!(core.lock().peers.enumerate().contains(&0));
+		assert!(core.lock().peers.enumerate().contains(&1));
+	}
+
 	#[test]
 	fn when_transaction_replaces_locked_transaction() {
 		// TODO
@@ -38,6 +38,8 @@ pub enum Task {
 	Inventory(PeerIndex, types::Inv),
 	/// Send headers
 	Headers(PeerIndex, types::Headers, Option<RequestId>),
+	/// Ask peer to announce new blocks using headers
+	SendHeaders(PeerIndex),
 	/// Relay new block to peers
 	RelayNewBlock(IndexedBlock),
 	/// Relay new transaction to peers
@@ -159,6 +161,13 @@ impl LocalSynchronizationTaskExecutor {
 		}
 	}
 
+	fn execute_sendheaders(&self, peer_index: PeerIndex) {
+		if let Some(connection) = self.peers.connection(peer_index) {
+			trace!(target: "sync", "Asking peer#{} to announce new blocks using headers", peer_index);
+			connection.send_sendheaders(&types::SendHeaders);
+		}
+	}
+
 	fn execute_relay_block(&self, block: IndexedBlock) {
 		for peer_index in self.peers.enumerate() {
 			match self.peers.filter_block(peer_index, &block) {
@@ -207,6 +216,7 @@ impl TaskExecutor for LocalSynchronizationTaskExecutor {
 			Task::NotFound(peer_index, notfound) => self.execute_notfound(peer_index, notfound),
 			Task::Inventory(peer_index, inventory) => self.execute_inventory(peer_index, inventory),
 			Task::Headers(peer_index, headers, request_id) => self.execute_headers(peer_index, headers, request_id),
+			Task::SendHeaders(peer_index) => self.execute_sendheaders(peer_index),
 			Task::RelayNewBlock(block) => self.execute_relay_block(block),
 			Task::RelayNewTransaction(transaction, fee_rate) => self.execute_relay_transaction(transaction, fee_rate),
 		}
@@ -301,6 +311,18 @@ pub mod tests {
 		assert_eq!(*c2.messages.lock().entry("headers".to_owned()).or_insert(0), 1);
 	}
 
+	#[test]
+	fn sendheaders_is_sent() {
+		let peers = Arc::new(PeersImpl::default());
+		let executor = LocalSynchronizationTaskExecutor::new(peers.clone());
+
+		let c1 = DummyOutboundSyncConnection::new();
+		peers.insert(1, c1.clone());
+
+		executor.execute(Task::SendHeaders(1));
+		assert_eq!(*c1.messages.lock().entry("sendheaders".to_owned()).or_insert(0), 1);
+	}
+
 	#[test]
 	fn relay_new_transaction_with_bloom_filter() {
 		let peers = Arc::new(PeersImpl::default());
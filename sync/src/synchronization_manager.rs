@@ -24,6 +24,8 @@ const DEFAULT_UNKNOWN_BLOCKS_MAX_LEN: usize = 16;
 const DEFAULT_ORPHAN_TRANSACTION_REMOVAL_TIME_MS: u32 = 10 * 60 * 1000;
 /// Maximal number of orphaned transactions
 const DEFAULT_ORPHAN_TRANSACTIONS_MAX_LEN: usize = 10000;
+/// Memory pool transaction expiration time
+const DEFAULT_MEMORY_POOL_EXPIRY_S: u64 = 14 * 24 * 60 * 60;
 
 /// Synchronization management worker
 pub struct ManagementWorker {
@@ -96,6 +98,12 @@ impl ManagementWorker {
 					}
 				}
 			}
+
+			// remove too old memory pool transactions
+			let expired_transactions = core.chain().expire_mempool(Duration::from_secs(DEFAULT_MEMORY_POOL_EXPIRY_S));
+			if !expired_transactions.is_empty() {
+				trace!(target: "sync", "Removed {} expired transactions from memory pool", expired_transactions.len());
+			}
 		}
 
 		trace!(target: "sync", "Stopping sync management thread");
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use chain::IndexedBlockHeader;
 use primitives::hash::H256;
 use super::{HashQueue, HashPosition};
@@ -22,6 +22,8 @@ pub struct BestHeadersChain {
 	headers: HashMap<H256, IndexedBlockHeader>,
 	/// Best chain
 	best: HashQueue,
+	/// Headers, which blocks have been forgotten while waiting for their parent
+	orphaned: HashSet<H256>,
 }
 
 impl BestHeadersChain {
@@ -31,6 +33,17 @@ impl BestHeadersChain {
 			storage_best_hash: storage_best_hash,
 			headers: HashMap::new(),
 			best: HashQueue::new(),
+			orphaned: HashSet::new(),
+		}
+	}
+
+	/// Create new best headers chain with space for at least `capacity` headers
+	pub fn with_capacity(storage_best_hash: H256, capacity: usize) -> Self {
+		BestHeadersChain {
+			storage_best_hash: storage_best_hash,
+			headers: HashMap::with_capacity(capacity),
+			best: HashQueue::with_capacity(capacity),
+			orphaned: HashSet::new(),
 		}
 	}
 
@@ -66,6 +79,18 @@ impl BestHeadersChain {
 			.unwrap_or_default()
 	}
 
+	/// Get number of headers, which blocks are waiting for their parent
+	pub fn orphaned(&self) -> u32 {
+		self.orphaned.len() as u32
+	}
+
+	/// Mark known header as dangling (its block has been forgotten)
+	pub fn mark_orphaned(&mut self, hash: &H256) {
+		if self.headers.contains_key(hash) {
+			self.orphaned.insert(hash.clone());
+		}
+	}
+
 	/// Get hash of best block
 	pub fn best_block_hash(&self) -> H256 {
 		self.best.back()
@@ -75,6 +100,11 @@ impl BestHeadersChain {
 
 	/// Insert new block header
 	pub fn insert(&mut self, header: IndexedBlockHeader) {
+		// block of dangling header has arrived
+		if self.orphaned.remove(&header.hash) {
+			return;
+		}
+
 		// append to the best chain
 		if self.best_block_hash() == header.raw.previous_header_hash {
 			let header_hash = header.hash.clone();
@@ -94,6 +124,7 @@ impl BestHeadersChain {
 	/// Remove block header with given hash and all its children
 	pub fn remove(&mut self, hash: &H256) {
 		if self.headers.remove(hash).is_some() {
+			self.orphaned.remove(hash);
 			match self.best.remove(hash) {
 				HashPosition::Front => self.clear(),
 				HashPosition::Inside(position) => self.clear_after(position),
@@ -114,6 +145,7 @@ impl BestHeadersChain {
 		if self.best.front().map(|h| &h == hash).unwrap_or(false) {
 			self.best.pop_front();
 			self.headers.remove(hash);
+			self.orphaned.remove(hash);
 		}
 		self.storage_best_hash = storage_best_hash.clone();
 	}
@@ -122,6 +154,7 @@ impl BestHeadersChain {
 	pub fn clear(&mut self) {
 		self.headers.clear();
 		self.best.clear();
+		self.orphaned.clear();
 	}
 
 	/// Remove headers after position
@@ -130,7 +163,9 @@ impl BestHeadersChain {
 			self.clear()
 		} else {
 			while self.best.len() > position {
-				self.headers.remove(&self.best.pop_back().expect("len() > position; qed"));
+				let hash = self.best.pop_back().expect("len() > position; qed");
+				self.headers.remove(&hash);
+				self.orphaned.remove(&hash);
 			}
 		}
 	}
@@ -228,4 +263,25 @@ mod tests {
 		assert_eq!(chain.information().best, 1);
 		assert_eq!(chain.information().total, 1);
 	}
+
+	#[test]
+	fn best_chain_orphaned() {
+		let mut chain = BestHeadersChain::new(test_data::genesis().hash());
+		let b1 = test_data::block_h1().block_header;
+		let b2 = test_data::block_h2().block_header;
+		chain.mark_orphaned(&b1.hash());
+		assert_eq!(chain.orphaned(), 0);
+
+		chain.insert(b1.clone().into());
+		chain.insert(b2.clone().into());
+		chain.mark_orphaned(&b2.hash());
+		assert_eq!(chain.orphaned(), 1);
+		chain.insert(b2.clone().into());
+		assert_eq!(chain.orphaned(), 0);
+		assert_eq!(chain.information().best, 2);
+
+		chain.mark_orphaned(&b2.hash());
+		chain.remove(&b1.hash());
+		assert_eq!(chain.orphaned(), 0);
+	}
 }
@@ -12,7 +12,11 @@ const MAX_COMPACT_BLOCK_PREFILLED_SIZE: usize = 10 * 1024;
 
 pub fn build_compact_block(block: &IndexedBlock, prefilled_transactions_indexes: HashSet<usize>) -> BlockHeaderAndIDs {
 	let nonce: u64 = thread_rng().gen();
+	build_compact_block_with_nonce(block, prefilled_transactions_indexes, nonce)
+}
 
+/// Build compact block, using given nonce to compute short transactions ids
+pub fn build_compact_block_with_nonce(block: &IndexedBlock, prefilled_transactions_indexes: HashSet<usize>, nonce: u64) -> BlockHeaderAndIDs {
 	let prefilled_transactions_len = prefilled_transactions_indexes.len();
 	let mut short_ids: Vec<ShortTransactionID> = Vec::with_capacity(block.transactions.len() - prefilled_transactions_len);
 	let mut prefilled_transactions: Vec<PrefilledTransaction> = Vec::with_capacity(prefilled_transactions_len);
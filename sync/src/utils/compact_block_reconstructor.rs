@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use chain::{ShortTransactionID, IndexedBlock, IndexedBlockHeader, IndexedTransaction, Transaction};
+use message::common::{BlockHeaderAndIDs, BlockTransactionsRequest};
+use miner::MemoryPool;
+use super::compact_block_builder::{short_transaction_id_keys, short_transaction_id};
+
+/// Compact block with transactions, found in memory pool
+#[derive(Debug, PartialEq)]
+pub struct PartiallyReconstructedBlock {
+	/// Header of the block
+	header: IndexedBlockHeader,
+	/// Block transactions. None for transactions, which must be requested from peer
+	transactions: Vec<Option<IndexedTransaction>>,
+}
+
+/// Fill compact block with prefilled transactions and transactions from memory pool.
+/// Transactions, which short id matches several memory pool transactions (or several block
+/// transactions), are left missing, so that they are requested from peer.
+/// Returns None if compact block is malformed.
+pub fn reconstruct_compact_block(compact_block: &BlockHeaderAndIDs, memory_pool: &MemoryPool) -> Option<PartiallyReconstructedBlock> {
+	let transactions_len = compact_block.short_ids.len() + compact_block.prefilled_transactions.len();
+	let mut transactions: Vec<Option<IndexedTransaction>> = vec![None; transactions_len];
+	let mut is_prefilled = vec![false; transactions_len];
+	for prefilled_transaction in &compact_block.prefilled_transactions {
+		let index = prefilled_transaction.index;
+		if index >= transactions_len || is_prefilled[index] {
+			return None;
+		}
+
+		is_prefilled[index] = true;
+		transactions[index] = Some(prefilled_transaction.transaction.clone().into());
+	}
+
+	// remember indexes of short ids. Index is None if several transactions share the same short id
+	let mut short_ids: HashMap<ShortTransactionID, Option<usize>> = HashMap::with_capacity(compact_block.short_ids.len());
+	let non_prefilled_indexes = (0..transactions_len).filter(|index| !is_prefilled[*index]);
+	for (short_id, index) in compact_block.short_ids.iter().zip(non_prefilled_indexes) {
+		short_ids.entry(short_id.clone())
+			.and_modify(|existing_index| *existing_index = None)
+			.or_insert(Some(index));
+	}
+
+	// match memory pool transactions against short ids
+	let (key0, key1) = short_transaction_id_keys(compact_block.nonce, &compact_block.header);
+	let mut collisions: Vec<usize> = Vec::new();
+	for hash in memory_pool.get_transactions_ids() {
+		let index = match short_ids.get(&short_transaction_id(key0, key1, &hash)) {
+			Some(&Some(index)) => index,
+			_ => continue,
+		};
+
+		if transactions[index].is_some() {
+			collisions.push(index);
+			continue;
+		}
+
+		let transaction = memory_pool.read_by_hash(&hash).cloned()
+			.expect("hash is read from the same memory pool; qed");
+		transactions[index] = Some(IndexedTransaction::new(hash, transaction));
+	}
+
+	// several memory pool transactions share the same short id => request full transaction
+	for index in collisions {
+		transactions[index] = None;
+	}
+
+	Some(PartiallyReconstructedBlock {
+		header: compact_block.header.clone().into(),
+		transactions: transactions,
+	})
+}
+
+impl PartiallyReconstructedBlock {
+	/// Get indexes of block transactions, which must be requested from peer
+	pub fn missing_indexes(&self) -> Vec<usize> {
+		self.transactions.iter()
+			.enumerate()
+			.filter(|&(_, transaction)| transaction.is_none())
+			.map(|(index, _)| index)
+			.collect()
+	}
+
+	/// Build `getblocktxn` request for missing transactions
+	pub fn block_transactions_request(&self) -> BlockTransactionsRequest {
+		BlockTransactionsRequest {
+			blockhash: self.header.hash.clone(),
+			indexes: self.missing_indexes(),
+		}
+	}
+
+	/// Fill missing transactions with transactions from `blocktxn` response.
+	/// Returns None if number of transactions does not match number of missing transactions
+	pub fn fill(mut self, missing_transactions: Vec<Transaction>) -> Option<IndexedBlock> {
+		let missing_indexes = self.missing_indexes();
+		if missing_indexes.len() != missing_transactions.len() {
+			return None;
+		}
+
+		for (index, transaction) in missing_indexes.into_iter().zip(missing_transactions) {
+			self.transactions[index] = Some(transaction.into());
+		}
+
+		self.into_block()
+	}
+
+	/// Convert into block. Returns None if some transactions are missing
+	pub fn into_block(self) -> Option<IndexedBlock> {
+		let transactions_len = self.transactions.len();
+		let transactions: Vec<IndexedTransaction> = self.transactions.into_iter().filter_map(|transaction| transaction).collect();
+		if transactions.len() != transactions_len {
+			return None;
+		}
+
+		Some(IndexedBlock::new(self.header, transactions))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate test_data;
+
+	use std::collections::HashSet;
+	use chain::IndexedBlock;
+	use miner::MemoryPool;
+	use super::super::compact_block_builder::build_compact_block;
+	use super::*;
+
+	#[test]
+	fn compact_block_is_reconstructed_from_memory_pool() {
+		let block: IndexedBlock = test_data::block_builder().header().parent(test_data::genesis().hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.transaction().output().value(20).build().build()
+			.transaction().output().value(30).build().build()
+			.transaction().output().value(40).build().build()
+			.build()
+			.into();
+
+		let mut memory_pool = MemoryPool::new();
+		memory_pool.insert_verified(block.transactions[1].clone());
+		memory_pool.insert_verified(block.transactions[3].clone());
+		memory_pool.insert_verified(test_data::block_builder().transaction().output().value(50).build().build().build().transactions[0].clone().into());
+
+		let prefilled: HashSet<_> = vec![0].into_iter().collect();
+		let compact_block = build_compact_block(&block, prefilled);
+		let partial_block = reconstruct_compact_block(&compact_block, &memory_pool).expect("compact block is valid");
+		assert_eq!(partial_block.missing_indexes(), vec![2]);
+		assert_eq!(partial_block.block_transactions_request(), BlockTransactionsRequest {
+			blockhash: block.hash().clone(),
+			indexes: vec![2],
+		});
+		assert_eq!(partial_block.fill(vec![block.transactions[2].raw.clone()]).map(IndexedBlock::to_raw_block), Some(block.clone().to_raw_block()));
+
+		// when all transactions are in memory pool, block is reconstructed without requests
+		memory_pool.insert_verified(block.transactions[2].clone());
+		let partial_block = reconstruct_compact_block(&compact_block, &memory_pool).expect("compact block is valid");
+		assert_eq!(partial_block.missing_indexes(), Vec::<usize>::new());
+		assert_eq!(partial_block.into_block().map(IndexedBlock::to_raw_block), Some(block.to_raw_block()));
+	}
+
+	#[test]
+	fn compact_block_short_id_collision_is_requested() {
+		let block: IndexedBlock = test_data::block_builder().header().parent(test_data::genesis().hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.transaction().output().value(20).build().build()
+			.transaction().output().value(30).build().build()
+			.build()
+			.into();
+
+		let mut memory_pool = MemoryPool::new();
+		memory_pool.insert_verified(block.transactions[1].clone());
+		memory_pool.insert_verified(block.transactions[2].clone());
+
+		// both transactions have the same short id
+		let mut compact_block = build_compact_block(&block, vec![0].into_iter().collect());
+		compact_block.short_ids[1] = compact_block.short_ids[0].clone();
+		let partial_block = reconstruct_compact_block(&compact_block, &memory_pool).expect("compact block is valid");
+		assert_eq!(partial_block.missing_indexes(), vec![1, 2]);
+		let missing_transactions = vec![block.transactions[1].raw.clone(), block.transactions[2].raw.clone()];
+		assert_eq!(partial_block.fill(missing_transactions).map(IndexedBlock::to_raw_block), Some(block.to_raw_block()));
+	}
+
+	#[test]
+	fn malformed_compact_block_is_rejected() {
+		let block: IndexedBlock = test_data::block_builder().header().parent(test_data::genesis().hash()).build()
+			.transaction().coinbase().output().value(10).build().build()
+			.build()
+			.into();
+
+		let mut compact_block = build_compact_block(&block, vec![0].into_iter().collect());
+		compact_block.prefilled_transactions[0].index = 1;
+		assert_eq!(reconstruct_compact_block(&compact_block, &MemoryPool::new()), None);
+	}
+}
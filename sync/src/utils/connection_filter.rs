@@ -1,10 +1,9 @@
-use bit_vec::BitVec;
-use chain::{IndexedBlock, IndexedTransaction};
+use chain::{IndexedBlock, IndexedTransaction, PartialMerkleTree};
 use message::types;
 use primitives::bytes::Bytes;
 use primitives::hash::H256;
 use synchronization_peers::MerkleBlockArtefacts;
-use utils::{KnownHashFilter, KnownHashType, BloomFilter, FeeRateFilter, build_compact_block, build_partial_merkle_tree};
+use utils::{KnownHashFilter, KnownHashType, BloomFilter, FeeRateFilter, build_compact_block};
 
 /// Filter, which controls data relayed over connection.
 #[derive(Debug, Default)]
@@ -92,7 +91,7 @@ impl ConnectionFilter {
 
 		// calculate hashes && match flags for all transactions
 		let (all_hashes, all_flags) = block.transactions.iter()
-			.fold((Vec::<H256>::with_capacity(all_len), BitVec::with_capacity(all_len)), |(mut all_hashes, mut all_flags), t| {
+			.fold((Vec::<H256>::with_capacity(all_len), Vec::<bool>::with_capacity(all_len)), |(mut all_hashes, mut all_flags), t| {
 				let flag = self.bloom_filter.filter_transaction(t);
 				all_flags.push(flag);
 				all_hashes.push(t.hash.clone());
@@ -103,20 +102,9 @@ impl ConnectionFilter {
 			});
 
 		// build partial merkle tree
-		let partial_merkle_tree = build_partial_merkle_tree(all_hashes, all_flags);
+		let partial_merkle_tree = PartialMerkleTree::build(all_len as u32, &all_flags, &all_hashes);
+		result.merkleblock.flags = partial_merkle_tree.flags_bytes();
 		result.merkleblock.hashes.extend(partial_merkle_tree.hashes);
-		// to_bytes() converts [true, false, true] to 0b10100000
-		// while protocol requires [true, false, true] to be serialized as 0x00000101
-		result.merkleblock.flags = partial_merkle_tree.flags.to_bytes().into_iter()
-			.map(|b|
-				((b & 0b10000000) >> 7) |
-				((b & 0b01000000) >> 5) |
-				((b & 0b00100000) >> 3) |
-				((b & 0b00010000) >> 1) |
-				((b & 0b00001000) << 1) |
-				((b & 0b00000100) << 3) |
-				((b & 0b00000010) << 5) |
-				((b & 0b00000001) << 7)).collect::<Vec<u8>>().into();
 		Some(result)
 	}
 }
@@ -1,5 +1,6 @@
 use std::ops::Index;
-use std::collections::{VecDeque, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque, HashSet, HashMap};
 use std::iter::repeat;
 use primitives::hash::H256;
 
@@ -15,10 +16,16 @@ pub enum HashPosition {
 }
 
 /// Ordered queue with O(1) contains() && random access operations cost.
+/// Elements with higher priority are kept closer to the front of the queue.
 #[derive(Debug, Clone)]
 pub struct HashQueue {
+	/// Elements with zero priority, in insertion order. Always follow elements with non-zero priority
 	queue: VecDeque<H256>,
+	/// Non-empty groups of elements with the same non-zero priority, in insertion order
+	groups: BTreeMap<Reverse<u32>, VecDeque<H256>>,
 	set: HashSet<H256>,
+	/// Priorities of elements, inserted with non-zero priority
+	priorities: HashMap<H256, u32>,
 }
 
 /// Chain of linked queues. First queue has index zero.
@@ -31,50 +38,87 @@ impl HashQueue {
 	pub fn new() -> Self {
 		HashQueue {
 			queue: VecDeque::new(),
+			groups: BTreeMap::new(),
 			set: HashSet::new(),
+			priorities: HashMap::new(),
 		}
 	}
 
+	/// Creates empty queue with space for at least `capacity` elements
+	pub fn with_capacity(capacity: usize) -> Self {
+		let mut queue = HashQueue::new();
+		queue.reserve(capacity);
+		queue
+	}
+
+	/// Reserves space for at least `additional` more elements
+	pub fn reserve(&mut self, additional: usize) {
+		self.queue.reserve(additional);
+		self.set.reserve(additional);
+	}
+
 	/// Clears the queue
 	pub fn clear(&mut self) {
 		self.set.clear();
 		self.queue.clear();
+		self.groups.clear();
+		self.priorities.clear();
 	}
 
 	/// Returns len of the given queue.
 	pub fn len(&self) -> u32 {
-		self.queue.len() as u32
+		self.set.len() as u32
 	}
 
 	/// Returns front element from the given queue.
 	pub fn front(&self) -> Option<H256> {
-		self.queue.front().cloned()
+		match self.groups.values().next() {
+			Some(group) => group.front().cloned(),
+			None => self.queue.front().cloned(),
+		}
 	}
 
 	/// Returns back element from the given queue.
 	pub fn back(&self) -> Option<H256> {
-		self.queue.back().cloned()
+		match self.queue.back() {
+			Some(hash) => Some(hash.clone()),
+			None => self.groups.values().next_back().and_then(|group| group.back()).cloned(),
+		}
 	}
 
 	/// Returns position of the element in the queue
 	pub fn position(&self, hash: &H256) -> Option<u32> {
-		self.queue.iter().enumerate()
-			.filter_map(|(pos, h)| if hash == h { Some(pos as u32) } else { None })
-			.nth(0)
+		if !self.set.contains(hash) {
+			return None;
+		}
+
+		let priority = self.priority(hash);
+		if priority == 0 {
+			let preceding = self.set.len() - self.queue.len();
+			return self.queue.iter()
+				.position(|h| h == hash)
+				.map(|pos| (preceding + pos) as u32);
+		}
+
+		let priority = Reverse(priority);
+		let preceding: usize = self.groups.range(..priority).map(|(_, group)| group.len()).sum();
+		self.groups[&priority].iter()
+			.position(|h| h == hash)
+			.map(|pos| (preceding + pos) as u32)
 	}
 
 	/// Returns element at position
 	pub fn at(&self, position: u32) -> Option<H256> {
-		self.queue.get(position as usize).cloned()
+		self.get(position as usize).cloned()
 	}
 
 	/// Returns previous-to back element from the given queue.
 	pub fn pre_back(&self) -> Option<H256> {
-		let queue_len = self.queue.len();
+		let queue_len = self.set.len();
 		if queue_len <= 1 {
 			return None;
 		}
-		Some(self.queue[queue_len - 2].clone())
+		self.at(queue_len as u32 - 2)
 	}
 
 	/// Returns true if queue contains element.
@@ -84,18 +128,17 @@ impl HashQueue {
 
 	/// Returns n elements from the front of the queue
 	pub fn front_n(&self, n: u32) -> Vec<H256> {
-		self.queue.iter().cloned().take(n as usize).collect()
+		self.iter().cloned().take(n as usize).collect()
+	}
+
+	/// Returns priority of the element. Elements, added without priority, have zero priority.
+	pub fn priority(&self, hash: &H256) -> u32 {
+		self.priorities.get(hash).cloned().unwrap_or(0)
 	}
 
 	/// Removes element from the front of the queue.
 	pub fn pop_front(&mut self) -> Option<H256> {
-		match self.queue.pop_front() {
-			Some(hash) => {
-				self.set.remove(&hash);
-				Some(hash)
-			},
-			None => None,
-		}
+		self.pop_front_with_priority().map(|(hash, _)| hash)
 	}
 
 	/// Removes n elements from the front of the queue.
@@ -112,22 +155,25 @@ impl HashQueue {
 
 	/// Removes element from the back of the queue.
 	pub fn pop_back(&mut self) -> Option<H256> {
-		match self.queue.pop_back() {
-			Some(hash) => {
-				self.set.remove(&hash);
-				Some(hash)
-			},
-			None => None,
+		if let Some(hash) = self.queue.pop_back() {
+			self.set.remove(&hash);
+			return Some(hash);
 		}
-	}
 
+		let priority = match self.groups.keys().next_back() {
+			Some(priority) => priority.clone(),
+			None => return None,
+		};
+
+		let hash = self.remove_from_group(priority, |group| group.pop_back());
+		self.set.remove(&hash);
+		self.priorities.remove(&hash);
+		Some(hash)
+	}
 
 	/// Adds element to the back of the queue.
 	pub fn push_back(&mut self, hash: H256) {
-		if !self.set.insert(hash.clone()) {
-			panic!("must be checked by caller");
-		}
-		self.queue.push_back(hash);
+		self.push_with_priority(hash, 0)
 	}
 
 	/// Adds elements to the back of the queue.
@@ -137,34 +183,131 @@ impl HashQueue {
 		}
 	}
 
+	/// Adds element to the queue before all elements with lower priority.
+	/// Elements with the same priority are kept in insertion order.
+	pub fn push_with_priority(&mut self, hash: H256, priority: u32) {
+		self.insert(hash, priority, false)
+	}
+
 	/// Removes element from the queue, returning its position.
 	pub fn remove(&mut self, hash: &H256) -> HashPosition {
-		if !self.set.remove(hash) {
-			return HashPosition::Missing;
+		match self.remove_with_priority(hash) {
+			Some((0, _)) => HashPosition::Front,
+			Some((position, _)) => HashPosition::Inside(position),
+			None => HashPosition::Missing,
 		}
+	}
 
-		if self.queue.front().expect("checked one line above") == hash {
-			self.queue.pop_front();
-			return HashPosition::Front;
+	/// Removes all elements from the queue.
+	pub fn remove_all(&mut self) -> VecDeque<H256> {
+		use std::mem::replace;
+
+		self.set.clear();
+		self.priorities.clear();
+		let queue = replace(&mut self.queue, VecDeque::new());
+		replace(&mut self.groups, BTreeMap::new())
+			.into_iter()
+			.flat_map(|(_, group)| group)
+			.chain(queue)
+			.collect()
+	}
+
+	/// Iterates over elements, starting from the front of the queue
+	fn iter<'a>(&'a self) -> Box<Iterator<Item=&'a H256> + 'a> {
+		if self.groups.is_empty() {
+			return Box::new(self.queue.iter());
 		}
 
-		for i in 0..self.queue.len() {
-			if self.queue[i] == *hash {
-				self.queue.remove(i);
-				return HashPosition::Inside(i as u32);
+		Box::new(self.groups.values().flat_map(|group| group.iter()).chain(self.queue.iter()))
+	}
+
+	fn get(&self, mut index: usize) -> Option<&H256> {
+		let prioritized = self.set.len() - self.queue.len();
+		if index >= prioritized {
+			return self.queue.get(index - prioritized);
+		}
+
+		for group in self.groups.values() {
+			if index < group.len() {
+				return group.get(index);
 			}
+
+			index -= group.len();
 		}
 
-		// unreachable because hash is not missing, not at the front and not inside
-		unreachable!()
+		None
 	}
 
-	/// Removes all elements from the queue.
-	pub fn remove_all(&mut self) -> VecDeque<H256> {
-		use std::mem::replace;
+	/// Inserts element to the back (or to the front) of elements with the same priority
+	fn insert(&mut self, hash: H256, priority: u32, to_front: bool) {
+		if !self.set.insert(hash.clone()) {
+			panic!("must be checked by caller");
+		}
 
-		self.set.clear();
-		replace(&mut self.queue, VecDeque::new())
+		{
+			let group = match priority {
+				0 => &mut self.queue,
+				_ => self.groups.entry(Reverse(priority)).or_insert_with(VecDeque::new),
+			};
+			if to_front {
+				group.push_front(hash.clone());
+			} else {
+				group.push_back(hash.clone());
+			}
+		}
+
+		if priority != 0 {
+			self.priorities.insert(hash, priority);
+		}
+	}
+
+	/// Removes element from the group, dropping the group if it becomes empty
+	fn remove_from_group<F>(&mut self, priority: Reverse<u32>, remove: F) -> H256 where F: FnOnce(&mut VecDeque<H256>) -> Option<H256> {
+		if priority.0 == 0 {
+			return remove(&mut self.queue).expect("group is checked by caller; qed");
+		}
+
+		let (hash, is_empty) = {
+			let group = self.groups.get_mut(&priority).expect("groups are never empty; qed");
+			let hash = remove(group).expect("groups are never empty; qed");
+			(hash, group.is_empty())
+		};
+
+		if is_empty {
+			self.groups.remove(&priority);
+		}
+		hash
+	}
+
+	/// Removes element from the front of the queue, returning it with its priority
+	fn pop_front_with_priority(&mut self) -> Option<(H256, u32)> {
+		let priority = match self.groups.keys().next() {
+			Some(priority) => priority.clone(),
+			None if !self.queue.is_empty() => Reverse(0),
+			None => return None,
+		};
+
+		let hash = self.remove_from_group(priority, |group| group.pop_front());
+		self.set.remove(&hash);
+		self.priorities.remove(&hash);
+		Some((hash, priority.0))
+	}
+
+	/// Removes element from the queue, returning its position and priority
+	fn remove_with_priority(&mut self, hash: &H256) -> Option<(u32, u32)> {
+		let position = match self.position(hash) {
+			Some(position) => position,
+			None => return None,
+		};
+
+		let priority = self.priority(hash);
+		self.remove_from_group(Reverse(priority), |group| {
+			let index = group.iter().position(|h| h == hash).expect("position is checked above; qed");
+			group.remove(index)
+		});
+		self.set.remove(hash);
+		self.priorities.remove(hash);
+		Some((position, priority))
 	}
 }
 
@@ -172,7 +315,7 @@ impl Index<u32> for HashQueue {
 	type Output = H256;
 
 	fn index(&self, index: u32) -> &Self::Output {
-		&self.queue[index as usize]
+		self.get(index as usize).expect("invalid index")
 	}
 }
 
@@ -280,11 +423,58 @@ impl HashQueueChain {
 		self.chain[queue_index].push_back_n(hashes)
 	}
 
+	/// Move n hashes from the front of one queue to the back of another queue, preserving their priorities.
+	pub fn move_front_n(&mut self, from_queue_index: usize, to_queue_index: usize, n: u32) -> Vec<H256> {
+		let mut moved = Vec::new();
+		for _ in 0..n {
+			match self.chain[from_queue_index].pop_front_with_priority() {
+				Some((hash, priority)) => {
+					self.chain[to_queue_index].insert(hash.clone(), priority, false);
+					moved.push(hash);
+				},
+				None => break,
+			}
+		}
+		moved
+	}
+
+	/// Move hash from one queue to the back of another queue, preserving its priority.
+	/// Returns false if hash is not in the source queue.
+	pub fn move_at(&mut self, from_queue_index: usize, to_queue_index: usize, hash: &H256) -> bool {
+		match self.chain[from_queue_index].remove_with_priority(hash) {
+			Some((_, priority)) => {
+				self.chain[to_queue_index].insert(hash.clone(), priority, false);
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Move hashes from one queue to another queue, so that they're in front of other hashes with the same priority.
+	/// The order of moved hashes with the same priority is preserved.
+	pub fn move_to_front_at(&mut self, from_queue_index: usize, to_queue_index: usize, hashes: Vec<H256>) {
+		for hash in hashes.into_iter().rev() {
+			if let Some((_, priority)) = self.chain[from_queue_index].remove_with_priority(&hash) {
+				self.chain[to_queue_index].insert(hash, priority, true);
+			}
+		}
+	}
+
+	/// Push hash to the given queue, keeping hashes with higher priority closer to the front.
+	pub fn push_with_priority_at(&mut self, queue_index: usize, hash: H256, priority: u32) {
+		self.chain[queue_index].push_with_priority(hash, priority)
+	}
+
 	/// Remove hash from given queue.
 	pub fn remove_at(&mut self, queue_index: usize, hash: &H256) -> HashPosition {
 		self.chain[queue_index].remove(hash)
 	}
 
+	/// Reserve space for at least `additional` more hashes in the given queue.
+	pub fn reserve_at(&mut self, queue_index: usize, additional: usize) {
+		self.chain[queue_index].reserve(additional)
+	}
+
 	/// Remove all items from given queue.
 	pub fn remove_all_at(&mut self, queue_index: usize) -> VecDeque<H256> {
 		self.chain[queue_index].remove_all()
@@ -393,4 +583,65 @@ mod tests {
 		assert_eq!(queue.pop_front_n(3), vec![H256::from(0), H256::from(1)]);
 		assert_eq!(queue.pop_front_n(3), vec![]);
 	}
+
+	#[test]
+	fn hash_queue_without_priorities_keeps_single_group() {
+		let mut queue = HashQueue::new();
+		queue.push_back_n(vec![H256::from(0), H256::from(1), H256::from(2)]);
+		assert!(queue.groups.is_empty());
+		assert_eq!(queue.at(1), Some(H256::from(1)));
+		assert_eq!(queue.position(&H256::from(2)), Some(2));
+
+		queue.push_with_priority(H256::from(3), 1);
+		assert_eq!(queue.front(), Some(H256::from(3)));
+		assert_eq!(queue.at(1), Some(H256::from(0)));
+		assert_eq!(queue.position(&H256::from(2)), Some(3));
+		assert_eq!(queue.back(), Some(H256::from(2)));
+
+		assert_eq!(queue.pop_front(), Some(H256::from(3)));
+		assert!(queue.groups.is_empty());
+		assert_eq!(queue.pop_back(), Some(H256::from(2)));
+		assert_eq!(queue.front_n(3), vec![H256::from(0), H256::from(1)]);
+	}
+
+	#[test]
+	fn hash_queue_push_with_priority() {
+		let mut queue = HashQueue::new();
+		queue.push_back(H256::from(0));
+		queue.push_with_priority(H256::from(1), 5);
+		queue.push_with_priority(H256::from(2), 10);
+		queue.push_with_priority(H256::from(3), 5);
+		queue.push_back(H256::from(4));
+		queue.push_with_priority(H256::from(5), 0);
+		assert_eq!(queue.front_n(6), vec![H256::from(2), H256::from(1), H256::from(3), H256::from(0), H256::from(4), H256::from(5)]);
+		assert_eq!(queue.priority(&H256::from(2)), 10);
+		assert_eq!(queue.priority(&H256::from(4)), 0);
+
+		assert_eq!(queue.remove(&H256::from(1)), HashPosition::Inside(1));
+		assert_eq!(queue.priority(&H256::from(1)), 0);
+		assert_eq!(queue.pop_front(), Some(H256::from(2)));
+		assert_eq!(queue.priority(&H256::from(2)), 0);
+	}
+
+	#[test]
+	fn hash_queue_chain_move_preserves_priority() {
+		let mut chain = HashQueueChain::with_number_of_queues(2);
+		chain.push_back_at(1, H256::from(0));
+		chain.push_with_priority_at(1, H256::from(1), 5);
+		chain.push_with_priority_at(1, H256::from(2), 10);
+		chain.push_with_priority_at(1, H256::from(3), 5);
+
+		assert_eq!(chain.move_front_n(1, 0, 2), vec![H256::from(2), H256::from(1)]);
+		assert!(chain.move_at(1, 0, &H256::from(0)));
+		assert!(!chain.move_at(1, 0, &H256::from(0)));
+		assert_eq!(chain.front_n_at(0, 3), vec![H256::from(2), H256::from(1), H256::from(0)]);
+
+		// moved back in front of elements with the same priority
+		chain.move_to_front_at(0, 1, vec![H256::from(1), H256::from(0)]);
+		assert_eq!(chain.front_n_at(1, 3), vec![H256::from(1), H256::from(3), H256::from(0)]);
+		assert_eq!(chain[1], H256::from(1));
+		assert_eq!(chain.at(3), Some(H256::from(0)));
+		assert_eq!(chain.remove_at(1, &H256::from(0)), HashPosition::Inside(2));
+		assert_eq!(chain.remove_at(1, &H256::from(1)), HashPosition::Front);
+	}
 }
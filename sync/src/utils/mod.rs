@@ -2,6 +2,9 @@ mod average_speed_meter;
 mod best_headers_chain;
 mod bloom_filter;
 mod compact_block_builder;
+// node never requests `cmpctblock` from peers (see LocalNode::on_compact_block) => reconstruction is only exercised by tests
+#[cfg(test)]
+mod compact_block_reconstructor;
 mod connection_filter;
 mod fee_rate_filter;
 mod hash_queue;
@@ -10,13 +13,14 @@ mod memory_pool_transaction_provider;
 mod message_block_headers_provider;
 mod orphan_blocks_pool;
 mod orphan_transactions_pool;
-mod partial_merkle_tree;
 mod synchronization_state;
 
 pub use self::average_speed_meter::AverageSpeedMeter;
 pub use self::best_headers_chain::{BestHeadersChain, Information as BestHeadersChainInformation};
 pub use self::bloom_filter::BloomFilter;
-pub use self::compact_block_builder::build_compact_block;
+pub use self::compact_block_builder::{build_compact_block, build_compact_block_with_nonce};
+#[cfg(test)]
+pub use self::compact_block_reconstructor::{PartiallyReconstructedBlock, reconstruct_compact_block};
 pub use self::connection_filter::ConnectionFilter;
 pub use self::fee_rate_filter::FeeRateFilter;
 pub use self::hash_queue::{HashQueue, HashQueueChain, HashPosition};
@@ -25,7 +29,6 @@ pub use self::memory_pool_transaction_provider::MemoryPoolTransactionOutputProvi
 pub use self::message_block_headers_provider::MessageBlockHeadersProvider;
 pub use self::orphan_blocks_pool::OrphanBlocksPool;
 pub use self::orphan_transactions_pool::{OrphanTransactionsPool, OrphanTransaction};
-pub use self::partial_merkle_tree::{PartialMerkleTree, build_partial_merkle_tree};
 pub use self::synchronization_state::SynchronizationState;
 
 /// Block height type
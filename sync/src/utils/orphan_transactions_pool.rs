@@ -4,6 +4,12 @@ use linked_hash_map::LinkedHashMap;
 use time;
 use chain::IndexedTransaction;
 use primitives::hash::H256;
+use ser::Serializable;
+
+/// Default maximal number of orphan transactions
+const DEFAULT_MAX_ORPHAN_TRANSACTIONS: usize = 100;
+/// Default maximal total size of orphan transactions
+const DEFAULT_MAX_ORPHAN_SIZE: usize = 10 * 1024 * 1024;
 
 #[derive(Debug)]
 /// Storage for transactions, for which we have no parent transactions yet.
@@ -13,6 +19,12 @@ pub struct OrphanTransactionsPool {
 	by_hash: LinkedHashMap<H256, OrphanTransaction>,
 	/// Orphan transactions by parent' transaction hash
 	by_parent: HashMap<H256, HashSet<H256>>,
+	/// Total size of all orphan transactions
+	total_size: usize,
+	/// Maximal number of orphan transactions
+	max_transactions: usize,
+	/// Maximal total size of orphan transactions
+	max_size: usize,
 }
 
 #[derive(Debug)]
@@ -20,6 +32,8 @@ pub struct OrphanTransactionsPool {
 pub struct OrphanTransaction {
 	/// Time when this transaction was inserted to the pool
 	pub insertion_time: f64,
+	/// Serialized transaction size
+	pub size: usize,
 	/// Transaction itself
 	pub transaction: IndexedTransaction,
 	/// Parent transactions, which are still unknown to us
@@ -27,14 +41,34 @@ pub struct OrphanTransaction {
 }
 
 impl OrphanTransactionsPool {
-	/// Create new pool
+	/// Create new pool with default limits
 	pub fn new() -> Self {
 		OrphanTransactionsPool {
 			by_hash: LinkedHashMap::new(),
 			by_parent: HashMap::new(),
+			total_size: 0,
+			max_transactions: DEFAULT_MAX_ORPHAN_TRANSACTIONS,
+			max_size: DEFAULT_MAX_ORPHAN_SIZE,
 		}
 	}
 
+	/// Set maximal number of orphan transactions. Oldest transactions are evicted when limit is exceeded
+	pub fn set_max_orphan_transactions(&mut self, max: usize) -> Vec<H256> {
+		self.max_transactions = max;
+		self.evict()
+	}
+
+	/// Set maximal total size of orphan transactions. Oldest transactions are evicted when limit is exceeded
+	pub fn set_max_orphan_size(&mut self, max: usize) -> Vec<H256> {
+		self.max_size = max;
+		self.evict()
+	}
+
+	/// Get total size of orphan transactions
+	pub fn size(&self) -> usize {
+		self.total_size
+	}
+
 	#[cfg(test)]
 	/// Get total number of transactions in pool
 	pub fn len(&self) -> usize {
@@ -51,8 +85,8 @@ impl OrphanTransactionsPool {
 		self.by_hash.contains_key(hash)
 	}
 
-	/// Insert orphan transaction
-	pub fn insert(&mut self, transaction: IndexedTransaction, unknown_parents: HashSet<H256>) {
+	/// Insert orphan transaction. Returns hashes of transactions, evicted to keep pool within limits
+	pub fn insert(&mut self, transaction: IndexedTransaction, unknown_parents: HashSet<H256>) -> Vec<H256> {
 		assert!(!self.by_hash.contains_key(&transaction.hash));
 		assert!(unknown_parents.iter().all(|h| transaction.raw.inputs.iter().any(|i| &i.previous_output.hash == h)));
 
@@ -63,7 +97,10 @@ impl OrphanTransactionsPool {
 		}
 
 		let hash = transaction.hash.clone();
-		self.by_hash.insert(hash, OrphanTransaction::new(transaction, unknown_parents));
+		let orphan = OrphanTransaction::new(transaction, unknown_parents);
+		self.total_size += orphan.size;
+		self.by_hash.insert(hash, orphan);
+		self.evict()
 	}
 
 	/// Remove all transactions, depending on this parent
@@ -85,8 +122,10 @@ impl OrphanTransactionsPool {
 					};
 
 					if all_parents_are_known {
+						let removed_orphan = self.by_hash.remove(child).expect("checked couple of lines above");
+						self.total_size -= removed_orphan.size;
 						removed_orphans_hashes.push(child.clone());
-						removed_orphans.push(self.by_hash.remove(child).expect("checked couple of lines above").transaction);
+						removed_orphans.push(removed_orphan.transaction);
 					}
 				}
 
@@ -105,12 +144,32 @@ impl OrphanTransactionsPool {
 		let mut removed: Vec<IndexedTransaction> = Vec::new();
 		for hash in hashes {
 			if let Some(transaction) = self.by_hash.remove(hash) {
+				self.total_size -= transaction.size;
 				removed.push(transaction.transaction);
 			}
 			removed.extend(self.remove_transactions_for_parent(hash));
 		}
 		removed
 	}
+
+	/// Evict oldest transactions until pool is within limits
+	fn evict(&mut self) -> Vec<H256> {
+		let mut evicted = Vec::new();
+		while self.by_hash.len() > self.max_transactions || self.total_size > self.max_size {
+			let (hash, orphan) = self.by_hash.pop_front().expect("pool is not empty, because limits are exceeded; qed");
+			self.total_size -= orphan.size;
+			for unknown_parent in &orphan.unknown_parents {
+				if let Entry::Occupied(mut children_entry) = self.by_parent.entry(unknown_parent.clone()) {
+					children_entry.get_mut().remove(&hash);
+					if children_entry.get().is_empty() {
+						children_entry.remove_entry();
+					}
+				}
+			}
+			evicted.push(hash);
+		}
+		evicted
+	}
 }
 
 impl OrphanTransaction {
@@ -118,6 +177,7 @@ impl OrphanTransaction {
 	pub fn new(transaction: IndexedTransaction, unknown_parents: HashSet<H256>) -> Self {
 		OrphanTransaction {
 			insertion_time: time::precise_time_s(),
+			size: transaction.raw.serialized_size(),
 			transaction: transaction,
 			unknown_parents: unknown_parents,
 		}
@@ -136,8 +196,9 @@ mod tests {
 
 	use std::collections::HashSet;
 	use self::test_data::{TransactionBuilder, ChainBuilder};
+	use chain::Transaction;
 	use primitives::hash::H256;
-	use super::OrphanTransactionsPool;
+	use super::{OrphanTransactionsPool, DEFAULT_MAX_ORPHAN_TRANSACTIONS};
 
 	#[test]
 	fn orphan_transaction_pool_empty_on_start() {
@@ -206,4 +267,53 @@ mod tests {
 		let removed: Vec<H256> = removed.into_iter().map(|tx| tx.hash).collect();
 		assert_eq!(removed, vec![chain.at(6).hash()]);
 	}
+
+	#[test]
+	fn orphan_transaction_pool_evicts_oldest_transactions() {
+		use ser::Serializable;
+
+		let chain = &mut ChainBuilder::new();
+		TransactionBuilder::with_output(100).store(chain)			// t1
+			.into_input(0).add_output(200).store(chain)				// t1 -> t2
+			.set_default_input(0).set_output(300).store(chain)		// t3
+			.into_input(0).set_output(400).store(chain)				// t3 -> t4
+			.set_default_input(0).set_output(500).store(chain)		// t5
+			.into_input(0).set_output(600).store(chain);			// t5 -> t6
+		let unknown = |index: usize| -> HashSet<H256> { chain.at(index).inputs.iter().map(|i| i.previous_output.hash.clone()).collect() };
+		let t4_size = chain.at(3).serialized_size();
+		let t6_size = chain.at(5).serialized_size();
+
+		let mut pool = OrphanTransactionsPool::new();
+		pool.set_max_orphan_transactions(2);
+		assert_eq!(pool.insert(chain.at(1).into(), unknown(1)), vec![]); // t2
+		assert_eq!(pool.insert(chain.at(3).into(), unknown(3)), vec![]); // t4
+		assert_eq!(pool.insert(chain.at(5).into(), unknown(5)), vec![chain.at(1).hash()]); // t6
+		assert_eq!(pool.len(), 2);
+		assert_eq!(pool.size(), t4_size + t6_size);
+
+		// evicted transaction is not returned when its parent becomes known
+		assert_eq!(pool.remove_transactions_for_parent(&chain.at(0).hash()).len(), 0);
+
+		assert_eq!(pool.set_max_orphan_size(t6_size), vec![chain.at(3).hash()]);
+		assert_eq!(pool.len(), 1);
+		assert_eq!(pool.size(), t6_size);
+		let removed: Vec<H256> = pool.remove_transactions_for_parent(&chain.at(4).hash()).into_iter().map(|tx| tx.hash).collect();
+		assert_eq!(removed, vec![chain.at(5).hash()]);
+		assert_eq!(pool.size(), 0);
+	}
+
+	#[test]
+	fn orphan_transaction_pool_is_limited_by_default() {
+		let mut pool = OrphanTransactionsPool::new();
+		let mut evicted = Vec::new();
+		let mut hashes = Vec::new();
+		for i in 0..DEFAULT_MAX_ORPHAN_TRANSACTIONS + 1 {
+			let transaction: Transaction = TransactionBuilder::with_default_input(i as u32).set_output(100).into();
+			let unknown = transaction.inputs.iter().map(|input| input.previous_output.hash.clone()).collect();
+			hashes.push(transaction.hash());
+			evicted.extend(pool.insert(transaction.into(), unknown));
+		}
+		assert_eq!(pool.len(), DEFAULT_MAX_ORPHAN_TRANSACTIONS);
+		assert_eq!(evicted, vec![hashes[0].clone()]);
+	}
 }
@@ -1,3 +1,4 @@
+use rayon::ThreadPool;
 use rayon::prelude::{IntoParallelRefIterator, IndexedParallelIterator, ParallelIterator};
 use db::Store;
 use network::Magic;
@@ -49,6 +50,17 @@ impl<'a> ChainAcceptor<'a> {
 		Ok(())
 	}
 
+	/// Same as `check`, but transactions and scripts of their inputs are verified in parallel, using given thread pool
+	pub fn check_on_pool(&self, pool: &ThreadPool) -> Result<(), Error> {
+		try!(self.block.check());
+		try!(self.header.check());
+		let transactions = &self.transactions;
+		pool.install(move || transactions.par_iter()
+			.enumerate()
+			.fold(|| Ok(()), |result, (index, tx)| result.and_then(|_| tx.check_parallel_scripts().map_err(|err| Error::Transaction(index, err))))
+			.reduce(|| Ok(()), |acc, check| acc.and(check)))
+	}
+
 	fn check_transactions(&self) -> Result<(), Error> {
 		self.transactions.par_iter()
 			.enumerate()
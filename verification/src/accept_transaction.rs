@@ -1,4 +1,6 @@
+use rayon::prelude::{IntoParallelRefIterator, IndexedParallelIterator, ParallelIterator};
 use primitives::hash::H256;
+use chain::TransactionOutput;
 use db::{TransactionMetaProvider, TransactionOutputProvider, BlockHeaderProvider};
 use network::{Magic, ConsensusParams};
 use script::{Script, verify_script, VerificationFlags, TransactionSignatureChecker, TransactionInputSigner};
@@ -7,7 +9,10 @@ use deployments::Deployments;
 use sigops::transaction_sigops;
 use canon::CanonTransaction;
 use constants::{COINBASE_MATURITY, MAX_BLOCK_SIGOPS};
+use chain::constants::{SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG};
 use error::TransactionError;
+use locktime::verify_relative_locktime;
+use timestamp::median_time_past;
 
 pub struct TransactionAcceptor<'a> {
 	pub bip30: TransactionBip30<'a>,
@@ -15,6 +20,7 @@ pub struct TransactionAcceptor<'a> {
 	pub maturity: TransactionMaturity<'a>,
 	pub overspent: TransactionOverspent<'a>,
 	pub double_spent: TransactionDoubleSpend<'a>,
+	pub sequence_locks: TransactionSequenceLocks<'a>,
 	pub eval: TransactionEval<'a>,
 }
 
@@ -42,17 +48,31 @@ impl<'a> TransactionAcceptor<'a> {
 			maturity: TransactionMaturity::new(transaction, meta_store, height),
 			overspent: TransactionOverspent::new(transaction, output_store),
 			double_spent: TransactionDoubleSpend::new(transaction, output_store),
+			sequence_locks: TransactionSequenceLocks::new(transaction, meta_store, &params, height, deployments, headers),
 			eval: TransactionEval::new(transaction, output_store, &params, height, time, deployments, headers),
 		}
 	}
 
 	pub fn check(&self) -> Result<(), TransactionError> {
+		try!(self.check_rules());
+		try!(self.eval.check());
+		Ok(())
+	}
+
+	/// Same as `check`, but input scripts are verified in parallel
+	pub fn check_parallel_scripts(&self) -> Result<(), TransactionError> {
+		try!(self.check_rules());
+		try!(self.eval.check_parallel());
+		Ok(())
+	}
+
+	fn check_rules(&self) -> Result<(), TransactionError> {
 		try!(self.bip30.check());
 		try!(self.missing_inputs.check());
 		try!(self.maturity.check());
 		try!(self.overspent.check());
 		try!(self.double_spent.check());
-		try!(self.eval.check());
+		try!(self.sequence_locks.check());
 		Ok(())
 	}
 }
@@ -208,6 +228,75 @@ impl<'a> TransactionMaturity<'a> {
 	}
 }
 
+/// Relative locktime (BIP68) validation
+pub struct TransactionSequenceLocks<'a> {
+	transaction: CanonTransaction<'a>,
+	/// Heights && median time past of blocks, required to check transaction (None if check is not required).
+	/// Headers are read on construction, because header provider can not be shared between threads
+	locks_data: Option<SequenceLocksData>,
+}
+
+struct SequenceLocksData {
+	input_heights: Vec<u32>,
+	input_times: Vec<u32>,
+	tip_height: u32,
+	tip_median_time_past: u32,
+}
+
+impl<'a> TransactionSequenceLocks<'a> {
+	fn new(
+		transaction: CanonTransaction<'a>,
+		store: &'a TransactionMetaProvider,
+		params: &ConsensusParams,
+		height: u32,
+		deployments: &'a Deployments,
+		headers: &'a BlockHeaderProvider,
+	) -> Self {
+		let is_required = !transaction.raw.is_coinbase()
+			&& transaction.raw.version >= 2
+			&& transaction.raw.inputs.iter().any(|input| input.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0)
+			&& deployments.csv(height, headers, params);
+		let locks_data = if is_required {
+			let tip_height = height.saturating_sub(1);
+			// outputs of transactions from the same block are spent at the block height
+			let input_heights: Vec<u32> = transaction.raw.inputs.iter()
+				.map(|input| store.transaction_meta(&input.previous_output.hash)
+					.map(|meta| meta.height())
+					.unwrap_or(height))
+				.collect();
+			// median time past is only read for inputs with time-based relative locktime
+			let input_times: Vec<u32> = transaction.raw.inputs.iter()
+				.zip(input_heights.iter())
+				.map(|(input, height)| match input.sequence & (SEQUENCE_LOCKTIME_DISABLE_FLAG | SEQUENCE_LOCKTIME_TYPE_FLAG) {
+					SEQUENCE_LOCKTIME_TYPE_FLAG => median_time_past(height.saturating_sub(1).into(), headers),
+					_ => 0,
+				})
+				.collect();
+
+			Some(SequenceLocksData {
+				input_heights: input_heights,
+				input_times: input_times,
+				tip_height: tip_height,
+				tip_median_time_past: median_time_past(tip_height.into(), headers),
+			})
+		} else {
+			None
+		};
+
+		TransactionSequenceLocks {
+			transaction: transaction,
+			locks_data: locks_data,
+		}
+	}
+
+	fn check(&self) -> Result<(), TransactionError> {
+		match self.locks_data {
+			Some(ref data) => verify_relative_locktime(&self.transaction.raw, &data.input_heights, &data.input_times, data.tip_height, data.tip_median_time_past),
+			None => Ok(()),
+		}
+	}
+}
+
 pub struct TransactionOverspent<'a> {
 	transaction: CanonTransaction<'a>,
 	store: DuplexTransactionOutputProvider<'a>,
@@ -337,6 +426,45 @@ impl<'a> TransactionEval<'a> {
 
 		Ok(())
 	}
+
+	/// Same as `check`, but input scripts are verified in parallel.
+	/// Previous outputs are read before verification, so that the store is not accessed from verification threads.
+	/// The error of input with lowest index is returned
+	fn check_parallel(&self) -> Result<(), TransactionError> {
+		if self.transaction.raw.is_coinbase() {
+			return Ok(());
+		}
+
+		let outputs: Vec<Option<TransactionOutput>> = self.transaction.raw.inputs.iter()
+			.map(|input| self.store.transaction_output(&input.previous_output, usize::max_value()))
+			.collect();
+
+		let flags = VerificationFlags::default()
+			.verify_p2sh(self.verify_p2sh)
+			.verify_locktime(self.verify_locktime)
+			.verify_checksequence(self.verify_checksequence)
+			.verify_dersig(self.verify_dersig);
+
+		outputs.par_iter()
+			.enumerate()
+			.map(|(index, output)| self.check_input(index, output.as_ref(), &flags))
+			.reduce(|| Ok(()), |acc, check| acc.and(check))
+	}
+
+	fn check_input(&self, index: usize, output: Option<&TransactionOutput>, flags: &VerificationFlags) -> Result<(), TransactionError> {
+		let input = &self.transaction.raw.inputs[index];
+		let output = try!(output.ok_or_else(|| TransactionError::UnknownReference(input.previous_output.hash.clone())));
+
+		let checker = TransactionSignatureChecker {
+			signer: self.transaction.raw.clone().into(),
+			input_index: index,
+		};
+
+		let input: Script = input.script_sig.clone().into();
+		let output: Script = output.script_pubkey.clone().into();
+
+		verify_script(&input, &output, flags, &checker).map_err(|_| TransactionError::Signature(index))
+	}
 }
 
 pub struct TransactionDoubleSpend<'a> {
@@ -1,5 +1,6 @@
 //! Bitcoin chain verifier
 
+use rayon::{Configuration, ThreadPool, InitError};
 use hash::H256;
 use chain::{IndexedBlock, IndexedBlockHeader, BlockHeader, Transaction};
 use db::{SharedStore, TransactionOutputProvider, BlockHeaderProvider, BlockOrigin};
@@ -19,6 +20,8 @@ pub struct BackwardsCompatibleChainVerifier {
 	store: SharedStore,
 	network: Magic,
 	deployments: Deployments,
+	/// Thread pool for parallel verification of input scripts
+	thread_pool: Option<ThreadPool>,
 }
 
 impl BackwardsCompatibleChainVerifier {
@@ -27,6 +30,22 @@ impl BackwardsCompatibleChainVerifier {
 			store: store,
 			network: network,
 			deployments: Deployments::new(),
+			thread_pool: None,
+		}
+	}
+
+	/// Verify input scripts in parallel, using thread pool with given number of threads.
+	/// Fails if thread pool can not be created
+	pub fn with_threads(mut self, threads: usize) -> Result<Self, InitError> {
+		let configuration = Configuration::new().set_num_threads(threads);
+		self.thread_pool = Some(try!(ThreadPool::new(configuration)));
+		Ok(self)
+	}
+
+	fn check_chain_acceptor(&self, chain_acceptor: &ChainAcceptor) -> Result<(), Error> {
+		match self.thread_pool {
+			Some(ref pool) => chain_acceptor.check_on_pool(pool),
+			None => chain_acceptor.check(),
 		}
 	}
 
@@ -47,21 +66,21 @@ impl BackwardsCompatibleChainVerifier {
 			BlockOrigin::CanonChain { block_number } => {
 				let canon_block = CanonBlock::new(block);
 				let chain_acceptor = ChainAcceptor::new(self.store.as_store(), self.network, canon_block, block_number, &self.deployments);
-				chain_acceptor.check()?;
+				self.check_chain_acceptor(&chain_acceptor)?;
 			},
 			BlockOrigin::SideChain(origin) => {
 				let block_number = origin.block_number;
 				let fork = self.store.fork(origin)?;
 				let canon_block = CanonBlock::new(block);
 				let chain_acceptor = ChainAcceptor::new(fork.store(), self.network, canon_block, block_number, &self.deployments);
-				chain_acceptor.check()?;
+				self.check_chain_acceptor(&chain_acceptor)?;
 			},
 			BlockOrigin::SideChainBecomingCanonChain(origin) => {
 				let block_number = origin.block_number;
 				let fork = self.store.fork(origin)?;
 				let canon_block = CanonBlock::new(block);
 				let chain_acceptor = ChainAcceptor::new(fork.store(), self.network, canon_block, block_number, &self.deployments);
-				chain_acceptor.check()?;
+				self.check_chain_acceptor(&chain_acceptor)?;
 			},
 		}
 
@@ -132,6 +151,7 @@ mod tests {
 
 	use std::sync::Arc;
 	use chain::IndexedBlock;
+	use primitives::bytes::Bytes;
 	use db::{BlockChainDatabase, Error as DBError};
 	use network::Magic;
 	use script;
@@ -425,4 +445,107 @@ mod tests {
 
 		assert_eq!(expected, verifier.verify(&block.into()));
 	}
+
+	#[test]
+	fn invalid_signature() {
+		let pubkey: Bytes = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".into();
+		let script_pubkey = script::Builder::default()
+			.push_data(&pubkey)
+			.push_opcode(script::Opcode::OP_CHECKSIG)
+			.into_script();
+		let genesis = test_data::block_builder()
+			.transaction()
+				.coinbase()
+				.build()
+			.transaction()
+				.output().value(50).script_pubkey_bytes(script_pubkey.to_bytes()).build()
+				.build()
+			.merkled_header().build()
+			.build();
+
+		let storage = BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]);
+		let reference_tx = genesis.transactions()[1].hash();
+
+		// strict DER encoded signature with r = 1 && s = 1 (+ SIGHASH_ALL)
+		let signature: Bytes = "300602010102010101".into();
+		let script_sig = script::Builder::default()
+			.push_data(&signature)
+			.into_script();
+		let block: IndexedBlock = test_data::block_builder()
+			.transaction().coinbase().build()
+			.transaction()
+				.input()
+					.hash(reference_tx)
+					.signature_bytes(script_sig.to_bytes())
+					.build()
+				.output().value(50).build()
+				.build()
+			.merkled_header().parent(genesis.hash()).build()
+			.build()
+			.into();
+
+		let verifier = ChainVerifier::new(Arc::new(storage), Magic::Unitest);
+		let expected = Err(Error::Transaction(1, TransactionError::Signature(0)));
+		assert_eq!(expected, verifier.verify(&block));
+	}
+
+	#[test]
+	fn parallel_scripts_verification() {
+		let invalid_script = script::Builder::default()
+			.push_opcode(script::Opcode::OP_0)
+			.into_script();
+
+		// transaction with 100 outputs, 2 of which can not be spent
+		let mut genesis_tx = test_data::block_builder()
+			.transaction()
+				.coinbase()
+				.build()
+			.transaction();
+		for index in 0..100 {
+			genesis_tx = match index {
+				37 | 80 => genesis_tx.output().value(1).script_pubkey_bytes(invalid_script.to_bytes()).build(),
+				_ => genesis_tx.output().value(1).build(),
+			};
+		}
+		let genesis = genesis_tx.build()
+			.merkled_header().build()
+			.build();
+
+		let storage = Arc::new(BlockChainDatabase::init_test_chain(vec![genesis.clone().into()]));
+		let reference_tx = genesis.transactions()[1].hash();
+
+		let serial_verifier = ChainVerifier::new(storage.clone(), Magic::Unitest);
+		let parallel_verifier = ChainVerifier::new(storage, Magic::Unitest).with_threads(4).unwrap();
+
+		// spend all valid outputs
+		let mut tx = test_data::block_builder()
+			.transaction().coinbase().build()
+			.transaction();
+		for index in (0..100).filter(|index| *index != 37 && *index != 80) {
+			tx = tx.input().hash(reference_tx.clone()).index(index).build();
+		}
+		let block: IndexedBlock = tx.output().value(98).build()
+			.build()
+			.merkled_header().parent(genesis.hash()).build()
+			.build()
+			.into();
+		assert_eq!(serial_verifier.verify(&block), Ok(()));
+		assert_eq!(parallel_verifier.verify(&block), Ok(()));
+
+		// spend all outputs => first invalid input is reported
+		let mut tx = test_data::block_builder()
+			.transaction().coinbase().build()
+			.transaction();
+		for index in 0..100 {
+			tx = tx.input().hash(reference_tx.clone()).index(index).build();
+		}
+		let block: IndexedBlock = tx.output().value(100).build()
+			.build()
+			.merkled_header().parent(genesis.hash()).build()
+			.build()
+			.into();
+		let expected = Err(Error::Transaction(1, TransactionError::Signature(37)));
+		assert_eq!(serial_verifier.verify(&block), expected);
+		assert_eq!(parallel_verifier.verify(&block), expected);
+	}
 }
@@ -93,5 +93,7 @@ pub enum TransactionError {
 	UnspentTransactionWithTheSameHash,
 	/// Using output that is surely spent
 	UsingSpentOutput(H256, u32),
+	/// Relative locktime (BIP68) of given input is not yet satisfied
+	RelativeLocktime(usize),
 }
 
@@ -69,6 +69,7 @@ mod canon;
 mod deployments;
 mod duplex_store;
 mod error;
+mod locktime;
 mod sigops;
 mod timestamp;
 mod work;
@@ -103,9 +104,10 @@ pub use verify_transaction::{TransactionVerifier, MemoryPoolTransactionVerifier}
 
 pub use chain_verifier::BackwardsCompatibleChainVerifier;
 pub use error::{Error, TransactionError};
+pub use locktime::verify_relative_locktime;
 pub use sigops::transaction_sigops;
-pub use timestamp::median_timestamp;
-pub use work::{work_required, is_valid_proof_of_work, is_valid_proof_of_work_hash, block_reward_satoshi};
+pub use timestamp::{median_timestamp, median_time_past};
+pub use work::{work_required, work_required_retarget, is_valid_proof_of_work, is_valid_proof_of_work_hash, block_reward_satoshi};
 
 /// Interface for block verification
 pub trait Verify : Send + Sync {
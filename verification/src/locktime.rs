@@ -0,0 +1,89 @@
+use chain::Transaction;
+use chain::constants::{SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG, SEQUENCE_LOCKTIME_MASK};
+use error::TransactionError;
+
+/// Time-based relative locktime is measured in units of 2^9 = 512 seconds
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// Checks that relative locktimes (BIP68) of all transaction inputs are satisfied.
+/// `input_heights[i]` is the height of the block, containing output spent by i-th input, and
+/// `input_times[i]` is the median time past of the block, preceding this block.
+/// Transaction is included in the block on top of the block with `tip_height` and `tip_median_time_past`
+pub fn verify_relative_locktime(
+	transaction: &Transaction,
+	input_heights: &[u32],
+	input_times: &[u32],
+	tip_height: u32,
+	tip_median_time_past: u32,
+) -> Result<(), TransactionError> {
+	// relative locktime is only enforced for transactions with version 2+
+	if transaction.version < 2 {
+		return Ok(());
+	}
+
+	for (index, input) in transaction.inputs.iter().enumerate() {
+		if input.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+			continue;
+		}
+
+		let locktime = (input.sequence & SEQUENCE_LOCKTIME_MASK) as u64;
+		let is_too_early = if input.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+			input_times[index] as u64 + (locktime << SEQUENCE_LOCKTIME_GRANULARITY) > tip_median_time_past as u64
+		} else {
+			input_heights[index] as u64 + locktime > tip_height as u64 + 1
+		};
+
+		if is_too_early {
+			return Err(TransactionError::RelativeLocktime(index));
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use chain::{Transaction, TransactionInput};
+	use chain::constants::{SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG};
+	use error::TransactionError;
+	use super::verify_relative_locktime;
+
+	fn transaction_with_sequences(version: i32, sequences: &[u32]) -> Transaction {
+		Transaction {
+			version: version,
+			inputs: sequences.iter().map(|sequence| TransactionInput {
+				sequence: *sequence,
+				..Default::default()
+			}).collect(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn relative_locktime_height_based() {
+		// input 1 requires 10 confirmations
+		let tx = transaction_with_sequences(2, &[SEQUENCE_FINAL, 10]);
+		let heights = [50, 100];
+		let times = [0, 0];
+
+		assert_eq!(verify_relative_locktime(&tx, &heights, &times, 108, 0), Err(TransactionError::RelativeLocktime(1)));
+		assert_eq!(verify_relative_locktime(&tx, &heights, &times, 109, 0), Ok(()));
+		// disabled relative locktime
+		let tx = transaction_with_sequences(2, &[SEQUENCE_FINAL, 10 | SEQUENCE_LOCKTIME_DISABLE_FLAG]);
+		assert_eq!(verify_relative_locktime(&tx, &heights, &times, 108, 0), Ok(()));
+		// not enforced for version 1 transactions
+		let tx = transaction_with_sequences(1, &[SEQUENCE_FINAL, 10]);
+		assert_eq!(verify_relative_locktime(&tx, &heights, &times, 108, 0), Ok(()));
+	}
+
+	#[test]
+	fn relative_locktime_time_based() {
+		// input 0 requires 2 * 512 seconds to pass
+		let tx = transaction_with_sequences(2, &[2 | SEQUENCE_LOCKTIME_TYPE_FLAG]);
+		let heights = [100];
+		let times = [1_000_000];
+
+		assert_eq!(verify_relative_locktime(&tx, &heights, &times, 200, 1_001_023), Err(TransactionError::RelativeLocktime(0)));
+		assert_eq!(verify_relative_locktime(&tx, &heights, &times, 200, 1_001_024), Ok(()));
+	}
+}
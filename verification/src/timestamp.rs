@@ -1,20 +1,40 @@
-use std::collections::BTreeSet;
 use chain::BlockHeader;
-use db::{BlockHeaderProvider, BlockAncestors};
+use db::{self, BlockHeaderProvider, BlockRef};
 
 /// Returns median timestamp, of given header ancestors.
 /// The header should be later expected to have higher timestamp
 /// than this median timestamp
 pub fn median_timestamp(header: &BlockHeader, store: &BlockHeaderProvider) -> u32 {
-	let timestamps: BTreeSet<_> = BlockAncestors::new(header.previous_header_hash.clone().into(), store)
-		.take(11)
-		.map(|header| header.time)
-		.collect();
+	median_time_past(header.previous_header_hash.clone().into(), store)
+}
 
-	if timestamps.is_empty() {
-		return 0;
-	}
+/// Returns median timestamp of given block and its ancestors
+pub fn median_time_past(block: BlockRef, store: &BlockHeaderProvider) -> u32 {
+	db::median_time_past(block, store).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate test_data;
 
-	let timestamps = timestamps.into_iter().collect::<Vec<_>>();
-	timestamps[timestamps.len() / 2]
+	use chain::IndexedBlock;
+	use db::{BlockChainDatabase, BlockRef};
+	use super::median_time_past;
+
+	#[test]
+	fn median_time_past_counts_equal_timestamps() {
+		let mut blocks: Vec<IndexedBlock> = Vec::new();
+		for time in [10, 10, 10, 20, 30].iter().cloned() {
+			let header = test_data::block_builder().header().time(time);
+			let header = match blocks.last() {
+				Some(parent) => header.parent(parent.hash().clone()),
+				None => header,
+			};
+			blocks.push(header.build().build().into());
+		}
+
+		let storage = BlockChainDatabase::init_test_chain(blocks);
+		// [10, 10, 10, 20, 30] => 10 (deduplicated set would give 20)
+		assert_eq!(median_time_past(BlockRef::Number(4), &storage), 10);
+	}
 }